@@ -0,0 +1,144 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Set this to update the checked-in headers in `include` – otherwise they're only generated into `OUT_DIR`
+const UPDATE_HEADERS_KEY_NAME: &str = "CONFIGURE_UPDATE_HEADERS";
+
+/// Generate the C headers from the `ffi` module, so host apps always build against the current signatures
+///
+/// A build never writes to the source tree unless it's asked to – the headers in `include` are checked in, and
+/// updated by building with `CONFIGURE_UPDATE_HEADERS=1` after changing the C interface.
+fn main() {
+    record_build_metadata();
+
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=src/ffi");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed={}", UPDATE_HEADERS_KEY_NAME);
+
+    let crate_directory = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_directory = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let config = cbindgen::Config::from_root_or_default(&crate_directory);
+
+    let update_headers = env::var(UPDATE_HEADERS_KEY_NAME)
+        .map(|value| !value.is_empty() && value != "0")
+        .unwrap_or(false);
+
+    // The Apple header describes a library built with the `apple` feature, so it turns on that feature's definitions
+    let mut apple_config = config.clone();
+    apple_config.after_includes = Some("#define CONFIGURE_APPLE".to_string());
+
+    let headers = [
+        ("include/configure.h", config),
+        ("include/apple/configure.h", apple_config),
+    ];
+
+    for (header_path, config) in headers {
+        let generated_path = out_directory.join(header_path);
+
+        if generate_header(&crate_directory, config, &generated_path) && update_headers {
+            copy_header(&generated_path, &crate_directory.join(header_path));
+        }
+    }
+}
+
+/// Write the header to `header_path`, returning whether it could be generated
+fn generate_header(crate_directory: &Path, config: cbindgen::Config, header_path: &Path) -> bool {
+    let result = cbindgen::Builder::new()
+        .with_crate(crate_directory)
+        .with_config(config)
+        .generate();
+
+    // A broken header shouldn't stop the Rust build, but it should be hard to miss
+    match result {
+        Ok(bindings) => bindings.write_to_file(header_path) || header_path.exists(),
+        Err(err) => {
+            println!(
+                "cargo:warning=Unable to generate {}: {}",
+                header_path.display(),
+                err
+            );
+            false
+        }
+    }
+}
+
+fn copy_header(generated_path: &Path, header_path: &Path) {
+    if let Err(err) = std::fs::copy(generated_path, header_path) {
+        println!(
+            "cargo:warning=Unable to update {}: {}",
+            header_path.display(),
+            err
+        );
+    }
+}
+
+/// Make the commit, date and target being built available to `configure version`
+///
+/// Builds from a source tarball have no git history, so packagers can set `CONFIGURE_GIT_COMMIT` themselves. Setting
+/// `SOURCE_DATE_EPOCH` makes the date reproducible.
+fn record_build_metadata() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=CONFIGURE_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git_commit = env::var("CONFIGURE_GIT_COMMIT")
+        .ok()
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()?;
+
+            match output.status.success() {
+                true => Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+                false => None,
+            }
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let build_time = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default()
+        });
+
+    println!("cargo:rustc-env=CONFIGURE_GIT_COMMIT={}", git_commit);
+    println!(
+        "cargo:rustc-env=CONFIGURE_BUILD_DATE={}",
+        date_from_unix_time(build_time)
+    );
+    println!(
+        "cargo:rustc-env=CONFIGURE_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+}
+
+/// `seconds` since the Unix epoch as a `YYYY-MM-DD` date in UTC
+///
+/// This is Howard Hinnant's `civil_from_days`, so the build script doesn't need a date library.
+fn date_from_unix_time(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -2,4 +2,7 @@ package com.automattic.android.configure
 
 open class ConfigureApplyTask : ConfigureBaseTask() {
     override val command = "apply"
+
+    // Nothing is asked outside a terminal anyway, and `--force` would decrypt every file on every build
+    override val nonInteractiveArguments = emptyList<String>()
 }
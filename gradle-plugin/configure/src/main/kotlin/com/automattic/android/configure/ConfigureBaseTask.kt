@@ -21,6 +21,9 @@ abstract class ConfigureBaseTask: DefaultTask() {
     @get:Input
     abstract val command: String
 
+    // Keeps the command from asking anything
+    protected open val nonInteractiveArguments = listOf("--force")
+
     @Throws(TaskExecutionException::class)
     @org.gradle.api.tasks.TaskAction
     fun runCommand() {
@@ -32,8 +35,9 @@ abstract class ConfigureBaseTask: DefaultTask() {
                 var args = mutableListOf(
                     "cargo", "run", command,
                     "--configuration-file-path", configureFilePath,
-                    "--force"
+                    "--project-root", project.rootDir.absolutePath
                 )
+                args.addAll(nonInteractiveArguments)
 
                 if(verboseOutput) {
                     args.add("-vvvv")
@@ -45,7 +49,11 @@ abstract class ConfigureBaseTask: DefaultTask() {
             val binaryPath = ConfigureHelpers.configureBinaryPath.toAbsolutePath().toString()
 
             project.exec {
-                var args = mutableListOf(binaryPath, command, "--force")
+                var args = mutableListOf(
+                    binaryPath, command,
+                    "--project-root", project.rootDir.absolutePath
+                )
+                args.addAll(nonInteractiveArguments)
 
                 if(verboseOutput) {
                     args.add("-vvvv")
@@ -0,0 +1,177 @@
+#ifndef CONFIGURE_H
+#define CONFIGURE_H
+
+/* This file is generated from src/ffi.rs by cbindgen – don't edit it directly */
+
+#include <stdbool.h>
+#include <stdint.h>
+#define CONFIGURE_APPLE
+
+// The version of the C interface described by `include/configure.h`
+#define CONFIGURE_ABI_VERSION 1
+
+// Returned by functions that don't report a specific error code when they fail – the details are logged
+#define CONFIGURE_ERROR_UNKNOWN -1
+
+// A log callback
+//
+// `level` is `1` (error) through `5` (trace). `message` is only valid for the duration of the call.
+typedef void (*ConfigureLogCallback)(int level, const char *message);
+
+// An event callback
+//
+// `event` is a JSON object with an `event` field naming what happened, and is only valid for the duration of the call.
+typedef void (*ConfigureEventCallback)(const char *event);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The version of the C interface this library provides – compare it with `CONFIGURE_ABI_VERSION` from the header
+uint32_t configure_abi_version(void);
+
+// Send the library's log messages to `callback` instead of printing them
+//
+// `max_level` is `0` (off) through `5` (trace) – values outside that range are clamped.
+void configure_set_log_callback(ConfigureLogCallback callback, int max_level);
+
+// Call `callback` with each step of an apply or update as it happens
+//
+// Replaces any callback registered before.
+void configure_set_event_callback(ConfigureEventCallback callback);
+
+// Set up the encryption library ahead of time
+//
+// Every function that needs it does this itself the first time, so this is optional – it's useful for finding out at
+// startup whether encryption works on this device. Returns `0` on success, or the error's code.
+int configure_init_encryption(void);
+
+#if !defined(CONFIGURE_APPLE)
+// Set up a project to use the configure tool, prompting for anything that's missing
+//
+// Returns `0` on success, or `CONFIGURE_ERROR_UNKNOWN`.
+int configure_init(void);
+#endif
+
+#if !defined(CONFIGURE_APPLE)
+// Decrypt the secrets already present in the project
+//
+// Returns `0` on success, or the error's code – `CONFIGURE_ERROR_UNKNOWN` if it was a panic.
+//
+// # Safety
+//
+// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+int configure_apply(bool interactive,
+                    const char *configuration_file_path);
+#endif
+
+#if !defined(CONFIGURE_APPLE)
+// Encrypt the latest secrets into the project, then decrypt them
+//
+// Returns `0` on success, or the error's code – `CONFIGURE_ERROR_UNKNOWN` if it was a panic.
+//
+// # Safety
+//
+// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+int configure_update(bool interactive,
+                     const char *configuration_file_path);
+#endif
+
+// Change the project name in the `.configure` file
+//
+// # Safety
+//
+// `project_name` must be a valid C string. `configuration_file_path` may be null to use the default configuration file.
+int configure_update_project_name(const char *project_name,
+                                  const char *configuration_file_path);
+
+// Change the secrets branch in the `.configure` file
+//
+// # Safety
+//
+// `branch_name` must be a valid C string. `configuration_file_path` may be null to use the default configuration file.
+int configure_update_branch_name(const char *branch_name,
+                                 const char *configuration_file_path);
+
+// Change the pinned secrets commit in the `.configure` file
+//
+// # Safety
+//
+// `pinned_hash` must be a valid C string. `configuration_file_path` may be null to use the default configuration file.
+int configure_update_pinned_hash(const char *pinned_hash,
+                                 const char *configuration_file_path);
+
+// Check that the `.configure` file can be read, and that its signature checks out if the project signs it
+//
+// # Safety
+//
+// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+int configure_validate(const char *configuration_file_path);
+
+// Create a new encryption key
+//
+// Returns null if the encryption library can't be used, and the reason is logged. Otherwise, the returned string must
+// be freed with `configure_free_string`.
+char *configure_generate_encryption_key(void);
+
+// The path to the project's `.configure` file
+//
+// Returns null if it can't be found. Otherwise, the returned string must be freed with `configure_free_string`.
+char *configure_find_configuration_file(void);
+
+// The secrets repo's branch, commit, sync state, working copy and remote, as a JSON object
+//
+// The object has `path`, `branch`, `hash`, `ahead`, `behind`, `dirty`, `remote`, and `remote_url` – `branch` is null
+// when HEAD is detached, and `ahead` and `behind` are null when the branch has no upstream. Returns null if the
+// secrets repo can't be read, and the reason is logged. Otherwise, the returned string must be freed with
+// `configure_free_string`.
+//
+// # Safety
+//
+// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+char *configure_secrets_repo_status(const char *configuration_file_path);
+
+// Encrypt a single file
+//
+// # Safety
+//
+// `input_file` and `encryption_key` must be valid C strings. `output_file` may be null, in which case `.enc` is
+// added to the input file path.
+int configure_encrypt_file(const char *input_file,
+                           const char *output_file,
+                           const char *encryption_key);
+
+// Decrypt a single file
+//
+// # Safety
+//
+// `input_file` and `encryption_key` must be valid C strings. `output_file` may be null, in which case the `.enc`
+// extension is removed from the input file path.
+int configure_decrypt_file(const char *input_file,
+                           const char *output_file,
+                           const char *encryption_key);
+
+// Free a string returned by this library
+//
+// # Safety
+//
+// `string` must have been returned by this library and not freed already. Passing null does nothing.
+void configure_free_string(char *string);
+
+#if defined(CONFIGURE_APPLE)
+// Decrypt the project's secrets without prompting
+//
+// Files that were modified locally are overwritten (a backup is kept), and files that haven't changed since the
+// last apply are skipped. The encryption key comes from the keys file or `CONFIGURE_ENCRYPTION_KEY`, as usual.
+//
+// # Safety
+//
+// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+int configure_apply_for_build(const char *configuration_file_path);
+#endif
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CONFIGURE_H */
@@ -21,7 +21,8 @@ module Configure
   end
 
   ffi_lib File.expand_path(lib_name, __dir__)
-  attach_function :init, [], :void
-  attach_function :apply, %i[bool string], :void
-  attach_function :update, %i[bool string], :void
+  # Signatures match `include/configure.h` – each function returns 0 on success
+  attach_function :init, :configure_init, [], :int
+  attach_function :apply, :configure_apply, %i[bool string], :int
+  attach_function :update, :configure_update, %i[bool string], :int
 end
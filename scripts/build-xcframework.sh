@@ -0,0 +1,34 @@
+#!/bin/bash -eu
+
+# Build `Configure.xcframework` from the `apple` feature's static library, for linking from an Xcode build phase
+# or a Swift package's `binaryTarget`. Run it from the repository root on a Mac with the Apple targets installed:
+#
+#   rustup target add aarch64-apple-darwin x86_64-apple-darwin aarch64-apple-ios aarch64-apple-ios-sim x86_64-apple-ios
+
+OUTPUT_DIRECTORY="target/xcframework"
+LIBRARY_NAME="libconfigure.a"
+
+for target in aarch64-apple-darwin x86_64-apple-darwin aarch64-apple-ios aarch64-apple-ios-sim x86_64-apple-ios; do
+  cargo build --release --lib --features apple --target "$target"
+done
+
+# Each platform needs a single library, so combine the architectures that share one
+mkdir -p "$OUTPUT_DIRECTORY/macos" "$OUTPUT_DIRECTORY/ios-simulator"
+
+lipo -create \
+  "target/aarch64-apple-darwin/release/$LIBRARY_NAME" \
+  "target/x86_64-apple-darwin/release/$LIBRARY_NAME" \
+  -output "$OUTPUT_DIRECTORY/macos/$LIBRARY_NAME"
+
+lipo -create \
+  "target/aarch64-apple-ios-sim/release/$LIBRARY_NAME" \
+  "target/x86_64-apple-ios/release/$LIBRARY_NAME" \
+  -output "$OUTPUT_DIRECTORY/ios-simulator/$LIBRARY_NAME"
+
+rm -rf "$OUTPUT_DIRECTORY/Configure.xcframework"
+
+xcodebuild -create-xcframework \
+  -library "$OUTPUT_DIRECTORY/macos/$LIBRARY_NAME" -headers include/apple \
+  -library "target/aarch64-apple-ios/release/$LIBRARY_NAME" -headers include/apple \
+  -library "$OUTPUT_DIRECTORY/ios-simulator/$LIBRARY_NAME" -headers include/apple \
+  -output "$OUTPUT_DIRECTORY/Configure.xcframework"
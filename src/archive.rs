@@ -0,0 +1,380 @@
+use crate::git::CommittedEntry;
+use crate::ConfigureError;
+use log::debug;
+use ring::digest::{digest, SHA256};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+/// Pack the contents of `directory` into an uncompressed tar archive, keeping file permissions
+///
+/// Paths in the archive are relative to `directory`, so it can be extracted anywhere. Ownership and timestamps are
+/// left out so that archiving the same files always produces the same bytes.
+pub fn create_archive(directory: &Path) -> Result<Vec<u8>, ConfigureError> {
+    debug!("Archiving {:?}", directory);
+
+    let mut builder = tar::Builder::new(Vec::new());
+
+    for relative_path in entries_in_directory(directory)? {
+        let path = directory.join(&relative_path);
+
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Err(ConfigureError::InputFileNotReadable { path: path.clone() }),
+        };
+
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+        header.set_mode(permissions_for(&metadata));
+
+        let result = if metadata.is_dir() {
+            header.set_size(0);
+            builder.append_data(&mut header, &relative_path, std::io::empty())
+        } else {
+            match std::fs::File::open(&path) {
+                Ok(file) => builder.append_data(&mut header, &relative_path, file),
+                Err(_) => return Err(ConfigureError::InputFileNotReadable { path: path.clone() }),
+            }
+        };
+
+        if result.is_err() {
+            return Err(ConfigureError::InputFileNotReadable { path: path.clone() });
+        }
+    }
+
+    match builder.into_inner() {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => Err(ConfigureError::InputFileNotReadable {
+            path: directory.to_path_buf(),
+        }),
+    }
+}
+
+/// `create_archive` for a directory read from a commit in the secrets repo instead of from disk
+///
+/// `path` is only used in error messages.
+pub fn create_archive_from_entries(
+    path: &Path,
+    entries: &[CommittedEntry],
+) -> Result<Vec<u8>, ConfigureError> {
+    debug!("Archiving {:?} from the secrets repo", path);
+
+    let mut builder = tar::Builder::new(Vec::new());
+
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(entry.mode);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+
+        let result = match &entry.contents {
+            Some(contents) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(contents.len() as u64);
+                builder.append_data(&mut header, &entry.path, contents.as_slice())
+            }
+            None => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                builder.append_data(&mut header, &entry.path, std::io::empty())
+            }
+        };
+
+        if result.is_err() {
+            return Err(ConfigureError::InputFileNotReadable {
+                path: path.join(&entry.path),
+            });
+        }
+    }
+
+    match builder.into_inner() {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => Err(ConfigureError::InputFileNotReadable {
+            path: path.to_path_buf(),
+        }),
+    }
+}
+
+#[cfg(unix)]
+fn permissions_for(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions_for(metadata: &std::fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+/// Every file and directory under `directory`, relative to it, with parents listed before their contents
+///
+/// Symbolic links can't be archived – following one could take in files from outside the directory, or never end –
+/// so finding one is an error.
+fn entries_in_directory(directory: &Path) -> Result<Vec<PathBuf>, ConfigureError> {
+    let mut entries = Vec::new();
+    add_entries_in_directory(directory, directory, &mut entries)?;
+    Ok(entries)
+}
+
+fn add_entries_in_directory(
+    root: &Path,
+    directory: &Path,
+    entries: &mut Vec<PathBuf>,
+) -> Result<(), ConfigureError> {
+    let mut paths = Vec::new();
+
+    match std::fs::read_dir(directory) {
+        Ok(directory_entries) => {
+            for entry in directory_entries {
+                match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))) {
+                    Ok(path_and_type) => paths.push(path_and_type),
+                    Err(_) => {
+                        return Err(ConfigureError::InputFileNotReadable {
+                            path: directory.to_path_buf(),
+                        })
+                    }
+                }
+            }
+        }
+        Err(_) => {
+            return Err(ConfigureError::InputFileNotReadable {
+                path: directory.to_path_buf(),
+            })
+        }
+    }
+
+    paths.sort_by(|(first, _), (second, _)| first.cmp(second));
+
+    for (path, file_type) in paths {
+        if file_type.is_symlink() {
+            debug!("{:?} is a symbolic link, which can't be archived", path);
+            return Err(ConfigureError::InputFileNotReadable { path });
+        }
+
+        entries.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+
+        if file_type.is_dir() {
+            add_entries_in_directory(root, &path, entries)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpack an archive made by `create_archive` into `destination`, creating it if needed
+pub fn extract_archive(archive: &[u8], destination: &Path) -> Result<(), ConfigureError> {
+    debug!("Extracting archive into {:?}", destination);
+
+    if std::fs::create_dir_all(destination).is_err() {
+        return Err(ConfigureError::OutputFileNotWritable {
+            path: destination.to_path_buf(),
+        });
+    }
+
+    let mut archive = tar::Archive::new(archive);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(false); // Archives are made without timestamps, so the extracted files are as new as the apply
+
+    match archive.unpack(destination) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(ConfigureError::OutputFileNotWritable {
+            path: destination.to_path_buf(),
+        }),
+    }
+}
+
+/// A description of every file in `directory` – one line per file with its path and the hash of its contents
+///
+/// This is used both to tell whether a directory changed and to show what changed, so it's plain text.
+pub fn manifest_for_directory(directory: &Path) -> Result<String, ConfigureError> {
+    let mut files = BTreeMap::new();
+
+    for relative_path in entries_in_directory(directory)? {
+        let path = directory.join(&relative_path);
+
+        if path.is_dir() {
+            continue;
+        }
+
+        match std::fs::read(&path) {
+            Ok(contents) => files.insert(manifest_path(&relative_path), hash(&contents)),
+            Err(_) => return Err(ConfigureError::InputFileNotReadable { path: path.clone() }),
+        };
+    }
+
+    Ok(render_manifest(&files))
+}
+
+/// The same manifest as `manifest_for_directory` would produce once `archive` is extracted
+pub fn manifest_for_archive(archive: &[u8]) -> Result<String, ConfigureError> {
+    let mut files = BTreeMap::new();
+    let mut archive = tar::Archive::new(archive);
+
+    let entries = match archive.entries() {
+        Ok(entries) => entries,
+        Err(_) => return Err(ConfigureError::DataDecryptionError),
+    };
+
+    for entry in entries {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => return Err(ConfigureError::DataDecryptionError),
+        };
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = match entry.path() {
+            Ok(path) => manifest_path(&path),
+            Err(_) => return Err(ConfigureError::DataDecryptionError),
+        };
+
+        let mut contents = Vec::new();
+        if entry.read_to_end(&mut contents).is_err() {
+            return Err(ConfigureError::DataDecryptionError);
+        }
+
+        files.insert(path, hash(&contents));
+    }
+
+    Ok(render_manifest(&files))
+}
+
+/// Paths are always written with forward slashes and without a leading `./`, so manifests match across platforms
+fn manifest_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn render_manifest(files: &BTreeMap<String, String>) -> String {
+    files
+        .iter()
+        .map(|(path, hash)| format!("{}  {}\n", hash, path))
+        .collect()
+}
+
+fn hash(bytes: &[u8]) -> String {
+    base64::encode(digest(&SHA256, bytes).as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_archive_manifest_matches_extracted_directory() {
+        let source = test_directory("source");
+        std::fs::create_dir_all(source.join("nested")).unwrap();
+        std::fs::write(source.join("a.mobileprovision"), b"profile").unwrap();
+        std::fs::write(source.join("nested/b.txt"), b"nested").unwrap();
+
+        let archive = create_archive(&source).unwrap();
+
+        let destination = test_directory("destination");
+        extract_archive(&archive, &destination).unwrap();
+
+        let manifest = manifest_for_archive(&archive).unwrap();
+        assert_eq!(manifest, manifest_for_directory(&destination).unwrap());
+        assert_eq!(manifest.lines().count(), 2);
+        assert!(manifest.contains("  nested/b.txt\n"));
+    }
+
+    #[test]
+    fn test_that_archiving_the_same_files_twice_produces_identical_bytes() {
+        let source = test_directory("deterministic");
+        std::fs::write(source.join("a.txt"), b"a").unwrap();
+
+        let first = create_archive(&source).unwrap();
+        rewrite_file(&source.join("a.txt"));
+        assert_eq!(first, create_archive(&source).unwrap());
+    }
+
+    #[test]
+    fn test_that_committed_entries_are_archived_like_files_on_disk() {
+        let entries = vec![
+            CommittedEntry {
+                path: PathBuf::from("nested"),
+                mode: 0o755,
+                contents: None,
+            },
+            CommittedEntry {
+                path: PathBuf::from("nested/b.txt"),
+                mode: 0o644,
+                contents: Some(b"nested".to_vec()),
+            },
+        ];
+
+        let archive = create_archive_from_entries(Path::new("profiles"), &entries).unwrap();
+
+        let destination = test_directory("committed-destination");
+        extract_archive(&archive, &destination).unwrap();
+
+        assert_eq!(
+            std::fs::read(destination.join("nested/b.txt")).unwrap(),
+            b"nested"
+        );
+        assert_eq!(
+            manifest_for_archive(&archive).unwrap(),
+            manifest_for_directory(&destination).unwrap()
+        );
+    }
+
+    fn rewrite_file(path: &Path) {
+        let contents = std::fs::read(path).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_that_extracting_an_archive_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let source = test_directory("permissions-source");
+        let script = source.join("script.sh");
+        std::fs::write(&script, b"#!/bin/sh").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+        let destination = test_directory("permissions-destination");
+        extract_archive(&create_archive(&source).unwrap(), &destination).unwrap();
+
+        let mode = std::fs::metadata(destination.join("script.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_that_directories_with_symbolic_links_are_not_archived() {
+        let source = test_directory("symlink-loop");
+        std::fs::write(source.join("a.txt"), b"a").unwrap();
+        std::os::unix::fs::symlink(&source, source.join("loop")).unwrap();
+
+        assert!(matches!(
+            create_archive(&source),
+            Err(ConfigureError::InputFileNotReadable { path }) if path == source.join("loop")
+        ));
+    }
+
+    fn test_directory(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("configure-archive-test-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
@@ -0,0 +1,147 @@
+//! Async versions of the operations that talk to the secrets server
+//!
+//! The network access (`git fetch` and resolving remote hashes) is awaited directly, so dropping the future stops it
+//! and kills the `git` process. The local work that follows runs on tokio's blocking thread pool. These never prompt –
+//! they behave like the non-interactive versions in the crate root, but return errors instead of exiting.
+//!
+//! They need to be run on a tokio runtime with the time and I/O drivers enabled.
+
+use crate::cancel::CancellationToken;
+use crate::configure::try_update_configuration;
+use crate::fs::{
+    find_secrets_repo, read_configuration_from_file, read_verified_configuration_from_file,
+    status_for_configuration,
+};
+use crate::git::SecretsRepo;
+use crate::state::ConfigurationStatus;
+use crate::ConfigureError;
+use log::warn;
+
+/// Fetch the latest secrets and update the project to use them – the async version of `update(false, …)`
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub async fn update_async(configuration_file_path: Option<String>) -> Result<(), ConfigureError> {
+    crate::encryption::init()?;
+
+    let configuration = read_configuration_from_file(&configuration_file_path)?;
+
+    if configuration.is_empty() {
+        warn!("Current configuration is empty – unable to update when running in non-interactive mode");
+        return Ok(());
+    }
+
+    let secrets_repo = secrets_repo()?;
+    secrets_repo
+        .update_local_copy_async(&secrets_repo.remote_for_configuration(&configuration)?)
+        .await?;
+
+    // The update itself can't be interrupted by dropping the future, so cancel it between files instead
+    let cancellation = CancellationToken::new();
+    let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+
+    run_blocking(move || {
+        try_update_configuration(
+            configuration_file_path,
+            false,
+            false,
+            false,
+            None,
+            &cancellation,
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Compares the project's decrypted files with the last successful `apply` – the async version of `status`
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub async fn status_async(
+    configuration_file_path: Option<String>,
+) -> Result<ConfigurationStatus, ConfigureError> {
+    run_blocking(move || {
+        let configuration = read_configuration_from_file(&configuration_file_path)?;
+        status_for_configuration(&configuration)
+    })
+    .await
+}
+
+/// Ask the server for the hash of the newest commit on the project's secrets branch
+///
+/// Nothing is fetched, so this is quick even for a large secrets repo – when offline, the hash from the last fetch is
+/// returned instead. Compare this with the project's pinned hash to find out whether `update_async` would change anything.
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub async fn latest_secrets_hash_async(
+    configuration_file_path: Option<String>,
+) -> Result<String, ConfigureError> {
+    let configuration = read_verified_configuration_from_file(&configuration_file_path)?;
+    let secrets_repo = secrets_repo()?;
+
+    secrets_repo
+        .query_remote_hash_for_branch_async(
+            &secrets_repo.remote_for_configuration(&configuration)?,
+            &configuration.branch,
+        )
+        .await
+}
+
+fn secrets_repo() -> Result<SecretsRepo, ConfigureError> {
+    Ok(SecretsRepo {
+        path: find_secrets_repo()?,
+    })
+}
+
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+/// Run `work` on the blocking thread pool, passing on any panic to the caller
+async fn run_blocking<T, F>(work: F) -> Result<T, ConfigureError>
+where
+    F: FnOnce() -> Result<T, ConfigureError> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_that_run_blocking_returns_the_result() {
+        let result = runtime().block_on(run_blocking(|| Ok(42)));
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[test]
+    #[should_panic(expected = "boom")]
+    fn test_that_run_blocking_passes_on_panics() {
+        let _ = runtime().block_on(run_blocking::<(), _>(|| panic!("boom")));
+    }
+}
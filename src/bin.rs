@@ -1,8 +1,18 @@
 use log::{debug, LevelFilter};
 use simplelog::CombinedLogger;
 use simplelog::Config;
+use simplelog::SharedLogger;
+use simplelog::SimpleLogger;
 use simplelog::TermLogger;
 use simplelog::TerminalMode;
+use simplelog::WriteLogger;
+use std::fs::File;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use structopt::clap::{AppSettings, Shell};
 use structopt::StructOpt;
 use structopt_flags::GetWithDefault;
 
@@ -17,8 +27,72 @@ struct Options {
 
     #[structopt(flatten)]
     verbose: structopt_flags::VerboseNoDef,
+
+    /// Only print errors, and what commands like `status` were asked to show
+    #[structopt(short = "q", long = "quiet", global = true)]
+    quiet: bool,
+
+    /// Treat fields that `.configure` and `keys.json` aren't expected to have as errors, instead of warnings
+    #[structopt(long = "strict", global = true)]
+    strict: bool,
+
+    /// Don't style the output with colors
+    #[structopt(long = "no-color", global = true)]
+    no_color: bool,
+
+    /// Always ask the secrets server for its latest commits, instead of reusing answers from the last minute
+    #[structopt(long = "no-cache", global = true)]
+    no_cache: bool,
+
+    /// Print how long fetching, hashing, encryption and writing files took, once the command has finished
+    #[structopt(long = "timings", global = true)]
+    timings: bool,
+
+    /// The project's directory – by default, the root of the git repo the current directory is in
+    ///
+    /// Use this for projects that aren't in a git repo, or when the tool is run from somewhere else.
+    #[structopt(long = "project-root", global = true, parse(from_os_str))]
+    project_root: Option<PathBuf>,
+
+    /// The secrets repo's directory – this takes precedence over `SECRETS_REPO` and the secrets repo in your settings
+    #[structopt(long = "secrets-root", global = true, parse(from_os_str))]
+    secrets_root: Option<PathBuf>,
+
+    /// Also write a detailed log to this file, to share when asking for help – keys and passphrases are masked
+    #[structopt(long = "log-file", global = true, parse(from_os_str))]
+    log_file: Option<PathBuf>,
+
+    /// `text` for people, or `json` to print each step of an apply or update as a JSON object on its own line
+    ///
+    /// With `json`, stdout only has the events – errors still go to stderr, and nothing is asked.
+    #[structopt(long = "output", global = true, default_value = "text")]
+    output: OutputFormat,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "Unknown output format {:?} – expected `text` or `json`",
+                string
+            )),
+        }
+    }
+}
+
+// Prompts would end up in the middle of the events, so `--output json` never asks anything
+static EVENTS_ONLY: AtomicBool = AtomicBool::new(false);
+
 #[derive(StructOpt)]
 enum Command {
     /// Update this project's encrypted mobile secrets to the latest version
@@ -32,22 +106,253 @@ enum Command {
     /// 3. Prompting the user to update to the latest secrets
     ///
     /// 4. Switching the mobile secrets repo to the pinned commit hash in the `.configure` file, then copying the files specified in the `files_to_copy` hash to their specified destination, encrypting them with the format "$filename.enc".
+    ///
+    /// Before step 4, the whole plan – the branch, the old and new pinned hash, and which files change – is shown, and nothing is written until it's confirmed.
+    #[structopt(after_help = examples::UPDATE)]
     Update {
         /// Run the command in non-interactive mode (useful for CI or embedded contexts)
         ///
         #[structopt(short = "f", long = "force")]
         should_run_noninteractive: bool,
 
+        /// Go ahead without asking anything – staying on the same branch and taking the latest secrets – after showing what will change
+        #[structopt(short = "y", long = "yes")]
+        accept_plan: bool,
+
         #[structopt(short = "c", long = "configuration-file-path")]
         configuration_file_path: Option<String>,
 
+        /// Pin the project to this commit on its secrets branch instead of the latest one
+        #[structopt(long = "commit")]
+        commit: Option<String>,
+
         #[structopt(subcommand)]
         subcommand: Option<UpdateSubCommand>,
     },
 
+    /// Pin every `.configure` file in the project to the same secrets
+    ///
+    /// Finds the `.configure` files under the project root, moves each one to the latest commit on its branch (or the given commit), and regenerates their encrypted files. The secrets repo is only fetched once.
+    #[structopt(after_help = examples::SYNC_PINS)]
+    SyncPins {
+        /// Run the command in non-interactive mode (useful for CI or embedded contexts)
+        ///
+        #[structopt(short = "f", long = "force")]
+        should_run_noninteractive: bool,
+
+        /// Pin every configuration to this commit instead of the latest one
+        #[structopt(long = "commit")]
+        commit: Option<String>,
+
+        /// Search this directory instead of the project root
+        #[structopt(long = "root")]
+        root: Option<String>,
+    },
+
+    /// Go back to the secrets this project used before its last update
+    ///
+    /// With `--file`, the newest `.bak` backup of each given destination file is restored instead, without changing the pinned hash.
+    #[structopt(after_help = examples::ROLLBACK)]
+    Rollback {
+        /// Run the command in non-interactive mode (useful for CI or embedded contexts)
+        ///
+        #[structopt(short = "f", long = "force")]
+        should_run_noninteractive: bool,
+
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// A destination file to restore from its backup – can be given more than once
+        #[structopt(long = "file")]
+        files: Vec<String>,
+    },
+
     /// Decrypt the current mobile secrets for this project.
     ///
+    #[structopt(after_help = examples::APPLY)]
     Apply {
+        /// Decrypt every file without asking anything, even ones that haven't changed since the last apply
+        ///
+        /// By default, files whose encrypted copy and destination both match the last apply are skipped. Outside a terminal (like in CI, or when run by Gradle), nothing is asked either way.
+        #[structopt(short = "f", long = "force")]
+        force: bool,
+
+        /// The configuration file to apply. Repeat this to apply several at once.
+        #[structopt(short = "c", long = "configuration-file-path", number_of_values = 1)]
+        configuration_file_paths: Vec<String>,
+
+        /// Also apply every `.configure` file in the project's subdirectories
+        ///
+        /// Without this or `-c`, the nearest `.configure` file is applied – the one in the current directory, or the first one above it in the project.
+        #[structopt(long = "recursive", visible_alias = "all")]
+        recursive: bool,
+
+        /// What to do with files that were edited since they were last applied: `overwrite`, `keep`, or `fail`
+        ///
+        /// If this isn't set, you'll be asked what to do – in non-interactive mode the file is overwritten (a backup is kept unless turned off in your settings).
+        #[structopt(long = "on-conflict")]
+        conflict_policy: Option<configure::ConflictPolicy>,
+
+        /// Recreate missing encrypted files from the secrets repo at the pinned hash, without asking
+        ///
+        /// Otherwise you'll be asked whether to do this – in non-interactive mode, missing encrypted files are an error.
+        #[structopt(long = "regenerate-missing")]
+        regenerate_missing: bool,
+
+        /// Only decrypt the files in this profile from the `.configure` file, like `ci` or `release-manager`
+        ///
+        /// The files are decrypted with the profile's key, if it has one.
+        #[structopt(long = "profile")]
+        profile: Option<String>,
+
+        /// Decrypt the files into a new temporary directory instead of the project
+        ///
+        /// With a command after `--`, the command is run with the directory in `CONFIGURE_EPHEMERAL_DIR`, and the directory is deleted when it exits. Otherwise the directory is printed, and it's up to you to delete it.
+        #[structopt(long = "ephemeral")]
+        ephemeral: bool,
+
+        /// With `--ephemeral`, print the directory and where each file was decrypted as JSON
+        #[structopt(long = "json", requires = "ephemeral")]
+        json: bool,
+
+        /// With `--ephemeral`, the command to run while the decrypted files exist
+        #[structopt(last = true, requires = "ephemeral")]
+        command: Vec<String>,
+    },
+
+    /// Show whether the project's decrypted files match the last `apply`
+    ///
+    /// Lists each configured file as up to date, outdated (the encrypted file changed), modified locally, missing, or not yet applied.
+    #[structopt(after_help = examples::STATUS)]
+    Status {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+    },
+
+    /// List the files this project manages
+    ///
+    /// Shows each file's source in the secrets repo, where its encrypted copy is kept, where it's decrypted to, whether those copies exist, and the hash of the contents written by the last `apply`.
+    #[structopt(after_help = examples::LIST_FILES)]
+    ListFiles {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// Print the list as JSON instead of a table
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Show the secrets repo's branch, commit, how far it is from its upstream, whether it has uncommitted changes, and the remote the project's secrets come from
+    ///
+    /// Nothing is fetched first, so the upstream is compared as of the last fetch.
+    #[structopt(after_help = examples::SECRETS_STATUS)]
+    SecretsStatus {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// Print the details as JSON instead
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Look for secrets outside the configured destinations, before they're committed
+    ///
+    /// Finds copies of decrypted files (or long values from them) anywhere else in the project, and anything that looks like a private key or an API token. Ignored files aren't scanned. Exits with an error if anything is found.
+    #[structopt(after_help = examples::SCAN)]
+    Scan {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// Scan the files in this commit instead of the working tree
+        #[structopt(long = "commit")]
+        commit: Option<String>,
+
+        /// Print what was found as JSON instead
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Print rules for gitleaks or git-secrets that flag the files this project decrypts
+    ///
+    /// gitleaks gets a rule for each decrypted file's name, added to its default rules. git-secrets only checks what's in files, so it gets the patterns `configure scan` uses for private keys and API tokens.
+    #[structopt(after_help = examples::EXPORT_LEAK_RULES)]
+    ExportLeakRules {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// The scanner to write rules for: `gitleaks` or `git-secrets`
+        #[structopt(long = "format", default_value = "gitleaks")]
+        format: configure::LeakRulesFormat,
+    },
+
+    /// Run a command with the project's secrets in its environment, without decrypting them to disk
+    ///
+    /// Each entry in the configured `.env`, `.properties` and `.xcconfig` files is passed on as a variable, with its key upper-cased and anything other than letters, digits and `_` replaced by `_`. The command's exit code is passed back.
+    #[structopt(after_help = examples::RUN)]
+    Run {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// Only pass on the entries from the file decrypted to this destination. Repeat this for several files.
+        #[structopt(long = "file", number_of_values = 1)]
+        files: Vec<String>,
+
+        /// Only use the files in this profile from the `.configure` file, with the profile's key
+        #[structopt(long = "profile")]
+        profile: Option<String>,
+
+        /// The command to run, and its arguments
+        #[structopt(required = true, last = true)]
+        command: Vec<String>,
+    },
+
+    /// Apply the project's secrets in a GitHub Actions job, and pass them on to the job's later steps
+    ///
+    /// Each entry in the configured `.env`, `.properties` and `.xcconfig` files is masked in the job's log, then added to `GITHUB_ENV` as an environment variable, named as it would be by `configure run`. The names of the variables are printed.
+    #[structopt(after_help = examples::GHA_EXPORT)]
+    GhaExport {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// Only pass on the entries from the file decrypted to this destination. Repeat this for several files.
+        #[structopt(long = "file", number_of_values = 1)]
+        files: Vec<String>,
+
+        /// Only apply the files in this profile from the `.configure` file, with the profile's key
+        #[structopt(long = "profile")]
+        profile: Option<String>,
+
+        /// Set the values as the step's outputs too, in `GITHUB_OUTPUT`
+        #[structopt(long = "outputs")]
+        outputs: bool,
+    },
+
+    /// Show who a certificate was issued to and when it expires, or what a provisioning profile allows, without decrypting it to disk
+    ///
+    /// Works with certificates (`.pem`, `.crt`, `.cer`), `.p12` files, keystores (`.jks`, `.keystore`) and provisioning profiles. Private keys are never shown. A password for a `.p12` file or keystore is read from `CONFIGURE_KEYSTORE_PASSWORD`, or asked for if it's needed.
+    #[structopt(after_help = examples::INSPECT)]
+    Inspect {
+        /// The file's destination, as it's written in the `.configure` file
+        destination: String,
+
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// Print the details as JSON instead
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Manage the local copy of the secrets repo
+    #[structopt(after_help = examples::SECRETS)]
+    Secrets {
+        #[structopt(subcommand)]
+        subcommand: SecretsSubCommand,
+    },
+
+    /// Delete encrypted files in `.configure-files` that no longer belong to any configured file
+    #[structopt(after_help = examples::GC)]
+    Gc {
         /// Run the command in non-interactive mode (useful for CI or embedded contexts)
         ///
         #[structopt(short = "f", long = "force")]
@@ -55,46 +360,203 @@ enum Command {
 
         #[structopt(short = "c", long = "configuration-file-path")]
         configuration_file_path: Option<String>,
+
+        /// Only list the files that would be deleted
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Upgrade a project set up by an older version of configure
+    ///
+    /// Moves encrypted files into `.configure-files`, rewrites the `.configure` file in the current format, and converts a flat `keys.json` to the structured format. The changes are shown before anything is touched.
+    #[structopt(after_help = examples::MIGRATE)]
+    Migrate {
+        /// Run the command in non-interactive mode (useful for CI or embedded contexts)
+        ///
+        #[structopt(short = "f", long = "force")]
+        should_run_noninteractive: bool,
+
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// Only show the changes that would be made
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Copy a new or updated secret into your local secrets repo
+    ///
+    /// The file is copied to the given path inside the secrets repo. It can optionally be put on a new branch and committed, but nothing is pushed to the server.
+    #[structopt(after_help = examples::PUSH_SECRET)]
+    PushSecret {
+        /// The plaintext file to copy
+        local_file: String,
+
+        /// Where the file should live, relative to the root of the secrets repo
+        repo_path: String,
+
+        /// Create this branch in the secrets repo and switch to it first
+        #[structopt(short = "b", long = "branch")]
+        branch: Option<String>,
+
+        /// Commit the file once it's copied
+        #[structopt(long = "commit")]
+        commit: bool,
+
+        /// The commit message – `{action}`, `{path}`, and `{file}` are filled in for you
+        #[structopt(short = "m", long = "message")]
+        message: Option<String>,
+    },
+
+    /// Sign the `.configure` file so that changes to it can't be applied unless someone with the project's signing key approves them
+    ///
+    /// A signing key is created in the secrets repo the first time this is run for a project, stored under the project's `origin` remote URL. The signature is written to `.configure.sig`, which should be committed alongside `.configure`. `configure update` re-signs the file automatically.
+    #[structopt(after_help = examples::SIGN)]
+    Sign {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+    },
+
+    /// Keep this project's secrets up to date while you work
+    ///
+    /// Changes to the `.configure` file or the encrypted files are applied as soon as they're noticed. The secrets repo is fetched periodically, and you'll be told when new secrets are available on the configured branch.
+    #[structopt(after_help = examples::WATCH)]
+    Watch {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// How often to check for local changes, in seconds
+        #[structopt(long = "interval", default_value = "10")]
+        interval: u64,
+
+        /// How often to fetch the secrets repo, in seconds
+        #[structopt(long = "fetch-interval", default_value = "900")]
+        fetch_interval: u64,
+
+        /// Only report local changes instead of applying them
+        #[structopt(long = "no-apply")]
+        no_apply: bool,
+    },
+
+    /// Run a long-lived server so build tools can apply or update secrets without starting a new process each time
+    ///
+    /// The daemon reads one JSON request per line from a Unix domain socket (or a named pipe on Windows) – for example `{"command":"apply","working_directory":"/path/to/project"}` – and replies with one line of JSON. Supported commands are `apply`, `update`, `status`, and `stop`. A request that fails gets `"ok":false` with the error and its code, and the daemon keeps running.
+    #[structopt(after_help = examples::DAEMON)]
+    Daemon {
+        /// The socket to listen on, in a directory only you can write to (defaults to a socket in `$XDG_RUNTIME_DIR` or your home directory, or a named pipe on Windows)
+        #[structopt(short = "s", long = "socket-path")]
+        socket_path: Option<String>,
+
+        /// Shut down after this many seconds without a request
+        #[structopt(long = "idle-timeout", default_value = "600")]
+        idle_timeout: u64,
+
+        #[structopt(subcommand)]
+        subcommand: Option<DaemonSubCommand>,
     },
 
     /// Change mobile secrets settings
     ///
-    /// This command will provide step-by-step help to make changes to the mobile secrets configuration.
-    Init,
+    /// This command will provide step-by-step help to make changes to the mobile secrets configuration. In Android and iOS projects, the files they usually need (like `google-services.json`, `sentry.properties`, and `.xcconfig` secrets) are suggested, so you can accept or edit them.
+    #[structopt(after_help = examples::INIT)]
+    Init {
+        /// Start from a shared template – the name of one in the secrets repo's `templates` directory, or a path or URL
+        ///
+        /// A template is a `.configure` file that leaves out whatever differs between projects. `{project_name}` in its files' paths is replaced with the project's name.
+        #[structopt(long = "template")]
+        template: Option<String>,
+    },
 
     /// Ensure the `.configure` file is valid
+    ///
+    /// The file is checked against the JSON Schema printed by `configure schema`. Fields the schema doesn't know about are reported as warnings, or as errors with `--strict`.
+    #[structopt(after_help = examples::VALIDATE)]
     Validate,
 
+    /// Print the JSON Schema for `.configure` files
+    #[structopt(after_help = examples::SCHEMA)]
+    Schema,
+
     /// Create a new encryption key for use with a project
+    #[structopt(after_help = examples::CREATE_KEY)]
     CreateKey,
 
+    /// Print a script that completes `configure`'s commands and options in your shell
+    ///
+    /// For example, add `source <(configure completions bash)` to `~/.bashrc`, or run `configure completions fish > ~/.config/fish/completions/configure.fish`. In bash and fish, `update set-branch-name` also completes the secrets repo's branches.
+    #[structopt(after_help = examples::COMPLETIONS)]
+    Completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
+
+    /// Print exactly which build of configure this is – its version, commit, build date, features, and encryption library
+    #[structopt(after_help = examples::VERSION)]
+    Version {
+        /// Print the details as JSON instead
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Generate documentation from these command definitions
+    #[structopt(after_help = examples::DOCS)]
+    Docs {
+        #[structopt(subcommand)]
+        subcommand: DocsSubCommand,
+    },
+
+    /// Print the secrets repo's branches, one per line – used by the completion scripts
+    #[structopt(setting = AppSettings::Hidden)]
+    CompleteBranches,
+
+    /// Inspect the keys in the secrets repo's `keys.json` without printing them
+    #[structopt(after_help = examples::KEYS)]
+    Keys {
+        #[structopt(subcommand)]
+        subcommand: KeysSubCommand,
+    },
+
     /// Encrypt a single file
+    #[structopt(after_help = examples::ENCRYPT_FILE)]
     EncryptFile {
-        #[structopt(short = "f", long = "input-file")]
-        input_file: String,
+        #[structopt(short = "f", long = "input-file", parse(from_os_str))]
+        input_file: PathBuf,
 
-        #[structopt(short = "o", long = "output-file")]
-        output_file: Option<String>,
+        #[structopt(short = "o", long = "output-file", parse(from_os_str))]
+        output_file: Option<PathBuf>,
 
         #[structopt(short = "k", long = "encryption-key")]
         encryption_key: Option<String>,
+
+        /// Derive the key from a passphrase instead – it's read from `CONFIGURE_PASSPHRASE`, or asked for
+        #[structopt(long = "passphrase", conflicts_with = "encryption-key")]
+        passphrase: bool,
     },
 
     /// Decrypt a single file
+    #[structopt(after_help = examples::DECRYPT_FILE)]
     DecryptFile {
-        #[structopt(short = "f", long = "input-file")]
-        input_file: String,
+        #[structopt(short = "f", long = "input-file", parse(from_os_str))]
+        input_file: PathBuf,
 
-        #[structopt(short = "o", long = "output-file")]
-        output_file: Option<String>,
+        #[structopt(short = "o", long = "output-file", parse(from_os_str))]
+        output_file: Option<PathBuf>,
 
-        #[structopt(short = "k", long = "encryption-key")]
-        encryption_key: String,
+        #[structopt(
+            short = "k",
+            long = "encryption-key",
+            required_unless = "passphrase",
+            conflicts_with = "passphrase"
+        )]
+        encryption_key: Option<String>,
+
+        /// The file was encrypted with a passphrase – it's read from `CONFIGURE_PASSPHRASE`, or asked for
+        #[structopt(long = "passphrase")]
+        passphrase: bool,
     },
 }
 
 #[derive(StructOpt)]
-#[allow(clippy::enum_variant_names)] // Clippy doesn't like that these all start with `set`
 enum UpdateSubCommand {
     /// Update the project name field in the .configure file for this project.
     ///
@@ -104,10 +566,22 @@ enum UpdateSubCommand {
         project_name: String,
     },
 
+    /// Update the key name field in the .configure file for this project
+    ///
+    /// Projects with the same key name share a single entry in the secrets repo's `keys.json`.
+    SetKeyName {
+        /// The entry in `keys.json` to use. Leave this out to go back to using the project name
+        key_name: Option<String>,
+    },
+
     /// Update the branch name field in the .configure file for this project
     SetBranchName {
         /// The new branch name to write to the `branch` field in the `.configure` file
         branch_name: String,
+
+        /// Write the branch name even if the secrets repo has no branch with that name, locally or on a remote
+        #[structopt(long = "allow-missing")]
+        allow_missing_branch: bool,
     },
 
     /// Update the pinned commit hash field in the .configure file for this project
@@ -115,60 +589,1014 @@ enum UpdateSubCommand {
         /// The new commit hash to write to the `pinned_hash` field in the `.configure` file
         commit_hash: String,
     },
+
+    /// Add a file to the `files_to_copy` list in the .configure file for this project
+    AddFile {
+        /// The file's path, relative to the root of the secrets repo
+        #[structopt(short = "s", long = "source")]
+        source: String,
+
+        /// Where the decrypted file should be written, relative to the project root
+        #[structopt(short = "d", long = "destination")]
+        destination: String,
+
+        /// Encrypt the file into the project right away, from the secrets repo's current checkout
+        ///
+        /// Otherwise the file is encrypted the next time you run `configure update`.
+        #[structopt(long = "encrypt")]
+        encrypt: bool,
+    },
+
+    /// Remove a file from the `files_to_copy` list in the .configure file for this project
+    RemoveFile {
+        /// The destination of the file to remove
+        #[structopt(
+            short = "d",
+            long = "destination",
+            required_unless = "index",
+            conflicts_with = "index"
+        )]
+        destination: Option<String>,
+
+        /// The position of the file to remove in `files_to_copy`, starting from zero
+        #[structopt(short = "i", long = "index")]
+        index: Option<usize>,
+    },
+}
+
+#[derive(StructOpt)]
+enum KeysSubCommand {
+    /// List every project with a key fingerprint, when the key was created, and which projects share it
+    List {
+        /// Print the list as JSON instead of a table
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Show the details of one project's key
+    Show {
+        /// The project's name in `keys.json`
+        project_name: String,
+
+        /// Print the key itself, after confirming
+        #[structopt(long = "reveal")]
+        reveal: bool,
+    },
+
+    /// Give a project a new key, keeping the old one so older files can still be decrypted
+    Rotate {
+        /// The project's name in `keys.json`
+        project_name: String,
+    },
+}
+
+#[derive(StructOpt)]
+enum SecretsSubCommand {
+    /// Check out only the secrets repo directories the project's files come from
+    ///
+    /// Uses git's sparse checkout, so it needs git 2.25 or later. Without `-c`, the directories for every `.configure` file in the project are included. Run it again after adding files from a new directory.
+    SparseInit {
+        #[structopt(short = "c", long = "configuration-file-path")]
+        configuration_file_path: Option<String>,
+
+        /// Clone the secrets repo from this URL first, without downloading the contents of files outside those directories
+        #[structopt(long = "clone")]
+        clone_url: Option<String>,
+
+        /// Print what was checked out as JSON instead
+        #[structopt(long = "json")]
+        json: bool,
+    },
+}
+
+#[derive(StructOpt)]
+enum DocsSubCommand {
+    /// Write a man page for `configure` and one for each of its commands, like `configure-update.1`
+    ///
+    /// Each page is made from the command's `--help`, including its examples, so packages can install them without keeping a copy up to date by hand.
+    Man {
+        /// The directory to write the pages to – it's created if it doesn't exist
+        #[structopt(
+            short = "o",
+            long = "output-dir",
+            parse(from_os_str),
+            default_value = "man"
+        )]
+        output_dir: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+enum DaemonSubCommand {
+    /// Ask a running daemon to shut down
+    Stop,
 }
 
 pub fn main() {
     let options = Options::from_args();
+    let events_only = options.output == OutputFormat::Json;
+
+    let log_level = match options.quiet || events_only {
+        true => LevelFilter::Error,
+        false => options.verbose.get_with_default(LevelFilter::Info),
+    };
+
+    // Piped output and CI logs get plain lines, without colors or terminal control codes. Some CI agents run commands
+    // in a pseudo-terminal, so the CI's own variables are checked too – the settings file isn't read until there's a
+    // logger to report problems with it.
+    let in_ci = configure::detect_ci_environment(None, |name| std::env::var(name).ok()).is_some();
+    let styled_output = !options.no_color
+        && !in_ci
+        && std::io::stdout().is_terminal()
+        && std::io::stderr().is_terminal();
 
-    match TermLogger::new(
-        options.verbose.get_with_default(LevelFilter::Info),
-        Config::default(),
-        TerminalMode::Mixed,
-    ) {
-        Some(logger) => {
-            CombinedLogger::init(vec![logger]).unwrap_or_default();
+    let logger: Box<dyn SharedLogger> = match styled_output
+        .then(|| TermLogger::new(log_level, Config::default(), TerminalMode::Mixed))
+        .flatten()
+    {
+        Some(logger) => logger,
+        None => SimpleLogger::new(log_level, Config::default()),
+    };
+
+    let mut loggers = vec![logger];
+    let mut max_level = log_level;
+
+    if let Some(path) = &options.log_file {
+        match File::create(path) {
+            Ok(file) => {
+                let file_level = log_level.max(LevelFilter::Debug);
+                loggers.push(WriteLogger::new(file_level, Config::default(), file));
+                max_level = file_level;
+            }
+            Err(err) => eprintln!("Unable to create the log file at {:?}: {}", path, err),
         }
+    }
 
-        None => println!("Unable to initialize logging"),
+    // Every message is redacted before any logger sees it, so keys can't end up on screen or in the log file
+    let logger = configure::RedactingLogger::new(*CombinedLogger::new(loggers));
+    if log::set_logger(Box::leak(Box::new(logger))).is_ok() {
+        log::set_max_level(max_level);
     }
+    configure::redact_panic_messages();
 
     debug!("libconfigure initialized");
 
+    // Read the user's settings before printing anything, so their UI preferences apply
+    configure::settings();
+
+    if let Some(ci) = configure::ci_environment() {
+        debug!("Running in {}, so output is plain and nothing is asked", ci);
+    }
+
+    if options.no_color {
+        configure::set_colors_enabled(false);
+    }
+    configure::set_quiet(options.quiet || events_only);
+
+    EVENTS_ONLY.store(events_only, Ordering::Relaxed);
+
+    // Each event is a single line, so tools can read them as they arrive
+    match options.output {
+        OutputFormat::Json => configure::set_event_listener(|event| {
+            if let Ok(line) = serde_json::to_string(event) {
+                println!("{}", line);
+            }
+        }),
+        OutputFormat::Text => configure::set_event_listener(|event| debug!("{}", event)),
+    }
+    configure::set_strict_parsing(options.strict);
+    configure::set_remote_cache_enabled(!options.no_cache);
+    configure::set_timings_enabled(options.timings);
+
+    // Build tools run commands from all sorts of directories, so these are resolved before anything looks for them
+    if options.project_root.is_some() {
+        configure::set_project_root(options.project_root.clone());
+    }
+    if options.secrets_root.is_some() {
+        configure::set_secrets_root(options.secrets_root.clone());
+    }
+
     match Options::from_args().command {
-        Command::Apply {
+        Command::Rollback {
             should_run_noninteractive,
             configuration_file_path,
-        } => configure::apply(!should_run_noninteractive, configuration_file_path),
+            files,
+        } => {
+            if files.is_empty() {
+                configure::rollback(
+                    interactive(should_run_noninteractive),
+                    configuration_file_path,
+                )
+            } else {
+                configure::restore_backups(files, configuration_file_path)
+            }
+        }
+        Command::Apply {
+            mut configuration_file_paths,
+            profile,
+            ephemeral: true,
+            json,
+            command,
+            ..
+        } => {
+            let result = match command.is_empty() {
+                true => configure::apply_ephemeral(profile, configuration_file_paths.pop()).map(
+                    |applied| {
+                        if json {
+                            println!(
+                                "{}",
+                                serde_json::to_string_pretty(&applied)
+                                    .expect("Unable to convert the decrypted files to JSON")
+                            );
+                        } else {
+                            println!("{}", applied.directory.display());
+                        }
+                        0
+                    },
+                ),
+                false => configure::run_with_ephemeral_files(
+                    command,
+                    profile,
+                    configuration_file_paths.pop(),
+                ),
+            };
+
+            match result {
+                Ok(code) => std::process::exit(code),
+                Err(err) => {
+                    eprintln!(
+                        "Unable to decrypt the files into a temporary directory: {}",
+                        err
+                    );
+                    std::process::exit(err.code());
+                }
+            }
+        }
+        Command::Apply {
+            force,
+            mut configuration_file_paths,
+            recursive,
+            conflict_policy,
+            regenerate_missing,
+            profile,
+            ..
+        } => {
+            let options = configure::ApplyOptions {
+                interactive: interactive(force),
+                conflict_policy,
+                force_decrypt: force,
+                regenerate_missing,
+                profile,
+                ..Default::default()
+            };
+
+            if recursive || configuration_file_paths.len() > 1 {
+                configure::apply_all(configuration_file_paths, recursive, options)
+            } else if let Err(err) =
+                configure::try_apply_with_options(configuration_file_paths.pop(), options)
+            {
+                eprintln!("Unable to apply the secrets: {}", err);
+                std::process::exit(err.code());
+            }
+        }
         Command::Update {
             should_run_noninteractive,
+            accept_plan,
             configuration_file_path,
+            commit,
             subcommand,
         } => match subcommand {
             Some(subcommand) => match subcommand {
                 UpdateSubCommand::SetProjectName { project_name } => {
-                    configure::update_project_name(project_name, configuration_file_path)
+                    configure::update_project_name(
+                        project_name,
+                        interactive(should_run_noninteractive),
+                        configuration_file_path,
+                    )
                 }
-                UpdateSubCommand::SetBranchName { branch_name } => {
-                    configure::update_branch_name(branch_name, configuration_file_path)
+                UpdateSubCommand::SetKeyName { key_name } => {
+                    configure::update_key_name(key_name, configuration_file_path)
                 }
+                UpdateSubCommand::SetBranchName {
+                    branch_name,
+                    allow_missing_branch,
+                } => configure::update_branch_name(
+                    branch_name,
+                    allow_missing_branch,
+                    configuration_file_path,
+                ),
                 UpdateSubCommand::SetCommitHash { commit_hash } => {
                     configure::update_pinned_hash(commit_hash, configuration_file_path)
                 }
+                UpdateSubCommand::AddFile {
+                    source,
+                    destination,
+                    encrypt,
+                } => configure::add_file(source, destination, encrypt, configuration_file_path),
+                UpdateSubCommand::RemoveFile { destination, index } => match (destination, index) {
+                    (Some(destination), _) => {
+                        configure::remove_file(destination, configuration_file_path)
+                    }
+                    (None, Some(index)) => {
+                        configure::remove_file_at_index(index, configuration_file_path)
+                    }
+                    (None, None) => {
+                        unreachable!("structopt requires a destination or index")
+                    }
+                },
             },
-            None => configure::update(!should_run_noninteractive, configuration_file_path),
+            None => match commit {
+                Some(commit) => configure::update_to_commit(
+                    interactive(should_run_noninteractive),
+                    accept_plan,
+                    commit,
+                    configuration_file_path,
+                ),
+                None => {
+                    if let Err(err) = configure::try_update(
+                        interactive(should_run_noninteractive),
+                        accept_plan,
+                        configuration_file_path,
+                    ) {
+                        eprintln!("Unable to update the project: {}", err);
+                        std::process::exit(err.code());
+                    }
+                }
+            },
+        },
+        Command::Status {
+            configuration_file_path,
+        } => print!("{}", configure::status(configuration_file_path)),
+        Command::ListFiles {
+            configuration_file_path,
+            json,
+        } => {
+            let list = configure::list_files(configuration_file_path);
+
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&list).expect("Unable to convert list to JSON")
+                )
+            } else {
+                print!("{}", list)
+            }
+        }
+        Command::SecretsStatus {
+            configuration_file_path,
+            json,
+        } => match configure::secrets_repo_status(configuration_file_path) {
+            Ok(info) if json => println!(
+                "{}",
+                serde_json::to_string_pretty(&info)
+                    .expect("Unable to convert secrets repo status to JSON")
+            ),
+            Ok(info) => println!("{}", info),
+            Err(err) => {
+                eprintln!("Unable to read the secrets repo's status: {}", err);
+                std::process::exit(err.code());
+            }
+        },
+        Command::Scan {
+            configuration_file_path,
+            commit,
+            json,
+        } => match configure::scan(commit, configuration_file_path) {
+            Ok(findings) => {
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&findings)
+                            .expect("Unable to convert the scan results to JSON")
+                    );
+                } else {
+                    for finding in &findings {
+                        println!("{}", finding);
+                    }
+                }
+
+                if !findings.is_empty() {
+                    let err = configure::ConfigureError::SecretsFoundInProject {
+                        count: findings.len(),
+                    };
+                    eprintln!("{}", err);
+                    std::process::exit(err.code());
+                }
+            }
+            Err(err) => {
+                eprintln!("Unable to scan the project: {}", err);
+                std::process::exit(err.code());
+            }
+        },
+        Command::ExportLeakRules {
+            configuration_file_path,
+            format,
+        } => match configure::export_leak_rules(format, configuration_file_path) {
+            Ok(rules) => print!("{}", rules),
+            Err(err) => {
+                eprintln!("Unable to export the rules: {}", err);
+                std::process::exit(err.code());
+            }
         },
-        Command::Init => configure::init(),
+        Command::Run {
+            configuration_file_path,
+            files,
+            profile,
+            command,
+        } => match configure::run_with_secrets(command, files, profile, configuration_file_path) {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                eprintln!("Unable to run the command: {}", err);
+                std::process::exit(err.code());
+            }
+        },
+        Command::GhaExport {
+            configuration_file_path,
+            files,
+            profile,
+            outputs,
+        } => match configure::gha_export(files, profile, outputs, configuration_file_path) {
+            Ok(names) => {
+                for name in names {
+                    println!("{}", name);
+                }
+            }
+            Err(err) => {
+                eprintln!("Unable to export the secrets: {}", err);
+                std::process::exit(err.code());
+            }
+        },
+        Command::Inspect {
+            destination,
+            configuration_file_path,
+            json,
+        } => match configure::inspect(destination, configuration_file_path) {
+            Ok(inspection) if json => println!(
+                "{}",
+                serde_json::to_string_pretty(&inspection)
+                    .expect("Unable to convert the file's details to JSON")
+            ),
+            Ok(inspection) => print!("{}", inspection),
+            Err(err) => {
+                eprintln!("Unable to inspect the file: {}", err);
+                std::process::exit(err.code());
+            }
+        },
+        Command::Secrets {
+            subcommand:
+                SecretsSubCommand::SparseInit {
+                    configuration_file_path,
+                    clone_url,
+                    json,
+                },
+        } => match configure::secrets_sparse_init(clone_url, configuration_file_path) {
+            Ok(checkout) if json => println!(
+                "{}",
+                serde_json::to_string_pretty(&checkout)
+                    .expect("Unable to convert the sparse checkout to JSON")
+            ),
+            Ok(checkout) => {
+                for directory in checkout.directories {
+                    println!("{}", directory);
+                }
+            }
+            Err(err) => {
+                eprintln!("Unable to set up a sparse checkout: {}", err);
+                std::process::exit(err.code());
+            }
+        },
+        Command::Gc {
+            should_run_noninteractive,
+            configuration_file_path,
+            dry_run,
+        } => configure::gc(
+            interactive(should_run_noninteractive),
+            dry_run,
+            configuration_file_path,
+        ),
+        Command::SyncPins {
+            should_run_noninteractive,
+            commit,
+            root,
+        } => configure::sync_pins(interactive(should_run_noninteractive), commit, root),
+        Command::Migrate {
+            should_run_noninteractive,
+            configuration_file_path,
+            dry_run,
+        } => configure::migrate(
+            interactive(should_run_noninteractive),
+            dry_run,
+            configuration_file_path,
+        ),
+        Command::PushSecret {
+            local_file,
+            repo_path,
+            branch,
+            commit,
+            message,
+        } => configure::push_secret(
+            local_file,
+            repo_path,
+            configure::PushSecretOptions {
+                branch,
+                commit,
+                message,
+            },
+        ),
+        Command::Sign {
+            configuration_file_path,
+        } => configure::sign(configuration_file_path),
+        Command::Daemon {
+            socket_path,
+            idle_timeout,
+            subcommand,
+        } => match subcommand {
+            Some(DaemonSubCommand::Stop) => configure::stop_daemon(socket_path),
+            None => configure::run_daemon(socket_path, Some(Duration::from_secs(idle_timeout))),
+        },
+        Command::Watch {
+            configuration_file_path,
+            interval,
+            fetch_interval,
+            no_apply,
+        } => configure::watch(
+            configuration_file_path,
+            configure::WatchOptions {
+                poll_interval: Duration::from_secs(interval),
+                fetch_interval: Duration::from_secs(fetch_interval),
+                apply_automatically: !no_apply,
+            },
+        ),
+        Command::Init { template: None } => configure::init(),
+        Command::Init {
+            template: Some(template),
+        } => configure::init_from_template(template),
         Command::Validate => configure::validate(),
-        Command::CreateKey => println!("{:}", configure::generate_encryption_key()),
+        Command::Schema => print!("{}", configure::configure_file_schema()),
+        Command::CreateKey => match configure::generate_encryption_key() {
+            Ok(key) => println!("{:}", key),
+            Err(err) => {
+                eprintln!("Unable to generate an encryption key: {}", err);
+                std::process::exit(err.code());
+            }
+        },
+        Command::Completions { shell } => print_completions(shell),
+        Command::Version { json: true } => println!(
+            "{}",
+            serde_json::to_string_pretty(&configure::build_info())
+                .expect("Unable to convert build info to JSON")
+        ),
+        Command::Version { json: false } => println!("{}", configure::build_info()),
+        Command::Docs {
+            subcommand: DocsSubCommand::Man { output_dir },
+        } => write_man_pages(&output_dir),
+        Command::CompleteBranches => {
+            for branch_name in configure::secrets_branch_names() {
+                println!("{}", branch_name);
+            }
+        }
+        Command::Keys { subcommand } => match subcommand {
+            KeysSubCommand::List { json } => {
+                let list = configure::list_keys();
+
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&list)
+                            .expect("Unable to convert list to JSON")
+                    )
+                } else {
+                    print!("{}", list)
+                }
+            }
+            KeysSubCommand::Show {
+                project_name,
+                reveal,
+            } => configure::show_key(project_name, reveal),
+            KeysSubCommand::Rotate { project_name } => configure::rotate_key(project_name),
+        },
+        Command::EncryptFile {
+            input_file,
+            output_file,
+            passphrase: true,
+            ..
+        } => configure::encrypt_single_file_with_passphrase(input_file, output_file),
         Command::EncryptFile {
             input_file,
             output_file,
             encryption_key,
-        } => configure::encrypt_single_file_path(&input_file, output_file, encryption_key),
+            ..
+        } => configure::encrypt_single_file(input_file, output_file, encryption_key),
+        Command::DecryptFile {
+            input_file,
+            output_file,
+            passphrase: true,
+            ..
+        } => configure::decrypt_single_file_with_passphrase(input_file, output_file),
         Command::DecryptFile {
             input_file,
             output_file,
             encryption_key,
-        } => configure::decrypt_single_file_path(&input_file, output_file, encryption_key),
+            ..
+        } => configure::decrypt_single_file(
+            input_file,
+            output_file,
+            encryption_key.expect("An encryption key is required without --passphrase"),
+        ),
     }
+
+    if options.timings {
+        configure::print_timings();
+    }
+}
+
+/// Completes `update set-branch-name` with the secrets repo's branches, then leaves everything else to the generated
+/// `_configure` function
+const BASH_BRANCH_COMPLETION: &str = r#"
+_configure_with_branches() {
+    if [[ "${COMP_WORDS[COMP_CWORD-1]}" == "set-branch-name" ]]; then
+        COMPREPLY=( $(compgen -W "$(configure complete-branches 2>/dev/null)" -- "${COMP_WORDS[COMP_CWORD]}") )
+        return 0
+    fi
+    _configure "$@"
+}
+
+complete -F _configure_with_branches -o bashdefault -o default configure
+"#;
+
+const FISH_BRANCH_COMPLETION: &str = r#"
+complete -c configure -n "__fish_seen_subcommand_from set-branch-name" -f -a "(configure complete-branches 2>/dev/null)"
+"#;
+
+fn print_completions(shell: Shell) {
+    let mut stdout = std::io::stdout();
+    Options::clap().gen_completions_to("configure", shell, &mut stdout);
+
+    match shell {
+        Shell::Bash => print!("{}", BASH_BRANCH_COMPLETION),
+        Shell::Fish => print!("{}", FISH_BRANCH_COMPLETION),
+        _ => (),
+    }
+}
+
+/// Write a man page for `configure`, and one for each command that isn't hidden, to `output_dir`
+fn write_man_pages(output_dir: &Path) {
+    let mut commands = vec![Vec::new()];
+    commands.extend(
+        Options::clap()
+            .p
+            .subcommands
+            .iter()
+            .filter(|subcommand| !subcommand.p.is_set(AppSettings::Hidden))
+            .map(|subcommand| vec![subcommand.get_name().to_string()]),
+    );
+
+    let page_names: Vec<String> = commands
+        .iter()
+        .map(|command| man_page_name(command))
+        .collect();
+
+    let result = std::fs::create_dir_all(output_dir).and_then(|_| {
+        for (command, page_name) in commands.iter().zip(&page_names) {
+            let mut page = render_man_page(page_name, &help_text(command));
+
+            // The main page points to the rest
+            if command.is_empty() {
+                page.push_str(".SH \"SEE ALSO\"\n");
+                let see_also: Vec<String> = page_names[1..]
+                    .iter()
+                    .map(|name| format!("\\fB{}\\fR(1)", roff_escape(name)))
+                    .collect();
+                page.push_str(&see_also.join(",\n"));
+                page.push('\n');
+            }
+
+            std::fs::write(output_dir.join(format!("{}.1", page_name)), page)?;
+        }
+
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => println!("Wrote {} man pages to {:?}", commands.len(), output_dir),
+        Err(err) => {
+            let err = configure::ConfigureError::from(err);
+            eprintln!("Unable to write the man pages to {:?}: {}", output_dir, err);
+            std::process::exit(err.code());
+        }
+    }
+}
+
+fn man_page_name(command: &[String]) -> String {
+    std::iter::once("configure")
+        .chain(command.iter().map(String::as_str))
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+/// The output of `configure <command> --help`, wrapped to a fixed width so the pages don't depend on the terminal
+fn help_text(command: &[String]) -> String {
+    let arguments = std::iter::once("configure")
+        .chain(command.iter().map(String::as_str))
+        .chain(std::iter::once("--help"));
+
+    match Options::clap()
+        .set_term_width(80)
+        .get_matches_from_safe(arguments)
+    {
+        Err(err) if err.kind == structopt::clap::ErrorKind::HelpDisplayed => err.message,
+        _ => String::new(),
+    }
+}
+
+/// Turn `--help` output into a man page – its `USAGE:`, `FLAGS:` (and so on) headings become sections, and
+/// everything before the first one is the description
+fn render_man_page(page_name: &str, help: &str) -> String {
+    // The first line is the command's name and version, which the man page has a place of its own for
+    let mut lines = help.lines().skip(1).peekable();
+
+    let mut description = Vec::new();
+    while let Some(line) = lines.next_if(|line| !is_help_heading(line)) {
+        description.push(line);
+    }
+
+    let summary = description
+        .iter()
+        .find(|line| !line.trim().is_empty())
+        .map_or("", |line| line.trim());
+
+    let mut page = format!(
+        ".TH {} 1 \"\" \"configure {}\" \"User Commands\"\n.SH NAME\n{} \\- {}\n",
+        roff_escape(&page_name.to_uppercase()),
+        env!("CARGO_PKG_VERSION"),
+        roff_escape(page_name),
+        roff_escape(summary)
+    );
+
+    let mut sections = vec![("DESCRIPTION".to_string(), description)];
+    for line in lines {
+        if is_help_heading(line) {
+            sections.push((line.trim_end_matches(':').to_string(), Vec::new()));
+        } else if let Some((_, section_lines)) = sections.last_mut() {
+            section_lines.push(line);
+        }
+    }
+
+    for (heading, section_lines) in sections {
+        let text = section_lines.join("\n");
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        page.push_str(&format!(".SH \"{}\"\n.nf\n", heading));
+        for line in text.trim_matches('\n').lines() {
+            page.push_str(&roff_line(line));
+            page.push('\n');
+        }
+        page.push_str(".fi\n");
+    }
+
+    page
+}
+
+fn is_help_heading(line: &str) -> bool {
+    line.len() > 1
+        && line.ends_with(':')
+        && line
+            .trim_end_matches(':')
+            .chars()
+            .all(|character| character.is_ascii_uppercase() || character == ' ')
+}
+
+fn roff_escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// A line of text that roff won't mistake for a request
+fn roff_line(line: &str) -> String {
+    let line = roff_escape(line);
+
+    if line.starts_with('.') || line.starts_with('\'') {
+        format!("\\&{}", line)
+    } else {
+        line
+    }
+}
+
+/// Commands only ask questions when someone is at a terminal to answer them
+fn interactive(should_run_noninteractive: bool) -> bool {
+    if should_run_noninteractive || EVENTS_ONLY.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    let interactive = configure::is_interactive_terminal();
+    if !interactive {
+        debug!("Not running in a terminal – continuing without asking any questions");
+    }
+
+    interactive
+}
+
+/// Examples shown at the end of each command's `--help`, and in its man page
+mod examples {
+    pub const UPDATE: &str = "EXAMPLES:
+    Update to the latest secrets on the project's branch, answering each question:
+        $ configure update
+
+    Update to the latest secrets, showing what will change without asking to confirm it:
+        $ configure update --yes
+
+    Update without asking anything, as a CI job would:
+        $ configure update --force
+
+    Pin the project to a specific secrets commit:
+        $ configure update --force --commit 8c3b5f6
+
+    Add a file from the secrets repo to the project:
+        $ configure update add-file --source android/secrets.properties --destination app/secrets.properties";
+
+    pub const SYNC_PINS: &str = "EXAMPLES:
+    Move every module in a monorepo to the latest secrets:
+        $ configure sync-pins --force
+
+    Pin every module to the same commit:
+        $ configure sync-pins --force --commit 8c3b5f6";
+
+    pub const ROLLBACK: &str = "EXAMPLES:
+    Go back to the secrets used before the last update:
+        $ configure rollback
+
+    Restore a decrypted file from its newest backup:
+        $ configure rollback --file app/secrets.properties";
+
+    pub const APPLY: &str = "EXAMPLES:
+    Decrypt this project's secrets – or the module's, when run from inside a module with its own .configure file:
+        $ configure apply
+
+    Decrypt another module's secrets:
+        $ configure apply -c libs/networking/.configure
+
+    Decrypt every module's secrets in CI, replacing anything edited by hand:
+        $ configure apply --force --recursive --on-conflict overwrite
+
+    Recreate encrypted files that weren't committed, from a local secrets repo:
+        $ configure apply --force --regenerate-missing
+
+    Decrypt only the files CI needs, with the CI key:
+        $ configure apply --force --profile ci
+
+    Decrypt into a temporary directory that's deleted once the build finishes:
+        $ configure apply --ephemeral -- sh -c 'fastlane beta secrets_dir:$CONFIGURE_EPHEMERAL_DIR'";
+
+    pub const STATUS: &str = "EXAMPLES:
+    Check whether the decrypted files are up to date:
+        $ configure status";
+
+    pub const LIST_FILES: &str = "EXAMPLES:
+    List the files this project manages:
+        $ configure list-files
+
+    Get the list as JSON, for a script:
+        $ configure list-files --json";
+
+    pub const SCAN: &str = "EXAMPLES:
+    Check the working tree for stray secrets before committing:
+        $ configure scan
+
+    Check what's in the last commit, from CI:
+        $ configure scan --commit HEAD --json";
+
+    pub const EXPORT_LEAK_RULES: &str = "EXAMPLES:
+    Write a gitleaks config for the project:
+        $ configure export-leak-rules > .gitleaks.toml
+
+    Have git-secrets ask configure for its patterns:
+        $ git secrets --add-provider -- configure export-leak-rules --format git-secrets";
+
+    pub const RUN: &str = "EXAMPLES:
+    Upload a beta with the project's secrets, without writing them to disk:
+        $ configure run -- fastlane beta
+
+    Only pass on the Sentry settings:
+        $ configure run --file sentry.properties -- sentry-cli releases list";
+
+    pub const GHA_EXPORT: &str = "EXAMPLES:
+    Decrypt the project's secrets in a workflow step, and make them available to the steps after it:
+        - run: configure gha-export --profile ci
+
+    Pass a value to another action as a step output:
+        - id: secrets
+          run: configure gha-export --file sentry.properties --outputs
+        - uses: getsentry/action-release@v1
+          env:
+            SENTRY_AUTH_TOKEN: ${{ steps.secrets.outputs.SENTRY_AUTH_TOKEN }}";
+
+    pub const INSPECT: &str = "EXAMPLES:
+    Check when the push certificate expires:
+        $ configure inspect fastlane/push.p12
+
+    List the entitlements in a provisioning profile, as JSON:
+        $ configure inspect fastlane/AppStore.mobileprovision --json";
+
+    pub const SECRETS_STATUS: &str = "EXAMPLES:
+    Check which commit of the secrets repo is checked out, and whether it has local changes:
+        $ configure secrets-status
+
+    Get the details as JSON, for a build scan:
+        $ configure secrets-status --json";
+
+    pub const SECRETS: &str = "EXAMPLES:
+    Only check out the parts of the secrets repo this project uses:
+        $ configure secrets sparse-init
+
+    Make a partial clone of a large secrets repo:
+        $ configure secrets sparse-init --clone git@github.com:example/mobile-secrets.git";
+
+    pub const GC: &str = "EXAMPLES:
+    See which encrypted files would be deleted:
+        $ configure gc --dry-run
+
+    Delete them:
+        $ configure gc --force";
+
+    pub const MIGRATE: &str = "EXAMPLES:
+    Preview the changes needed to bring a project up to date:
+        $ configure migrate --dry-run
+
+    Make them:
+        $ configure migrate";
+
+    pub const PUSH_SECRET: &str = "EXAMPLES:
+    Copy a new secret into the secrets repo on its own branch, and commit it:
+        $ configure push-secret secrets.properties android/WordPress/secrets.properties --branch add-secrets --commit";
+
+    pub const SIGN: &str = "EXAMPLES:
+    Sign the project's .configure file:
+        $ configure sign";
+
+    pub const WATCH: &str = "EXAMPLES:
+    Apply changes as they're pulled, and check the server every five minutes:
+        $ configure watch --fetch-interval 300";
+
+    pub const DAEMON: &str = "EXAMPLES:
+    Start a daemon that stops after an hour without requests:
+        $ configure daemon --idle-timeout 3600
+
+    Stop it:
+        $ configure daemon stop";
+
+    pub const INIT: &str = "EXAMPLES:
+    Set up a project, or change its settings:
+        $ configure init
+
+    Start from the template the secrets repo keeps in templates/android-app.configure:
+        $ configure init --template android-app";
+
+    pub const VALIDATE: &str = "EXAMPLES:
+    Check the .configure file, treating unknown fields as errors:
+        $ configure --strict validate";
+
+    pub const SCHEMA: &str = "EXAMPLES:
+    Save the schema for an editor to use:
+        $ configure schema > configure.schema.json";
+
+    pub const CREATE_KEY: &str = "EXAMPLES:
+    Create a key for a new project:
+        $ configure create-key";
+
+    pub const COMPLETIONS: &str = "EXAMPLES:
+    Complete commands in bash:
+        $ source <(configure completions bash)
+
+    Install completions for fish:
+        $ configure completions fish > ~/.config/fish/completions/configure.fish";
+
+    pub const VERSION: &str = "EXAMPLES:
+    Include the exact build in a bug report:
+        $ configure version
+
+    Record it in a CI log, for a script to read:
+        $ configure version --json";
+
+    pub const DOCS: &str = "EXAMPLES:
+    Write the man pages for a package:
+        $ configure docs man --output-dir share/man/man1";
+
+    pub const KEYS: &str = "EXAMPLES:
+    List every project's key, without printing the keys:
+        $ configure keys list
+
+    Give a project a new key:
+        $ configure keys rotate WordPress-Android";
+
+    pub const ENCRYPT_FILE: &str = "EXAMPLES:
+    Encrypt a file with a new key, which is printed:
+        $ configure encrypt-file --input-file secrets.json
+
+    Encrypt a file with a passphrase from the environment:
+        $ CONFIGURE_PASSPHRASE=... configure encrypt-file --input-file secrets.json --passphrase";
+
+    pub const DECRYPT_FILE: &str = "EXAMPLES:
+    Decrypt a file with its key:
+        $ configure decrypt-file --input-file secrets.json.enc --output-file secrets.json --encryption-key $KEY";
 }
@@ -0,0 +1,60 @@
+//! Exactly which build of configure is running, for bug reports and CI logs
+
+use serde::Serialize;
+use std::fmt;
+
+/// The Cargo features this build was compiled with
+const FEATURES: [(&str, bool); 7] = [
+    ("cli", cfg!(feature = "cli")),
+    ("fs", cfg!(feature = "fs")),
+    ("git", cfg!(feature = "git")),
+    ("interactive-ui", cfg!(feature = "interactive-ui")),
+    ("ffi", cfg!(feature = "ffi")),
+    ("async", cfg!(feature = "async")),
+    ("apple", cfg!(feature = "apple")),
+];
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+
+    /// The commit this was built from, or `unknown` if it was built outside of a git checkout
+    pub git_commit: &'static str,
+
+    /// When this was built, as `YYYY-MM-DD` in UTC
+    pub build_date: &'static str,
+
+    /// The target triple this was built for, like `aarch64-apple-darwin`
+    pub target: &'static str,
+
+    pub features: Vec<&'static str>,
+
+    /// The library doing the encryption, and its version
+    pub encryption_library: String,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("CONFIGURE_GIT_COMMIT"),
+        build_date: env!("CONFIGURE_BUILD_DATE"),
+        target: env!("CONFIGURE_TARGET"),
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| *feature)
+            .collect(),
+        encryption_library: crate::encryption::library_version(),
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "configure {}", self.version)?;
+        writeln!(f, "Commit:     {}", self.git_commit)?;
+        writeln!(f, "Built:      {}", self.build_date)?;
+        writeln!(f, "Target:     {}", self.target)?;
+        writeln!(f, "Features:   {}", self.features.join(", "))?;
+        write!(f, "Encryption: {}", self.encryption_library)
+    }
+}
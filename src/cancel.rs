@@ -0,0 +1,52 @@
+use crate::ConfigureError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Lets a host stop a long-running operation from another thread
+///
+/// Clones share the same state, so keep one and pass another to `apply` or `update`. Work stops at the next file
+/// boundary with `ConfigureError::Cancelled`, and anything already changed is put back the same way it would be if
+/// that step had failed.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Ask every operation using this token (or a clone of it) to stop
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns `ConfigureError::Cancelled` if the token has been cancelled, so callers can use `?` between steps
+    pub(crate) fn check(&self) -> Result<(), ConfigureError> {
+        if self.is_cancelled() {
+            return Err(ConfigureError::Cancelled);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_cancelling_a_clone_cancels_the_original() {
+        let token = CancellationToken::new();
+        assert!(token.check().is_ok());
+
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(ConfigureError::Cancelled)));
+    }
+}
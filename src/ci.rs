@@ -0,0 +1,193 @@
+//! Recognising CI environments, so pipelines don't need the same flags in every step
+//!
+//! When a CI environment is detected, commands run non-interactively (as if `--force` were given), output is plain
+//! text without colors or spinners, and the project's key can come from the CI's own secrets. Most CIs put secrets in
+//! environment variables, so `key_secret` in the `[ci]` section of the settings file can name the one with the key –
+//! for pipelines that already have it under another name. On Buildkite, `buildkite-agent secret get` is asked for it
+//! too. Some CI agents run commands in a pseudo-terminal, so checking for a terminal isn't enough to tell.
+//!
+//! Detection can be overridden with `CONFIGURE_CI` (or `enabled` in the `[ci]` section of the settings file):
+//! `false` turns it off, and `true` treats any environment as CI.
+
+use crate::settings::settings;
+use log::{debug, info};
+use serde::Serialize;
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use zeroize::Zeroizing;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CiEnvironment {
+    Buildkite,
+    CircleCi,
+    GitHubActions,
+    GitLab,
+    Jenkins,
+
+    /// A CI that only set `CI`, or one that `CONFIGURE_CI` says this is
+    Other,
+}
+
+impl fmt::Display for CiEnvironment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CiEnvironment::Buildkite => "Buildkite",
+            CiEnvironment::CircleCi => "CircleCI",
+            CiEnvironment::GitHubActions => "GitHub Actions",
+            CiEnvironment::GitLab => "GitLab CI",
+            CiEnvironment::Jenkins => "Jenkins",
+            CiEnvironment::Other => "CI",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The CI environment that the variables `var` looks up describe, taking `enabled` into account
+///
+/// `enabled` is `Some(false)` to never detect one, `Some(true)` to always have one, and `None` to go by the variables.
+pub fn detect_ci_environment(
+    enabled: Option<bool>,
+    var: impl Fn(&str) -> Option<String>,
+) -> Option<CiEnvironment> {
+    let is_true = |name: &str| var(name).map_or(false, |value| value == "true");
+
+    let detected = if is_true("BUILDKITE") {
+        Some(CiEnvironment::Buildkite)
+    } else if is_true("CIRCLECI") {
+        Some(CiEnvironment::CircleCi)
+    } else if is_true("GITHUB_ACTIONS") {
+        Some(CiEnvironment::GitHubActions)
+    } else if is_true("GITLAB_CI") {
+        Some(CiEnvironment::GitLab)
+    } else if var("JENKINS_URL").is_some() {
+        Some(CiEnvironment::Jenkins)
+    } else if var("CI").map_or(false, |value| value == "true" || value == "1") {
+        Some(CiEnvironment::Other)
+    } else {
+        None
+    };
+
+    match enabled {
+        Some(false) => None,
+        Some(true) => detected.or(Some(CiEnvironment::Other)),
+        None => detected,
+    }
+}
+
+/// The CI environment this is running in, if any
+pub fn ci_environment() -> Option<CiEnvironment> {
+    detect_ci_environment(settings().ci.enabled, |name| std::env::var(name).ok())
+}
+
+// Asking Buildkite means running a command, so it's only done once per run
+static CI_KEY: OnceLock<Option<Zeroizing<String>>> = OnceLock::new();
+
+/// The project key from the CI's own secrets, if it has one and it has the key
+///
+/// It's in the environment variable `key_secret` in the `[ci]` section of the settings file names, or on Buildkite, in
+/// the Buildkite secret with that name – `CONFIGURE_ENCRYPTION_KEY` when it isn't set.
+pub fn key_from_ci() -> Option<Zeroizing<String>> {
+    CI_KEY
+        .get_or_init(|| {
+            let environment = ci_environment()?;
+            let name = settings()
+                .ci
+                .key_secret
+                .clone()
+                .unwrap_or_else(|| crate::ENCRYPTION_KEY_NAME.to_string());
+
+            if let Ok(key) = std::env::var(&name).map(Zeroizing::new) {
+                info!(
+                    "Found an environment variable named {}. Using its value as the encryption key",
+                    name
+                );
+                return Some(key);
+            }
+
+            match environment {
+                CiEnvironment::Buildkite => buildkite_secret(&name),
+                _ => None,
+            }
+        })
+        .clone()
+}
+
+fn buildkite_secret(name: &str) -> Option<Zeroizing<String>> {
+    debug!("Running `buildkite-agent secret get {}`", name);
+    let output = match Command::new("buildkite-agent")
+        .arg("secret")
+        .arg("get")
+        .arg(name)
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) => {
+            debug!("Unable to run buildkite-agent: {}", err);
+            return None;
+        }
+    };
+
+    let key = Zeroizing::new(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if !output.status.success() || key.is_empty() {
+        debug!(
+            "Buildkite doesn't have a secret named {}: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+
+    info!(
+        "Using the Buildkite secret named {} as the encryption key",
+        name
+    );
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_that_ci_environments_are_detected_from_their_variables() {
+        assert_eq!(
+            detect_ci_environment(None, env(&[("CI", "true"), ("BUILDKITE", "true")])),
+            Some(CiEnvironment::Buildkite)
+        );
+        assert_eq!(
+            detect_ci_environment(None, env(&[("CI", "true"), ("CIRCLECI", "true")])),
+            Some(CiEnvironment::CircleCi)
+        );
+        assert_eq!(
+            detect_ci_environment(None, env(&[("CI", "1")])),
+            Some(CiEnvironment::Other)
+        );
+        assert_eq!(detect_ci_environment(None, env(&[])), None);
+    }
+
+    #[test]
+    fn test_that_detection_can_be_overridden() {
+        assert_eq!(
+            detect_ci_environment(Some(false), env(&[("BUILDKITE", "true")])),
+            None
+        );
+        assert_eq!(
+            detect_ci_environment(Some(true), env(&[])),
+            Some(CiEnvironment::Other)
+        );
+    }
+}
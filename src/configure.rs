@@ -1,48 +1,657 @@
+use crate::cancel::CancellationToken;
+pub use crate::error::ConfigureError;
+use crate::events::{emit, Event};
 use crate::fs::*;
 use crate::git::*;
+use crate::paths::{add_extension, parent_directory, portable};
+use crate::platform::{detect_platforms, suggested_files, Platform, SuggestedFile};
+use crate::settings::settings;
+use crate::template::fill_in_project_name;
+use crate::transaction::StagedChanges;
 use crate::ui::*;
 use chrono::prelude::*;
-use indicatif::ProgressBar;
 
-use console::style;
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::path::{Path, PathBuf};
-use thiserror::Error;
-
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+use std::str::FromStr;
+
+/// The fields a `.configure` file can take from the file it extends
+pub const INHERITED_FIELDS: [&str; 13] = [
+    "project_name",
+    "key_name",
+    "key_provider",
+    "key_reference",
+    "secrets_submodule",
+    "remote",
+    "remote_url",
+    "remote_host_key",
+    "branch",
+    "pinned_hash",
+    "deterministic_encryption",
+    "passphrase_encryption",
+    "record_provenance",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Configuration {
+    /// Another `.configure` file, relative to this one, that any fields in `INHERITED_FIELDS` left out of this one are
+    /// taken from
+    ///
+    /// This lets a monorepo keep its branch and pinned hash in one root file, with each module only listing its own
+    /// files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+
+    /// Fragments, relative to this file, whose files, profiles and plugins are added to this file's
+    ///
+    /// This lets modules share a common set of secrets, like analytics keys, without listing them in every file.
+    /// Fragments can't include others.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
     pub project_name: String,
+
+    /// The name of this project's entry in `keys.json`, if it isn't `project_name`
+    ///
+    /// This lets projects share a key on purpose, and keeps a project's key when the project is renamed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_name: Option<String>,
+
+    /// Where the secrets repo is checked out as a submodule of the project, relative to the project root
+    ///
+    /// Without this, the secrets repo is looked for in the usual places outside the project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets_submodule: Option<String>,
+
+    /// The secrets repo remote to fetch `branch` from, if it isn't the one the branch tracks (or `origin`)
+    ///
+    /// This lets forks and mirrors of the secrets repo be used without renaming their remotes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+
+    /// The URL the secrets repo remote must have, checked before anything is fetched from it
+    ///
+    /// This stops secrets being taken from the wrong fork in a checkout with several remotes. `git@host:path`,
+    /// `ssh://` and `https://` URLs for the same repository all match, as do ones with or without a trailing `.git`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_url: Option<String>,
+
+    /// The SHA256 fingerprint (as printed by `ssh-keygen -l`) that `known_hosts` must have for the remote's host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_host_key: Option<String>,
+
     pub branch: String,
     pub pinned_hash: String,
     pub files_to_copy: Vec<File>,
+
+    /// Named subsets of `files_to_copy` for different roles, like `ci` or `release-manager`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+
+    /// The plugin to get this project's key from before looking in `keys.json`, like one that reads it from Vault
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_provider: Option<String>,
+
+    /// Where the key provider keeps this project's key, like `op://Mobile/WordPress-Android/password` for 1Password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_reference: Option<String>,
+
+    /// External commands that can be used as the `key_provider`, or as a file's `processors`, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub plugins: BTreeMap<String, Plugin>,
+
+    /// Encrypt unchanged files to identical bytes on every `update`, so they don't show up in diffs
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deterministic_encryption: bool,
+
+    /// Derive the encryption key from a shared passphrase instead of keeping a key in the secrets repo's `keys.json`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub passphrase_encryption: bool,
+
+    /// Record each file's source path and secrets commit in its encrypted copy, so it can be reported without
+    /// decrypting the file
+    ///
+    /// Older versions of this tool can't read files with this metadata.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub record_provenance: bool,
+
+    /// The values taken from the file this one extends, so they're left out again when it's written
+    #[serde(skip)]
+    inherited: serde_json::Map<String, serde_json::Value>,
+
+    /// What was added from the included fragments, so it's left out again when it's written
+    #[serde(skip)]
+    included: ConfigurationFragment,
+
+    /// The directory in `.configure-files` this configuration's encrypted files and apply state are kept in – empty
+    /// for the project's root `.configure` file
+    ///
+    /// Each `.configure` file in a monorepo gets its own, so modules don't clean up or overwrite each other's files.
+    #[serde(skip)]
+    files_namespace: PathBuf,
 }
 
 impl Configuration {
     pub fn is_empty(&self) -> bool {
-        self == &Configuration::default()
+        // Where the files are kept comes from where the configuration was read, not from anything in it
+        self == &Configuration {
+            files_namespace: self.files_namespace.clone(),
+            ..Configuration::default()
+        }
     }
 
-    pub fn from_str(string: String) -> Result<Configuration, ConfigureError> {
-        match serde_json::from_str(&string) {
-            Ok(configuration) => Ok(configuration),
+    /// Start putting a configuration together in code – see `ConfigurationBuilder`
+    pub fn builder() -> ConfigurationBuilder {
+        ConfigurationBuilder::default()
+    }
+
+    /// Parse a `.configure` file that extends the one containing `parent`, taking anything it leaves out from there
+    pub fn from_str_extending(
+        string: String,
+        parent: String,
+    ) -> Result<Configuration, ConfigureError> {
+        let mut fields = match serde_json::from_str(&string) {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => return Err(ConfigureError::ConfigureFileNotValid),
+        };
+
+        let parent_fields = match serde_json::from_str(&parent) {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => return Err(ConfigureError::ConfigureFileNotValid),
+        };
+
+        let inherited: serde_json::Map<String, serde_json::Value> = parent_fields
+            .into_iter()
+            .filter(|(field, _)| INHERITED_FIELDS.contains(&field.as_str()))
+            .collect();
+
+        for (field, value) in &inherited {
+            fields.entry(field.clone()).or_insert_with(|| value.clone());
+        }
+
+        match serde_json::from_value::<Configuration>(serde_json::Value::Object(fields)) {
+            Ok(mut configuration) => {
+                configuration.inherited = inherited;
+                Ok(configuration)
+            }
             Err(_) => Err(ConfigureError::ConfigureFileNotValid),
         }
     }
 
-    pub fn to_string(&self) -> Result<String, ConfigureError> {
-        match serde_json::to_string_pretty(&self) {
+    /// The contents of a `.configure` file for this configuration – `Display` gives the same, for when there's nowhere
+    /// to put an error
+    pub fn to_json(&self) -> Result<String, ConfigureError> {
+        let mut fields = match serde_json::to_value(self.without_included()) {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => return Err(ConfigureError::ConfigureDataNotValid),
+        };
+
+        // Values that differ from the file being extended are written out, even if they'd normally be left out for
+        // being the default, so they still override it. Ones that match are left for it to provide.
+        for (field, inherited) in &self.inherited {
+            if !fields.contains_key(field.as_str()) {
+                // Without a key name, the project name is used – writing it out has the same effect
+                let default = match field.as_str() {
+                    "key_name" => serde_json::Value::String(self.project_name.clone()),
+                    // Without a remote or submodule, they're worked out from the secrets repo and the user's settings
+                    "remote" | "remote_url" | "remote_host_key" | "secrets_submodule"
+                    | "key_provider" | "key_reference" => serde_json::Value::Null,
+                    _ => serde_json::Value::Bool(false),
+                };
+
+                if &default != inherited {
+                    fields.insert(field.clone(), default);
+                }
+            }
+        }
+
+        let mut fields: serde_json::Map<String, serde_json::Value> = fields
+            .into_iter()
+            .filter(|(field, value)| self.inherited.get(field) != Some(value))
+            .collect();
+
+        // Recorded so older versions can tell they might not understand the file
+        fields.insert(
+            "tool_version".to_string(),
+            serde_json::Value::String(crate::schema::TOOL_VERSION.to_string()),
+        );
+
+        match serde_json::to_string_pretty(&fields) {
             Ok(string) => Ok(string),
             Err(_) => Err(ConfigureError::ConfigureDataNotValid),
         }
     }
 
-    pub fn set_pinned_hash_from_repo(&mut self, repo: &SecretsRepo) {
-        let latest_hash = repo
-            .latest_local_hash_for_branch(&self.branch)
-            .expect("Unable to fetch the latest secrets hash");
+    /// Pin to the newest commit on the configuration's branch – the secrets repo stays on whatever it had checked out
+    pub fn set_pinned_hash_from_repo(&mut self, repo: &SecretsRepo) -> Result<(), ConfigureError> {
+        self.pinned_hash = repo.latest_local_hash_for_branch(&self.branch)?;
+        Ok(())
+    }
+
+    /// Add a file to `files_to_copy`, as long as it doesn't write over anything another file writes
+    pub fn add_file(&mut self, file: File) -> Result<(), ConfigureError> {
+        file.check()?;
+
+        if self
+            .files_to_copy
+            .iter()
+            .any(|existing| existing.destination == file.destination)
+        {
+            return Err(ConfigureError::DestinationAlreadyConfigured {
+                destination: file.destination.clone(),
+            });
+        }
+
+        let index = self.files_to_copy.len();
+        self.files_to_copy.push(file);
+
+        let conflicts: Vec<String> = self
+            .destination_conflicts()
+            .iter()
+            .filter(|conflict| conflict.first == index || conflict.second == index)
+            .map(DestinationConflict::to_string)
+            .collect();
+
+        if !conflicts.is_empty() {
+            self.files_to_copy.pop();
+            return Err(ConfigureError::DestinationsConflict { conflicts });
+        }
+
+        Ok(())
+    }
+
+    /// Every pair of files in `files_to_copy` that would write over each other
+    ///
+    /// Besides sharing a destination, a file can't be decrypted inside another file's destination, or over another
+    /// file's encrypted copy – and since encrypted copies are named after their destination's file name, two
+    /// destinations with the same name conflict too.
+    pub fn destination_conflicts(&self) -> Vec<DestinationConflict> {
+        let project_root = Path::new("");
+        let mut conflicts = Vec::new();
+
+        for (first, first_file) in self.files_to_copy.iter().enumerate() {
+            let destination = Path::new(&first_file.destination);
+
+            for (second, second_file) in self.files_to_copy.iter().enumerate() {
+                if first == second {
+                    continue;
+                }
+
+                let other_destination = Path::new(&second_file.destination);
+                let encrypted_destination = second_file.get_encrypted_destination_in(project_root);
+
+                let kind = if destination == other_destination {
+                    DestinationConflictKind::SameDestination
+                } else if first_file.get_encrypted_destination_in(project_root)
+                    == encrypted_destination
+                {
+                    DestinationConflictKind::SameEncryptedDestination
+                } else if other_destination.starts_with(destination) {
+                    DestinationConflictKind::ContainsDestination
+                } else if encrypted_destination.starts_with(destination) {
+                    DestinationConflictKind::ContainsEncryptedDestination
+                } else {
+                    continue;
+                };
+
+                // Conflicts that go both ways are only reported once
+                if kind.is_symmetric() && second < first {
+                    continue;
+                }
+
+                conflicts.push(DestinationConflict {
+                    first,
+                    second,
+                    kind,
+                    destination: first_file.destination.clone(),
+                    other_destination: second_file.destination.clone(),
+                });
+            }
+        }
+
+        conflicts
+    }
+
+    /// Fail if any files in `files_to_copy` write over each other, listing every conflict
+    pub fn check_destinations(&self) -> Result<(), ConfigureError> {
+        let conflicts: Vec<String> = self
+            .destination_conflicts()
+            .iter()
+            .map(DestinationConflict::to_string)
+            .collect();
+
+        match conflicts.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigureError::DestinationsConflict { conflicts }),
+        }
+    }
+
+    /// Remove the file written to `destination` from `files_to_copy`
+    pub fn remove_file(&mut self, destination: &str) -> Result<File, ConfigureError> {
+        match self
+            .files_to_copy
+            .iter()
+            .position(|file| file.destination == destination)
+        {
+            Some(index) => Ok(self.files_to_copy.remove(index)),
+            None => Err(ConfigureError::DestinationNotConfigured),
+        }
+    }
+
+    /// Remove the file at `index` (starting from zero) from `files_to_copy`
+    pub fn remove_file_at(&mut self, index: usize) -> Result<File, ConfigureError> {
+        if index >= self.files_to_copy.len() {
+            return Err(ConfigureError::DestinationNotConfigured);
+        }
+
+        Ok(self.files_to_copy.remove(index))
+    }
+
+    pub fn set_project_name(&mut self, project_name: &str) -> Result<(), ConfigureError> {
+        self.project_name = check_not_empty("project_name", project_name)?;
+        Ok(())
+    }
+
+    /// Store this project's keys under `key_name` in `keys.json`, or under the project name when it's `None`
+    pub fn set_key_name(&mut self, key_name: Option<&str>) -> Result<(), ConfigureError> {
+        self.key_name = match key_name {
+            Some(key_name) => Some(check_not_empty("key_name", key_name)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    pub fn set_branch(&mut self, branch: &str) -> Result<(), ConfigureError> {
+        let branch = check_not_empty("branch", branch)?;
+
+        if !git2::Reference::is_valid_name(&format!("refs/heads/{}", branch)) {
+            return Err(ConfigureError::ConfigurationValueNotValid {
+                field: "branch".to_string(),
+                reason: format!("{:?} isn't a valid branch name", branch),
+            });
+        }
+
+        self.branch = branch;
+        Ok(())
+    }
+
+    /// Pin to the secrets commit `hash` – a full SHA-1 or SHA-256 commit hash, not an abbreviated one
+    pub fn set_pinned_hash(&mut self, hash: &str) -> Result<(), ConfigureError> {
+        let hash = hash.trim();
+
+        if !matches!(hash.len(), 40 | 64) || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ConfigureError::ConfigurationValueNotValid {
+                field: "pinned_hash".to_string(),
+                reason: format!("{:?} isn't a full commit hash", hash),
+            });
+        }
+
+        self.pinned_hash = hash.to_lowercase();
+        Ok(())
+    }
+
+    /// Add or replace the profile called `name`, as long as it only lists files in `files_to_copy`
+    pub fn set_profile(&mut self, name: &str, profile: Profile) -> Result<(), ConfigureError> {
+        let name = check_not_empty("profiles", name)?;
+        let previous = self.profiles.insert(name.clone(), profile);
+
+        if let Err(err) = self.for_profile(&name) {
+            match previous {
+                Some(previous) => self.profiles.insert(name, previous),
+                None => self.profiles.remove(&name),
+            };
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_profile(&mut self, name: &str) -> Result<Profile, ConfigureError> {
+        self.profiles
+            .remove(name)
+            .ok_or_else(|| ConfigureError::ProfileNotFound {
+                profile: name.to_string(),
+            })
+    }
+
+    /// Add or replace the plugin called `name`
+    pub fn set_plugin(&mut self, name: &str, plugin: Plugin) -> Result<(), ConfigureError> {
+        let name = check_not_empty("plugins", name)?;
+
+        if plugin
+            .command
+            .first()
+            .map_or(true, |program| program.trim().is_empty())
+        {
+            return Err(ConfigureError::ConfigurationValueNotValid {
+                field: "plugins".to_string(),
+                reason: format!("the {:?} plugin doesn't have a command to run", name),
+            });
+        }
+
+        self.plugins.insert(name, plugin);
+        Ok(())
+    }
+
+    /// This configuration with only the files in `profile`, decrypted with the profile's key if it has one
+    pub fn for_profile(&self, profile: &str) -> Result<Configuration, ConfigureError> {
+        let selected = match self.profiles.get(profile) {
+            Some(selected) => selected,
+            None => {
+                return Err(ConfigureError::ProfileNotFound {
+                    profile: profile.to_string(),
+                })
+            }
+        };
+
+        if let Some(destination) = selected.files.iter().find(|destination| {
+            !self
+                .files_to_copy
+                .iter()
+                .any(|file| &&file.destination == destination)
+        }) {
+            return Err(ConfigureError::ProfileFileNotConfigured {
+                profile: profile.to_string(),
+                destination: destination.clone(),
+            });
+        }
+
+        let mut configuration = self.clone();
+        configuration
+            .files_to_copy
+            .retain(|file| selected.files.contains(&file.destination));
+
+        if selected.key_name.is_some() {
+            configuration.key_name = selected.key_name.clone();
+        }
+
+        Ok(configuration)
+    }
+
+    /// The files whose secrets have expired on `today`, or will within `EXPIRY_WARNING_DAYS`
+    pub fn expiring_files(&self, today: NaiveDate) -> Vec<ExpiringFile> {
+        self.files_to_copy
+            .iter()
+            .filter_map(|file| {
+                let days_left = file.days_until_expiry(today)?;
+
+                match days_left <= EXPIRY_WARNING_DAYS {
+                    true => Some(ExpiringFile {
+                        destination: file.destination.clone(),
+                        expires: file.expires?,
+                        days_left,
+                    }),
+                    false => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Log a warning for each file whose secret has expired, or will soon
+    pub fn warn_about_expiring_files(&self) {
+        for file in self.expiring_files(Local::now().naive_local().date()) {
+            warn!(
+                "{} – replace it in the secrets repo and update its `expires` date",
+                file
+            );
+        }
+    }
+
+    /// Check that every profile only lists files in `files_to_copy`
+    pub fn check_profiles(&self) -> Result<(), ConfigureError> {
+        for profile in self.profiles.keys() {
+            self.for_profile(profile)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that the key provider and every file's processors are plugins that can be found
+    pub fn check_plugins(&self) -> Result<(), ConfigureError> {
+        if let Some(name) = &self.key_provider {
+            crate::plugins::key_provider(self, name)?;
+        }
+
+        for file in &self.files_to_copy {
+            if file.is_directory && !file.processors.is_empty() {
+                return Err(ConfigureError::DirectoryNotProcessable {
+                    destination: file.destination.clone(),
+                });
+            }
+
+            for name in &file.processors {
+                crate::plugins::file_processor(self, name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the files, profiles and plugins from the fragments in `include`, given with their paths in the same order
+    ///
+    /// Fragments are merged in order, after this configuration's own files. Anything this configuration has itself
+    /// takes priority, but two fragments can't configure the same destination, profile or plugin differently – which
+    /// one won would depend on the order of `include`.
+    pub fn merge_included(
+        &mut self,
+        fragments: Vec<(String, ConfigurationFragment)>,
+    ) -> Result<(), ConfigureError> {
+        for (include, fragment) in fragments {
+            let conflict = |name: &str| ConfigureError::IncludeConflict {
+                include: include.clone(),
+                name: name.to_string(),
+            };
+
+            for file in fragment.files_to_copy {
+                match self
+                    .included
+                    .files_to_copy
+                    .iter()
+                    .find(|included| included.destination == file.destination)
+                {
+                    Some(included) if included == &file => continue,
+                    Some(_) => return Err(conflict(&file.destination)),
+                    None => (),
+                }
+
+                if self
+                    .files_to_copy
+                    .iter()
+                    .any(|own| own.destination == file.destination)
+                {
+                    debug!(
+                        "Using this configuration's own {}, instead of the one in {}",
+                        file.destination, include
+                    );
+                    continue;
+                }
+
+                self.files_to_copy.push(file.clone());
+                self.included.files_to_copy.push(file);
+            }
+
+            for (name, profile) in fragment.profiles {
+                match self.included.profiles.get(&name) {
+                    Some(included) if included == &profile => continue,
+                    Some(_) => return Err(conflict(&name)),
+                    None => (),
+                }
+
+                if !self.profiles.contains_key(&name) {
+                    self.profiles.insert(name.clone(), profile.clone());
+                    self.included.profiles.insert(name, profile);
+                }
+            }
+
+            for (name, plugin) in fragment.plugins {
+                match self.included.plugins.get(&name) {
+                    Some(included) if included == &plugin => continue,
+                    Some(_) => return Err(conflict(&name)),
+                    None => (),
+                }
+
+                if !self.plugins.contains_key(&name) {
+                    self.plugins.insert(name.clone(), plugin.clone());
+                    self.included.plugins.insert(name, plugin);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// This configuration as it's written, without anything from the included fragments
+    fn without_included(&self) -> Configuration {
+        let mut configuration = self.clone();
+
+        configuration
+            .files_to_copy
+            .retain(|file| !self.included.files_to_copy.contains(file));
+        configuration
+            .profiles
+            .retain(|name, profile| self.included.profiles.get(name) != Some(profile));
+        configuration
+            .plugins
+            .retain(|name, plugin| self.included.plugins.get(name) != Some(plugin));
+
+        configuration
+    }
+
+    /// The name this project's keys are stored under – `key_name` if it's set, otherwise `project_name`
+    pub fn key_name(&self) -> &str {
+        self.key_name.as_deref().unwrap_or(&self.project_name)
+    }
+
+    /// Keep this configuration's files in `namespace` within `.configure-files` – see `files_namespace_for`
+    pub(crate) fn set_files_namespace(&mut self, namespace: PathBuf) {
+        self.files_namespace = namespace;
+    }
+
+    /// The directory this configuration's encrypted files and apply state are kept in, in the project at
+    /// `project_root`
+    pub fn files_directory_in(&self, project_root: &Path) -> PathBuf {
+        project_root
+            .join(".configure-files")
+            .join(&self.files_namespace)
+    }
+
+    /// Where the encrypted copy of `file` is kept
+    pub fn encrypted_destination_for(&self, file: &File) -> PathBuf {
+        // If something goes wrong, fall back to putting the file next to where it's specified to go
+        match find_project_root() {
+            Ok(project_root) => self.encrypted_destination_in(file, &project_root),
+            Err(_) => add_extension(Path::new(&file.destination), "enc"),
+        }
+    }
 
-        self.pinned_hash = latest_hash;
+    /// Where the encrypted copy of `file` is kept in the project at `project_root`
+    pub fn encrypted_destination_in(&self, file: &File, project_root: &Path) -> PathBuf {
+        file.get_encrypted_destination_in_directory(&self.files_directory_in(project_root))
     }
 
     fn needs_project_name(&self) -> bool {
@@ -54,137 +663,401 @@ impl Configuration {
     }
 }
 
+/// Parse the contents of a `.configure` file – one that extends another can't be parsed on its own, so use
+/// `TryFrom<&Path>` for those
+impl FromStr for Configuration {
+    type Err = ConfigureError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match serde_json::from_str(string) {
+            Ok(configuration) => Ok(configuration),
+            Err(_) => Err(ConfigureError::ConfigureFileNotValid),
+        }
+    }
+}
+
+/// Written the way it's saved, so parsing it gives the same configuration back
+impl fmt::Display for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_json().map_err(|_| fmt::Error)?)
+    }
+}
+
+/// Read the `.configure` file at a path, along with any file it extends
+impl TryFrom<&Path> for Configuration {
+    type Error = ConfigureError;
+
+    fn try_from(configure_file_path: &Path) -> Result<Self, Self::Error> {
+        read_configuration_at(configure_file_path)
+    }
+}
+
 impl Default for Configuration {
     fn default() -> Self {
         let files_to_copy: Vec<File> = Vec::new();
         Configuration {
+            extends: None,
+            include: Vec::new(),
             project_name: "".to_string(),
+            key_name: None,
+            secrets_submodule: None,
+            remote: None,
+            remote_url: None,
+            remote_host_key: None,
             branch: "".to_string(),
             pinned_hash: "".to_string(),
             files_to_copy,
+            profiles: BTreeMap::new(),
+            key_provider: None,
+            key_reference: None,
+            plugins: BTreeMap::new(),
+            deterministic_encryption: false,
+            passphrase_encryption: false,
+            record_provenance: false,
+            inherited: serde_json::Map::new(),
+            included: ConfigurationFragment::default(),
+            files_namespace: PathBuf::new(),
         }
     }
 }
 
-#[derive(Error, Debug)]
-pub enum ConfigureError {
-    #[error("Unable to decrypt file")]
-    DataDecryptionError,
+/// A file named in a `.configure` file's `include`, with secrets that several modules share
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigurationFragment {
+    pub files_to_copy: Vec<File>,
+    pub profiles: BTreeMap<String, Profile>,
+    pub plugins: BTreeMap<String, Plugin>,
+}
 
-    #[allow(dead_code)] // This isn't currently used, but removing it will mess up our expected exit codes
-    #[error("Unable to encrypt file")]
-    DataEncryptionError,
+/// Puts a `Configuration` together in code, for tools that generate `.configure` files
+///
+/// Each value is checked as it's given, and `build` returns the first problem – or the configuration, once it has a
+/// project name and branch, and its files and profiles fit together. Plugins aren't looked up, since a tool might
+/// register them later; `Configuration::check_plugins` does that.
+///
+/// ```ignore
+/// let configuration = Configuration::builder()
+///     .project_name("WordPress-Android")
+///     .branch("trunk")
+///     .add_file(File::new("android/wpandroid/gradle.properties", "gradle.properties")?)
+///     .build()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct ConfigurationBuilder {
+    configuration: Configuration,
+    error: Option<ConfigureError>,
+}
 
-    #[error("Unknown git error")]
-    SecretsRepoError,
+impl ConfigurationBuilder {
+    pub fn project_name(self, project_name: &str) -> Self {
+        self.apply(|configuration| configuration.set_project_name(project_name))
+    }
 
-    #[error("Invalid git status")]
-    GitStatusParsingError,
+    pub fn key_name(self, key_name: &str) -> Self {
+        self.apply(|configuration| configuration.set_key_name(Some(key_name)))
+    }
 
-    #[error("Unable to find current secrets repo branch")]
-    GitGetCurrentBranchError,
+    pub fn branch(self, branch: &str) -> Self {
+        self.apply(|configuration| configuration.set_branch(branch))
+    }
 
-    #[error("Invalid git status")]
-    GitStatusUnknownError,
+    pub fn pinned_hash(self, hash: &str) -> Self {
+        self.apply(|configuration| configuration.set_pinned_hash(hash))
+    }
 
-    #[error("Unable to find the root of the respository – are you sure you're running this inside a git repo?")]
-    ProjectNotPresent,
+    pub fn secrets_submodule(self, path: &str) -> Self {
+        self.apply(|configuration| {
+            configuration.secrets_submodule = Some(check_not_empty("secrets_submodule", path)?);
+            Ok(())
+        })
+    }
 
-    #[error("The .configure file is missing or could not be read")]
-    ConfigureFileNotReadable,
+    pub fn remote(self, remote: &str) -> Self {
+        self.apply(|configuration| {
+            configuration.remote = Some(check_not_empty("remote", remote)?);
+            Ok(())
+        })
+    }
 
-    #[error("The .configure file could not be written")]
-    ConfigureFileNotWritable,
+    /// Get the key from the plugin called `key_provider`, which finds it with `key_reference` if it needs one
+    pub fn key_provider(self, key_provider: &str, key_reference: Option<&str>) -> Self {
+        self.apply(|configuration| {
+            configuration.key_provider = Some(check_not_empty("key_provider", key_provider)?);
+            configuration.key_reference = match key_reference {
+                Some(reference) => Some(check_not_empty("key_reference", reference)?),
+                None => None,
+            };
+            Ok(())
+        })
+    }
 
-    #[error("Unable to parse configuration file – the JSON is probably invalid")]
-    ConfigureFileNotValid,
+    pub fn add_file(self, file: File) -> Self {
+        self.apply(|configuration| configuration.add_file(file))
+    }
 
-    #[error("Unable to save an configuration data – it couldn't be converted to JSON")]
-    ConfigureDataNotValid,
+    /// Add a profile – its files can be added before or after it
+    pub fn profile(self, name: &str, profile: Profile) -> Self {
+        self.apply(|configuration| {
+            configuration
+                .profiles
+                .insert(check_not_empty("profiles", name)?, profile);
+            Ok(())
+        })
+    }
 
-    #[error("No secrets repository could be found on this machine")]
-    SecretsNotPresent,
+    pub fn plugin(self, name: &str, plugin: Plugin) -> Self {
+        self.apply(|configuration| configuration.set_plugin(name, plugin))
+    }
 
-    #[error("An encrypted file is missing – unable to apply secrets to project. Run `configure update` to fix this")]
-    EncryptedFileMissing,
+    pub fn deterministic_encryption(mut self, enabled: bool) -> Self {
+        self.configuration.deterministic_encryption = enabled;
+        self
+    }
 
-    #[error("Unable to read keys.json file in your secrets repo")]
-    KeysFileNotReadable,
+    pub fn passphrase_encryption(mut self, enabled: bool) -> Self {
+        self.configuration.passphrase_encryption = enabled;
+        self
+    }
 
-    #[error("Unable to write keys.json file in your secrets repo")]
-    KeysFileNotWritable,
+    pub fn record_provenance(mut self, enabled: bool) -> Self {
+        self.configuration.record_provenance = enabled;
+        self
+    }
 
-    #[error("keys.json file in your secrets repo is not valid – it might be invalid JSON, or it could be structured incorrectly")]
-    KeysFileIsNotValid,
+    pub fn build(self) -> Result<Configuration, ConfigureError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
 
-    #[error("Attempted to save invalid keys.json data – it couldn't be converted to JSON")]
-    KeysDataIsNotValid,
+        let configuration = self.configuration;
+        check_not_empty("project_name", &configuration.project_name)?;
+        check_not_empty("branch", &configuration.branch)?;
 
-    #[error("That project key is not defined in keys.json")]
-    MissingProjectKey,
+        configuration.check_destinations()?;
+        configuration.check_profiles()?;
+        Ok(configuration)
+    }
 
-    #[error("No environment variable or secrets repository found – cannot decrypt files")]
-    MissingDecryptionKey,
+    /// Make a change, unless an earlier one already failed – only the first problem is reported
+    fn apply(
+        mut self,
+        change: impl FnOnce(&mut Configuration) -> Result<(), ConfigureError>,
+    ) -> Self {
+        if self.error.is_none() {
+            if let Err(err) = change(&mut self.configuration) {
+                self.error = Some(err);
+            }
+        }
 
-    #[error("This decryption key is not valid base64")]
-    DecryptionKeyEncodingError,
+        self
+    }
+}
 
-    #[error("This decryption key is not a sodium-compatible key")]
-    DecryptionKeyParsingError,
+/// `value` without surrounding whitespace, as long as that leaves something
+fn check_not_empty(field: &str, value: &str) -> Result<String, ConfigureError> {
+    match value.trim() {
+        "" => Err(ConfigureError::ConfigurationValueNotValid {
+            field: field.to_string(),
+            reason: "it can't be empty".to_string(),
+        }),
+        value => Ok(value.to_string()),
+    }
+}
+
+/// Two files in `files_to_copy` that would write over each other, by their positions (starting from zero)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DestinationConflict {
+    pub first: usize,
+    pub second: usize,
+    pub kind: DestinationConflictKind,
+    pub destination: String,
+    pub other_destination: String,
+}
 
-    #[error("Unable to read input file")]
-    InputFileNotReadable,
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DestinationConflictKind {
+    /// Both files are decrypted to the same place
+    SameDestination,
 
-    #[error("Unable to write output file")]
-    OutputFileNotWritable,
+    /// Their destinations have the same file name, so their encrypted copies would be the same file
+    SameEncryptedDestination,
+
+    /// The second file is decrypted inside the first one's destination
+    ContainsDestination,
+
+    /// The second file's encrypted copy is inside (or is) the first one's destination
+    ContainsEncryptedDestination,
 }
 
-// These implementations should be removed as soon as we can adopt `ConfigureError` everywhere
-impl From<std::io::Error> for ConfigureError {
-    fn from(_error: std::io::Error) -> Self {
-        ConfigureError::DataDecryptionError
+impl DestinationConflictKind {
+    fn is_symmetric(&self) -> bool {
+        matches!(
+            self,
+            DestinationConflictKind::SameDestination
+                | DestinationConflictKind::SameEncryptedDestination
+        )
     }
 }
 
-impl From<git2::Error> for ConfigureError {
-    fn from(_error: git2::Error) -> Self {
-        ConfigureError::SecretsRepoError
+impl std::fmt::Display for DestinationConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (first, second) = (self.first, self.second);
+
+        match self.kind {
+            DestinationConflictKind::SameDestination => write!(
+                f,
+                "files_to_copy[{}] and files_to_copy[{}] are both decrypted to {:?}",
+                first, second, self.destination
+            ),
+            DestinationConflictKind::SameEncryptedDestination => write!(
+                f,
+                "files_to_copy[{}] ({:?}) and files_to_copy[{}] ({:?}) have the same file name, so their encrypted copies would overwrite each other",
+                first, self.destination, second, self.other_destination
+            ),
+            DestinationConflictKind::ContainsDestination => write!(
+                f,
+                "files_to_copy[{}] is decrypted to {:?}, inside files_to_copy[{}]'s destination {:?}",
+                second, self.other_destination, first, self.destination
+            ),
+            DestinationConflictKind::ContainsEncryptedDestination => write!(
+                f,
+                "files_to_copy[{}]'s destination {:?} would overwrite the encrypted copy of files_to_copy[{}]",
+                first, self.destination, second
+            ),
+        }
     }
 }
 
-impl From<std::num::ParseIntError> for ConfigureError {
-    fn from(_error: std::num::ParseIntError) -> Self {
-        ConfigureError::GitStatusParsingError
+/// How many days before a secret expires to start warning about it
+pub const EXPIRY_WARNING_DAYS: i64 = 30;
+
+/// A file in `files_to_copy` whose secret has expired, or will soon
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ExpiringFile {
+    pub destination: String,
+    pub expires: NaiveDate,
+
+    /// Negative once it's expired
+    pub days_left: i64,
+}
+
+impl std::fmt::Display for ExpiringFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.days_left {
+            days if days < 0 => write!(f, "{} expired on {}", self.destination, self.expires),
+            0 => write!(f, "{} expires today", self.destination),
+            days => write!(
+                f,
+                "{} expires on {}, in {} day(s)",
+                self.destination, self.expires, days
+            ),
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Profile {
+    /// The destinations of the files in `files_to_copy` this profile decrypts
+    pub files: Vec<String>,
+
+    /// The name of the entry in `keys.json` to decrypt the files with, if it isn't the project's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_name: Option<String>,
+}
+
+/// An external command in a `.configure` file's `plugins`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Plugin {
+    /// The program to run, followed by its arguments
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct File {
     #[serde(rename = "file")]
     pub source: String,
     pub destination: String,
+
+    /// Whether `source` is a directory – it's archived into a single encrypted file, and extracted to `destination`
+    #[serde(
+        rename = "directory",
+        default,
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub is_directory: bool,
+
+    /// When the secret stops working, like a push certificate's expiry date – `apply`, `status` and `validate` warn
+    /// as it gets close
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<NaiveDate>,
+
+    /// The plugins the decrypted contents go through, in order, before they're written to `destination`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processors: Vec<String>,
 }
 
 impl File {
-    pub fn get_encrypted_destination(&self) -> String {
-        // This monstrosity tries to ensure we put files in the `.configure-files` directory for temporary storage. If something goes wrong,
-        // we fall back to just putting the file where it's specified to go
-        if let Ok(project_root) = find_project_root() {
-            let destination = Path::new(&self.destination);
-            if let Some(os_file_name) = destination.file_name() {
-                if let Some(file_name) = os_file_name.to_str() {
-                    if let Some(destination) = project_root
-                        .join(".configure-files")
-                        .join(file_name.to_owned() + ".enc")
-                        .to_str()
-                    {
-                        return destination.to_string();
-                    }
-                }
-            }
+    /// A file that's decrypted from `source` in the secrets repo to `destination` in the project
+    pub fn new(source: &str, destination: &str) -> Result<File, ConfigureError> {
+        let file = File {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        };
+
+        file.check()?;
+        Ok(file)
+    }
+
+    /// A directory that's archived from `source` in the secrets repo, and extracted to `destination` in the project
+    pub fn directory(source: &str, destination: &str) -> Result<File, ConfigureError> {
+        Ok(File {
+            is_directory: true,
+            ..File::new(source, destination)?
+        })
+    }
+
+    /// Check that the file has a source and a destination
+    pub fn check(&self) -> Result<(), ConfigureError> {
+        check_not_empty("file", &self.source)?;
+        check_not_empty("destination", &self.destination)?;
+        Ok(())
+    }
+
+    /// How many days are left before the secret expires on `today` – negative once it's expired
+    pub fn days_until_expiry(&self, today: NaiveDate) -> Option<i64> {
+        self.expires
+            .map(|expires| expires.signed_duration_since(today).num_days())
+    }
+
+    /// Where the encrypted copy of this file is kept if it's in the project's root `.configure` file – use
+    /// `Configuration::encrypted_destination_for` for files that could be in any
+    pub fn get_encrypted_destination(&self) -> PathBuf {
+        Configuration::default().encrypted_destination_for(self)
+    }
+
+    /// Where the encrypted copy of this file is kept in the project at `project_root`, if it's in the project's root
+    /// `.configure` file
+    pub fn get_encrypted_destination_in(&self, project_root: &Path) -> PathBuf {
+        self.get_encrypted_destination_in_directory(&project_root.join(".configure-files"))
+    }
+
+    /// Where the encrypted copy of this file is kept in `files_directory` – see `Configuration::files_directory_in`
+    pub fn get_encrypted_destination_in_directory(&self, files_directory: &Path) -> PathBuf {
+        let destination = Path::new(&self.destination);
+
+        match destination.file_name() {
+            Some(file_name) => add_extension(&files_directory.join(file_name), "enc"),
+            None => add_extension(destination, "enc"),
         }
+    }
 
-        self.destination.clone() + ".enc"
+    /// Where versions of this tool before `.configure-files` kept the encrypted copy – right next to the destination
+    pub fn get_legacy_encrypted_destination_in(&self, project_root: &Path) -> PathBuf {
+        add_extension(&project_root.join(&self.destination), "enc")
     }
 
     pub fn get_decrypted_destination(&self) -> String {
@@ -198,86 +1071,531 @@ impl File {
     fn get_backup_destination_for_date(&self, date: DateTime<Utc>) -> PathBuf {
         let path = Path::new(&self.destination);
 
-        let directory = path.parent().unwrap_or_else(|| Path::new("/")); // If we're at the root of the file system
+        let directory = parent_directory(path);
 
-        let file_stem = path
-            .file_stem()
-            .unwrap_or_default() // Ensure one exists
-            .to_str() // Convert from OsStr
-            .unwrap_or_default(); // Blank on failure
+        let datetime = date.format(BACKUP_DATE_FORMAT).to_string();
 
-        let extension = path
-            .extension()
-            .unwrap_or_default()
-            .to_str()
-            .unwrap_or_default();
+        // Build the name from `OsStr`s, so names that aren't valid UTF-8 are kept as they are
+        let mut filename = path.file_stem().unwrap_or_default().to_os_string();
+        filename.push("-");
+        filename.push(&datetime);
 
-        let datetime = date.format("%Y-%m-%d-%H-%M-%S").to_string();
+        if let Some(extension) = path.extension().filter(|extension| !extension.is_empty()) {
+            filename.push(".");
+            filename.push(extension);
+        }
 
-        let filename: String = if extension.is_empty() {
-            format!("{:}-{:}.bak", file_stem, datetime)
-        } else {
-            format!("{:}-{:}.{:}.bak", file_stem, datetime, extension)
-        };
+        filename.push(".bak");
 
         directory.join(filename)
     }
+
+    /// When the backup named `file_name` was made – `None` if it isn't a backup of this file
+    pub fn backup_date_from_file_name(&self, file_name: &str) -> Option<NaiveDateTime> {
+        let path = Path::new(&self.destination);
+        let file_stem = path.file_stem()?.to_str()?;
+
+        let suffix = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if !extension.is_empty() => format!(".{}.bak", extension),
+            _ => ".bak".to_string(),
+        };
+
+        let datetime = file_name
+            .strip_prefix(file_stem)?
+            .strip_prefix('-')?
+            .strip_suffix(suffix.as_str())?;
+
+        NaiveDateTime::parse_from_str(datetime, BACKUP_DATE_FORMAT).ok()
+    }
+}
+
+const BACKUP_DATE_FORMAT: &str = "%Y-%m-%d-%H-%M-%S";
+
+/// What to do when a destination file was edited since the last time it was applied
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Replace the local file (a backup is still made)
+    Overwrite,
+
+    /// Leave the local file alone
+    Keep,
+
+    /// Stop with an error
+    Fail,
 }
 
-pub fn apply_configuration(configuration: &Configuration) {
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "keep" => Ok(ConflictPolicy::Keep),
+            "fail" => Ok(ConflictPolicy::Fail),
+            _ => Err(format!(
+                "Unknown conflict policy {:?} – expected `overwrite`, `keep`, or `fail`",
+                string
+            )),
+        }
+    }
+}
+
+/// How a single conflicting destination file should be handled
+#[derive(Debug, Eq, PartialEq)]
+pub enum ConflictResolution {
+    Overwrite,
+    Keep,
+}
+
+/// What to do with a decrypted file left at a destination that's no longer in the configuration
+#[derive(Debug, Eq, PartialEq)]
+pub enum PreviousFileResolution {
+    /// Move it to the file's new destination
+    Move,
+    Delete,
+    Keep,
+}
+
+/// What to do with a project's keys when the project is renamed
+#[derive(Debug, Eq, PartialEq)]
+pub enum KeyRenameResolution {
+    /// Move the keys to the new name
+    Rename,
+
+    /// Copy the keys to the new name, leaving them under the old one too
+    Copy,
+
+    /// Leave the keys where they are, and set `key_name` to the old name
+    KeepKeyName,
+}
+
+/// Options that control how `apply_configuration` writes decrypted files
+#[derive(Debug, Clone, Default)]
+pub struct ApplyOptions {
+    /// Whether the user can be prompted for decisions
+    pub interactive: bool,
+
+    /// How to handle locally modified destination files. If this is `None`, interactive runs will ask the user
+    /// and non-interactive runs will overwrite the file.
+    pub conflict_policy: Option<ConflictPolicy>,
+
+    /// Decrypt every file, even the ones that haven't changed since the last apply
+    pub force_decrypt: bool,
+
+    /// Recreate missing encrypted files from the secrets repo at the pinned hash without asking. Interactive runs
+    /// ask first, and other runs fail without them.
+    pub regenerate_missing: bool,
+
+    /// Only decrypt the files in this profile from the `.configure` file, with the profile's key
+    pub profile: Option<String>,
+
+    /// Stops the apply before the next file once it's cancelled
+    pub cancellation: CancellationToken,
+}
+
+impl ApplyOptions {
+    pub fn resolve_conflict(
+        &self,
+        destination: &Path,
+        local_contents: &[u8],
+        new_contents: &[u8],
+    ) -> Result<ConflictResolution, ConfigureError> {
+        match self.conflict_policy {
+            Some(ConflictPolicy::Overwrite) => Ok(ConflictResolution::Overwrite),
+            Some(ConflictPolicy::Keep) => Ok(ConflictResolution::Keep),
+            Some(ConflictPolicy::Fail) => Err(ConfigureError::DestinationFileModified {
+                path: destination.to_path_buf(),
+            }),
+            None if self.interactive => Ok(prompt_for_conflict_resolution(
+                destination,
+                local_contents,
+                new_contents,
+            )),
+            None => Ok(ConflictResolution::Overwrite),
+        }
+    }
+
+    /// Decide what to do with a decrypted file at a destination that was removed from the configuration
+    ///
+    /// `moved_to` is the file's new destination, if it looks like it was moved rather than removed. Returns `None`
+    /// when nobody can be asked – nothing is ever deleted without confirmation.
+    pub fn resolve_previous_file(
+        &self,
+        destination: &Path,
+        moved_to: Option<&Path>,
+    ) -> Option<PreviousFileResolution> {
+        if self.interactive {
+            Some(prompt_for_previous_file_resolution(destination, moved_to))
+        } else {
+            None
+        }
+    }
+}
+
+pub fn apply_configuration(
+    configuration: &Configuration,
+    options: &ApplyOptions,
+) -> Result<(), ConfigureError> {
+    configuration.warn_about_expiring_files();
+
     // Decrypt the project's configuration files
-    decrypt_files_for_configuration(configuration).expect("Unable to decrypt and copy files");
+    decrypt_files_for_configuration(configuration, options)?;
 
     debug!("All Files Copied!");
 
-    info!("Done")
+    info!("Done");
+    Ok(())
 }
 
-pub fn update_configuration(
+/// Fetch the latest secrets, re-encrypt the project's files from them, and apply them
+///
+/// Pass `fetch: false` if the secrets repo has already been fetched – the async API does this so the network
+/// access can be awaited (and cancelled) separately. `requested_commit` pins the project to that commit on its branch
+/// instead of the latest one, without asking. If `cancellation` is cancelled partway through, the secrets repo and
+/// project are put back the same way they would be after a failure.
+///
+/// With `accept_plan`, the update goes ahead without asking – on the same branch, to the latest secrets – but its plan
+/// is still shown. This is `update --yes`. Unlike running non-interactively, files changed since they were last
+/// applied are still asked about.
+pub(crate) fn try_update_configuration(
     configuration_file_path: Option<String>,
     interactive: bool,
-) -> Configuration {
-    let mut configuration = read_configuration_from_file(&configuration_file_path)
-        .expect("Unable to read configuration from `.configure` file");
+    accept_plan: bool,
+    fetch: bool,
+    requested_commit: Option<&str>,
+    cancellation: &CancellationToken,
+) -> Result<Configuration, ConfigureError> {
+    let configuration = read_verified_configuration_from_file(&configuration_file_path)?;
 
-    let secrets_repo = SecretsRepo::default();
-    let starting_branch = secrets_repo
-        .current_branch()
-        .expect("Unable to determine current mobile secrets branch");
-    let starting_ref = secrets_repo
-        .current_hash()
-        .expect("Unable to determine current mobile secrets commit hash");
+    // Held until the update has been applied, so another update or apply can't write the project's files in between
+    let _lock = lock_project()?;
+
+    // Everything is decided before anything changes, so there's a single plan to show and agree to
+    let asks_questions = interactive && !accept_plan;
 
     heading("Configure Update");
+    emit(Event::UpdateStarted);
+
+    let result = run_update(
+        configuration,
+        interactive,
+        asks_questions,
+        fetch,
+        requested_commit,
+        &configuration_file_path,
+        cancellation,
+    );
+
+    match &result {
+        Ok(_) => emit(Event::UpdateFinished),
+        Err(err) => emit(Event::UpdateFailed { code: err.code() }),
+    }
+
+    result
+}
+
+/// The steps of `try_update_configuration` after the configuration has been read
+fn run_update(
+    mut configuration: Configuration,
+    interactive: bool,
+    asks_questions: bool,
+    fetch: bool,
+    requested_commit: Option<&str>,
+    configuration_file_path: &Option<String>,
+    cancellation: &CancellationToken,
+) -> Result<Configuration, ConfigureError> {
+    let previous_configuration = configuration.clone();
+
+    let secrets_repo = SecretsRepo {
+        path: find_secrets_repo()?,
+    };
 
+    //
+    // Steps 1–6 – Fetch the latest secrets, then stage the updated `.configure` file and the encrypted files read from
+    //             the new pinned commit. The secrets repo's working copy is never touched, so it can be on any branch
+    //             and have local changes. Nothing in the project is written yet, so a failure leaves it as it was.
+    //
+    // Asking for a specific commit means the choice is already made, so don't offer to switch branches
+    let staged = match prepare_update(
+        &secrets_repo,
+        &mut configuration,
+        asks_questions && requested_commit.is_none(),
+        fetch,
+        cancellation,
+    ) {
+        Ok(true) => stage_update(
+            &secrets_repo,
+            &mut configuration,
+            asks_questions,
+            requested_commit,
+            configuration_file_path,
+            cancellation,
+        )
+        .map(Some),
+        Ok(false) => Ok(None),
+        Err(err) => Err(err),
+    };
+
+    let staged = match staged {
+        Ok(Some(staged)) => staged,
+        Ok(None) => {
+            debug!("Exiting without updating hash");
+            return Ok(configuration);
+        }
+        Err(err) => {
+            info!("Nothing in the project was changed");
+            return Err(err);
+        }
+    };
+
+    let plan = UpdatePlan::new(&secrets_repo, &previous_configuration, &configuration)?;
+    if !plan.is_empty() {
+        heading("Update Plan");
+        message(&plan.to_string());
+
+        if asks_questions && !confirm("Go ahead with this update?") {
+            info!("Nothing in the project was changed");
+            return Ok(previous_configuration);
+        }
+    }
+
+    //
+    // Step 7 – Write the staged files. If any of them can't be written, the ones already written are put back.
+    //
+    let committed = match staged.commit() {
+        Ok(committed) => committed,
+        Err(err) => {
+            info!("Nothing in the project was changed");
+            return Err(err);
+        }
+    };
+
+    //
+    // Step 8 – Apply these changes to the current repo, putting the `.configure` file and encrypted files back
+    //          if that fails so they still match the secrets that are in use
+    //
+    let apply_options = ApplyOptions {
+        interactive,
+        cancellation: cancellation.clone(),
+        ..Default::default()
+    };
+
+    if let Err(err) = decrypt_files_while_locked(
+        &configuration,
+        &apply_options,
+        &mut DecryptionKeys::default(),
+    ) {
+        committed.roll_back();
+        info!("Unable to apply the updated secrets, so the project was rolled back");
+        return Err(err);
+    }
+
+    if previous_configuration.pinned_hash != configuration.pinned_hash {
+        emit(Event::PinUpdated {
+            old: previous_configuration.pinned_hash.clone(),
+            new: configuration.pinned_hash.clone(),
+        });
+    }
+
+    info!("Done");
+
+    //
+    // Step 9 - All done!
+    //
+    Ok(configuration)
+}
+
+/// Everything an `update` is about to change, shown before anything is written
+#[derive(Debug, Clone, Default, Serialize, Eq, PartialEq)]
+pub struct UpdatePlan {
+    pub previous_branch: String,
+    pub branch: String,
+    pub previous_pinned_hash: String,
+    pub pinned_hash: String,
+
+    /// The destinations of the files whose sources changed between the two pinned commits
+    pub changed_files: Vec<String>,
+
+    /// The destinations of the files that weren't encrypted by the last update
+    pub added_files: Vec<String>,
+
+    /// The names of the encrypted files from the last update that no file uses any more
+    pub removed_files: Vec<String>,
+}
+
+impl UpdatePlan {
+    pub fn new(
+        secrets_repo: &SecretsRepo,
+        previous: &Configuration,
+        configuration: &Configuration,
+    ) -> Result<UpdatePlan, ConfigureError> {
+        let encrypted_names: Vec<(String, &str)> = configuration
+            .files_to_copy
+            .iter()
+            .map(|file| {
+                (
+                    encrypted_file_name(&configuration.encrypted_destination_for(file)),
+                    file.destination.as_str(),
+                )
+            })
+            .collect();
+
+        let (added_files, removed_files) = match read_encrypted_files_manifest(configuration)? {
+            Some(manifest) => (
+                encrypted_names
+                    .iter()
+                    .filter(|(name, _)| !manifest.files.contains_key(name))
+                    .map(|(_, destination)| destination.to_string())
+                    .collect(),
+                manifest
+                    .files
+                    .keys()
+                    .filter(|name| !encrypted_names.iter().any(|(other, _)| other == *name))
+                    .cloned()
+                    .collect(),
+            ),
+            // Nothing to compare against – this is the first update since the manifest was introduced
+            None => (Vec::new(), Vec::new()),
+        };
+
+        Ok(UpdatePlan {
+            previous_branch: previous.branch.clone(),
+            branch: configuration.branch.clone(),
+            previous_pinned_hash: previous.pinned_hash.clone(),
+            pinned_hash: configuration.pinned_hash.clone(),
+            changed_files: changed_destinations(secrets_repo, configuration, &previous.pinned_hash),
+            added_files,
+            removed_files,
+        })
+    }
+
+    /// Whether the update leaves the project as it is
+    pub fn is_empty(&self) -> bool {
+        self.previous_branch == self.branch
+            && self.previous_pinned_hash == self.pinned_hash
+            && self.changed_files.is_empty()
+            && self.added_files.is_empty()
+            && self.removed_files.is_empty()
+    }
+}
+
+impl fmt::Display for UpdatePlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let short = |hash: &str| hash.chars().take(7).collect::<String>();
+
+        if self.previous_branch != self.branch {
+            writeln!(
+                f,
+                "Branch:        {} → {}",
+                self.previous_branch, self.branch
+            )?;
+        }
+
+        match self.previous_pinned_hash == self.pinned_hash {
+            true => writeln!(f, "Pinned hash:   {} (unchanged)", short(&self.pinned_hash))?,
+            false => writeln!(
+                f,
+                "Pinned hash:   {} → {}",
+                short(&self.previous_pinned_hash),
+                short(&self.pinned_hash)
+            )?,
+        }
+
+        for (label, files) in [
+            ("Re-encrypted", &self.changed_files),
+            ("Added", &self.added_files),
+            ("Removed", &self.removed_files),
+        ] {
+            if !files.is_empty() {
+                writeln!(f, "{:<14} {}", format!("{}:", label), files.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The destinations of the files whose sources differ between `previous_hash` and the configuration's pinned hash
+///
+/// The commits on either side are compared, so going back to an earlier commit lists the files it reverts. When
+/// they can't be compared, every file is listed.
+fn changed_destinations(
+    secrets_repo: &SecretsRepo,
+    configuration: &Configuration,
+    previous_hash: &str,
+) -> Vec<String> {
+    if previous_hash == configuration.pinned_hash {
+        return Vec::new();
+    }
+
+    let commits = secrets_repo
+        .commits_between(previous_hash, &configuration.pinned_hash)
+        .and_then(|mut commits| {
+            commits
+                .extend(secrets_repo.commits_between(&configuration.pinned_hash, previous_hash)?);
+            Ok(commits)
+        });
+
+    let commits = match commits {
+        Ok(commits) => commits,
+        Err(err) => {
+            debug!(
+                "Unable to compare {} with the new pinned hash: {}",
+                previous_hash, err
+            );
+            return configuration
+                .files_to_copy
+                .iter()
+                .map(|file| file.destination.clone())
+                .collect();
+        }
+    };
+
+    configuration
+        .files_to_copy
+        .iter()
+        .filter(|file| {
+            commits.iter().any(|commit| {
+                sources_changed_by(configuration, commit).contains(&file.source.as_str())
+            })
+        })
+        .map(|file| file.destination.clone())
+        .collect()
+}
+
+/// Steps 1–3 of `try_update_configuration` – returns whether the update should go ahead
+fn prepare_update(
+    secrets_repo: &SecretsRepo,
+    configuration: &mut Configuration,
+    interactive: bool,
+    fetch: bool,
+    cancellation: &CancellationToken,
+) -> Result<bool, ConfigureError> {
     //
     // Step 1 – Fetch the latest mobile secrets from the server
     //          We need them in order to update the pinned hash
     //
-    let bar = ProgressBar::new_spinner();
-    bar.enable_steady_tick(125);
-    bar.set_message("Fetching Latest Mobile Secrets");
-
-    secrets_repo
-        .update_local_copy()
-        .expect("Unable to fetch latest mobile secrets");
+    if fetch {
+        let spinner = Spinner::new("Fetching Latest Mobile Secrets");
+        secrets_repo.update_local_copy(&secrets_repo.remote_for_configuration(configuration)?)?;
+        spinner.finish();
+    }
 
-    bar.finish_and_clear();
+    cancellation.check()?;
 
     //
     // Step 2 – Check if the user wants to use a different secrets branch
     //
     if interactive {
-        configuration = prompt_for_branch(&secrets_repo, configuration, true);
+        *configuration = prompt_for_branch(secrets_repo, std::mem::take(configuration), true)?;
     }
 
     //
-    // Step 3 – Check if the current configuration branch is in sync with the server or not.or
+    // Step 3 – Check if the current configuration branch is in sync with the server or not.
     // If not, check with the user whether they'd like to continue
     //
-    let status = secrets_repo
-        .status()
-        .expect("Unable to get secrets repo status");
+    let status = secrets_repo.status()?;
 
     debug!("Repo status is: {:?}", status);
 
@@ -299,124 +1617,313 @@ pub fn update_configuration(
 
                 confirm("Would you like to continue?")
             }
+            RepoSyncState::Diverged { ahead, behind } => {
+                warn(&format!(
+                    "Your local secrets repo has diverged from the server – it has {:?} change(s) that the server does not, and the server has {:?} that it does not",
+                    ahead, behind
+                ));
+
+                confirm("Would you like to continue?")
+            }
             RepoSyncState::Synced => true,
         };
 
-    if !should_continue {
-        debug!("Exiting without updating hash");
-        return configuration;
-    }
+    Ok(should_continue)
+}
 
+/// Steps 4–6 of `try_update_configuration` – choosing the new pinned commit, and everything that produces new
+/// project files
+fn stage_update(
+    secrets_repo: &SecretsRepo,
+    configuration: &mut Configuration,
+    interactive: bool,
+    requested_commit: Option<&str>,
+    configuration_file_path: &Option<String>,
+    cancellation: &CancellationToken,
+) -> Result<StagedChanges, ConfigureError> {
     //
     // Step 4 – Check if the project's secrets are out of date compared to the server.
     //          If they out of date, we'll prompt the user to pull the latest remote
     //          changes into the local secrets repo before continuing.
     //
-    let distance = secrets_repo.commits_ahead_of_configuration(&configuration);
-    debug!(
-        "The project is {:} commit(s) behind the latest secrets",
-        distance
-    );
-
-    // Update the pinned hash when nothing has changed – this helps fill in the blanks when creating a `.configure` file by hand
-    if distance == 0 {
-        let latest_commit_hash = secrets_repo
-            .latest_remote_hash_for_branch(&configuration.branch)
-            .expect("Unable to fetch latest commit hash");
-        configuration.pinned_hash = latest_commit_hash;
-    } else {
-        let message = format!(
-            "This project is {:} commit(s) behind the latest secrets. Would you like to use the latest secrets?",
-            distance
-        );
-
-        // Prompt to update to most recent secrets data in the branch (if we're in interactive mode – if not, just do it)
-        if !interactive || confirm(&message) {
-            let latest_commit_hash = secrets_repo
-                .latest_remote_hash_for_branch(&configuration.branch)
-                .expect("Unable to fetch latest commit hash");
-
+    let latest_commit_hash = secrets_repo.latest_remote_hash_for_branch(
+        &secrets_repo.remote_for_configuration(configuration)?,
+        &configuration.branch,
+    )?;
+
+    let target_commit_hash = match requested_commit {
+        // The caller already chose a commit, so there's nothing to ask
+        Some(hash) => Some(secrets_repo.resolve_commit_on_branch(
+            hash,
+            &configuration.branch,
+            &latest_commit_hash,
+        )?),
+        None => {
+            let distance = match secrets_repo.commits_ahead_of_configuration(configuration) {
+                Ok(distance) => Some(distance),
+                // There's nothing to compare against, but moving to the latest secrets on the branch still fixes it
+                Err(err @ ConfigureError::PinnedHashNotInBranchHistory { .. }) => {
+                    warn(&err.to_string());
+                    None
+                }
+                Err(err) => return Err(err),
+            };
             debug!(
-                "Moving the secrets repo to {:?} at {:?}",
-                &configuration.branch, latest_commit_hash
+                "The project's distance from the latest secrets is {:?}",
+                distance
             );
 
-            secrets_repo
-                .switch_to_branch_at_revision(&configuration.branch, &latest_commit_hash)
-                .expect("Unable to check out branch at revision");
-
-            // Update the pinned hash in `.configure` file before continuing
-            debug!(
-                "Updating the .configure file pinned hash to {:?}",
-                latest_commit_hash
-            );
-            configuration.pinned_hash = latest_commit_hash;
+            if distance.map(|distance| distance.is_zero()).unwrap_or(false) {
+                // Update the pinned hash when nothing has changed – this helps fill in the blanks when creating a `.configure` file by hand
+                configuration.pinned_hash = latest_commit_hash;
+                None
+            } else if !interactive {
+                // If we're not in interactive mode, just use the latest secrets
+                Some(latest_commit_hash)
+            } else {
+                choose_secrets_commit(secrets_repo, configuration, distance, &latest_commit_hash)
+            }
         }
+    };
+
+    if let Some(target_commit_hash) = target_commit_hash {
+        // Update the pinned hash in `.configure` file before continuing
+        debug!(
+            "Updating the .configure file pinned hash to {:?}",
+            target_commit_hash
+        );
+        configuration.pinned_hash = target_commit_hash;
     }
 
-    //
-    // Step 5 – Write out encrypted files as needed
-    //
-    let configure_file_path = resolve_configure_file_path(&configuration_file_path).expect("");
-    write_configuration_to(&configuration, &configure_file_path)
-        .expect("Unable to write configuration");
+    cancellation.check()?;
 
-    //
-    // Step 6 – Write out encrypted files as needed
-    //
-    let encryption_key =
-        encryption_key_for_configuration(&configuration).expect("Unable to find encryption key");
-    write_encrypted_files_for_configuration(&configuration, encryption_key)
-        .expect("Unable to copy encrypted files");
+    let mut staged = StagedChanges::default();
 
     //
-    // Step 7 – Roll the secrets repo back to how it was before we started
+    // Step 5 – Stage the updated `.configure` file
     //
-    secrets_repo
-        .switch_to_branch_at_revision(&starting_branch, &starting_ref)
-        .expect("Unable to roll back to branch");
+    let configure_file_path = resolve_configure_file_path(configuration_file_path)?;
+    stage_signed_configuration(configuration, &configure_file_path, &mut staged)?;
 
     //
-    // Step 8 – Apply these changes to the current repo
+    // Step 6 – Stage the encrypted files read from the new pinned commit, and a manifest recording which commit
+    //          they came from
     //
-    apply_configuration(&configuration);
+    let encryption_key = encryption_key_for_configuration(configuration)?;
+    stage_encrypted_files_for_configuration(
+        configuration,
+        &encryption_key,
+        &mut staged,
+        cancellation,
+    )?;
+    stage_encrypted_files_manifest(configuration, &mut staged)?;
+
+    Ok(staged)
+}
 
-    //
-    // Step 9 - All done!
-    //
+/// Ask which commit to move the project to – the latest one, an earlier one picked from a list, or none at all
+///
+/// The user can list the commits that the latest secrets would bring in before they decide.
+fn choose_secrets_commit(
+    secrets_repo: &SecretsRepo,
+    configuration: &Configuration,
+    distance: Option<CommitDistance>,
+    latest_commit_hash: &str,
+) -> Option<String> {
+    let situation = match distance {
+        Some(distance) if distance.behind == 0 => format!(
+            "This project is {:} commit(s) behind the latest secrets.",
+            distance.ahead
+        ),
+        Some(distance) => format!(
+            "This project is pinned to a commit with {:} change(s) that aren't on the {} branch.",
+            distance.behind, configuration.branch
+        ),
+        None => format!(
+            "This project is pinned to a commit that isn't on the {} branch.",
+            configuration.branch
+        ),
+    };
+    let question = format!("{} Would you like to use the latest secrets?", situation);
+
+    loop {
+        match choose(
+            &question,
+            &[
+                "Yes",
+                "Show the changes first",
+                "Pick a specific commit",
+                "No",
+            ],
+        ) {
+            0 => return Some(latest_commit_hash.to_string()),
+            1 => show_incoming_commits(secrets_repo, configuration, latest_commit_hash),
+            2 => return pick_commit(secrets_repo, latest_commit_hash),
+            _ => return None,
+        }
+    }
+}
+
+fn show_incoming_commits(
+    secrets_repo: &SecretsRepo,
+    configuration: &Configuration,
+    latest_commit_hash: &str,
+) {
+    let commits = match secrets_repo.commits_between(&configuration.pinned_hash, latest_commit_hash)
+    {
+        Ok(commits) => commits,
+        Err(err) => {
+            warn(&format!("Unable to list the changes: {}", err));
+            return;
+        }
+    };
+
+    newline();
+    for commit in &commits {
+        message(&format!(
+            "{} {}",
+            highlight(commit.short_hash()),
+            commit.subject
+        ));
+
+        let sources = sources_changed_by(configuration, commit);
+        if !sources.is_empty() {
+            message(&format!("        Touches {}", sources.join(", ")));
+        }
+    }
+    newline();
+}
+
+// How many commits to offer when picking one – older ones can be pinned with `update --commit`
+const PICKABLE_COMMIT_COUNT: usize = 20;
+
+fn pick_commit(secrets_repo: &SecretsRepo, latest_commit_hash: &str) -> Option<String> {
+    let commits = match secrets_repo.recent_commits(latest_commit_hash, PICKABLE_COMMIT_COUNT) {
+        Ok(commits) => commits,
+        Err(err) => {
+            warn(&format!("Unable to list the commits: {}", err));
+            return None;
+        }
+    };
+
+    let items: Vec<String> = commits
+        .iter()
+        .map(|commit| format!("{} {}", commit.short_hash(), commit.subject))
+        .collect();
+    let items: Vec<&str> = items.iter().map(String::as_str).collect();
+
+    let index = choose("Which commit would you like to use?", &items);
+    Some(commits[index].hash.clone())
+}
+
+/// The project's source files (or directories) that `commit` changed
+fn sources_changed_by<'a>(
+    configuration: &'a Configuration,
+    commit: &CommitSummary,
+) -> Vec<&'a str> {
     configuration
+        .files_to_copy
+        .iter()
+        .map(|file| file.source.as_str())
+        .filter(|source| {
+            commit
+                .changed_paths
+                .iter()
+                .any(|path| Path::new(path).starts_with(Path::new(source.trim_end_matches('/'))))
+        })
+        .collect()
+}
+
+/// Recreate encrypted files that are missing from the project, using the secrets repo at the pinned hash
+///
+/// This happens with `regenerate_missing`, or once the user agrees to it. Otherwise nothing changes, and applying the
+/// configuration fails as it would have. Like `update`, the files are read from the commit without checking it out.
+pub fn regenerate_missing_encrypted_files(
+    configuration: &Configuration,
+    options: &ApplyOptions,
+) -> Result<(), ConfigureError> {
+    let missing_files: Vec<&File> = configuration
+        .files_to_copy
+        .iter()
+        .filter(|file| !configuration.encrypted_destination_for(file).exists())
+        .collect();
+
+    if missing_files.is_empty() || configuration.pinned_hash.is_empty() {
+        return Ok(());
+    }
+
+    if let Err(err) = find_secrets_repo() {
+        debug!("Unable to regenerate the missing encrypted files: {}", err);
+        return Ok(());
+    }
+
+    let should_regenerate = options.regenerate_missing
+        || (options.interactive
+            && confirm(&format!(
+                "{} encrypted file(s) are missing. Would you like to regenerate them from the secrets repo at {}?",
+                missing_files.len(),
+                configuration.pinned_hash
+            )));
+
+    if !should_regenerate {
+        return Ok(());
+    }
+
+    for file in &missing_files {
+        write_encrypted_file_for_configuration(
+            configuration,
+            file,
+            Some(&configuration.pinned_hash),
+        )?;
+        info!(
+            "Regenerated {:?} from the secrets repo",
+            configuration.encrypted_destination_for(file)
+        );
+    }
+
+    Ok(())
 }
 
 pub fn validate_configuration(configuration: Configuration) {
-    println!("{:?}", configuration);
+    info!("{:?}", configuration);
 }
 
-pub fn setup_configuration(mut configuration: Configuration) {
+pub fn setup_configuration(mut configuration: Configuration) -> Result<(), ConfigureError> {
     heading("Configure Setup");
-    println!("Let's get configuration set up for this project.");
+    message("Let's get configuration set up for this project.");
     newline();
 
-    let repo = SecretsRepo::default();
+    let repo = SecretsRepo {
+        path: find_secrets_repo()?,
+    };
 
     // Help the user set the `project_name` field
     configuration = prompt_for_project_name_if_needed(configuration);
+    fill_in_project_name(&mut configuration);
 
     // Help the user set the `branch` field
-    configuration = prompt_for_branch(&repo, configuration, true);
+    configuration = prompt_for_branch(&repo, configuration, true)?;
 
     // Set the latest automatically hash based on the selected branch
-    configuration.set_pinned_hash_from_repo(&repo);
+    configuration.set_pinned_hash_from_repo(&repo)?;
 
     // Help the user add files
     configuration = prompt_to_add_files(configuration);
 
-    info!("Writing changes to .configure");
+    // Files added here can't conflict, but ones from before (or from a template) might
+    for conflict in configuration.destination_conflicts() {
+        warn(&format!(
+            "{} – edit the .configure file to fix this",
+            conflict
+        ));
+    }
+
+    debug!("Writing changes to .configure");
 
-    write_configuration(&configuration).expect("Unable to save configure file");
+    write_configuration(&configuration)?;
 
     // Create a key in `keys.json` for the project if one doesn't already exist
     generate_encryption_key_if_needed(&configuration)
-        .expect("Unable to generate an encryption key for this project");
 }
 
 fn prompt_for_project_name_if_needed(mut configuration: Configuration) -> Configuration {
@@ -425,95 +1932,471 @@ fn prompt_for_project_name_if_needed(mut configuration: Configuration) -> Config
         return configuration;
     }
 
-    let project_name = prompt("What is the name of your project?");
-    configuration.project_name = project_name.clone();
-    println!("Project Name set to: {:?}", project_name);
+    let project_name = prompt("What is the name of your project?");
+    configuration.project_name = project_name.clone();
+    info!("Project Name set to: {:?}", project_name);
+
+    configuration
+}
+
+/// Make sure the secrets repo has a branch named `branch_name` before it's written to a `.configure` file
+pub fn check_branch_exists(repo: &SecretsRepo, branch_name: &str) -> Result<(), ConfigureError> {
+    match repo.branch_exists(branch_name)? {
+        true => Ok(()),
+        false => Err(ConfigureError::BranchNotFound {
+            branch: branch_name.to_string(),
+        }),
+    }
+}
+
+fn prompt_for_branch(
+    repo: &SecretsRepo,
+    mut configuration: Configuration,
+    force: bool,
+) -> Result<Configuration, ConfigureError> {
+    // If there's already a branch set, don't bother updating it – unless the secrets repo doesn't have it
+    if !configuration.needs_branch() && !force {
+        match check_branch_exists(repo, &configuration.branch) {
+            Ok(()) => return Ok(configuration),
+            Err(err) => warn(&err.to_string()),
+        }
+    }
+
+    let branches = repo.local_branch_names()?;
+
+    // A detached HEAD is reported as `HEAD`, which isn't one of the branches
+    let current_branch = repo
+        .current_branch()
+        .ok()
+        .filter(|branch| branches.contains(branch));
+
+    info!("Using the secrets repository at {:?}", repo.path);
+    newline();
+    message("Which branch would you like to use?");
+    match &current_branch {
+        Some(branch) => message(&format!("Current Branch: {}", highlight(branch))),
+        None => message("The secrets repo isn't on a branch right now"),
+    }
+
+    // Suggest the branch that's already configured (perhaps by a template), then the user's default branch, if this
+    // secrets repo has them
+    let suggested_branch = if branches.contains(&configuration.branch) {
+        Some(configuration.branch.as_str())
+    } else {
+        match &settings().default_branch {
+            Some(branch) if branches.contains(branch) => Some(branch.as_str()),
+            _ => current_branch.as_deref(),
+        }
+    };
+
+    let selected_branch = select(&branches, suggested_branch)?;
+    check_branch_exists(repo, &selected_branch)?;
+
+    configuration.branch = selected_branch.clone();
+    info!("Secrets repo branch set to: {:?}", selected_branch);
+
+    Ok(configuration)
+}
+
+fn prompt_to_add_files(mut configuration: Configuration) -> Configuration {
+    configuration = prompt_to_add_suggested_files(configuration);
+    configuration = prompt_to_pick_files(configuration);
+
+    let mut message = "Would you like to add files?";
+
+    if !configuration.files_to_copy.is_empty() {
+        message = "Would you like to add additional files?";
+    }
+
+    while confirm(message) {
+        if let Some(file) = prompt_to_add_file(None) {
+            if let Err(err) = configuration.add_file(file) {
+                warn(&err.to_string());
+            }
+        }
+    }
+
+    configuration
+}
+
+/// Offer the files that Android and iOS projects usually need, if they aren't configured already
+fn prompt_to_add_suggested_files(mut configuration: Configuration) -> Configuration {
+    let project_root = match find_project_root() {
+        Ok(project_root) => project_root,
+        Err(_) => return configuration,
+    };
+
+    let platforms: Vec<String> = detect_platforms(&project_root)
+        .iter()
+        .map(Platform::to_string)
+        .collect();
+
+    if platforms.is_empty() {
+        return configuration;
+    }
+
+    newline();
+    message(&format!(
+        "This looks like an {} project – you can add the secrets these usually need, changing their paths if you like.",
+        platforms.join(" and ")
+    ));
+
+    for suggestion in suggested_files(&project_root, &configuration.project_name) {
+        let already_configured = configuration
+            .files_to_copy
+            .iter()
+            .any(|file| file.destination == suggestion.destination);
+
+        if already_configured
+            || !confirm(&format!(
+                "Would you like to add {} ({})?",
+                suggestion.destination, suggestion.platform
+            ))
+        {
+            continue;
+        }
+
+        if let Some(file) = prompt_to_add_file(Some(&suggestion)) {
+            if let Err(err) = configuration.add_file(file) {
+                warn(&err.to_string());
+            }
+        }
+    }
 
     configuration
 }
 
-fn prompt_for_branch(
-    repo: &SecretsRepo,
-    mut configuration: Configuration,
-    force: bool,
-) -> Configuration {
-    // If there's already a branch set, don't bother updating it
-    if !configuration.needs_branch() && !force {
+/// Offer the files in the secrets repo as a checklist, adding each one that's picked with a default destination
+///
+/// If the secrets repo has directories named after the project, the list can be narrowed down to what's in them.
+fn prompt_to_pick_files(mut configuration: Configuration) -> Configuration {
+    let secrets_root = match find_secrets_repo() {
+        Ok(secrets_root) => secrets_root,
+        Err(_) => return configuration,
+    };
+
+    newline();
+    if !confirm("Would you like to pick several files from a list of the secrets repo's files?") {
         return configuration;
     }
 
-    let current_branch = repo
-        .current_branch()
-        .expect("Unable to determine current mobile secrets branch");
-    let branches = repo
-        .local_branch_names()
-        .expect("Unable to fetch mobile secrets branches");
+    let project_name = configuration.project_name.clone();
+    let mut files: Vec<String> = match find_files_in_secrets_repo(&secrets_root) {
+        Ok(files) => files
+            .into_iter()
+            .filter(|file| {
+                !configuration
+                    .files_to_copy
+                    .iter()
+                    .any(|existing| &existing.source == file)
+            })
+            .collect(),
+        Err(err) => {
+            warn(&format!("Unable to list the secrets repo's files: {}", err));
+            return configuration;
+        }
+    };
 
-    println!("Using the secrets repository at {:?}", repo.path);
-    newline();
-    println!("Which branch would you like to use?");
-    println!("Current Branch: {}", style(&current_branch).green());
+    let project_files: Vec<String> = files
+        .iter()
+        .filter(|file| project_directory_in(file, &project_name).is_some())
+        .cloned()
+        .collect();
+
+    if !project_files.is_empty()
+        && project_files.len() < files.len()
+        && confirm(&format!(
+            "Only list the files in directories named {:?}?",
+            project_name
+        ))
+    {
+        files = project_files;
+    }
 
-    let selected_branch =
-        select(branches, &current_branch).expect("Unable to read selected branch");
+    let picked = match select_many(&files) {
+        Ok(picked) => picked,
+        Err(err) => {
+            warn(&err.to_string());
+            return configuration;
+        }
+    };
 
-    configuration.branch = selected_branch.clone();
-    println!("Secrets repo branch set to: {:?}", selected_branch);
+    let suggestions = find_project_root()
+        .map(|project_root| suggested_files(&project_root, &project_name))
+        .unwrap_or_default();
+
+    for source in picked {
+        let destination = default_destination(&source, &project_name, &suggestions);
+        message(&format!("Adding {} → {}", source, destination));
+
+        let file = File {
+            is_directory: false,
+            source,
+            destination,
+            expires: None,
+            processors: Vec::new(),
+        };
+
+        if let Err(err) = configuration.add_file(file) {
+            warn(&err.to_string());
+        }
+    }
+
+    message("Destinations can be changed in the .configure file, or by removing and adding the file again.");
 
     configuration
 }
 
-fn prompt_to_add_files(mut configuration: Configuration) -> Configuration {
-    let mut files = configuration.files_to_copy;
+/// Where a file picked from the secrets repo is decrypted to, unless the user changes it
+///
+/// That's the destination a platform suggests for it if there is one. Otherwise it's the file's path inside the
+/// directory named after the project – `android/WordPress/app/google-services.json` goes to
+/// `app/google-services.json` – or just its name at the project root.
+fn default_destination(source: &str, project_name: &str, suggestions: &[SuggestedFile]) -> String {
+    if let Some(suggestion) = suggestions
+        .iter()
+        .find(|suggestion| suggestion.source == source)
+    {
+        return suggestion.destination.clone();
+    }
 
-    let mut message = "Would you like to add files?";
+    match project_directory_in(source, project_name) {
+        Some(index) => source[index..].to_string(),
+        None => source.rsplit('/').next().unwrap_or(source).to_string(),
+    }
+}
 
-    if !files.is_empty() {
-        message = "Would you like to add additional files?";
+/// Where the part of `path` inside a directory named after the project starts, ignoring case
+fn project_directory_in(path: &str, project_name: &str) -> Option<usize> {
+    if project_name.is_empty() {
+        return None;
     }
 
-    while confirm(message) {
-        match prompt_to_add_file() {
-            Some(file) => files.push(file),
-            None => continue,
+    let mut start = 0;
+    for component in path.split('/') {
+        start += component.len() + 1;
+
+        if component.eq_ignore_ascii_case(project_name) && start < path.len() {
+            return Some(start);
         }
     }
 
-    configuration.files_to_copy = files;
-
-    configuration
+    None
 }
 
-fn prompt_to_add_file() -> Option<File> {
-    let relative_source_file_path =
-        prompt("Enter the source file path (relative to the secrets root):");
-
+/// Ask for a file's source and destination, starting from `suggestion`'s if there is one
+///
+/// Sources are completed from the secrets repo, and destinations from the project.
+fn prompt_to_add_file(suggestion: Option<&SuggestedFile>) -> Option<File> {
     let secrets_root = match find_secrets_repo() {
         Ok(repo_path) => repo_path,
         Err(_) => return None,
     };
 
+    let relative_source_file_path = portable(&prompt_for_path(
+        "Enter the source file path, relative to the secrets root.",
+        &secrets_root,
+        suggestion.map(|suggestion| suggestion.source.as_str()),
+        PathKind::Existing,
+    ));
+
     let full_source_file_path = secrets_root.join(&relative_source_file_path);
 
     if !full_source_file_path.exists() {
-        println!("Source File does not exist: {:?}", full_source_file_path);
+        warn!("Source File does not exist: {:?}", full_source_file_path);
         return None;
     }
 
-    let relative_destination_file_path =
-        prompt("Enter the destination file path (relative to the project root):");
-
     let project_root = find_project_root().unwrap();
+
+    let relative_destination_file_path = portable(&prompt_for_path(
+        "Enter the destination file path, relative to the project root.",
+        &project_root,
+        suggestion.map(|suggestion| suggestion.destination.as_str()),
+        PathKind::New,
+    ));
+
     let full_destination_file_path = project_root.join(&relative_destination_file_path);
 
     debug!("Destination: {:?}", full_destination_file_path);
 
+    if !destination_can_be_written(&full_destination_file_path, full_source_file_path.is_dir()) {
+        return None;
+    }
+
     Some(File {
         source: relative_source_file_path,
         destination: relative_destination_file_path,
+        is_directory: full_source_file_path.is_dir(),
+        expires: None,
+        processors: Vec::new(),
     })
 }
 
+/// Check that a file (or directory) can be decrypted to `destination`, saying why not if it can't
+///
+/// Missing directories are created when the file is decrypted, so they're fine – but a file in the way isn't.
+fn destination_can_be_written(destination: &Path, is_directory: bool) -> bool {
+    if destination.is_dir() && !is_directory {
+        warn(&format!(
+            "{:?} is a directory – enter the path of the file to write inside it",
+            destination
+        ));
+        return false;
+    }
+
+    let existing_ancestor = destination
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.exists());
+
+    match existing_ancestor {
+        Some(ancestor) if !ancestor.is_dir() => {
+            warn(&format!(
+                "{:?} is a file, so {:?} can't be written inside it",
+                ancestor, destination
+            ));
+            false
+        }
+        Some(ancestor) if ancestor != parent_directory(destination) => {
+            message(&format!(
+                "{:?} doesn't exist yet – it'll be created when the file is decrypted",
+                parent_directory(destination)
+            ));
+            true
+        }
+        _ => true,
+    }
+}
+
+fn prompt_for_previous_file_resolution(
+    destination: &Path,
+    moved_to: Option<&Path>,
+) -> PreviousFileResolution {
+    warn(&format!(
+        "{:?} is no longer part of the configuration, but a decrypted copy was left behind",
+        destination
+    ));
+
+    match moved_to {
+        Some(moved_to) => {
+            let move_option = format!("Move it to {:?}", moved_to);
+            let options = [move_option.as_str(), "Delete it", "Keep it"];
+
+            match choose("What would you like to do?", &options) {
+                0 => PreviousFileResolution::Move,
+                1 => PreviousFileResolution::Delete,
+                _ => PreviousFileResolution::Keep,
+            }
+        }
+        None => match choose("What would you like to do?", &["Delete it", "Keep it"]) {
+            0 => PreviousFileResolution::Delete,
+            _ => PreviousFileResolution::Keep,
+        },
+    }
+}
+
+/// Change the project's name, making sure its keys can still be found afterwards
+///
+/// If the keys are stored under the old name, the user can choose to rename or copy them in `keys.json`. When there's
+/// nobody to ask, `key_name` is set to the old name instead, so the project keeps using the same keys.
+pub fn rename_project(
+    configuration: &mut Configuration,
+    project_name: String,
+    interactive: bool,
+) -> Result<(), ConfigureError> {
+    let key_name = configuration.key_name().to_string();
+    configuration.project_name = project_name;
+
+    let keys_would_be_lost = configuration.key_name.is_none()
+        && key_name != configuration.project_name
+        && has_keys_named(&key_name)?
+        && !has_keys_named(&configuration.project_name)?;
+
+    if !keys_would_be_lost {
+        return Ok(());
+    }
+
+    let resolution = if interactive {
+        prompt_for_key_rename_resolution(&key_name, &configuration.project_name)
+    } else {
+        KeyRenameResolution::KeepKeyName
+    };
+
+    match resolution {
+        KeyRenameResolution::Rename => rename_keys(&key_name, &configuration.project_name, false),
+        KeyRenameResolution::Copy => rename_keys(&key_name, &configuration.project_name, true),
+        KeyRenameResolution::KeepKeyName => {
+            info!("Keeping {:?} as this project's key name", key_name);
+            configuration.key_name = Some(key_name);
+            Ok(())
+        }
+    }
+}
+
+fn prompt_for_key_rename_resolution(key_name: &str, project_name: &str) -> KeyRenameResolution {
+    warn(&format!(
+        "This project's keys are stored under {:?} in keys.json – without them, its files can't be decrypted",
+        key_name
+    ));
+
+    let rename_option = format!("Rename them to {:?}", project_name);
+    let copy_option = format!(
+        "Copy them to {:?}, keeping {:?} too",
+        project_name, key_name
+    );
+    let keep_option = format!("Keep using {:?} as this project's key name", key_name);
+    let options = [
+        rename_option.as_str(),
+        copy_option.as_str(),
+        keep_option.as_str(),
+    ];
+
+    match choose("What would you like to do?", &options) {
+        0 => KeyRenameResolution::Rename,
+        1 => KeyRenameResolution::Copy,
+        _ => KeyRenameResolution::KeepKeyName,
+    }
+}
+
+fn prompt_for_conflict_resolution(
+    destination: &Path,
+    local_contents: &[u8],
+    new_contents: &[u8],
+) -> ConflictResolution {
+    warn(&format!(
+        "{:?} has been modified since it was last applied",
+        destination
+    ));
+
+    let options = [
+        "Overwrite it (a backup will be kept)",
+        "Keep my local changes",
+        "View the differences",
+    ];
+
+    loop {
+        match choose("What would you like to do?", &options) {
+            0 => return ConflictResolution::Overwrite,
+            1 => return ConflictResolution::Keep,
+            _ => message(&render_diff(local_contents, new_contents)),
+        }
+    }
+}
+
+fn render_diff(local_contents: &[u8], new_contents: &[u8]) -> String {
+    match (
+        std::str::from_utf8(local_contents),
+        std::str::from_utf8(new_contents),
+    ) {
+        (Ok(local), Ok(new)) => TextDiff::from_lines(local, new)
+            .unified_diff()
+            .header("local", "secrets")
+            .to_string(),
+        _ => "Binary files differ".to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // Import the parent scope
@@ -536,7 +2419,7 @@ mod tests {
 
     #[test]
     fn test_that_invalid_configuration_cannot_be_deseralized() {
-        assert!(Configuration::from_str("".to_string()).is_err())
+        assert!("".parse::<Configuration>().is_err())
     }
 
     #[test]
@@ -544,6 +2427,310 @@ mod tests {
         assert!(Configuration::default().to_string().is_ok())
     }
 
+    #[test]
+    fn test_that_add_file_rejects_duplicate_destinations() {
+        let mut configuration = Configuration::default();
+        configuration
+            .add_file(test_file("a.json", "app/a.json"))
+            .unwrap();
+        assert!(matches!(
+            configuration.add_file(test_file("b.json", "app/a.json")),
+            Err(ConfigureError::DestinationAlreadyConfigured { .. })
+        ));
+        assert_eq!(configuration.files_to_copy.len(), 1);
+    }
+
+    #[test]
+    fn test_that_remove_file_removes_by_destination() {
+        let mut configuration = Configuration::default();
+        configuration
+            .add_file(test_file("a.json", "app/a.json"))
+            .unwrap();
+        configuration
+            .add_file(test_file("b.json", "app/b.json"))
+            .unwrap();
+
+        let removed = configuration.remove_file("app/a.json").unwrap();
+        assert_eq!(removed.source, "a.json");
+        assert_eq!(
+            configuration.files_to_copy,
+            vec![test_file("b.json", "app/b.json")]
+        );
+    }
+
+    #[test]
+    fn test_that_remove_file_at_rejects_out_of_range_index() {
+        let mut configuration = Configuration::default();
+        configuration
+            .add_file(test_file("a.json", "app/a.json"))
+            .unwrap();
+        assert!(configuration.remove_file_at(1).is_err());
+        assert!(configuration.remove_file_at(0).is_ok());
+    }
+
+    #[test]
+    fn test_that_the_builder_puts_a_configuration_together() {
+        let configuration = Configuration::builder()
+            .project_name("example")
+            .branch("trunk")
+            .add_file(File::new("example/secrets.properties", "secrets.properties").unwrap())
+            .profile(
+                "ci",
+                Profile {
+                    files: vec!["secrets.properties".to_string()],
+                    key_name: None,
+                },
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(configuration.project_name, "example");
+        assert_eq!(configuration.branch, "trunk");
+        assert_eq!(
+            configuration.files_to_copy,
+            vec![test_file(
+                "example/secrets.properties",
+                "secrets.properties"
+            )]
+        );
+        assert!(configuration.profiles.contains_key("ci"));
+    }
+
+    #[test]
+    fn test_that_the_builder_reports_the_first_problem() {
+        let result = Configuration::builder()
+            .project_name("example")
+            .branch("not a..branch")
+            .pinned_hash("abc123")
+            .build();
+
+        assert!(matches!(
+            result,
+            Err(ConfigureError::ConfigurationValueNotValid { field, .. }) if field == "branch"
+        ));
+
+        assert!(matches!(
+            Configuration::builder().project_name("example").build(),
+            Err(ConfigureError::ConfigurationValueNotValid { field, .. }) if field == "branch"
+        ));
+
+        assert!(matches!(
+            Configuration::builder()
+                .project_name("example")
+                .branch("trunk")
+                .profile(
+                    "ci",
+                    Profile {
+                        files: vec!["missing.json".to_string()],
+                        key_name: None
+                    }
+                )
+                .build(),
+            Err(ConfigureError::ProfileFileNotConfigured { .. })
+        ));
+    }
+
+    #[test]
+    fn test_that_files_need_a_source_and_destination() {
+        assert!(File::new("", "app/a.json").is_err());
+        assert!(File::new("a.json", "  ").is_err());
+        assert!(
+            File::directory("certificates", "app/certificates")
+                .unwrap()
+                .is_directory
+        );
+
+        let mut configuration = Configuration::default();
+        assert!(configuration.add_file(test_file("a.json", "")).is_err());
+    }
+
+    #[test]
+    fn test_that_pinned_hashes_must_be_full_commit_hashes() {
+        let mut configuration = Configuration::default();
+
+        assert!(configuration.set_pinned_hash("abc123").is_err());
+        assert!(configuration.set_pinned_hash(&"g".repeat(40)).is_err());
+        configuration
+            .set_pinned_hash("0123456789ABCDEF0123456789abcdef01234567")
+            .unwrap();
+        assert_eq!(
+            configuration.pinned_hash,
+            "0123456789abcdef0123456789abcdef01234567"
+        );
+    }
+
+    fn test_file(source: &str, destination: &str) -> File {
+        File {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_that_deterministic_encryption_is_off_unless_configured() {
+        let configuration: Configuration =
+            r#"{"project_name":"a","branch":"b","pinned_hash":"c","files_to_copy":[]}"#
+                .parse()
+                .unwrap();
+        assert!(!configuration.deterministic_encryption);
+        assert!(!configuration
+            .to_string()
+            .contains("deterministic_encryption"));
+    }
+
+    #[test]
+    fn test_that_extending_configurations_inherit_what_they_leave_out() {
+        let parent = r#"{"project_name":"WordPress","branch":"trunk","pinned_hash":"abc","files_to_copy":[],"deterministic_encryption":true}"#;
+        let child = r#"{"extends":"../.configure","files_to_copy":[{"file":"a.json","destination":"a.json"}]}"#;
+
+        let mut configuration =
+            Configuration::from_str_extending(child.to_string(), parent.to_string()).unwrap();
+        assert_eq!(configuration.project_name, "WordPress");
+        assert_eq!(configuration.pinned_hash, "abc");
+        assert!(configuration.deterministic_encryption);
+        assert_eq!(configuration.files_to_copy.len(), 1);
+
+        // Only the fields that differ from the parent are written back
+        let serialized = configuration.to_string();
+        assert!(!serialized.contains("project_name"));
+        assert!(!serialized.contains("pinned_hash"));
+
+        configuration.pinned_hash = "def".to_string();
+        configuration.deterministic_encryption = false;
+        let serialized = configuration.to_string();
+        assert!(serialized.contains("\"pinned_hash\": \"def\""));
+        assert!(serialized.contains("\"deterministic_encryption\": false"));
+
+        let reread = Configuration::from_str_extending(serialized, parent.to_string()).unwrap();
+        assert_eq!(reread, configuration);
+    }
+
+    #[test]
+    fn test_that_configurations_survive_a_round_trip() {
+        let mut configuration = Configuration::builder()
+            .project_name("example")
+            .branch("trunk")
+            .pinned_hash("0123456789abcdef0123456789abcdef01234567")
+            .add_file(File::new("example/secrets.properties", "secrets.properties").unwrap())
+            .record_provenance(true)
+            .build()
+            .unwrap();
+        configuration.files_to_copy[0].expires = NaiveDate::from_ymd_opt(2030, 1, 31);
+        configuration.files_to_copy[0].processors = vec!["template".to_string()];
+
+        assert_eq!(
+            configuration.to_string().parse::<Configuration>().unwrap(),
+            configuration
+        );
+
+        let value = serde_json::to_value(&configuration).unwrap();
+        assert_eq!(
+            serde_json::from_value::<Configuration>(value).unwrap(),
+            configuration
+        );
+    }
+
+    #[test]
+    fn test_that_included_fragments_are_merged_but_not_written_back() {
+        let mut configuration = Configuration::builder()
+            .project_name("example")
+            .branch("trunk")
+            .add_file(test_file("example/analytics.json", "app/analytics.json"))
+            .build()
+            .unwrap();
+        configuration.include = vec!["shared.json".to_string(), "more.json".to_string()];
+
+        let fragment = |files: Vec<File>| ConfigurationFragment {
+            files_to_copy: files,
+            ..Default::default()
+        };
+
+        configuration
+            .merge_included(vec![
+                (
+                    "shared.json".to_string(),
+                    fragment(vec![
+                        test_file("shared/analytics.json", "app/analytics.json"),
+                        test_file("shared/sentry.properties", "app/sentry.properties"),
+                    ]),
+                ),
+                (
+                    "more.json".to_string(),
+                    fragment(vec![test_file(
+                        "shared/sentry.properties",
+                        "app/sentry.properties",
+                    )]),
+                ),
+            ])
+            .unwrap();
+
+        // The configuration's own file wins, and a file both fragments have is only added once
+        assert_eq!(
+            configuration.files_to_copy,
+            vec![
+                test_file("example/analytics.json", "app/analytics.json"),
+                test_file("shared/sentry.properties", "app/sentry.properties"),
+            ]
+        );
+
+        let written = configuration.to_string();
+        assert!(written.contains("\"include\""));
+        assert!(!written.contains("sentry.properties"));
+
+        assert!(matches!(
+            configuration.merge_included(vec![(
+                "other.json".to_string(),
+                fragment(vec![test_file(
+                    "other/sentry.properties",
+                    "app/sentry.properties"
+                )]),
+            )]),
+            Err(ConfigureError::IncludeConflict { .. })
+        ));
+    }
+
+    #[test]
+    fn test_that_update_plans_list_what_changes() {
+        let mut plan = UpdatePlan {
+            previous_branch: "trunk".to_string(),
+            branch: "trunk".to_string(),
+            previous_pinned_hash: "0123456789abcdef".to_string(),
+            pinned_hash: "0123456789abcdef".to_string(),
+            ..Default::default()
+        };
+        assert!(plan.is_empty());
+
+        plan.branch = "release/1.2".to_string();
+        plan.pinned_hash = "fedcba9876543210".to_string();
+        plan.changed_files = vec!["app/a.json".to_string(), "app/b.json".to_string()];
+        plan.removed_files = vec!["c.json.enc".to_string()];
+        assert!(!plan.is_empty());
+
+        assert_eq!(
+            plan.to_string(),
+            "Branch:        trunk → release/1.2\n\
+             Pinned hash:   0123456 → fedcba9\n\
+             Re-encrypted:  app/a.json, app/b.json\n\
+             Removed:       c.json.enc\n"
+        );
+    }
+
+    #[test]
+    fn test_that_key_name_defaults_to_project_name() {
+        let mut configuration = Configuration {
+            project_name: "WordPress-iOS".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(configuration.key_name(), "WordPress-iOS");
+        assert!(!configuration.to_string().contains("key_name"));
+
+        configuration.key_name = Some("WordPress".to_string());
+        assert_eq!(configuration.key_name(), "WordPress");
+    }
+
     #[test]
     fn test_that_default_configuration_is_empty() {
         assert!(Configuration::default().is_empty())
@@ -554,6 +2741,9 @@ mod tests {
         let file = File {
             source: "".to_string(),
             destination: ".configure-files/file".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
         };
         assert_eq!(
             Path::new(&file.get_encrypted_destination())
@@ -568,6 +2758,9 @@ mod tests {
         let file = File {
             source: "".to_string(),
             destination: ".configure-files/file.txt".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
         };
         assert_eq!(
             Path::new(&file.get_encrypted_destination())
@@ -583,6 +2776,9 @@ mod tests {
         let file = File {
             source: "".to_string(),
             destination: "foo/bar/file".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
         };
         assert_eq!(
             Path::new(&file.get_encrypted_destination())
@@ -599,15 +2795,66 @@ mod tests {
         let file = File {
             source: "".to_string(),
             destination: ".configure-files/file".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
         };
         assert_eq!(file.get_decrypted_destination(), ".configure-files/file")
     }
 
+    #[test]
+    fn test_that_sources_changed_by_matches_files_and_directories() {
+        let configuration = Configuration {
+            files_to_copy: vec![
+                File {
+                    source: "android/secrets.json".to_string(),
+                    destination: "secrets.json".to_string(),
+                    is_directory: false,
+                    expires: None,
+                    processors: Vec::new(),
+                },
+                File {
+                    source: "ios/certificates/".to_string(),
+                    destination: "certificates".to_string(),
+                    is_directory: true,
+                    expires: None,
+                    processors: Vec::new(),
+                },
+                File {
+                    source: "web/secrets.json".to_string(),
+                    destination: "web.json".to_string(),
+                    is_directory: false,
+                    expires: None,
+                    processors: Vec::new(),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let commit = CommitSummary {
+            hash: "0123456789".to_string(),
+            subject: "Rotate the certificates".to_string(),
+            changed_paths: vec![
+                "android/secrets.json".to_string(),
+                "ios/certificates/dist.p12".to_string(),
+                "web/secrets.json.old".to_string(),
+            ],
+        };
+
+        assert_eq!(
+            sources_changed_by(&configuration, &commit),
+            vec!["android/secrets.json", "ios/certificates/"]
+        );
+    }
+
     #[test]
     fn test_that_get_backup_destination_has_bak_extension() {
         let file = File {
             source: "".to_string(),
             destination: ".configure-files/file".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
         };
         assert_eq!(file.get_backup_destination().extension().unwrap(), "bak")
     }
@@ -617,6 +2864,9 @@ mod tests {
         let file = File {
             source: "".to_string(),
             destination: "/.configure-files/file.txt".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
         };
         assert_eq!(
             file.get_backup_destination_for_date(get_zero_date()),
@@ -624,11 +2874,55 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_that_get_backup_destination_works_for_files_without_a_directory() {
+        let file = File {
+            source: "".to_string(),
+            destination: "file.txt".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        };
+        assert_eq!(
+            file.get_backup_destination_for_date(get_zero_date()),
+            Path::new("file-1970-01-01-00-00-00.txt.bak")
+        )
+    }
+
+    #[test]
+    fn test_that_backup_dates_are_read_from_backup_file_names() {
+        let file = File {
+            source: "".to_string(),
+            destination: "config/secrets.json".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        };
+        let backup = file.get_backup_destination_for_date(get_zero_date());
+
+        assert_eq!(
+            file.backup_date_from_file_name(backup.file_name().unwrap().to_str().unwrap()),
+            Some(get_zero_date().naive_utc())
+        );
+        assert_eq!(
+            file.backup_date_from_file_name("secrets-1970-01-01-00-00-00.yml.bak"),
+            None
+        );
+        assert_eq!(
+            file.backup_date_from_file_name("secrets-old.json.bak"),
+            None
+        );
+        assert_eq!(file.backup_date_from_file_name("secrets.json"), None);
+    }
+
     #[test]
     fn test_that_get_backup_destination_works_for_files_without_extension() {
         let file = File {
             source: "".to_string(),
             destination: ".configure-files/file".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
         };
         assert_eq!(
             file.get_backup_destination_for_date(get_zero_date()),
@@ -636,6 +2930,262 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_that_conflict_policy_can_be_parsed() {
+        assert_eq!(
+            "keep".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::Keep
+        );
+        assert!("sometimes".parse::<ConflictPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_that_non_interactive_conflicts_overwrite_by_default() {
+        let options = ApplyOptions::default();
+        assert_eq!(
+            options
+                .resolve_conflict(Path::new("file"), b"local", b"new")
+                .unwrap(),
+            ConflictResolution::Overwrite
+        )
+    }
+
+    #[test]
+    fn test_that_fail_conflict_policy_returns_error() {
+        let options = ApplyOptions {
+            interactive: true,
+            conflict_policy: Some(ConflictPolicy::Fail),
+            ..Default::default()
+        };
+        assert!(options
+            .resolve_conflict(Path::new("file"), b"local", b"new")
+            .is_err())
+    }
+
+    #[test]
+    fn test_that_render_diff_shows_changed_lines() {
+        let diff = render_diff(b"a\nb\n", b"a\nc\n");
+        assert!(diff.contains("-b") && diff.contains("+c"))
+    }
+
+    #[test]
+    fn test_that_render_diff_does_not_print_binary_contents() {
+        assert_eq!(render_diff(&[0xff, 0xfe], b"text"), "Binary files differ")
+    }
+
+    #[test]
+    fn test_that_conflicting_destinations_are_found() {
+        let file = |source: &str, destination: &str| File {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        };
+        let configuration = Configuration {
+            files_to_copy: vec![
+                file("a/google-services.json", "app/google-services.json"),
+                file("b/google-services.json", "wear/google-services.json"),
+                file("config", "config"),
+                file("secrets.json", "config/secrets.json"),
+                file("everything", ".configure-files"),
+            ],
+            ..Default::default()
+        };
+
+        let conflicts: Vec<(usize, usize, DestinationConflictKind)> = configuration
+            .destination_conflicts()
+            .iter()
+            .map(|conflict| (conflict.first, conflict.second, conflict.kind))
+            .collect();
+
+        assert!(conflicts.contains(&(0, 1, DestinationConflictKind::SameEncryptedDestination)));
+        assert!(!conflicts.contains(&(1, 0, DestinationConflictKind::SameEncryptedDestination)));
+        assert!(conflicts.contains(&(2, 3, DestinationConflictKind::ContainsDestination)));
+        assert!(conflicts.contains(&(4, 0, DestinationConflictKind::ContainsEncryptedDestination)));
+        assert!(configuration.check_destinations().is_err());
+    }
+
+    #[test]
+    fn test_that_files_that_would_conflict_are_not_added() {
+        let mut configuration = Configuration::default();
+        configuration
+            .add_file(File {
+                source: "a/secrets.json".to_string(),
+                destination: "app/secrets.json".to_string(),
+                is_directory: false,
+                expires: None,
+                processors: Vec::new(),
+            })
+            .unwrap();
+
+        assert!(matches!(
+            configuration.add_file(File {
+                source: "b/secrets.json".to_string(),
+                destination: "wear/secrets.json".to_string(),
+                is_directory: false,
+                expires: None,
+                processors: Vec::new(),
+            }),
+            Err(ConfigureError::DestinationsConflict { .. })
+        ));
+        assert_eq!(configuration.files_to_copy.len(), 1);
+        assert!(configuration.check_destinations().is_ok());
+    }
+
+    #[test]
+    fn test_that_files_near_or_past_their_expiry_are_found() {
+        let file = |destination: &str, expires: Option<NaiveDate>| File {
+            source: destination.to_string(),
+            destination: destination.to_string(),
+            is_directory: false,
+            expires,
+            processors: Vec::new(),
+        };
+
+        let configuration = Configuration {
+            files_to_copy: vec![
+                file("push.p12", Some(NaiveDate::from_ymd(2024, 3, 1))),
+                file("api.properties", Some(NaiveDate::from_ymd(2024, 3, 20))),
+                file("distant.p12", Some(NaiveDate::from_ymd(2025, 1, 1))),
+                file("google-services.json", None),
+            ],
+            ..Default::default()
+        };
+
+        let expiring = configuration.expiring_files(NaiveDate::from_ymd(2024, 3, 10));
+        assert_eq!(expiring.len(), 2);
+        assert_eq!(expiring[0].to_string(), "push.p12 expired on 2024-03-01");
+        assert_eq!(
+            expiring[1].to_string(),
+            "api.properties expires on 2024-03-20, in 10 day(s)"
+        );
+    }
+
+    #[test]
+    fn test_that_expiry_dates_are_read_from_the_configure_file() {
+        let file: File = serde_json::from_str(
+            r#"{ "file": "ios/push.p12", "destination": "push.p12", "expires": "2024-03-01" }"#,
+        )
+        .unwrap();
+
+        assert_eq!(file.expires, Some(NaiveDate::from_ymd(2024, 3, 1)));
+        assert!(!serde_json::to_string(&File {
+            expires: None,
+            processors: Vec::new(),
+            ..file
+        })
+        .unwrap()
+        .contains("expires"));
+    }
+
+    #[test]
+    fn test_that_profiles_select_their_files_and_key() {
+        let file = |destination: &str| File {
+            source: format!("android/{}", destination),
+            destination: destination.to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        };
+
+        let mut profiles = BTreeMap::new();
+        profiles.insert(
+            "ci".to_string(),
+            Profile {
+                files: vec!["app/google-services.json".to_string()],
+                key_name: Some("example-ci".to_string()),
+            },
+        );
+        profiles.insert(
+            "broken".to_string(),
+            Profile {
+                files: vec!["app/missing.json".to_string()],
+                key_name: None,
+            },
+        );
+
+        let configuration = Configuration {
+            project_name: "example".to_string(),
+            files_to_copy: vec![
+                file("app/google-services.json"),
+                file("app/release.keystore"),
+            ],
+            profiles,
+            ..Default::default()
+        };
+
+        let ci = configuration.for_profile("ci").unwrap();
+        assert_eq!(ci.files_to_copy, vec![file("app/google-services.json")]);
+        assert_eq!(ci.key_name(), "example-ci");
+
+        assert!(matches!(
+            configuration.for_profile("broken"),
+            Err(ConfigureError::ProfileFileNotConfigured { .. })
+        ));
+        assert!(matches!(
+            configuration.for_profile("release-manager"),
+            Err(ConfigureError::ProfileNotFound { .. })
+        ));
+        assert!(configuration.check_profiles().is_err());
+    }
+
+    #[test]
+    fn test_that_picked_files_get_sensible_default_destinations() {
+        let suggestions = [SuggestedFile {
+            platform: Platform::Android,
+            source: "android/WordPress/google-services.json".to_string(),
+            destination: "WordPress/google-services.json".to_string(),
+        }];
+
+        assert_eq!(
+            default_destination(
+                "android/WordPress/google-services.json",
+                "WordPress",
+                &suggestions
+            ),
+            "WordPress/google-services.json"
+        );
+        assert_eq!(
+            default_destination("android/wordpress/config/secrets.json", "WordPress", &[]),
+            "config/secrets.json"
+        );
+        assert_eq!(
+            default_destination("shared/sentry.properties", "WordPress", &[]),
+            "sentry.properties"
+        );
+        assert_eq!(
+            default_destination("WordPress", "WordPress", &[]),
+            "WordPress"
+        );
+    }
+
+    #[test]
+    fn test_that_destinations_cannot_be_written_inside_files() {
+        let root =
+            std::env::temp_dir().join(format!("configure-destination-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        std::fs::write(root.join("README.md"), "").unwrap();
+
+        assert!(destination_can_be_written(
+            &root.join("app/secrets.json"),
+            false
+        ));
+        assert!(destination_can_be_written(
+            &root.join("config/secrets.json"),
+            false
+        ));
+        assert!(!destination_can_be_written(
+            &root.join("README.md/secrets.json"),
+            false
+        ));
+        assert!(!destination_can_be_written(&root.join("app"), false));
+        assert!(destination_can_be_written(&root.join("app"), true));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
     fn get_zero_date() -> DateTime<Utc> {
         Utc.timestamp(0, 0)
     }
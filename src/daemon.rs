@@ -0,0 +1,746 @@
+//! A long-lived server for build tools, so they don't start a new process (and set up encryption) for every step
+//!
+//! Requests and responses are single lines of JSON on a Unix domain socket, or a named pipe on Windows.
+
+use crate::cancel::CancellationToken;
+use crate::configure::try_update_configuration;
+use crate::fs::{read_configuration_from_file, status_for_configuration};
+use crate::{ApplyOptions, ConfigureError, ConflictPolicy};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long the daemon waits for a request before shutting itself down, unless told otherwise
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A single request sent to the daemon, as one line of JSON
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    Apply {
+        /// The directory the request should run in – usually the project root
+        working_directory: Option<String>,
+        configuration_file_path: Option<String>,
+        #[serde(default)]
+        conflict_policy: Option<ConflictPolicy>,
+        #[serde(default)]
+        force_decrypt: bool,
+    },
+    Update {
+        working_directory: Option<String>,
+        configuration_file_path: Option<String>,
+    },
+    Status {
+        working_directory: Option<String>,
+        configuration_file_path: Option<String>,
+    },
+    Stop,
+}
+
+/// The daemon's reply to a request, as one line of JSON
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DaemonResponse {
+    pub ok: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// The error's stable code (see `ConfigureError::code`), when the request failed with one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+}
+
+impl DaemonResponse {
+    fn success(result: Option<serde_json::Value>) -> DaemonResponse {
+        DaemonResponse {
+            ok: true,
+            result,
+            error: None,
+            code: None,
+        }
+    }
+
+    fn failure(error: String) -> DaemonResponse {
+        DaemonResponse {
+            ok: false,
+            result: None,
+            error: Some(error),
+            code: None,
+        }
+    }
+}
+
+impl From<ConfigureError> for DaemonResponse {
+    fn from(err: ConfigureError) -> DaemonResponse {
+        DaemonResponse {
+            code: Some(err.code()),
+            ..DaemonResponse::failure(crate::redact::redact(&err.to_string()).into_owned())
+        }
+    }
+}
+
+/// The socket used when the caller doesn't provide one – one per user, in a directory only that user can write to
+pub fn default_socket_path() -> PathBuf {
+    platform::default_socket_path()
+}
+
+/// Run a single request, sending its error back in the response – one bad request mustn't stop the daemon
+fn handle_request(request: DaemonRequest) -> DaemonResponse {
+    // Requests may change directory, so put things back afterwards for the next one
+    let original_directory = std::env::current_dir().ok();
+
+    // Nothing should panic, but a panic would take down every client's daemon, so it's caught just in case
+    let result = std::panic::catch_unwind(
+        move || -> Result<Option<serde_json::Value>, DaemonResponse> {
+            match request {
+                DaemonRequest::Apply {
+                    working_directory,
+                    configuration_file_path,
+                    conflict_policy,
+                    force_decrypt,
+                } => {
+                    change_directory(working_directory)?;
+                    crate::apply_cancellable(
+                        configuration_file_path,
+                        ApplyOptions {
+                            interactive: false,
+                            conflict_policy,
+                            force_decrypt,
+                            ..Default::default()
+                        },
+                    )?;
+                    Ok(None)
+                }
+                DaemonRequest::Update {
+                    working_directory,
+                    configuration_file_path,
+                } => {
+                    change_directory(working_directory)?;
+                    crate::encryption::init()?;
+                    try_update_configuration(
+                        configuration_file_path,
+                        false,
+                        false,
+                        true,
+                        None,
+                        &CancellationToken::new(),
+                    )?;
+                    Ok(None)
+                }
+                DaemonRequest::Status {
+                    working_directory,
+                    configuration_file_path,
+                } => {
+                    change_directory(working_directory)?;
+                    let configuration = read_configuration_from_file(&configuration_file_path)?;
+                    let status = status_for_configuration(&configuration)?;
+                    serde_json::to_value(status)
+                        .map(Some)
+                        .map_err(|err| DaemonResponse::failure(err.to_string()))
+                }
+                DaemonRequest::Stop => Ok(None),
+            }
+        },
+    );
+
+    if let Some(directory) = original_directory {
+        let _ = std::env::set_current_dir(directory);
+    }
+
+    match result {
+        Ok(Ok(result)) => DaemonResponse::success(result),
+        Ok(Err(response)) => response,
+        Err(panic) => DaemonResponse::failure(panic_message(panic)),
+    }
+}
+
+fn change_directory(working_directory: Option<String>) -> Result<(), DaemonResponse> {
+    match working_directory {
+        Some(directory) => std::env::set_current_dir(&directory).map_err(|err| {
+            DaemonResponse::failure(format!(
+                "Unable to change to directory {:?}: {}",
+                directory, err
+            ))
+        }),
+        None => Ok(()),
+    }
+}
+
+// Responses often end up in an IDE's logs, so they're redacted like any other message
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        crate::redact::redact(message).into_owned()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        crate::redact::redact(message).into_owned()
+    } else {
+        "The request failed".to_string()
+    }
+}
+
+fn handle_line(line: &str) -> (DaemonResponse, bool) {
+    match serde_json::from_str::<DaemonRequest>(line) {
+        Ok(DaemonRequest::Stop) => (DaemonResponse::success(None), true),
+        Ok(request) => {
+            debug!("Handling daemon request: {:?}", request);
+            (handle_request(request), false)
+        }
+        Err(err) => (
+            DaemonResponse::failure(format!("Invalid request: {}", err)),
+            false,
+        ),
+    }
+}
+
+/// What every connection shares with the loop accepting them
+struct DaemonState {
+    // Requests change the process's working directory, so only one runs at a time
+    requests: Mutex<()>,
+    last_activity: Mutex<Instant>,
+    stopping: AtomicBool,
+}
+
+impl DaemonState {
+    fn new() -> DaemonState {
+        DaemonState {
+            requests: Mutex::new(()),
+            last_activity: Mutex::new(Instant::now()),
+            stopping: AtomicBool::new(false),
+        }
+    }
+
+    fn handle(&self, line: &str) -> (DaemonResponse, bool) {
+        let result = {
+            let _request = self.requests.lock().unwrap_or_else(PoisonError::into_inner);
+            handle_line(line)
+        };
+
+        self.record_activity();
+        result
+    }
+
+    fn record_activity(&self) {
+        *self
+            .last_activity
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+    }
+
+    fn is_idle(&self, idle_timeout: Duration) -> bool {
+        self.last_activity
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .elapsed()
+            >= idle_timeout
+    }
+
+    fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+}
+
+/// Serve every request on a connection until the client hangs up or asks the daemon to stop
+///
+/// Each connection gets its own thread, so a client that keeps its connection open between requests (like a Gradle
+/// daemon) doesn't hold up the others.
+fn serve<R: Read, W: Write>(reader: R, mut writer: W, state: &DaemonState) -> std::io::Result<()> {
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (response, should_stop) = state.handle(&line);
+        write_response(&mut writer, &response)?;
+
+        // Only once the response is written, so the client hears back before the daemon exits
+        if should_stop {
+            state.stop();
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_response<W: Write>(writer: &mut W, response: &DaemonResponse) -> std::io::Result<()> {
+    let json = serde_json::to_string(response)?;
+    writer.write_all(json.as_bytes())?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::*;
+    use std::fs::DirBuilder;
+    use std::io::ErrorKind;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    // `/tmp` is shared by every user, so the socket lives in the user's runtime directory (or their home directory)
+    pub fn default_socket_path() -> PathBuf {
+        let directory = match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(path) if !path.is_empty() => PathBuf::from(path).join("configure"),
+            _ => dirs::home_dir()
+                .expect("Unable to determine user home directory")
+                .join(".configure-daemon"),
+        };
+
+        directory.join("daemon.sock")
+    }
+
+    /// Make sure nobody else can replace the socket – if another user could, they could stop the daemon from starting,
+    /// or pretend to be it and read every request. The directory is created (only accessible to the current user) if
+    /// it doesn't exist yet.
+    pub(super) fn secure_socket_directory(socket_path: &Path) -> std::io::Result<()> {
+        let directory = match socket_path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+
+        match DirBuilder::new().mode(0o700).create(directory) {
+            Err(err) if err.kind() != ErrorKind::AlreadyExists => return Err(err),
+            _ => {}
+        }
+
+        let metadata = std::fs::symlink_metadata(directory)?;
+        if !metadata.is_dir() || metadata.uid() != current_user() || metadata.mode() & 0o022 != 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "The socket must be in a directory only you can write to, which {:?} isn't",
+                    directory
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_socket_owner(socket_path: &Path) -> std::io::Result<()> {
+        if std::fs::symlink_metadata(socket_path)?.uid() != current_user() {
+            return Err(std::io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("The socket at {:?} belongs to another user", socket_path),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn current_user() -> u32 {
+        unsafe { libc::getuid() }
+    }
+
+    pub fn run(socket_path: &Path, idle_timeout: Duration) -> std::io::Result<()> {
+        secure_socket_directory(socket_path)?;
+
+        if std::fs::symlink_metadata(socket_path).is_ok() {
+            check_socket_owner(socket_path)?;
+
+            if UnixStream::connect(socket_path).is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("A daemon is already listening at {:?}", socket_path),
+                ));
+            }
+
+            debug!("Removing stale socket at {:?}", socket_path);
+            std::fs::remove_file(socket_path)?;
+        }
+
+        let listener = UnixListener::bind(socket_path)?;
+        listener.set_nonblocking(true)?;
+
+        info!("Listening at {:?}", socket_path);
+
+        let state = Arc::new(DaemonState::new());
+
+        while !state.is_stopping() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    state.record_activity();
+
+                    let state = Arc::clone(&state);
+                    std::thread::spawn(move || {
+                        if let Err(err) = serve_stream(stream, &state) {
+                            warn!("Unable to serve daemon client: {}", err);
+                        }
+                    });
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                    if state.is_idle(idle_timeout) {
+                        info!("No requests for {:?} – shutting down", idle_timeout);
+                        break;
+                    }
+
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        std::fs::remove_file(socket_path)?;
+        Ok(())
+    }
+
+    fn serve_stream(stream: UnixStream, state: &DaemonState) -> std::io::Result<()> {
+        // Accepted sockets can inherit the listener's non-blocking mode on some platforms
+        stream.set_nonblocking(false)?;
+        serve(stream.try_clone()?, stream, state)
+    }
+
+    pub fn send(socket_path: &Path, request: &str) -> std::io::Result<DaemonResponse> {
+        secure_socket_directory(socket_path)?;
+        check_socket_owner(socket_path)?;
+
+        let mut stream = UnixStream::connect(socket_path)?;
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(b"\n")?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::*;
+    use std::fs::{File, OpenOptions};
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+    use std::path::Path;
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::sync::Arc;
+    use windows_sys::Win32::Foundation::{ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX, SECURITY_IDENTIFICATION,
+    };
+    use windows_sys::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+    const BUFFER_SIZE: u32 = 64 * 1024;
+
+    // Pipe names are shared by every user, so the user's name is part of it
+    pub fn default_socket_path() -> PathBuf {
+        let user = std::env::var("USERNAME").unwrap_or_else(|_| "default".to_string());
+        PathBuf::from(format!(r"\\.\pipe\configure-{}", user))
+    }
+
+    /// Create an instance of the pipe for the next client to connect to
+    ///
+    /// The first instance must create the pipe, so the daemon never joins a pipe someone else created. The pipe's
+    /// default security only lets the user who created it (and administrators) send requests.
+    fn create_instance(name: &[u16], first: bool) -> std::io::Result<File> {
+        let mut open_mode = PIPE_ACCESS_DUPLEX;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                std::ptr::null(),
+            )
+        };
+
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(unsafe { File::from_raw_handle(handle) })
+    }
+
+    /// Wait for a client to connect to `pipe`
+    fn connect(pipe: &File) -> std::io::Result<()> {
+        if unsafe { ConnectNamedPipe(pipe.as_raw_handle(), std::ptr::null_mut()) } != 0 {
+            return Ok(());
+        }
+
+        // The client may have connected before the daemon started waiting, which is fine
+        let err = std::io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(code) if code as u32 == ERROR_PIPE_CONNECTED => Ok(()),
+            _ => Err(err),
+        }
+    }
+
+    pub fn run(socket_path: &Path, idle_timeout: Duration) -> std::io::Result<()> {
+        let name: Vec<u16> = socket_path
+            .as_os_str()
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+
+        let pipe = create_instance(&name, true).map_err(|err| match err.kind() {
+            std::io::ErrorKind::PermissionDenied => std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("Something is already listening at {:?}", socket_path),
+            ),
+            _ => err,
+        })?;
+
+        info!("Listening at {:?}", socket_path);
+
+        let state = Arc::new(DaemonState::new());
+
+        // Waiting for a client blocks, so that happens on its own thread while this one watches for the timeout
+        let (sender, accept_result) = channel();
+        {
+            let state = Arc::clone(&state);
+            std::thread::spawn(move || {
+                let _ = sender.send(accept_clients(pipe, &name, &state));
+            });
+        }
+
+        while !state.is_stopping() {
+            match accept_result.recv_timeout(POLL_INTERVAL) {
+                Ok(result) => return result,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            if state.is_idle(idle_timeout) {
+                info!("No requests for {:?} – shutting down", idle_timeout);
+                state.stop();
+            }
+        }
+
+        // Connecting wakes up the thread waiting for clients, so it can close the pipe
+        let _ = OpenOptions::new().read(true).write(true).open(socket_path);
+
+        Ok(())
+    }
+
+    fn accept_clients(
+        mut pipe: File,
+        name: &[u16],
+        state: &Arc<DaemonState>,
+    ) -> std::io::Result<()> {
+        loop {
+            connect(&pipe)?;
+
+            if state.is_stopping() {
+                return Ok(());
+            }
+
+            state.record_activity();
+
+            let client = std::mem::replace(&mut pipe, create_instance(name, false)?);
+            let state = Arc::clone(state);
+            std::thread::spawn(move || {
+                let result = client
+                    .try_clone()
+                    .and_then(|reader| serve(reader, &client, &state));
+
+                if let Err(err) = result {
+                    warn!("Unable to serve daemon client: {}", err);
+                }
+            });
+        }
+    }
+
+    pub fn send(socket_path: &Path, request: &str) -> std::io::Result<DaemonResponse> {
+        // Whoever created the pipe may only identify the client, not act on its behalf
+        let mut pipe = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .security_qos_flags(SECURITY_IDENTIFICATION)
+            .open(socket_path)?;
+
+        pipe.write_all(request.as_bytes())?;
+        pipe.write_all(b"\n")?;
+
+        let mut line = String::new();
+        BufReader::new(pipe).read_line(&mut line)?;
+
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+/// Serve requests on `socket_path` until asked to stop or until `idle_timeout` passes without a request
+pub fn run(socket_path: &std::path::Path, idle_timeout: Duration) -> std::io::Result<()> {
+    platform::run(socket_path, idle_timeout)
+}
+
+/// Ask the daemon listening on `socket_path` to shut down
+pub fn stop(socket_path: &std::path::Path) -> std::io::Result<DaemonResponse> {
+    platform::send(socket_path, r#"{"command":"stop"}"#)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_apply_request_can_be_parsed() {
+        let request: DaemonRequest = serde_json::from_str(
+            r#"{"command":"apply","working_directory":"/tmp","configuration_file_path":null,"conflict_policy":"keep"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            request,
+            DaemonRequest::Apply {
+                working_directory: Some("/tmp".to_string()),
+                configuration_file_path: None,
+                conflict_policy: Some(ConflictPolicy::Keep),
+                force_decrypt: false,
+            }
+        )
+    }
+
+    #[test]
+    fn test_that_stop_request_stops_the_daemon() {
+        let (response, should_stop) = handle_line(r#"{"command":"stop"}"#);
+        assert!(response.ok);
+        assert!(should_stop);
+    }
+
+    #[test]
+    fn test_that_invalid_request_returns_error_response() {
+        let (response, should_stop) = handle_line(r#"{"command":"explode"}"#);
+        assert!(!response.ok);
+        assert!(!should_stop);
+    }
+
+    #[test]
+    fn test_that_a_failed_request_is_reported_without_stopping_the_daemon() {
+        let path = std::env::temp_dir()
+            .join("configure-daemon-test-missing")
+            .join(".configure");
+        let request = serde_json::json!({
+            "command": "apply",
+            "configuration_file_path": path,
+        });
+
+        let (response, should_stop) = handle_line(&request.to_string());
+        assert!(!response.ok);
+        assert!(!should_stop);
+        assert_eq!(
+            response.code,
+            Some(ConfigureError::ConfigureFileNotReadable { path }.code())
+        );
+    }
+
+    #[test]
+    fn test_that_panics_are_reported_as_error_messages() {
+        let panic =
+            std::panic::catch_unwind(|| panic!("Unable to read configuration")).unwrap_err();
+        assert_eq!(panic_message(panic), "Unable to read configuration");
+    }
+
+    #[test]
+    fn test_that_the_stop_response_is_sent_before_stopping() {
+        let state = DaemonState::new();
+        let mut output = Vec::new();
+
+        serve(&b"{\"command\":\"stop\"}\n"[..], &mut output, &state).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"ok\":true}\n");
+        assert!(state.is_stopping());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_that_an_idle_client_does_not_hold_up_other_clients() {
+        let directory = std::env::temp_dir().join(format!(
+            "configure-daemon-test-clients-{}",
+            std::process::id()
+        ));
+        let socket_path = directory.join("daemon.sock");
+
+        let daemon = {
+            let socket_path = socket_path.clone();
+            std::thread::spawn(move || run(&socket_path, Duration::from_secs(30)))
+        };
+
+        // The first client connects as soon as the daemon is listening, and then says nothing
+        let mut idle_client = None;
+        for _ in 0..100 {
+            match std::os::unix::net::UnixStream::connect(&socket_path) {
+                Ok(stream) => {
+                    idle_client = Some(stream);
+                    break;
+                }
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        assert!(idle_client.is_some());
+        assert!(stop(&socket_path).unwrap().ok);
+
+        daemon.join().unwrap().unwrap();
+        std::fs::remove_dir(&directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_that_default_socket_path_is_not_in_temp_directory() {
+        assert!(!default_socket_path().starts_with(std::env::temp_dir()))
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_that_socket_directory_is_created_for_the_current_user_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory =
+            std::env::temp_dir().join(format!("configure-daemon-test-new-{}", std::process::id()));
+        platform::secure_socket_directory(&directory.join("daemon.sock")).unwrap();
+
+        let mode = std::fs::metadata(&directory).unwrap().permissions().mode();
+        std::fs::remove_dir(&directory).unwrap();
+
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_that_socket_directory_others_can_write_to_is_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = std::env::temp_dir().join(format!(
+            "configure-daemon-test-shared-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::set_permissions(&directory, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+        let result = platform::secure_socket_directory(&directory.join("daemon.sock"));
+        std::fs::remove_dir(&directory).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+    }
+}
@@ -1,24 +1,236 @@
+use crate::timings::{measure, Phase};
 use crate::ConfigureError;
 use log::debug;
-use sodiumoxide::base64::Variant;
-use sodiumoxide::base64::{decode, encode};
-use sodiumoxide::crypto::secretbox;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fs::{read, write};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use zeroize::{Zeroize, Zeroizing};
+
+// libsodium can't be compiled to WebAssembly, so browser builds use a pure Rust implementation of the same primitives
+#[cfg(not(target_arch = "wasm32"))]
+mod libsodium;
+#[cfg(not(target_arch = "wasm32"))]
+use libsodium as backend;
+
+#[cfg(target_arch = "wasm32")]
+mod rust_crypto;
+#[cfg(target_arch = "wasm32")]
+use rust_crypto as backend;
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 24;
+/// The Poly1305 tag at the start of every encrypted payload – even empty contents encrypt to at least this much
+const TAG_SIZE: usize = 16;
+const SALT_SIZE: usize = 16;
+
+/// Marks a file encrypted with a passphrase-derived key – the header also holds the salt and derivation parameters
+const PASSPHRASE_MAGIC: &[u8; 8] = b"CFGPASS1";
+const PASSPHRASE_HEADER_SIZE: usize = PASSPHRASE_MAGIC.len() + 4 + 4 + SALT_SIZE;
+
+/// Marks a file that starts with provenance metadata – the rest of the file is in one of the formats above
+const PROVENANCE_MAGIC: &[u8; 8] = b"CFGPROV1";
+const PROVENANCE_MAC_SIZE: usize = NONCE_SIZE;
+
+/// The largest provenance a file may claim to have, so a crafted file can't make us allocate too much
+const MAX_PROVENANCE_SIZE: usize = 64 * 1024;
+
+/// The most expensive parameters a file's header may ask for, so a crafted file can't exhaust memory
+const MAX_OPS_LIMIT: u32 = 16;
+const MAX_MEM_LIMIT_KIB: u32 = 1024 * 1024;
+
+static INITIALIZED: OnceLock<bool> = OnceLock::new();
+
+/// Set up the encryption library
+///
+/// This happens the first time anything needs it, so there's no need to call it first – doing so just finds out
+/// early whether it works. Trying again after a failure doesn't help, so the first result is kept.
+pub fn init() -> Result<(), ConfigureError> {
+    let initialized = *INITIALIZED.get_or_init(|| {
+        debug!("Initializing the encryption library");
+        backend::init()
+    });
+
+    match initialized {
+        true => Ok(()),
+        false => Err(ConfigureError::EncryptionNotAvailable),
+    }
+}
+
+/// The name and version of the library doing the encryption, like `libsodium 1.0.18`
+pub fn library_version() -> String {
+    backend::library_version()
+}
+
+/// Where an encrypted file came from
+///
+/// This is kept in plain text ahead of the encrypted data, so it can be read without the key. It's authenticated with
+/// the key, together with the rest of the file, so changing it makes the file impossible to decrypt.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The file's path in the secrets repo
+    pub source: String,
+
+    /// The secrets repo commit the file was encrypted from
+    pub commit: String,
+
+    /// When the file was encrypted, as an RFC 3339 timestamp
+    pub encrypted_at: String,
+}
+
+/// Put `provenance` at the start of an encrypted file
+///
+/// Only files encrypted with a key can have provenance – a passphrase derives a different key for every file, so
+/// there's no single key to authenticate it with.
+pub fn add_provenance(
+    encrypted_bytes: &[u8],
+    provenance: &Provenance,
+    key: &EncryptionKey,
+) -> Result<Vec<u8>, ConfigureError> {
+    init()?;
+
+    let key = match &key.secret {
+        Secret::Key(key) => key,
+        Secret::Passphrase(_) => return Err(ConfigureError::DataEncryptionError),
+    };
+
+    let metadata = match serde_json::to_vec(provenance) {
+        Ok(metadata) => metadata,
+        Err(_) => return Err(ConfigureError::DataEncryptionError),
+    };
+
+    // Provenance byte layout:
+    // |==============|=================|==================|==================|=================|
+    // | 0          7 | 8            11 | 12             n | n + 1     n + 24 | n + 25        ∞ |
+    // |==============|=================|==================|==================|=================|
+    // |  "CFGPROV1"  |   length (LE)   |  metadata (JSON) |       MAC        | encrypted file  |
+    // |==============|=================|==================|==================|=================|
+    let length = (metadata.len() as u32).to_le_bytes();
+    let mac = provenance_mac(key, &length, &metadata, encrypted_bytes);
+
+    Ok([
+        &PROVENANCE_MAGIC[..],
+        &length,
+        &metadata,
+        &mac,
+        encrypted_bytes,
+    ]
+    .concat())
+}
+
+/// The provenance at the start of an encrypted file, if it has any
+///
+/// This doesn't need the key, so it isn't checked – that happens when the file is decrypted.
+pub fn read_provenance(input: &[u8]) -> Option<Provenance> {
+    let (provenance, _) = split_provenance(input).ok()?;
+    serde_json::from_slice(provenance?.metadata).ok()
+}
+
+/// An encrypted file's provenance, split from the rest of it
+struct ProvenanceHeader<'a> {
+    length: &'a [u8],
+    metadata: &'a [u8],
+    mac: &'a [u8],
+}
+
+/// Split the provenance (if there is any) from the encrypted data that follows it
+fn split_provenance(input: &[u8]) -> Result<(Option<ProvenanceHeader>, &[u8]), ConfigureError> {
+    if !input.starts_with(PROVENANCE_MAGIC) {
+        return Ok((None, input));
+    }
+
+    let metadata_start = PROVENANCE_MAGIC.len() + 4;
+    if input.len() < metadata_start {
+        return Err(ConfigureError::EncryptedDataTruncated);
+    }
+
+    let length = &input[PROVENANCE_MAGIC.len()..metadata_start];
+    let mut length_bytes = [0; 4];
+    length_bytes.copy_from_slice(length);
+    let metadata_length = u32::from_le_bytes(length_bytes) as usize;
+
+    if metadata_length > MAX_PROVENANCE_SIZE {
+        return Err(ConfigureError::EncryptedDataCorrupt);
+    }
+
+    let metadata_end = metadata_start + metadata_length;
+    if input.len() < metadata_end + PROVENANCE_MAC_SIZE {
+        return Err(ConfigureError::EncryptedDataTruncated);
+    }
 
-pub fn init() {
-    sodiumoxide::init().expect("Unable to initialize libsodium");
+    let header = ProvenanceHeader {
+        length,
+        metadata: &input[metadata_start..metadata_end],
+        mac: &input[metadata_end..metadata_end + PROVENANCE_MAC_SIZE],
+    };
+
+    Ok((Some(header), &input[metadata_end + PROVENANCE_MAC_SIZE..]))
+}
+
+fn provenance_mac(
+    key: &backend::Key,
+    length: &[u8],
+    metadata: &[u8],
+    encrypted_bytes: &[u8],
+) -> [u8; PROVENANCE_MAC_SIZE] {
+    backend::keyed_hash(
+        key,
+        b"configure-provenance",
+        &[length, metadata, encrypted_bytes].concat(),
+    )
 }
 
-pub fn generate_key() -> EncryptionKey {
+pub fn generate_key() -> Result<EncryptionKey, ConfigureError> {
     debug!("Generating an encryption key");
+    init()?;
 
-    // It shouldn't be possible for this to failure, except in very weird
-    // circumstances (like if the system's entropy source is exhausted). For that reason, we don't
-    // use a `Result` type here.
-    let key_bytes = secretbox::gen_key();
-    EncryptionKey::from_str(&encode_key(&key_bytes)).expect("Unable to generate new encryption key")
+    Ok(EncryptionKey {
+        secret: Secret::Key(backend::generate_key()),
+    })
+}
+
+/// How expensive it is to derive a key from a passphrase – higher values make guessing the passphrase slower
+///
+/// These are stored in each encrypted file's header, so changing them doesn't affect files that already exist.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PassphraseParameters {
+    /// The number of passes Argon2id makes over its memory
+    pub ops_limit: u32,
+
+    /// How much memory Argon2id uses, in KiB
+    pub mem_limit_kib: u32,
+}
+
+impl Default for PassphraseParameters {
+    // libsodium's "interactive" limits – every file has its own salt, so this cost is paid once per file
+    fn default() -> Self {
+        PassphraseParameters {
+            ops_limit: 2,
+            mem_limit_kib: 64 * 1024,
+        }
+    }
+}
+
+impl PassphraseParameters {
+    fn is_valid(&self) -> bool {
+        (1..=MAX_OPS_LIMIT).contains(&self.ops_limit)
+            && (8..=MAX_MEM_LIMIT_KIB).contains(&self.mem_limit_kib)
+    }
+}
+
+/// How the nonce for an encrypted file is chosen
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NonceMode {
+    /// A new random nonce every time – encrypting the same contents twice gives different output
+    Random,
+
+    /// A nonce derived from a keyed hash of the contents, so unchanged contents always encrypt to the same bytes
+    ///
+    /// This keeps `.enc` files stable in git. The trade-off is that anyone who can see two encrypted files can tell
+    /// whether their contents are identical – nothing else about the contents is revealed.
+    Deterministic,
 }
 
 pub fn encrypt_file(
@@ -28,14 +240,45 @@ pub fn encrypt_file(
 ) -> Result<(), ConfigureError> {
     let file_contents = match read(input_path) {
         Ok(file_contents) => file_contents,
-        Err(_err) => return Err(ConfigureError::InputFileNotReadable),
+        Err(_err) => {
+            return Err(ConfigureError::InputFileNotReadable {
+                path: input_path.to_path_buf(),
+            })
+        }
     };
 
-    let encrypted_bytes = encrypt_bytes(&file_contents, key);
+    encrypt_bytes_to_file(&file_contents, output_path, key, NonceMode::Random)
+}
 
-    match write(&output_path, encrypted_bytes) {
+/// Encrypt `bytes` that are already in memory, writing the result to `output_path`
+pub fn encrypt_bytes_to_file(
+    bytes: &[u8],
+    output_path: &Path,
+    key: &EncryptionKey,
+    nonce_mode: NonceMode,
+) -> Result<(), ConfigureError> {
+    match write(
+        output_path,
+        encrypt_bytes_with_mode(bytes, key, nonce_mode)?,
+    ) {
         Ok(()) => Ok(()),
-        Err(_err) => Err(ConfigureError::OutputFileNotWritable),
+        Err(_err) => Err(ConfigureError::OutputFileNotWritable {
+            path: output_path.to_path_buf(),
+        }),
+    }
+}
+
+/// Encrypt `bytes`, choosing the nonce according to `nonce_mode`
+pub fn encrypt_bytes_with_mode(
+    bytes: &[u8],
+    key: &EncryptionKey,
+    nonce_mode: NonceMode,
+) -> Result<Vec<u8>, ConfigureError> {
+    init()?;
+
+    match nonce_mode {
+        NonceMode::Random => encrypt_bytes(bytes, key),
+        NonceMode::Deterministic => encrypt_bytes_with_nonce(bytes, key, derive_nonce(bytes, key)),
     }
 }
 
@@ -44,30 +287,155 @@ pub fn decrypt_file(
     output_path: &Path,
     key: &EncryptionKey,
 ) -> Result<(), ConfigureError> {
+    let decrypted_bytes = decrypt_file_contents(input_path, key)?;
+
+    match write(output_path, decrypted_bytes) {
+        Ok(()) => Ok(()),
+        Err(_err) => Err(ConfigureError::OutputFileNotWritable {
+            path: output_path.to_path_buf(),
+        }),
+    }
+}
+
+/// Decrypt the file at `input_path` into memory without writing it anywhere
+pub fn decrypt_file_contents(
+    input_path: &Path,
+    key: &EncryptionKey,
+) -> Result<Vec<u8>, ConfigureError> {
+    let (decrypted_bytes, _) =
+        decrypt_file_contents_with_keys(input_path, std::slice::from_ref(key))?;
+    Ok(decrypted_bytes)
+}
+
+/// Decrypt the file at `input_path` with the first of `keys` that works, returning the index of that key too
+pub fn decrypt_file_contents_with_keys(
+    input_path: &Path,
+    keys: &[EncryptionKey],
+) -> Result<(Vec<u8>, usize), ConfigureError> {
     let file_contents = match read(input_path) {
         Ok(file_contents) => file_contents,
-        Err(_err) => return Err(ConfigureError::InputFileNotReadable),
+        Err(_err) => {
+            return Err(ConfigureError::InputFileNotReadable {
+                path: input_path.to_path_buf(),
+            })
+        }
     };
 
-    let decrypted_bytes = match decrypt_bytes(&file_contents, key) {
-        Ok(decrypted_bytes) => decrypted_bytes,
-        Err(_err) => return Err(ConfigureError::DataDecryptionError),
-    };
+    decrypt_bytes_with_keys(&file_contents, keys)
+}
 
-    match write(&output_path, decrypted_bytes) {
-        Ok(()) => Ok(()),
-        Err(_err) => Err(ConfigureError::OutputFileNotWritable),
+/// Encrypt `input` with a random nonce
+pub fn encrypt_bytes(input: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, ConfigureError> {
+    init()?;
+    encrypt_bytes_with_nonce(input, key, backend::generate_nonce())
+}
+
+fn encrypt_bytes_with_nonce(
+    input: &[u8],
+    key: &EncryptionKey,
+    nonce: [u8; NONCE_SIZE],
+) -> Result<Vec<u8>, ConfigureError> {
+    match &key.secret {
+        Secret::Key(key) => Ok([&nonce[..], &seal_bytes(input, &nonce, key)].concat()),
+        Secret::Passphrase(secret) => {
+            // Argon2id can need a lot of memory, so this can fail
+            let header = PassphraseHeader::new(secret.parameters);
+            let derived_key = header
+                .derive_key(&secret.passphrase)
+                .map_err(|_| ConfigureError::DataEncryptionError)?;
+
+            Ok([
+                &header.to_bytes()[..],
+                &nonce[..],
+                &seal_bytes(input, &nonce, &derived_key),
+            ]
+            .concat())
+        }
     }
 }
 
-fn encrypt_bytes(input: &[u8], key: &EncryptionKey) -> Vec<u8> {
-    let nonce = secretbox::gen_nonce();
-    let secret_bytes = secretbox::seal(input, &nonce, &key.key);
+fn seal_bytes(input: &[u8], nonce: &[u8; NONCE_SIZE], key: &backend::Key) -> Vec<u8> {
+    measure(Phase::Encryption, || backend::seal(input, nonce, key))
+}
 
-    [&nonce[..], &secret_bytes].concat()
+/// Derive a nonce from the contents being encrypted, using a keyed BLAKE2b hash
+///
+/// The hash input is prefixed with a fixed label so these hashes can't be confused with any other use of the key.
+fn derive_nonce(input: &[u8], key: &EncryptionKey) -> [u8; NONCE_SIZE] {
+    match &key.secret {
+        Secret::Key(key) => backend::keyed_hash(key, b"configure-deterministic-nonce", input),
+        // Every passphrase-encrypted file gets a new salt, so its output can't be repeatable anyway
+        Secret::Passphrase(_) => backend::generate_nonce(),
+    }
 }
 
-fn decrypt_bytes(input: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, ConfigureError> {
+/// Decrypt the contents of an `.enc` file
+pub fn decrypt_bytes(input: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, ConfigureError> {
+    init()?;
+
+    let (provenance, input) = split_provenance(input)?;
+
+    if let Some(provenance) = provenance {
+        let is_authentic = match &key.secret {
+            Secret::Key(key) => {
+                let mac = provenance_mac(key, provenance.length, provenance.metadata, input);
+                constant_time_eq(&mac, provenance.mac)
+            }
+            Secret::Passphrase(_) => false,
+        };
+
+        if !is_authentic {
+            return Err(ConfigureError::WrongDecryptionKey);
+        }
+    }
+
+    match &key.secret {
+        // A file encrypted with a passphrase can't be decrypted with a key, however intact it is
+        Secret::Key(_) if input.starts_with(PASSPHRASE_MAGIC) => {
+            Err(ConfigureError::WrongDecryptionKey)
+        }
+        Secret::Key(key) => open_bytes(input, key),
+        Secret::Passphrase(secret) => {
+            // Passphrase-encrypted files start with a header:
+            // |==============|=================|=================|==================|=================|
+            // | 0          7 | 8            11 | 12           15 | 16            31 | 32            ∞ |
+            // |==============|=================|=================|==================|=================|
+            // |  "CFGPASS1"  | ops limit (LE)  | mem limit (KiB) |       salt       |  nonce + data   |
+            // |==============|=================|=================|==================|=================|
+            let header = PassphraseHeader::from_bytes(input)?;
+            let derived_key = header.derive_key(&secret.passphrase)?;
+
+            open_bytes(&input[PASSPHRASE_HEADER_SIZE..], &derived_key)
+        }
+    }
+}
+
+/// Decrypt `input` with the first of `keys` that works, returning the index of that key along with the contents
+///
+/// `.enc` files don't record which key encrypted them, so each key is tried in turn. Using the wrong one fails
+/// authentication rather than producing garbage, so this can't pick the wrong key. A damaged file fails the same way
+/// whichever key is used, so that's reported straight away.
+pub fn decrypt_bytes_with_keys(
+    input: &[u8],
+    keys: &[EncryptionKey],
+) -> Result<(Vec<u8>, usize), ConfigureError> {
+    let mut last_error = ConfigureError::WrongDecryptionKey;
+
+    for (index, key) in keys.iter().enumerate() {
+        match decrypt_bytes(input, key) {
+            Ok(decrypted_bytes) => return Ok((decrypted_bytes, index)),
+            Err(
+                err @ (ConfigureError::EncryptedDataTruncated
+                | ConfigureError::EncryptedDataCorrupt),
+            ) => return Err(err),
+            Err(err) => last_error = err,
+        }
+    }
+
+    Err(last_error)
+}
+
+fn open_bytes(input: &[u8], key: &backend::Key) -> Result<Vec<u8>, ConfigureError> {
     // Encoded Format byte layout:
     // |======================================|=====================================|
     // | 0                                 23 | 24                                ∞ |
@@ -75,70 +443,260 @@ fn decrypt_bytes(input: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, Configure
     // |                nonce                 |           encrypted data            |
     // |======================================|=====================================|
 
-    const NONCE_SIZE: usize = 24;
+    if input.len() < NONCE_SIZE + TAG_SIZE {
+        return Err(ConfigureError::EncryptedDataTruncated);
+    }
 
     // Read the nonce bytes
-    let mut nonce_bytes: [u8; NONCE_SIZE] = Default::default();
-    nonce_bytes.copy_from_slice(&input[0..NONCE_SIZE]);
-    let nonce = sodiumoxide::crypto::secretbox::Nonce(nonce_bytes);
+    let mut nonce: [u8; NONCE_SIZE] = Default::default();
+    nonce.copy_from_slice(&input[0..NONCE_SIZE]);
 
     // Read the encrypted data bytes
     let data_bytes = &input[NONCE_SIZE..];
 
-    let decrypted_bytes = match secretbox::open(data_bytes, &nonce, &key.key) {
-        Ok(decrypted_bytes) => decrypted_bytes,
-        Err(_) => return Err(ConfigureError::DataDecryptionError),
-    };
+    // The payload is long enough, so it failed authentication – either it's the wrong key or the file was modified
+    match measure(Phase::Decryption, || backend::open(data_bytes, &nonce, key)) {
+        Some(decrypted_bytes) => Ok(decrypted_bytes),
+        None => Err(ConfigureError::WrongDecryptionKey),
+    }
+}
 
-    Ok(decrypted_bytes)
+/// Compare secrets without the time taken revealing where they differ – only whether their lengths match
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
 }
 
-fn encode_key(key: &sodiumoxide::crypto::secretbox::Key) -> String {
-    encode(key, Variant::Original)
+fn encode_key(key: &backend::Key) -> String {
+    base64::encode(backend::key_bytes(key))
 }
 
 fn decode_key(key: &str) -> Result<EncryptionKey, ConfigureError> {
-    match decode(key.trim(), Variant::Original) {
+    match base64::decode(key.trim()).map(Zeroizing::new) {
         Ok(decoded_key) => {
-            if decoded_key.len() != 32 {
+            if decoded_key.len() != KEY_SIZE {
                 return Err(ConfigureError::DecryptionKeyParsingError);
             }
 
-            let mut key_bytes: [u8; 32] = Default::default();
+            let mut key_bytes: [u8; KEY_SIZE] = Default::default();
             key_bytes.copy_from_slice(&decoded_key);
-            let raw_key = sodiumoxide::crypto::secretbox::Key(key_bytes);
 
-            Ok(EncryptionKey { key: raw_key })
+            let key = backend::key_from_bytes(key_bytes);
+            key_bytes.zeroize();
+
+            Ok(EncryptionKey {
+                secret: Secret::Key(key),
+            })
         }
         Err(_err) => Err(ConfigureError::DecryptionKeyEncodingError),
     }
 }
 
+/// The salt and parameters needed to derive a file's key from a passphrase
+struct PassphraseHeader {
+    parameters: PassphraseParameters,
+    salt: [u8; SALT_SIZE],
+}
+
+impl PassphraseHeader {
+    fn new(parameters: PassphraseParameters) -> PassphraseHeader {
+        PassphraseHeader {
+            parameters,
+            salt: backend::generate_salt(),
+        }
+    }
+
+    fn from_bytes(input: &[u8]) -> Result<PassphraseHeader, ConfigureError> {
+        // Without the header, this file was encrypted with a key rather than a passphrase
+        if !input.starts_with(PASSPHRASE_MAGIC) {
+            return Err(ConfigureError::WrongDecryptionKey);
+        }
+
+        if input.len() < PASSPHRASE_HEADER_SIZE {
+            return Err(ConfigureError::EncryptedDataTruncated);
+        }
+
+        let read_u32 = |offset: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&input[offset..offset + 4]);
+            u32::from_le_bytes(bytes)
+        };
+
+        let parameters = PassphraseParameters {
+            ops_limit: read_u32(8),
+            mem_limit_kib: read_u32(12),
+        };
+
+        if !parameters.is_valid() {
+            return Err(ConfigureError::EncryptedDataCorrupt);
+        }
+
+        let mut salt = [0; SALT_SIZE];
+        salt.copy_from_slice(&input[16..PASSPHRASE_HEADER_SIZE]);
+
+        Ok(PassphraseHeader { parameters, salt })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        [
+            &PASSPHRASE_MAGIC[..],
+            &self.parameters.ops_limit.to_le_bytes(),
+            &self.parameters.mem_limit_kib.to_le_bytes(),
+            &self.salt,
+        ]
+        .concat()
+    }
+
+    fn derive_key(&self, passphrase: &str) -> Result<backend::Key, ConfigureError> {
+        debug!("Deriving a key from a passphrase");
+
+        match measure(Phase::KeyDerivation, || {
+            backend::derive_key(
+                passphrase.as_bytes(),
+                &self.salt,
+                self.parameters.ops_limit,
+                self.parameters.mem_limit_kib,
+            )
+        }) {
+            Some(key) => Ok(key),
+            None => Err(ConfigureError::DataDecryptionError),
+        }
+    }
+}
+
+/// A passphrase, and the parameters used when encrypting with it
+///
+/// The passphrase is wiped from memory when this is dropped.
+struct PassphraseSecret {
+    passphrase: Zeroizing<String>,
+    parameters: PassphraseParameters,
+}
+
+impl PartialEq for PassphraseSecret {
+    fn eq(&self, other: &PassphraseSecret) -> bool {
+        constant_time_eq(self.passphrase.as_bytes(), other.passphrase.as_bytes())
+            && self.parameters == other.parameters
+    }
+}
+
+impl Eq for PassphraseSecret {}
+
+impl fmt::Debug for PassphraseSecret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PassphraseSecret")
+            .field("passphrase", &"****")
+            .field("parameters", &self.parameters)
+            .finish()
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Secret {
+    Key(backend::Key),
+
+    /// A new key is derived for every file, using the salt in its header
+    Passphrase(PassphraseSecret),
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct EncryptionKey {
-    pub key: sodiumoxide::crypto::secretbox::Key,
+    secret: Secret,
 }
 
 impl std::fmt::Display for EncryptionKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let string_key = encode_key(&self.key);
-        f.write_str(&string_key)
+        match &self.secret {
+            Secret::Key(key) => f.write_str(&encode_key(key)),
+            // Passphrases are never written anywhere, so there's nothing useful to show
+            Secret::Passphrase(_) => f.write_str("(passphrase)"),
+        }
+    }
+}
+
+/// Parse a base64-encoded key
+impl FromStr for EncryptionKey {
+    type Err = ConfigureError;
+
+    fn from_str(encryption_key: &str) -> Result<Self, Self::Err> {
+        decode_key(encryption_key)
+    }
+}
+
+/// Keys are serialized as the same base64 string `Display` gives, so they can be kept in JSON or TOML
+///
+/// Passphrases can't be – they're never written anywhere.
+impl Serialize for EncryptionKey {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.secret {
+            Secret::Key(key) => serializer.serialize_str(&encode_key(key)),
+            Secret::Passphrase(_) => {
+                Err(serde::ser::Error::custom("passphrases can't be serialized"))
+            }
+        }
     }
 }
 
-impl From<sodiumoxide::crypto::secretbox::Key> for EncryptionKey {
-    fn from(raw_key: sodiumoxide::crypto::secretbox::Key) -> EncryptionKey {
-        EncryptionKey { key: raw_key }
+impl<'de> Deserialize<'de> for EncryptionKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = Zeroizing::new(String::deserialize(deserializer)?);
+        encoded.parse().map_err(serde::de::Error::custom)
     }
 }
 
 impl EncryptionKey {
-    pub fn from_str(encryption_key: &str) -> Result<EncryptionKey, ConfigureError> {
-        match decode_key(encryption_key) {
-            Ok(encryption_key) => Ok(encryption_key as EncryptionKey),
-            Err(err) => Err(err),
+    /// A short identifier for this key that doesn't reveal it, for telling keys apart
+    ///
+    /// Passphrases don't have one – they derive a different key for every file.
+    pub fn fingerprint(&self) -> Option<String> {
+        match &self.secret {
+            Secret::Key(key) => {
+                let hash = backend::keyed_hash(key, b"configure-key-fingerprint", b"");
+                Some(
+                    hash[..8]
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect(),
+                )
+            }
+            Secret::Passphrase(_) => None,
         }
     }
+
+    /// Use a passphrase instead of a random key, with the default key derivation parameters
+    pub fn from_passphrase(passphrase: &str) -> Result<EncryptionKey, ConfigureError> {
+        EncryptionKey::from_passphrase_with_parameters(passphrase, PassphraseParameters::default())
+    }
+
+    /// Use a passphrase instead of a random key
+    ///
+    /// The parameters only apply when encrypting – decryption uses the ones in each file's header.
+    pub fn from_passphrase_with_parameters(
+        passphrase: &str,
+        parameters: PassphraseParameters,
+    ) -> Result<EncryptionKey, ConfigureError> {
+        if passphrase.is_empty() {
+            return Err(ConfigureError::PassphraseNotValid);
+        }
+
+        if !parameters.is_valid() {
+            return Err(ConfigureError::PassphraseParametersNotValid);
+        }
+
+        init()?;
+
+        // A passphrase can't be recognised in a log message by its shape, so remember to mask it
+        crate::redact::add_secret(passphrase);
+
+        Ok(EncryptionKey {
+            secret: Secret::Passphrase(PassphraseSecret {
+                passphrase: Zeroizing::new(passphrase.to_string()),
+                parameters,
+            }),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -147,21 +705,266 @@ mod tests {
     use super::*;
     use rand::prelude::*;
 
+    #[test]
+    fn test_that_init_can_be_called_more_than_once() {
+        assert!(init().is_ok());
+        assert!(init().is_ok());
+    }
+
+    #[test]
+    fn test_that_secrets_are_compared_by_value() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert_eq!(
+            passphrase_key("correct horse"),
+            passphrase_key("correct horse")
+        );
+        assert_ne!(
+            passphrase_key("correct horse"),
+            passphrase_key("battery staple")
+        );
+    }
+
     #[test]
     fn test_that_generate_key_generates_valid_key() {
-        assert!(decode_key(&generate_key().to_string()).is_ok())
+        assert!(decode_key(&generate_key().unwrap().to_string()).is_ok())
     }
 
     #[test]
     fn test_end_to_end_encryption() {
         let random_bytes = rand::thread_rng().gen::<[u8; 32]>().to_vec();
-        let key = generate_key();
-        let encrypted_bytes = encrypt_bytes(&random_bytes, &key);
+        let key = generate_key().unwrap();
+        let encrypted_bytes = encrypt_bytes(&random_bytes, &key).unwrap();
         let decrypted_bytes =
             decrypt_bytes(&encrypted_bytes, &key).expect("Decryption must succeed");
         assert_eq!(random_bytes, decrypted_bytes);
     }
 
+    #[test]
+    fn test_that_deterministic_encryption_is_repeatable() {
+        let key = generate_key().unwrap();
+        let first =
+            encrypt_bytes_with_nonce(b"secret", &key, derive_nonce(b"secret", &key)).unwrap();
+        let second =
+            encrypt_bytes_with_nonce(b"secret", &key, derive_nonce(b"secret", &key)).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(decrypt_bytes(&first, &key).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_that_deterministic_nonce_depends_on_contents_and_key() {
+        let key = generate_key().unwrap();
+        assert_ne!(
+            derive_nonce(b"secret", &key),
+            derive_nonce(b"secret2", &key)
+        );
+        assert_ne!(
+            derive_nonce(b"secret", &key),
+            derive_nonce(b"secret", &generate_key().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_that_random_encryption_differs_each_time() {
+        let key = generate_key().unwrap();
+        assert_ne!(
+            encrypt_bytes(b"secret", &key).unwrap(),
+            encrypt_bytes(b"secret", &key).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_that_decrypting_input_shorter_than_a_nonce_fails() {
+        assert!(decrypt_bytes(&[0; 10], &generate_key().unwrap()).is_err())
+    }
+
+    #[test]
+    fn test_that_decrypting_with_several_keys_uses_the_one_that_works() {
+        let old_key = generate_key().unwrap();
+        let encrypted_bytes = encrypt_bytes(b"secret", &old_key).unwrap();
+
+        let (decrypted_bytes, index) =
+            decrypt_bytes_with_keys(&encrypted_bytes, &[generate_key().unwrap(), old_key]).unwrap();
+
+        assert_eq!(decrypted_bytes, b"secret");
+        assert_eq!(index, 1);
+    }
+
+    #[test]
+    fn test_that_decrypting_fails_when_none_of_the_keys_work() {
+        let encrypted_bytes = encrypt_bytes(b"secret", &generate_key().unwrap()).unwrap();
+
+        assert!(matches!(
+            decrypt_bytes_with_keys(
+                &encrypted_bytes,
+                &[generate_key().unwrap(), generate_key().unwrap()]
+            ),
+            Err(ConfigureError::WrongDecryptionKey)
+        ));
+    }
+
+    #[test]
+    fn test_that_keys_survive_a_round_trip() {
+        let key = generate_key().unwrap();
+        assert_eq!(key.to_string().parse::<EncryptionKey>().unwrap(), key);
+
+        let json = serde_json::to_string(&key).unwrap();
+        assert_eq!(json, format!("\"{}\"", key));
+        assert_eq!(serde_json::from_str::<EncryptionKey>(&json).unwrap(), key);
+
+        assert!("not a key".parse::<EncryptionKey>().is_err());
+        assert!(serde_json::to_string(&passphrase_key("correct horse")).is_err());
+    }
+
+    #[test]
+    fn test_that_fingerprints_are_stable_and_differ_between_keys() {
+        let key = EncryptionKey::from_str("B6EeQVtVMBvtZQxEFruq8bUrlPqjtfYdxv2NpL18w1o=").unwrap();
+        let fingerprint = key.fingerprint().unwrap();
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(key.fingerprint(), Some(fingerprint.clone()));
+        assert_ne!(generate_key().unwrap().fingerprint(), Some(fingerprint));
+    }
+
+    // Cheap parameters, so the tests don't spend their time deriving keys
+    fn passphrase_key(passphrase: &str) -> EncryptionKey {
+        let parameters = PassphraseParameters {
+            ops_limit: 1,
+            mem_limit_kib: 8,
+        };
+        EncryptionKey::from_passphrase_with_parameters(passphrase, parameters).unwrap()
+    }
+
+    #[test]
+    fn test_end_to_end_passphrase_encryption() {
+        let encrypted_bytes = encrypt_bytes(b"secret", &passphrase_key("correct horse")).unwrap();
+        assert!(encrypted_bytes.starts_with(PASSPHRASE_MAGIC));
+        assert_eq!(
+            decrypt_bytes(&encrypted_bytes, &passphrase_key("correct horse")).unwrap(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    fn test_that_decrypting_with_the_wrong_passphrase_fails() {
+        let encrypted_bytes = encrypt_bytes(b"secret", &passphrase_key("correct horse")).unwrap();
+        assert!(decrypt_bytes(&encrypted_bytes, &passphrase_key("battery staple")).is_err());
+    }
+
+    #[test]
+    fn test_that_passphrase_encryption_uses_a_new_salt_each_time() {
+        let key = passphrase_key("correct horse");
+        let first = encrypt_bytes_with_mode(b"secret", &key, NonceMode::Deterministic).unwrap();
+        let second = encrypt_bytes_with_mode(b"secret", &key, NonceMode::Deterministic).unwrap();
+        assert_ne!(
+            first[..PASSPHRASE_HEADER_SIZE],
+            second[..PASSPHRASE_HEADER_SIZE]
+        );
+    }
+
+    #[test]
+    fn test_that_passphrase_headers_with_excessive_parameters_are_rejected() {
+        let mut encrypted_bytes =
+            encrypt_bytes(b"secret", &passphrase_key("correct horse")).unwrap();
+        encrypted_bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            decrypt_bytes(&encrypted_bytes, &passphrase_key("correct horse")),
+            Err(ConfigureError::EncryptedDataCorrupt)
+        ));
+    }
+
+    #[test]
+    fn test_that_truncated_files_are_reported_as_truncated() {
+        let key = generate_key().unwrap();
+        let encrypted_bytes = encrypt_bytes(b"secret", &key).unwrap();
+
+        for length in [0, 10, NONCE_SIZE, NONCE_SIZE + TAG_SIZE - 1] {
+            assert!(matches!(
+                decrypt_bytes(&encrypted_bytes[..length], &key),
+                Err(ConfigureError::EncryptedDataTruncated)
+            ));
+        }
+
+        let encrypted_bytes = encrypt_bytes(b"secret", &passphrase_key("correct horse")).unwrap();
+        assert!(matches!(
+            decrypt_bytes(&encrypted_bytes[..20], &passphrase_key("correct horse")),
+            Err(ConfigureError::EncryptedDataTruncated)
+        ));
+    }
+
+    #[test]
+    fn test_that_modified_files_fail_authentication() {
+        let key = generate_key().unwrap();
+        let mut encrypted_bytes = encrypt_bytes(b"secret", &key).unwrap();
+        let last = encrypted_bytes.len() - 1;
+        encrypted_bytes[last] ^= 1;
+
+        assert!(matches!(
+            decrypt_bytes(&encrypted_bytes, &key),
+            Err(ConfigureError::WrongDecryptionKey)
+        ));
+    }
+
+    #[test]
+    fn test_that_files_without_a_passphrase_header_are_rejected() {
+        let encrypted_bytes = encrypt_bytes(b"secret", &generate_key().unwrap()).unwrap();
+        assert!(decrypt_bytes(&encrypted_bytes, &passphrase_key("correct horse")).is_err());
+    }
+
+    #[test]
+    fn test_that_empty_passphrases_are_rejected() {
+        assert!(matches!(
+            EncryptionKey::from_passphrase(""),
+            Err(ConfigureError::PassphraseNotValid)
+        ));
+    }
+
+    fn test_provenance() -> Provenance {
+        Provenance {
+            source: "android/secrets.json".to_string(),
+            commit: "0123456789abcdef".to_string(),
+            encrypted_at: "2024-05-01T12:00:00+00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_that_files_with_provenance_can_be_decrypted() {
+        let key = generate_key().unwrap();
+        let encrypted_bytes = encrypt_bytes(b"secret", &key).unwrap();
+        let with_provenance = add_provenance(&encrypted_bytes, &test_provenance(), &key).unwrap();
+
+        assert_eq!(read_provenance(&with_provenance), Some(test_provenance()));
+        assert_eq!(read_provenance(&encrypted_bytes), None);
+        assert_eq!(decrypt_bytes(&with_provenance, &key).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_that_changed_provenance_fails_to_decrypt() {
+        let key = generate_key().unwrap();
+        let encrypted_bytes = encrypt_bytes(b"secret", &key).unwrap();
+        let mut provenance = test_provenance();
+        let with_provenance = add_provenance(&encrypted_bytes, &provenance, &key).unwrap();
+
+        provenance.commit = "fedcba9876543210".to_string();
+        let original_metadata = serde_json::to_vec(&test_provenance()).unwrap();
+        let changed_metadata = serde_json::to_vec(&provenance).unwrap();
+        let position = with_provenance
+            .windows(original_metadata.len())
+            .position(|window| window == original_metadata.as_slice())
+            .unwrap();
+
+        let mut tampered = with_provenance.clone();
+        tampered[position..position + changed_metadata.len()].copy_from_slice(&changed_metadata);
+
+        assert!(decrypt_bytes(&tampered, &key).is_err());
+    }
+
+    #[test]
+    fn test_that_passphrase_encrypted_files_cannot_have_provenance() {
+        let key = EncryptionKey::from_passphrase("correct horse").unwrap();
+        assert!(add_provenance(b"", &test_provenance(), &key).is_err());
+    }
+
     #[test]
     fn test_that_decode_key_succeeds_for_valid_key() {
         assert!(decode_key("B6EeQVtVMBvtZQxEFruq8bUrlPqjtfYdxv2NpL18w1o=").is_ok())
@@ -184,7 +987,7 @@ mod tests {
 
     #[test]
     fn test_that_decode_key_returns_exit_code_20() {
-        assert_eq!(decode_key("Invalid base64").unwrap_err() as i32, 19);
+        assert_eq!(decode_key("Invalid base64").unwrap_err().code(), 19);
     }
 
     #[test]
@@ -194,6 +997,6 @@ mod tests {
 
     #[test]
     fn test_that_decode_key_returns_exit_code_19() {
-        assert_eq!(decode_key("dGhpcyBpcyBhIHRlc3Q=").unwrap_err() as i32, 20)
+        assert_eq!(decode_key("dGhpcyBpcyBhIHRlc3Q=").unwrap_err().code(), 20)
     }
 }
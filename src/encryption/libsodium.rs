@@ -0,0 +1,77 @@
+use super::{KEY_SIZE, NONCE_SIZE, SALT_SIZE};
+use sodiumoxide::crypto::pwhash::argon2id13;
+use sodiumoxide::crypto::{generichash, secretbox};
+
+pub use sodiumoxide::crypto::secretbox::Key;
+
+/// Whether libsodium could be set up – it fails if there's no usable source of randomness
+pub fn init() -> bool {
+    sodiumoxide::init().is_ok()
+}
+
+/// The version of libsodium this was linked against
+pub fn library_version() -> String {
+    format!("libsodium {}", sodiumoxide::version::version_string())
+}
+
+pub fn generate_key() -> Key {
+    secretbox::gen_key()
+}
+
+pub fn key_from_bytes(bytes: [u8; KEY_SIZE]) -> Key {
+    Key(bytes)
+}
+
+pub fn key_bytes(key: &Key) -> &[u8] {
+    &key[..]
+}
+
+pub fn generate_nonce() -> [u8; NONCE_SIZE] {
+    secretbox::gen_nonce().0
+}
+
+pub fn generate_salt() -> [u8; SALT_SIZE] {
+    argon2id13::gen_salt().0
+}
+
+pub fn seal(message: &[u8], nonce: &[u8; NONCE_SIZE], key: &Key) -> Vec<u8> {
+    secretbox::seal(message, &secretbox::Nonce(*nonce), key)
+}
+
+pub fn open(ciphertext: &[u8], nonce: &[u8; NONCE_SIZE], key: &Key) -> Option<Vec<u8>> {
+    secretbox::open(ciphertext, &secretbox::Nonce(*nonce), key).ok()
+}
+
+/// A BLAKE2b hash of `label` followed by `input`, keyed with `key`
+pub fn keyed_hash(key: &Key, label: &[u8], input: &[u8]) -> [u8; NONCE_SIZE] {
+    let mut state = generichash::State::new(NONCE_SIZE, Some(&key[..]))
+        .expect("Nonce and key sizes are valid for BLAKE2b");
+    state.update(label).expect("Unable to hash file contents");
+    state.update(input).expect("Unable to hash file contents");
+
+    let digest = state.finalize().expect("Unable to hash file contents");
+
+    let mut hash = [0; NONCE_SIZE];
+    hash.copy_from_slice(digest.as_ref());
+    hash
+}
+
+/// An Argon2id key derived from `passphrase` – `None` if the memory couldn't be allocated
+pub fn derive_key(
+    passphrase: &[u8],
+    salt: &[u8; SALT_SIZE],
+    ops_limit: u32,
+    mem_limit_kib: u32,
+) -> Option<Key> {
+    let mut key = [0; KEY_SIZE];
+    argon2id13::derive_key(
+        &mut key,
+        passphrase,
+        &argon2id13::Salt(*salt),
+        argon2id13::OpsLimit(ops_limit as usize),
+        argon2id13::MemLimit(mem_limit_kib as usize * 1024),
+    )
+    .ok()?;
+
+    Some(Key(key))
+}
@@ -0,0 +1,111 @@
+//! A pure Rust implementation of the libsodium primitives the encryption module uses
+//!
+//! libsodium can't be built for `wasm32-unknown-unknown`, so this is used there instead. It reads and writes exactly
+//! the same bytes: `crypto_secretbox_easy` is XSalsa20-Poly1305 with the tag first, `crypto_generichash` with a
+//! key is keyed BLAKE2b, and `crypto_pwhash_argon2id` is Argon2id v1.3 with a single lane.
+
+use super::{KEY_SIZE, NONCE_SIZE, SALT_SIZE};
+use argon2::{Algorithm, Argon2, Params, Version};
+use blake2::digest::consts::U24;
+use blake2::digest::Mac;
+use blake2::Blake2bMac;
+use crypto_secretbox::aead::{Aead, KeyInit};
+use crypto_secretbox::XSalsa20Poly1305;
+use std::fmt;
+use zeroize::Zeroize;
+
+#[derive(Clone, Eq)]
+pub struct Key([u8; KEY_SIZE]);
+
+impl PartialEq for Key {
+    // Compare every byte, so the time taken doesn't reveal where the keys differ
+    fn eq(&self, other: &Key) -> bool {
+        super::constant_time_eq(&self.0, &other.0)
+    }
+}
+
+// Like libsodium's keys, wipe the key from memory once it's no longer needed
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Key(****)")
+    }
+}
+
+pub fn init() -> bool {
+    true
+}
+
+pub fn library_version() -> String {
+    "RustCrypto".to_string()
+}
+
+pub fn generate_key() -> Key {
+    let mut bytes = [0; KEY_SIZE];
+    getrandom::getrandom(&mut bytes).expect("Unable to generate an encryption key");
+    Key(bytes)
+}
+
+pub fn key_from_bytes(bytes: [u8; KEY_SIZE]) -> Key {
+    Key(bytes)
+}
+
+pub fn key_bytes(key: &Key) -> &[u8] {
+    &key.0
+}
+
+pub fn generate_nonce() -> [u8; NONCE_SIZE] {
+    let mut bytes = [0; NONCE_SIZE];
+    getrandom::getrandom(&mut bytes).expect("Unable to generate a nonce");
+    bytes
+}
+
+pub fn generate_salt() -> [u8; SALT_SIZE] {
+    let mut bytes = [0; SALT_SIZE];
+    getrandom::getrandom(&mut bytes).expect("Unable to generate a salt");
+    bytes
+}
+
+pub fn seal(message: &[u8], nonce: &[u8; NONCE_SIZE], key: &Key) -> Vec<u8> {
+    XSalsa20Poly1305::new(&key.0.into())
+        .encrypt(nonce.into(), message)
+        .expect("Unable to encrypt data")
+}
+
+pub fn open(ciphertext: &[u8], nonce: &[u8; NONCE_SIZE], key: &Key) -> Option<Vec<u8>> {
+    XSalsa20Poly1305::new(&key.0.into())
+        .decrypt(nonce.into(), ciphertext)
+        .ok()
+}
+
+/// A BLAKE2b hash of `label` followed by `input`, keyed with `key`
+pub fn keyed_hash(key: &Key, label: &[u8], input: &[u8]) -> [u8; NONCE_SIZE] {
+    let mut mac = <Blake2bMac<U24> as Mac>::new_from_slice(&key.0)
+        .expect("The key size is valid for BLAKE2b");
+    mac.update(label);
+    mac.update(input);
+
+    mac.finalize().into_bytes().into()
+}
+
+/// An Argon2id key derived from `passphrase` – `None` if the parameters are out of range
+pub fn derive_key(
+    passphrase: &[u8],
+    salt: &[u8; SALT_SIZE],
+    ops_limit: u32,
+    mem_limit_kib: u32,
+) -> Option<Key> {
+    let params = Params::new(mem_limit_kib, ops_limit, 1, Some(KEY_SIZE)).ok()?;
+
+    let mut key = [0; KEY_SIZE];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut key)
+        .ok()?;
+
+    Some(Key(key))
+}
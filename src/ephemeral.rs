@@ -0,0 +1,250 @@
+//! Decrypting a project's files into a temporary directory, instead of into the project
+//!
+//! `apply --ephemeral` is for CI runners that mustn't keep plaintext secrets between steps. The files are decrypted
+//! into a new directory under the system's temp directory that only the current user can read, at the same paths
+//! they'd have in the project. When a command is given after `--`, it's run with the directory's path in
+//! `CONFIGURE_EPHEMERAL_DIR`, and the directory is deleted as soon as the command exits – whether or not it succeeded.
+//! Otherwise the path is printed, and deleting the directory is up to the caller.
+
+use crate::archive::extract_archive;
+use crate::configure::Configuration;
+use crate::encryption::decrypt_file_contents_with_keys;
+use crate::fs::{find_project_root, DecryptionKeys};
+use crate::local::LocalOverrides;
+use crate::plugins::process_file;
+use crate::ConfigureError;
+use log::{debug, info, warn};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use zeroize::Zeroizing;
+
+/// The environment variable a wrapped command finds the decrypted files' directory in
+pub const EPHEMERAL_DIRECTORY_VARIABLE: &str = "CONFIGURE_EPHEMERAL_DIR";
+
+/// Where an ephemeral apply decrypted the project's files
+#[derive(Debug, Clone, Serialize)]
+pub struct EphemeralApply {
+    /// The temporary directory the files are in
+    pub directory: PathBuf,
+
+    /// Each file's destination in the project, and where it was decrypted to instead
+    pub files: Vec<EphemeralFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EphemeralFile {
+    pub destination: String,
+    pub path: PathBuf,
+}
+
+/// A temporary directory that's deleted when it's dropped, unless it's kept
+pub struct EphemeralDirectory {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl EphemeralDirectory {
+    /// Make a new directory for `project_name`'s files that only the current user can read
+    ///
+    /// The directory is created with its permissions already set, and never reuses one that's already there – so
+    /// nobody else can get a look at the files between it being made and them being written.
+    pub fn create(project_name: &str) -> Result<EphemeralDirectory, ConfigureError> {
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(12)
+            .map(char::from)
+            .collect();
+        let path = std::env::temp_dir().join(format!(
+            "configure-{}-{}",
+            directory_name_for(project_name),
+            suffix
+        ));
+
+        let mut builder = std::fs::DirBuilder::new();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+
+        builder.create(&path)?;
+
+        Ok(EphemeralDirectory { path, keep: false })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the directory in place for the caller to delete
+    pub fn keep(mut self) -> PathBuf {
+        self.keep = true;
+        self.path.clone()
+    }
+}
+
+impl Drop for EphemeralDirectory {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+
+        debug!("Deleting the decrypted files in {:?}", self.path);
+        if let Err(err) = std::fs::remove_dir_all(&self.path) {
+            warn!(
+                "Unable to delete the decrypted files in {:?} – delete them by hand: {}",
+                self.path, err
+            );
+        }
+    }
+}
+
+// The project name comes from the `.configure` file, so anything that isn't safe in a file name is replaced
+fn directory_name_for(project_name: &str) -> String {
+    project_name
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '-',
+            },
+        )
+        .collect()
+}
+
+/// Where the file at `destination` goes in `directory` – destinations that would end up outside it are an error
+fn path_in_directory(directory: &Path, destination: &str) -> Result<PathBuf, ConfigureError> {
+    let is_inside = Path::new(destination)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    match is_inside {
+        true => Ok(directory.join(destination)),
+        false => Err(ConfigureError::EphemeralDestinationNotValid {
+            destination: destination.to_string(),
+        }),
+    }
+}
+
+/// Decrypt `configuration`'s files into `directory`, at the paths they'd have in the project
+pub fn decrypt_files_into(
+    configuration: &Configuration,
+    directory: &EphemeralDirectory,
+) -> Result<Vec<EphemeralFile>, ConfigureError> {
+    let project_root = find_project_root()?;
+    let overrides = LocalOverrides::read(&project_root)?;
+    let mut decryption_keys = DecryptionKeys::default();
+    let mut files = Vec::new();
+
+    for file in overrides.files_to_apply(configuration) {
+        let source = configuration.encrypted_destination_in(file, &project_root);
+        if !source.exists() {
+            return Err(ConfigureError::EncryptedFileMissing { path: source });
+        }
+
+        let path = path_in_directory(directory.path(), overrides.destination_for(file))?;
+        let keys = decryption_keys.for_configuration(configuration)?;
+        let (contents, _) = decrypt_file_contents_with_keys(&source, keys)?;
+        let contents = Zeroizing::new(process_file(configuration, file, contents)?);
+
+        debug!("Decrypting {:?} to {:?}", source, path);
+        if file.is_directory {
+            extract_archive(&contents, &path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            if std::fs::write(&path, contents.as_slice()).is_err() {
+                return Err(ConfigureError::OutputFileNotWritable { path });
+            }
+        }
+
+        files.push(EphemeralFile {
+            destination: file.destination.clone(),
+            path,
+        });
+    }
+
+    Ok(files)
+}
+
+/// Run `command` with the decrypted files' directory in its environment, then delete the directory
+///
+/// Returns the command's exit code – a command that was stopped by a signal counts as exit code 1.
+pub fn run_with_directory(
+    command: &[String],
+    directory: EphemeralDirectory,
+) -> Result<i32, ConfigureError> {
+    let (program, arguments) = match command.split_first() {
+        Some(split) => split,
+        None => {
+            return Err(ConfigureError::CommandNotRunnable {
+                command: String::new(),
+            })
+        }
+    };
+
+    info!(
+        "Running {} with the decrypted files in {:?}",
+        program,
+        directory.path()
+    );
+
+    let status = Command::new(program)
+        .args(arguments)
+        .env(EPHEMERAL_DIRECTORY_VARIABLE, directory.path())
+        .status()
+        .map_err(|err| {
+            debug!("Unable to start {}: {}", program, err);
+            ConfigureError::CommandNotRunnable {
+                command: program.clone(),
+            }
+        })?;
+
+    drop(directory);
+
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_ephemeral_directories_are_deleted_unless_kept() {
+        let directory = EphemeralDirectory::create("example").unwrap();
+        let path = directory.path().to_path_buf();
+        std::fs::write(path.join("secrets.properties"), "token=abc").unwrap();
+        assert!(path.exists());
+
+        drop(directory);
+        assert!(!path.exists());
+
+        let kept = EphemeralDirectory::create("example").unwrap().keep();
+        assert!(kept.exists());
+        std::fs::remove_dir_all(&kept).unwrap();
+    }
+
+    #[test]
+    fn test_that_project_names_cannot_leave_the_temp_directory() {
+        assert_eq!(directory_name_for("../../etc"), "------etc");
+        assert_eq!(directory_name_for("My App_2"), "My-App_2");
+    }
+
+    #[test]
+    fn test_that_destinations_outside_the_directory_are_rejected() {
+        let directory = Path::new("/tmp/configure-example");
+
+        assert_eq!(
+            path_in_directory(directory, "config/secrets.json").unwrap(),
+            directory.join("config/secrets.json")
+        );
+        assert!(path_in_directory(directory, "../secrets.json").is_err());
+        assert!(path_in_directory(directory, "/etc/secrets.json").is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Everything that can go wrong while configuring a project
+///
+/// Every error has a stable numeric code (see `code`), which is also the process exit code. Codes never change once
+/// they've been released, so scripts can rely on them – new errors are added at the end with the next unused code.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ConfigureError {
+    #[error("Unable to decrypt file")]
+    DataDecryptionError,
+
+    #[error("Unable to encrypt file")]
+    DataEncryptionError,
+
+    #[cfg(feature = "git")]
+    #[error("Unknown git error: {0}")]
+    SecretsRepoError(#[from] git2::Error),
+
+    #[error("Invalid git status: {0}")]
+    GitStatusParsingError(#[from] std::num::ParseIntError),
+
+    #[error("Unable to find current secrets repo branch")]
+    GitGetCurrentBranchError,
+
+    #[error("Invalid git status")]
+    GitStatusUnknownError,
+
+    #[error("Unable to find the root of the respository – are you sure you're running this inside a git repo? Projects that aren't in one can give their directory with `--project-root`.")]
+    ProjectNotPresent,
+
+    #[error("The .configure file at {} is missing or could not be read", path.display())]
+    ConfigureFileNotReadable { path: PathBuf },
+
+    #[error("The .configure file at {} could not be written", path.display())]
+    ConfigureFileNotWritable { path: PathBuf },
+
+    #[error("Unable to parse configuration file – the JSON is probably invalid")]
+    ConfigureFileNotValid,
+
+    #[error("Unable to save an configuration data – it couldn't be converted to JSON")]
+    ConfigureDataNotValid,
+
+    #[error("No secrets repository could be found on this machine")]
+    SecretsNotPresent,
+
+    #[error("The encrypted file at {} is missing – unable to apply secrets to project. Run `configure update` to fix this", path.display())]
+    EncryptedFileMissing { path: PathBuf },
+
+    #[error("Unable to read the keys file at {}", path.display())]
+    KeysFileNotReadable { path: PathBuf },
+
+    #[error("Unable to write the keys file at {}", path.display())]
+    KeysFileNotWritable { path: PathBuf },
+
+    #[error("The keys file at {} is not valid – it might be invalid JSON, or it could be structured incorrectly", path.display())]
+    KeysFileIsNotValid { path: PathBuf },
+
+    #[error("Attempted to save invalid keys.json data – it couldn't be converted to JSON")]
+    KeysDataIsNotValid,
+
+    #[error("There's no key for the {project:?} project in keys.json")]
+    MissingProjectKey { project: String },
+
+    #[error("No environment variable or secrets repository found – cannot decrypt files")]
+    MissingDecryptionKey,
+
+    #[error("This decryption key is not valid base64")]
+    DecryptionKeyEncodingError,
+
+    #[error("This decryption key is not a sodium-compatible key")]
+    DecryptionKeyParsingError,
+
+    #[error("Unable to read {}", path.display())]
+    InputFileNotReadable { path: PathBuf },
+
+    #[error("Unable to write {}", path.display())]
+    OutputFileNotWritable { path: PathBuf },
+
+    #[error("Unable to read the .configure-files/state.json file")]
+    StateFileNotReadable,
+
+    #[error("Unable to write the .configure-files/state.json file")]
+    StateFileNotWritable,
+
+    #[error(
+        "{} was modified since it was last applied – refusing to overwrite it",
+        path.display()
+    )]
+    DestinationFileModified { path: PathBuf },
+
+    #[error("A git network operation timed out – set CONFIGURE_GIT_TIMEOUT to allow more time")]
+    GitNetworkTimeout,
+
+    #[error("This signing key is not valid")]
+    SigningKeyNotValid,
+
+    #[error("Unable to read signing_keys.json file in your secrets repo")]
+    SigningKeysFileNotReadable,
+
+    #[error("Unable to write signing_keys.json file in your secrets repo")]
+    SigningKeysFileNotWritable,
+
+    #[error(
+        "This project requires a signed .configure file, but {} is missing",
+        path.display()
+    )]
+    ConfigureFileSignatureMissing { path: PathBuf },
+
+    #[error("The .configure file signature doesn't match its contents – it may have been changed without being re-signed")]
+    ConfigureFileSignatureInvalid,
+
+    #[error("{} doesn't exist in your secrets repo", path.display())]
+    SourceFileMissing { path: PathBuf },
+
+    #[error("Another file in the .configure file is already written to {destination:?}")]
+    DestinationAlreadyConfigured { destination: String },
+
+    #[error("No file in the .configure file matches that destination or index")]
+    DestinationNotConfigured,
+
+    #[error("{} is a directory – add `\"directory\": true` to its entry in the .configure file", path.display())]
+    SourceIsDirectory { path: PathBuf },
+
+    #[error(
+        "Secrets must be stored at a path inside the secrets repo, like `android/app/secrets.json`"
+    )]
+    SecretPathNotValid,
+
+    #[error("The secrets repo has uncommitted changes – commit or stash them, then try again")]
+    SecretsRepoHasLocalChanges,
+
+    #[error("Unable to read or write a file: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("A string passed to the C API was null or not valid UTF-8")]
+    FfiArgumentNotValid,
+
+    #[error("The operation was cancelled")]
+    Cancelled,
+
+    #[error("Passphrases can't be empty")]
+    PassphraseNotValid,
+
+    #[error("Passphrase key derivation needs 1–16 passes and between 8 KiB and 1 GiB of memory")]
+    PassphraseParametersNotValid,
+
+    #[error("This project's files are encrypted with a passphrase – set the CONFIGURE_PASSPHRASE environment variable, or run interactively to be asked for it")]
+    MissingPassphrase,
+
+    #[error("{hash} isn't a commit on the {branch} branch of the secrets repo")]
+    CommitNotOnBranch { hash: String, branch: String },
+
+    #[error("There are no earlier secrets to roll back to – only the commits this project was pinned to when it was applied are remembered")]
+    NoPreviousPinnedHash,
+
+    #[error("There's no backup of {} to restore", path.display())]
+    BackupNotFound { path: PathBuf },
+
+    #[error("The secrets repo has no branch named {branch}, locally or on a remote")]
+    BranchNotFound { branch: String },
+
+    #[error("There's nothing to choose from")]
+    NothingToSelect,
+
+    #[error("The .configure file doesn't match the schema – {count} problem(s) were found")]
+    ConfigureFileDoesNotMatchSchema { count: usize },
+
+    #[error("{} has fields this version of configure doesn't know about: {fields}", path.display())]
+    UnknownFields { path: PathBuf, fields: String },
+
+    #[error("The .configure file at {} can't be extended – it must exist, and can't extend another file itself", path.display())]
+    ConfigureFileCannotBeExtended { path: PathBuf },
+
+    #[error("Unable to initialize the encryption library – this usually means the system's source of randomness isn't available")]
+    EncryptionNotAvailable,
+
+    #[error("The encrypted file is incomplete – it may have been cut short while it was copied or checked out. Run `configure update` to replace it.")]
+    EncryptedDataTruncated,
+
+    #[error("The encrypted file couldn't be decrypted with this key – check that it's the right key for the project (or the right passphrase). If it is, the file has been modified since it was encrypted.")]
+    WrongDecryptionKey,
+
+    #[error("The encrypted file is damaged – its header isn't valid. Run `configure update` to replace it.")]
+    EncryptedDataCorrupt,
+
+    #[error("{} was written by configure {version}, which is newer than this version ({}) – update configure before using this project", path.display(), env!("CARGO_PKG_VERSION"))]
+    WrittenByNewerVersion { path: PathBuf, version: String },
+
+    #[error("Unable to find the template {template} – named templates are kept in the secrets repo's `templates` directory, like `templates/android-app.configure`")]
+    TemplateNotFound { template: String },
+
+    #[error("The template {template} isn't valid – it should be a .configure file, which can leave out any field but can't extend another file")]
+    TemplateNotValid { template: String },
+
+    #[error("{} isn't a directory, so it can't be used as the project root", path.display())]
+    ProjectRootNotFound { path: PathBuf },
+
+    #[error("{} isn't a directory, so it can't be used as the secrets repo", path.display())]
+    SecretsRootNotFound { path: PathBuf },
+
+    #[error("{path:?} isn't in the secrets repo at {revision} – has it been committed?")]
+    SourceNotInRevision { path: String, revision: String },
+
+    #[error("The pinned hash {hash} doesn't share any history with the {branch} branch in the secrets repo")]
+    PinnedHashNotInBranchHistory { hash: String, branch: String },
+
+    #[error("The secrets repo doesn't have a remote named {remote:?} – add it with `git remote add`, or change the `remote` in the .configure file")]
+    RemoteNotFound { remote: String },
+
+    #[error("The project doesn't have a submodule at {path:?} – add the secrets repo with `git submodule add`, or change the `secrets_submodule` in the .configure file")]
+    SecretsSubmoduleNotFound { path: String },
+
+    #[error("Unable to check out the secrets repo submodule at {path:?} – try `git submodule update --init` to see what went wrong")]
+    SecretsSubmoduleNotCheckedOut { path: String },
+
+    #[error("No .configure file found at {} – run `configure init` to set up the project", path.display())]
+    ConfigureFileNotFound { path: PathBuf },
+
+    #[error("Files in the .configure file would write over each other: {}", conflicts.join("; "))]
+    DestinationsConflict { conflicts: Vec<String> },
+
+    #[error("{} isn't valid – it can only have an `exclude` list and a `destinations` map", path.display())]
+    LocalOverridesNotValid { path: PathBuf },
+
+    #[error("The .configure file doesn't have a profile named {profile:?}")]
+    ProfileNotFound { profile: String },
+
+    #[error("The {profile:?} profile lists {destination:?}, but no file in the .configure file is decrypted there")]
+    ProfileFileNotConfigured {
+        profile: String,
+        destination: String,
+    },
+
+    #[error("The project doesn't have a commit {commit:?} to scan")]
+    ProjectCommitNotFound { commit: String },
+
+    #[error("{count} possible secret(s) were found outside the configured destinations – move them somewhere ignored, or remove them, before committing")]
+    SecretsFoundInProject { count: usize },
+
+    #[error("None of the configured files are .env, .properties or .xcconfig files with entries to pass on")]
+    NoSecretsForEnvironment,
+
+    #[error("Unable to run {command:?} – check that it's installed and on your PATH")]
+    CommandNotRunnable { command: String },
+
+    #[error("{destination} can't be inspected – only certificates (.pem, .crt, .cer), .p12 files, keystores (.jks, .keystore) and provisioning profiles are recognised")]
+    FileTypeNotInspectable { destination: String },
+
+    #[error("Unable to read {destination} – it may be damaged, or need a password (set CONFIGURE_KEYSTORE_PASSWORD to give it)")]
+    FileNotInspectable { destination: String },
+
+    #[error("The secrets repo's {remote:?} remote points at {actual}, but the .configure file expects {expected} – check you're using the right fork, or change the `remote_url` in the .configure file")]
+    RemoteUrlMismatch {
+        remote: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("known_hosts doesn't have the host key {fingerprint} for {host} – check you're connecting to the right server, or change the `remote_host_key` in the .configure file")]
+    RemoteHostKeyMismatch { host: String, fingerprint: String },
+
+    #[error("There's already a secrets repo at {} – run `configure secrets sparse-init` without `--clone` to use it", path.display())]
+    SecretsRepoAlreadyExists { path: PathBuf },
+
+    #[error("Unable to set up a sparse checkout of the secrets repo (it needs git 2.25 or later): {reason}")]
+    SparseCheckoutFailed { reason: String },
+
+    #[error("There's no plugin named {plugin:?} – register it, or add it to the `plugins` in the .configure file")]
+    PluginNotFound { plugin: String },
+
+    #[error("The {plugin:?} plugin failed – run with --verbose to see its command")]
+    PluginFailed { plugin: String },
+
+    #[error("{destination} is a directory, so it can't have `processors`")]
+    DirectoryNotProcessable { destination: String },
+
+    #[error("Unable to read the key from Vault: {reason}")]
+    VaultRequestFailed { reason: String },
+
+    #[error("Unable to read the key from {provider}: {reason}")]
+    KeyProviderFailed { provider: String, reason: String },
+
+    #[error("The {provider} key provider needs a `key_reference` in the .configure file, saying where the key is")]
+    KeyReferenceMissing { provider: String },
+
+    #[error("{variable} isn't set – is this running in a GitHub Actions job?")]
+    GitHubActionsFileMissing { variable: String },
+
+    #[error("The configuration's `{field}` isn't valid – {reason}")]
+    ConfigurationValueNotValid { field: String, reason: String },
+
+    #[error("Unable to include {} – it has to exist, and can only have `files_to_copy`, `profiles` and `plugins`", path.display())]
+    IncludeNotValid { path: PathBuf },
+
+    #[error("{include} configures {name} differently from another included file – move it to one of them, or to the .configure file itself")]
+    IncludeConflict { include: String, name: String },
+
+    #[error("Signing keys belong to the project's `origin` remote, and this project doesn't have one – add it, then sign again")]
+    ProjectRemoteNotFound,
+
+    #[error("Unable to include {include:?} – included files have to be inside the .configure file's directory")]
+    IncludePathNotValid { include: String },
+
+    #[error("Unable to fetch the latest secrets from {remote:?}: {reason} – set CONFIGURE_OFFLINE=true to use what was last fetched instead")]
+    SecretsFetchFailed { remote: String, reason: String },
+
+    #[error("Unable to decrypt {destination:?} into a temporary directory – only destinations inside the project can be")]
+    EphemeralDestinationNotValid { destination: String },
+
+    #[error("Another configure process is still using {} – if none is running, delete the file and try again", path.display())]
+    LockNotAvailable { path: PathBuf },
+}
+
+impl ConfigureError {
+    /// The stable numeric code for this error, used as the process exit code
+    pub fn code(&self) -> i32 {
+        match self {
+            // 0 means success, so this error has its own code – it used to exit as if nothing went wrong
+            ConfigureError::DataDecryptionError => 38,
+            ConfigureError::DataEncryptionError => 1,
+            #[cfg(feature = "git")]
+            ConfigureError::SecretsRepoError(_) => 2,
+            ConfigureError::GitStatusParsingError(_) => 3,
+            ConfigureError::GitGetCurrentBranchError => 4,
+            ConfigureError::GitStatusUnknownError => 5,
+            ConfigureError::ProjectNotPresent => 6,
+            ConfigureError::ConfigureFileNotReadable { .. } => 7,
+            ConfigureError::ConfigureFileNotWritable { .. } => 8,
+            ConfigureError::ConfigureFileNotValid => 9,
+            ConfigureError::ConfigureDataNotValid => 10,
+            ConfigureError::SecretsNotPresent => 11,
+            ConfigureError::EncryptedFileMissing { .. } => 12,
+            ConfigureError::KeysFileNotReadable { .. } => 13,
+            ConfigureError::KeysFileNotWritable { .. } => 14,
+            ConfigureError::KeysFileIsNotValid { .. } => 15,
+            ConfigureError::KeysDataIsNotValid => 16,
+            ConfigureError::MissingProjectKey { .. } => 17,
+            ConfigureError::MissingDecryptionKey => 18,
+            ConfigureError::DecryptionKeyEncodingError => 19,
+            ConfigureError::DecryptionKeyParsingError => 20,
+            ConfigureError::InputFileNotReadable { .. } => 21,
+            ConfigureError::OutputFileNotWritable { .. } => 22,
+            ConfigureError::StateFileNotReadable => 23,
+            ConfigureError::StateFileNotWritable => 24,
+            ConfigureError::DestinationFileModified { .. } => 25,
+            ConfigureError::GitNetworkTimeout => 26,
+            ConfigureError::SigningKeyNotValid => 27,
+            ConfigureError::SigningKeysFileNotReadable => 28,
+            ConfigureError::SigningKeysFileNotWritable => 29,
+            ConfigureError::ConfigureFileSignatureMissing { .. } => 30,
+            ConfigureError::ConfigureFileSignatureInvalid => 31,
+            ConfigureError::SourceFileMissing { .. } => 32,
+            ConfigureError::DestinationAlreadyConfigured { .. } => 33,
+            ConfigureError::DestinationNotConfigured => 34,
+            ConfigureError::SourceIsDirectory { .. } => 35,
+            ConfigureError::SecretPathNotValid => 36,
+            ConfigureError::SecretsRepoHasLocalChanges => 37,
+            ConfigureError::IoError(_) => 39,
+            ConfigureError::FfiArgumentNotValid => 40,
+            ConfigureError::Cancelled => 41,
+            ConfigureError::PassphraseNotValid => 42,
+            ConfigureError::PassphraseParametersNotValid => 43,
+            ConfigureError::MissingPassphrase => 44,
+            ConfigureError::CommitNotOnBranch { .. } => 45,
+            ConfigureError::NoPreviousPinnedHash => 46,
+            ConfigureError::BackupNotFound { .. } => 47,
+            ConfigureError::BranchNotFound { .. } => 48,
+            ConfigureError::NothingToSelect => 49,
+            ConfigureError::ConfigureFileDoesNotMatchSchema { .. } => 50,
+            ConfigureError::UnknownFields { .. } => 51,
+            ConfigureError::ConfigureFileCannotBeExtended { .. } => 52,
+            ConfigureError::EncryptionNotAvailable => 53,
+            ConfigureError::EncryptedDataTruncated => 54,
+            ConfigureError::WrongDecryptionKey => 55,
+            ConfigureError::EncryptedDataCorrupt => 56,
+            ConfigureError::WrittenByNewerVersion { .. } => 57,
+            ConfigureError::TemplateNotFound { .. } => 58,
+            ConfigureError::TemplateNotValid { .. } => 59,
+            ConfigureError::ProjectRootNotFound { .. } => 60,
+            ConfigureError::SecretsRootNotFound { .. } => 61,
+            ConfigureError::SourceNotInRevision { .. } => 62,
+            ConfigureError::PinnedHashNotInBranchHistory { .. } => 63,
+            ConfigureError::RemoteNotFound { .. } => 64,
+            ConfigureError::SecretsSubmoduleNotFound { .. } => 65,
+            ConfigureError::SecretsSubmoduleNotCheckedOut { .. } => 66,
+            ConfigureError::ConfigureFileNotFound { .. } => 67,
+            ConfigureError::DestinationsConflict { .. } => 68,
+            ConfigureError::LocalOverridesNotValid { .. } => 69,
+            ConfigureError::ProfileNotFound { .. } => 70,
+            ConfigureError::ProfileFileNotConfigured { .. } => 71,
+            ConfigureError::ProjectCommitNotFound { .. } => 72,
+            ConfigureError::SecretsFoundInProject { .. } => 73,
+            ConfigureError::NoSecretsForEnvironment => 74,
+            ConfigureError::CommandNotRunnable { .. } => 75,
+            ConfigureError::FileTypeNotInspectable { .. } => 76,
+            ConfigureError::FileNotInspectable { .. } => 77,
+            ConfigureError::RemoteUrlMismatch { .. } => 78,
+            ConfigureError::RemoteHostKeyMismatch { .. } => 79,
+            ConfigureError::SecretsRepoAlreadyExists { .. } => 80,
+            ConfigureError::SparseCheckoutFailed { .. } => 81,
+            ConfigureError::PluginNotFound { .. } => 82,
+            ConfigureError::PluginFailed { .. } => 83,
+            ConfigureError::DirectoryNotProcessable { .. } => 84,
+            ConfigureError::VaultRequestFailed { .. } => 85,
+            ConfigureError::KeyProviderFailed { .. } => 86,
+            ConfigureError::KeyReferenceMissing { .. } => 87,
+            ConfigureError::GitHubActionsFileMissing { .. } => 88,
+            ConfigureError::ConfigurationValueNotValid { .. } => 89,
+            ConfigureError::IncludeNotValid { .. } => 90,
+            ConfigureError::IncludeConflict { .. } => 91,
+            ConfigureError::ProjectRemoteNotFound => 92,
+            ConfigureError::IncludePathNotValid { .. } => 93,
+            ConfigureError::SecretsFetchFailed { .. } => 94,
+            ConfigureError::EphemeralDestinationNotValid { .. } => 95,
+            ConfigureError::LockNotAvailable { .. } => 96,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_error_codes_are_stable() {
+        assert_eq!(ConfigureError::ProjectNotPresent.code(), 6);
+        assert_eq!(ConfigureError::MissingDecryptionKey.code(), 18);
+        assert_eq!(
+            ConfigureError::SourceFileMissing {
+                path: PathBuf::from("android/secrets.json")
+            }
+            .code(),
+            32
+        );
+        assert_eq!(ConfigureError::DataDecryptionError.code(), 38);
+    }
+
+    #[test]
+    fn test_that_error_messages_include_their_context() {
+        let err = ConfigureError::EncryptedFileMissing {
+            path: PathBuf::from(".configure-files/secrets.json.enc"),
+        };
+        assert!(err
+            .to_string()
+            .contains(".configure-files/secrets.json.enc"));
+
+        let err = ConfigureError::MissingProjectKey {
+            project: "Demo".to_string(),
+        };
+        assert!(err.to_string().contains("\"Demo\""));
+    }
+
+    #[test]
+    fn test_that_wrapped_errors_keep_their_source() {
+        use std::error::Error;
+
+        let err = ConfigureError::from(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "permission denied",
+        ));
+
+        assert_eq!(err.code(), 39);
+        assert!(err.source().is_some());
+        assert!(err.to_string().contains("permission denied"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Structured events for each step of an apply or update, for tools that drive the library
+//!
+//! Log messages are written for people and can change between releases – events are the stable way for a host (an
+//! IDE plugin, a CI dashboard, an app using the C interface) to follow along. Register a listener with
+//! `set_event_listener`. The CLI logs each event at debug level, and with `--output json` prints one JSON object per
+//! line on stdout instead.
+
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Something that happened during an apply or update
+///
+/// Serialized with an `event` field naming the variant in snake case, e.g. `{"event":"file_decrypted","path":"…"}`.
+#[derive(Debug, Clone, Serialize, Eq, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    UpdateStarted,
+    UpdateFinished,
+
+    /// The update stopped with an error – `code` is its `ConfigureError::code`
+    UpdateFailed {
+        code: i32,
+    },
+
+    ApplyStarted,
+    ApplyFinished,
+    ApplyFailed {
+        code: i32,
+    },
+
+    /// `git fetch` is about to run in the secrets repo
+    FetchStarted {
+        remote: String,
+    },
+    FetchFinished {
+        remote: String,
+    },
+
+    /// A file was re-encrypted from the secrets repo – it isn't written to the project until the update goes ahead
+    FileEncrypted {
+        path: PathBuf,
+    },
+    FileDecrypted {
+        path: PathBuf,
+    },
+
+    /// A file was left alone because it's already up to date
+    FileUnchanged {
+        path: PathBuf,
+    },
+
+    /// A file was left alone because it was edited by hand and the user chose to keep their changes
+    FileKept {
+        path: PathBuf,
+    },
+
+    /// The file that was at a destination was moved to `path` before it was overwritten
+    BackupCreated {
+        path: PathBuf,
+    },
+
+    /// The project's pinned hash changed, and the project was written to match
+    PinUpdated {
+        old: String,
+        new: String,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::UpdateStarted => write!(f, "Update started"),
+            Event::UpdateFinished => write!(f, "Update finished"),
+            Event::ApplyStarted => write!(f, "Apply started"),
+            Event::ApplyFinished => write!(f, "Apply finished"),
+            Event::UpdateFailed { code } => write!(f, "Update failed with error {}", code),
+            Event::ApplyFailed { code } => write!(f, "Apply failed with error {}", code),
+            Event::FetchStarted { remote } => write!(f, "Fetching {}", remote),
+            Event::FetchFinished { remote } => write!(f, "Fetched {}", remote),
+            Event::FileEncrypted { path } => write!(f, "Encrypted {:?}", path),
+            Event::FileDecrypted { path } => write!(f, "Decrypted {:?}", path),
+            Event::FileUnchanged { path } => write!(f, "{:?} is unchanged", path),
+            Event::FileKept { path } => write!(f, "Kept local changes to {:?}", path),
+            Event::BackupCreated { path } => write!(f, "Backed up to {:?}", path),
+            Event::PinUpdated { old, new } => write!(f, "Pinned hash {} → {}", old, new),
+        }
+    }
+}
+
+type EventListener = Arc<dyn Fn(&Event) + Send + Sync>;
+
+static EVENT_LISTENER: Mutex<Option<EventListener>> = Mutex::new(None);
+
+/// Call `listener` with each event from now on, replacing any listener registered before
+///
+/// The listener is called on whichever thread is doing the work, while it waits – keep it quick.
+///
+/// # Arguments
+///
+/// * `listener` - Called with each event as it happens
+pub fn set_event_listener<F>(listener: F)
+where
+    F: Fn(&Event) + Send + Sync + 'static,
+{
+    if let Ok(mut current_listener) = EVENT_LISTENER.lock() {
+        *current_listener = Some(Arc::new(listener));
+    }
+}
+
+/// Stop sending events to the listener registered with `set_event_listener`
+pub fn clear_event_listener() {
+    if let Ok(mut current_listener) = EVENT_LISTENER.lock() {
+        *current_listener = None;
+    }
+}
+
+/// Send `event` to the registered listener, if there is one
+pub(crate) fn emit(event: Event) {
+    // The lock is released before the listener runs, so a listener that registers another one (or does work that
+    // emits events of its own) doesn't deadlock
+    let listener = match EVENT_LISTENER.lock() {
+        Ok(listener) => listener.clone(),
+        Err(_) => return,
+    };
+
+    if let Some(listener) = listener {
+        listener(&event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_events_are_serialized_with_their_name() {
+        let event = Event::PinUpdated {
+            old: "abc".to_string(),
+            new: "def".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"pin_updated","old":"abc","new":"def"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::ApplyStarted).unwrap(),
+            r#"{"event":"apply_started"}"#
+        );
+    }
+
+    #[test]
+    fn test_that_the_listener_receives_emitted_events() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let listener_received = received.clone();
+        set_event_listener(move |event| {
+            listener_received.lock().unwrap().push(event.clone());
+
+            // Listeners can cause events of their own without deadlocking
+            if let Event::FileDecrypted { path } = event {
+                emit(Event::BackupCreated { path: path.clone() });
+            }
+        });
+
+        let path = PathBuf::from("secrets.json");
+        emit(Event::FileDecrypted { path: path.clone() });
+        clear_event_listener();
+
+        // Other tests can emit events while this one runs, so only check for the ones sent here
+        let received = received.lock().unwrap();
+        assert!(received.contains(&Event::FileDecrypted { path: path.clone() }));
+        assert!(received.contains(&Event::BackupCreated { path }));
+    }
+}
@@ -0,0 +1,513 @@
+//! The C interface to the library
+//!
+//! Every exported function is prefixed with `configure_`. Strings passed in are only borrowed for the duration of the
+//! call, and may be null where noted. Strings returned by the library must be freed with `configure_free_string`.
+//! Functions that can fail return `0` on success, or the error's stable code (see `ConfigureError::code`).
+//!
+//! `include/configure.h` is generated from this file by `build.rs` – build with `CONFIGURE_UPDATE_HEADERS=1` to update
+//! the checked-in copy after changing anything here. Bump `CONFIGURE_ABI_VERSION` whenever an exported signature
+//! changes or a function is removed – adding a new function doesn't need a bump.
+//!
+//! With the `apple` feature, the functions that can prompt in a terminal are left out in favour of the ones in
+//! `apple`, and `include/apple/configure.h` describes what's exported.
+
+use crate::configure::{rename_project, validate_configuration, ConfigureError};
+use crate::encryption::{self, EncryptionKey};
+use crate::events::set_event_listener;
+use crate::fs::{
+    infer_decryption_output_filename, infer_encryption_output_filename,
+    read_configuration_from_file, read_verified_configuration_from_file,
+    resolve_configure_file_path, write_signed_configuration_to,
+};
+use crate::logging::set_log_handler;
+use libc::{c_char, c_int};
+use log::{error, LevelFilter};
+use std::ffi::{CStr, CString};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[cfg(feature = "apple")]
+pub mod apple;
+
+/// The version of the C interface described by `include/configure.h`
+pub const CONFIGURE_ABI_VERSION: u32 = 1;
+
+/// Returned by functions that don't report a specific error code when they fail – the details are logged
+pub const CONFIGURE_ERROR_UNKNOWN: c_int = -1;
+
+/// A log callback
+///
+/// `level` is `1` (error) through `5` (trace). `message` is only valid for the duration of the call.
+pub type LogCallback = extern "C" fn(level: c_int, message: *const c_char);
+
+/// An event callback
+///
+/// `event` is a JSON object with an `event` field naming what happened, and is only valid for the duration of the call.
+pub type EventCallback = extern "C" fn(event: *const c_char);
+
+/// The version of the C interface this library provides – compare it with `CONFIGURE_ABI_VERSION` from the header
+#[no_mangle]
+pub extern "C" fn configure_abi_version() -> u32 {
+    CONFIGURE_ABI_VERSION
+}
+
+/// Send the library's log messages to `callback` instead of printing them
+///
+/// `max_level` is `0` (off) through `5` (trace) – values outside that range are clamped.
+#[no_mangle]
+pub extern "C" fn configure_set_log_callback(callback: LogCallback, max_level: c_int) {
+    set_log_handler(
+        move |level, message| {
+            // Interior NUL bytes can't be represented in a C string, so strip them rather than dropping the message
+            let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+            callback(level as c_int, message.as_ptr());
+        },
+        level_filter_from_c_int(max_level),
+    );
+}
+
+/// Call `callback` with each step of an apply or update as it happens
+///
+/// Replaces any callback registered before.
+#[no_mangle]
+pub extern "C" fn configure_set_event_callback(callback: EventCallback) {
+    set_event_listener(move |event| {
+        let event = serde_json::to_string(event).unwrap_or_default();
+        let event = CString::new(event).unwrap_or_default();
+        callback(event.as_ptr());
+    });
+}
+
+/// Set up the encryption library ahead of time
+///
+/// Every function that needs it does this itself the first time, so this is optional – it's useful for finding out at
+/// startup whether encryption works on this device. Returns `0` on success, or the error's code.
+#[no_mangle]
+pub extern "C" fn configure_init_encryption() -> c_int {
+    result_to_c(encryption::init())
+}
+
+/// Set up a project to use the configure tool, prompting for anything that's missing
+///
+/// Returns `0` on success, or `CONFIGURE_ERROR_UNKNOWN`.
+#[cfg(not(feature = "apple"))]
+#[no_mangle]
+pub extern "C" fn configure_init() -> c_int {
+    unit_to_c(crate::init)
+}
+
+/// Decrypt the secrets already present in the project
+///
+/// Returns `0` on success, or the error's code – `CONFIGURE_ERROR_UNKNOWN` if it was a panic.
+///
+/// # Safety
+///
+/// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+#[cfg(not(feature = "apple"))]
+#[no_mangle]
+pub unsafe extern "C" fn configure_apply(
+    interactive: bool,
+    configuration_file_path: *const c_char,
+) -> c_int {
+    match optional_string_from_c(configuration_file_path) {
+        Ok(path) => result_to_c_catching_panics(move || {
+            crate::try_apply_with_options(
+                path,
+                crate::ApplyOptions {
+                    interactive,
+                    ..Default::default()
+                },
+            )
+        }),
+        Err(err) => result_to_c(Err(err)),
+    }
+}
+
+/// Encrypt the latest secrets into the project, then decrypt them
+///
+/// Returns `0` on success, or the error's code – `CONFIGURE_ERROR_UNKNOWN` if it was a panic.
+///
+/// # Safety
+///
+/// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+#[cfg(not(feature = "apple"))]
+#[no_mangle]
+pub unsafe extern "C" fn configure_update(
+    interactive: bool,
+    configuration_file_path: *const c_char,
+) -> c_int {
+    match optional_string_from_c(configuration_file_path) {
+        Ok(path) => {
+            result_to_c_catching_panics(move || crate::try_update(interactive, false, path))
+        }
+        Err(err) => result_to_c(Err(err)),
+    }
+}
+
+/// Change the project name in the `.configure` file
+///
+/// # Safety
+///
+/// `project_name` must be a valid C string. `configuration_file_path` may be null to use the default configuration file.
+#[no_mangle]
+pub unsafe extern "C" fn configure_update_project_name(
+    project_name: *const c_char,
+    configuration_file_path: *const c_char,
+) -> c_int {
+    result_to_c((|| {
+        let project_name = string_from_c(project_name)?;
+        let configuration_file_path = optional_string_from_c(configuration_file_path)?;
+
+        let mut configuration = read_configuration_from_file(&configuration_file_path)?;
+        rename_project(&mut configuration, project_name, false)?;
+        write_signed_configuration_to(
+            &configuration,
+            &resolve_configure_file_path(&configuration_file_path)?,
+        )
+    })())
+}
+
+/// Change the secrets branch in the `.configure` file
+///
+/// # Safety
+///
+/// `branch_name` must be a valid C string. `configuration_file_path` may be null to use the default configuration file.
+#[no_mangle]
+pub unsafe extern "C" fn configure_update_branch_name(
+    branch_name: *const c_char,
+    configuration_file_path: *const c_char,
+) -> c_int {
+    result_to_c((|| {
+        let branch_name = string_from_c(branch_name)?;
+        let configuration_file_path = optional_string_from_c(configuration_file_path)?;
+        crate::modify_configuration(&configuration_file_path, |configuration| {
+            configuration.branch = branch_name
+        })
+    })())
+}
+
+/// Change the pinned secrets commit in the `.configure` file
+///
+/// # Safety
+///
+/// `pinned_hash` must be a valid C string. `configuration_file_path` may be null to use the default configuration file.
+#[no_mangle]
+pub unsafe extern "C" fn configure_update_pinned_hash(
+    pinned_hash: *const c_char,
+    configuration_file_path: *const c_char,
+) -> c_int {
+    result_to_c((|| {
+        let pinned_hash = string_from_c(pinned_hash)?;
+        let configuration_file_path = optional_string_from_c(configuration_file_path)?;
+        crate::modify_configuration(&configuration_file_path, |configuration| {
+            configuration.pinned_hash = pinned_hash
+        })
+    })())
+}
+
+/// Check that the `.configure` file can be read, and that its signature checks out if the project signs it
+///
+/// # Safety
+///
+/// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn configure_validate(configuration_file_path: *const c_char) -> c_int {
+    result_to_c((|| {
+        let configuration_file_path = optional_string_from_c(configuration_file_path)?;
+        let configuration = read_verified_configuration_from_file(&configuration_file_path)?;
+
+        if configuration.is_empty() {
+            return Err(ConfigureError::ConfigureFileNotValid);
+        }
+
+        configuration.check_destinations()?;
+        configuration.check_profiles()?;
+        configuration.check_plugins()?;
+        validate_configuration(configuration);
+        Ok(())
+    })())
+}
+
+/// Create a new encryption key
+///
+/// Returns null if the encryption library can't be used, and the reason is logged. Otherwise, the returned string must
+/// be freed with `configure_free_string`.
+#[no_mangle]
+pub extern "C" fn configure_generate_encryption_key() -> *mut c_char {
+    match crate::generate_encryption_key() {
+        Ok(key) => string_to_c(key),
+        Err(err) => {
+            error!("{}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// The path to the project's `.configure` file
+///
+/// Returns null if it can't be found. Otherwise, the returned string must be freed with `configure_free_string`.
+#[no_mangle]
+pub extern "C" fn configure_find_configuration_file() -> *mut c_char {
+    match crate::find_configuration_file() {
+        path if path.is_empty() => std::ptr::null_mut(),
+        path => string_to_c(path),
+    }
+}
+
+/// The secrets repo's branch, commit, sync state, working copy and remote, as a JSON object
+///
+/// The object has `path`, `branch`, `hash`, `ahead`, `behind`, `dirty`, `remote`, and `remote_url` – `branch` is null
+/// when HEAD is detached, and `ahead` and `behind` are null when the branch has no upstream. Returns null if the
+/// secrets repo can't be read, and the reason is logged. Otherwise, the returned string must be freed with
+/// `configure_free_string`.
+///
+/// # Safety
+///
+/// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn configure_secrets_repo_status(
+    configuration_file_path: *const c_char,
+) -> *mut c_char {
+    match optional_string_from_c(configuration_file_path).and_then(crate::secrets_repo_status) {
+        Ok(info) => serde_json::to_string(&info)
+            .map(string_to_c)
+            .unwrap_or(std::ptr::null_mut()),
+        Err(err) => {
+            error!("{}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Encrypt a single file
+///
+/// # Safety
+///
+/// `input_file` and `encryption_key` must be valid C strings. `output_file` may be null, in which case `.enc` is
+/// added to the input file path.
+#[no_mangle]
+pub unsafe extern "C" fn configure_encrypt_file(
+    input_file: *const c_char,
+    output_file: *const c_char,
+    encryption_key: *const c_char,
+) -> c_int {
+    result_to_c((|| {
+        let input_file = PathBuf::from(string_from_c(input_file)?);
+        let output_file = match optional_string_from_c(output_file)? {
+            Some(path) => PathBuf::from(path),
+            None => infer_encryption_output_filename(&input_file),
+        };
+        let encryption_key = EncryptionKey::from_str(&string_from_c(encryption_key)?)?;
+
+        encryption::encrypt_file(&input_file, &output_file, &encryption_key)
+    })())
+}
+
+/// Decrypt a single file
+///
+/// # Safety
+///
+/// `input_file` and `encryption_key` must be valid C strings. `output_file` may be null, in which case the `.enc`
+/// extension is removed from the input file path.
+#[no_mangle]
+pub unsafe extern "C" fn configure_decrypt_file(
+    input_file: *const c_char,
+    output_file: *const c_char,
+    encryption_key: *const c_char,
+) -> c_int {
+    result_to_c((|| {
+        let input_file = PathBuf::from(string_from_c(input_file)?);
+        let output_file = match optional_string_from_c(output_file)? {
+            Some(path) => PathBuf::from(path),
+            None => infer_decryption_output_filename(&input_file),
+        };
+        let encryption_key = EncryptionKey::from_str(&string_from_c(encryption_key)?)?;
+
+        encryption::decrypt_file(&input_file, &output_file, &encryption_key)
+    })())
+}
+
+/// Free a string returned by this library
+///
+/// # Safety
+///
+/// `string` must have been returned by this library and not freed already. Passing null does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn configure_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Convert a C log level – `0` (off) through `5` (trace) – clamping values outside that range
+fn level_filter_from_c_int(level: c_int) -> LevelFilter {
+    match level {
+        i32::MIN..=0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+unsafe fn string_from_c(string: *const c_char) -> Result<String, ConfigureError> {
+    match optional_string_from_c(string)? {
+        Some(string) => Ok(string),
+        None => Err(ConfigureError::FfiArgumentNotValid),
+    }
+}
+
+/// A null pointer is treated as a missing argument
+unsafe fn optional_string_from_c(string: *const c_char) -> Result<Option<String>, ConfigureError> {
+    if string.is_null() {
+        return Ok(None);
+    }
+
+    match CStr::from_ptr(string).to_str() {
+        Ok(string) => Ok(Some(string.to_string())),
+        Err(_) => Err(ConfigureError::FfiArgumentNotValid),
+    }
+}
+
+fn string_to_c(string: String) -> *mut c_char {
+    match CString::new(string) {
+        Ok(string) => string.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// C callers get `0` for success, or the error's code – the error itself is logged
+fn result_to_c(result: Result<(), ConfigureError>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            error!("{}", err);
+            err.code()
+        }
+    }
+}
+
+/// Run one of the library's functions that panics on failure, without letting the panic unwind into C
+#[cfg(not(feature = "apple"))]
+fn unit_to_c<F>(function: F) -> c_int
+where
+    F: FnOnce() + std::panic::UnwindSafe,
+{
+    match std::panic::catch_unwind(function) {
+        Ok(()) => 0,
+        Err(_) => CONFIGURE_ERROR_UNKNOWN,
+    }
+}
+
+/// `result_to_c` for the library's functions that can still panic partway through, like when a prompt fails
+#[cfg(not(feature = "apple"))]
+fn result_to_c_catching_panics<F>(function: F) -> c_int
+where
+    F: FnOnce() -> Result<(), ConfigureError> + std::panic::UnwindSafe,
+{
+    match std::panic::catch_unwind(function) {
+        Ok(result) => result_to_c(result),
+        Err(_) => CONFIGURE_ERROR_UNKNOWN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+
+    #[test]
+    fn test_that_single_file_encryption_works_through_the_c_api() {
+        let contents: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(64)
+            .map(char::from)
+            .collect();
+
+        let directory =
+            std::env::temp_dir().join(format!("configure-ffi-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let input_file_path = directory.join("secrets.json");
+        let output_file_path = directory.join("decrypted.json");
+        std::fs::write(&input_file_path, &contents).unwrap();
+
+        let key = configure_generate_encryption_key();
+        let input = CString::new(input_file_path.to_str().unwrap()).unwrap();
+        let encrypted = CString::new(directory.join("secrets.json.enc").to_str().unwrap()).unwrap();
+        let output = CString::new(output_file_path.to_str().unwrap()).unwrap();
+
+        unsafe {
+            assert_eq!(
+                configure_encrypt_file(input.as_ptr(), std::ptr::null(), key),
+                0
+            );
+            assert_eq!(
+                configure_decrypt_file(encrypted.as_ptr(), output.as_ptr(), key),
+                0
+            );
+            configure_free_string(key);
+        }
+
+        assert_eq!(
+            std::fs::read_to_string(&output_file_path).unwrap(),
+            contents
+        );
+    }
+
+    #[test]
+    fn test_that_c_api_returns_error_code_for_null_arguments() {
+        let code =
+            unsafe { configure_encrypt_file(std::ptr::null(), std::ptr::null(), std::ptr::null()) };
+        assert_eq!(code, ConfigureError::FfiArgumentNotValid.code());
+    }
+
+    #[test]
+    #[cfg(not(feature = "apple"))]
+    fn test_that_panics_are_not_passed_to_c_callers() {
+        assert_eq!(
+            unit_to_c(|| panic!("Expected panic")),
+            CONFIGURE_ERROR_UNKNOWN
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "apple"))]
+    fn test_that_a_missing_configuration_file_is_returned_as_its_error_code() {
+        let path = std::env::temp_dir()
+            .join("configure-ffi-test-missing")
+            .join(".configure");
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+
+        let code = unsafe { configure_apply(false, path.as_ptr()) };
+        assert_eq!(
+            code,
+            ConfigureError::ConfigureFileNotReadable {
+                path: PathBuf::new()
+            }
+            .code()
+        );
+    }
+
+    #[test]
+    fn test_that_level_filter_from_c_int_maps_levels_in_order() {
+        assert_eq!(level_filter_from_c_int(0), LevelFilter::Off);
+        assert_eq!(level_filter_from_c_int(1), LevelFilter::Error);
+        assert_eq!(level_filter_from_c_int(3), LevelFilter::Info);
+        assert_eq!(level_filter_from_c_int(5), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_that_level_filter_from_c_int_clamps_out_of_range_values() {
+        assert_eq!(level_filter_from_c_int(-4), LevelFilter::Off);
+        assert_eq!(level_filter_from_c_int(42), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_that_c_log_levels_match_log_crate_levels() {
+        assert_eq!(Level::Error as c_int, 1);
+        assert_eq!(Level::Trace as c_int, 5);
+    }
+}
@@ -0,0 +1,59 @@
+//! Entry points for Xcode build phases
+//!
+//! Built with the `apple` feature, which also leaves out the parts of the C interface that prompt in a terminal –
+//! there's nobody to answer them during an Xcode build. Link the static library and import `include/apple`,
+//! whose `module.modulemap` lets Swift `import Configure` directly.
+
+use super::{optional_string_from_c, result_to_c};
+use crate::configure::ApplyOptions;
+use crate::fs::{decrypt_files_for_configuration, read_verified_configuration_from_file};
+use libc::{c_char, c_int};
+use log::warn;
+
+/// Decrypt the project's secrets without prompting
+///
+/// Files that were modified locally are overwritten (a backup is kept), and files that haven't changed since the
+/// last apply are skipped. The encryption key comes from the keys file or `CONFIGURE_ENCRYPTION_KEY`, as usual.
+///
+/// # Safety
+///
+/// `configuration_file_path` may be null to use the default configuration file, otherwise it must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn configure_apply_for_build(
+    configuration_file_path: *const c_char,
+) -> c_int {
+    result_to_c((|| {
+        let configuration_file_path = optional_string_from_c(configuration_file_path)?;
+        let configuration = read_verified_configuration_from_file(&configuration_file_path)?;
+
+        if configuration.is_empty() {
+            warn!("Unable to apply configuration – it is empty");
+            return Ok(());
+        }
+
+        decrypt_files_for_configuration(&configuration, &ApplyOptions::default())
+    })())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configure::ConfigureError;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_that_applying_for_build_reports_a_missing_configuration_file() {
+        let path = std::env::temp_dir().join("configure-apple-test-missing/.configure");
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+
+        let code = unsafe { configure_apply_for_build(path.as_ptr()) };
+
+        assert_eq!(
+            code,
+            ConfigureError::ConfigureFileNotReadable {
+                path: Default::default()
+            }
+            .code()
+        );
+    }
+}
@@ -1,21 +1,67 @@
-use crate::encryption::{decrypt_file, encrypt_file, generate_key};
-use crate::Configuration;
-use crate::ConfigureError;
+use crate::archive::{
+    create_archive, create_archive_from_entries, extract_archive, manifest_for_archive,
+    manifest_for_directory,
+};
+use crate::configure::regenerate_missing_encrypted_files;
+use crate::encryption::{
+    add_provenance, decrypt_bytes, decrypt_file_contents_with_keys, encrypt_bytes_with_mode,
+    generate_key, read_provenance, NonceMode, Provenance,
+};
+use crate::events::{emit, Event};
+use crate::git::{check_out_submodule, SecretsRepo};
+use crate::keys::{KeyRole, KeysFile};
+use crate::local::{LocalOverrides, LOCAL_OVERRIDES_FILE_NAME};
+use crate::lock::{FileLock, LOCK_TIMEOUT};
+use crate::paths::{add_extension, canonicalize, parent_directory, portable};
+use crate::schema::{
+    check_tool_version, report_unknown_fields, unknown_configure_fields, TOOL_VERSION,
+};
+use crate::settings::settings;
+use crate::signing::{signature_path_for, SigningKey, VerifyingKey};
+use crate::state::{
+    ApplyState, ConfigurationStatus, EncryptedFilesManifest, FileList, FileStatus, ManagedFile,
+};
+use crate::timings::{self, measure, Phase};
+use crate::transaction::StagedChanges;
+use crate::ui::prompt_secret;
 use crate::EncryptionKey;
-use log::{debug, info};
+use crate::{
+    ApplyOptions, CancellationToken, Configuration, ConfigureError, ConflictResolution,
+    PreviousFileResolution,
+};
+use log::{debug, info, warn};
 use ring::digest::{Context, SHA256};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::fs::{create_dir_all, remove_file, rename, File};
-use std::io::{BufReader, Error, Read, Write};
+use std::fs::{create_dir_all, rename, write, File};
+use std::io::{BufReader, Error, IsTerminal, Read, Write};
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use zeroize::Zeroizing;
 
 /// Find the .configure file in the current project
 pub fn find_configure_file() -> Result<PathBuf, ConfigureError> {
     let configure_file_path = get_configure_file_path()?;
 
+    // Creating one here would leave an empty `.configure` in any repo the tool was run in by mistake
+    if !configure_file_path.exists() {
+        return Err(ConfigureError::ConfigureFileNotFound {
+            path: configure_file_path,
+        });
+    }
+
+    debug!("Configure file found at: {:?}", configure_file_path);
+
+    Ok(configure_file_path)
+}
+
+/// The project's `.configure` file, creating an empty one if there isn't one yet – only `init` should do this
+pub fn find_or_create_configure_file() -> Result<PathBuf, ConfigureError> {
+    let configure_file_path = get_configure_file_path()?;
+
     if !configure_file_path.exists() {
         info!(
             "No configure file found at: {:?}. Creating one for you",
@@ -25,14 +71,162 @@ pub fn find_configure_file() -> Result<PathBuf, ConfigureError> {
         write_configuration_to(&Configuration::default(), &configure_file_path)?
     }
 
-    debug!("Configure file found at: {:?}", configure_file_path);
-
     Ok(configure_file_path)
 }
 
+/// Every `.configure` file under `root`, for applying several projects in a monorepo at once
+///
+/// Hidden directories (like `.git` and `.configure-files`) aren't searched, and symbolic links aren't followed.
+pub fn find_configure_files_in(root: &Path) -> Result<Vec<PathBuf>, ConfigureError> {
+    let mut configure_files = Vec::new();
+    let mut directories = vec![root.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let name = entry.file_name();
+
+            if file_type.is_file() && name == ".configure" {
+                configure_files.push(entry.path());
+            } else if file_type.is_dir() && !name.to_string_lossy().starts_with('.') {
+                directories.push(entry.path());
+            }
+        }
+    }
+
+    configure_files.sort();
+    Ok(configure_files)
+}
+
+/// Every file in the secrets repo that could be copied into a project, as `/`-separated paths from its root
+///
+/// Hidden files and directories (like `.git`) are left out, and so is `keys.json` – it holds the keys themselves.
+pub fn find_files_in_secrets_repo(secrets_root: &Path) -> Result<Vec<String>, ConfigureError> {
+    let mut files = Vec::new();
+    let mut directories = vec![PathBuf::new()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(secrets_root.join(&directory))? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let name = entry.file_name();
+
+            if name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let path = directory.join(&name);
+            if file_type.is_dir() {
+                directories.push(path);
+            } else if file_type.is_file() && path != Path::new("keys.json") {
+                files.push(portable(&path.to_string_lossy()));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// The `.configure` file nearest the current directory, or the one at the project root if there isn't one on the way
 fn get_configure_file_path() -> Result<PathBuf, ConfigureError> {
     let project_root = find_project_root()?;
-    Ok(project_root.join(".configure"))
+    let current_dir = env::current_dir().expect("Unable to determine current directory");
+
+    match nearest_configure_file(&current_dir, &project_root) {
+        Some(path) => Ok(path),
+        None => Ok(project_root.join(".configure")),
+    }
+}
+
+/// The first `.configure` file in `directory` or the directories above it, without going past `root`
+///
+/// This lets each module of a monorepo have its own `.configure` file, used when the tool is run from inside it.
+/// Nothing is found when `directory` isn't inside `root`.
+pub fn nearest_configure_file(directory: &Path, root: &Path) -> Option<PathBuf> {
+    let directory = canonicalize(directory).ok()?;
+    let root = canonicalize(root).ok()?;
+
+    if !directory.starts_with(&root) {
+        return None;
+    }
+
+    directory
+        .ancestors()
+        .take_while(|ancestor| ancestor.starts_with(&root))
+        .map(|ancestor| ancestor.join(".configure"))
+        .find(|path| path.is_file())
+}
+
+/// The directory in `.configure-files` that the `.configure` file at `configure_file_path` keeps its files in
+///
+/// It's where the file is relative to the project root, so the root `.configure` file keeps using `.configure-files`
+/// itself and `android/.configure` uses `.configure-files/android`. A file with another name is told apart by its name
+/// too. One outside the project shares the root's.
+pub fn files_namespace_for(project_root: &Path, configure_file_path: &Path) -> PathBuf {
+    let project_root = canonicalize(project_root).unwrap_or_else(|_| project_root.to_path_buf());
+    let configure_file_path =
+        canonicalize(configure_file_path).unwrap_or_else(|_| configure_file_path.to_path_buf());
+
+    let relative_path = match configure_file_path.strip_prefix(&project_root) {
+        Ok(relative_path) => relative_path,
+        Err(_) => return PathBuf::new(),
+    };
+
+    if relative_path.file_name() == Some(std::ffi::OsStr::new(".configure")) {
+        relative_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    } else {
+        relative_path.to_path_buf()
+    }
+}
+
+fn get_state_file_path(configuration: &Configuration) -> Result<PathBuf, ConfigureError> {
+    let project_root = find_project_root()?;
+    Ok(configuration
+        .files_directory_in(&project_root)
+        .join("state.json"))
+}
+
+fn get_manifest_file_path(configuration: &Configuration) -> Result<PathBuf, ConfigureError> {
+    let project_root = find_project_root()?;
+    Ok(configuration
+        .files_directory_in(&project_root)
+        .join("manifest.json"))
+}
+
+/// Wait for any other `configure` process that's writing to the project to finish, then hold it off until the returned
+/// lock is dropped
+pub fn lock_project() -> Result<FileLock, ConfigureError> {
+    let lock_path = find_project_root()?.join(".configure-files").join(".lock");
+    FileLock::acquire(&lock_path, LOCK_TIMEOUT)
+}
+
+fn read_apply_state(configuration: &Configuration) -> Result<ApplyState, ConfigureError> {
+    ApplyState::read_from(&get_state_file_path(configuration)?)
+}
+
+/// The commit `configuration` used before the last time its pinned hash changed – what `rollback` goes back to
+pub fn previous_pinned_hash(configuration: &Configuration) -> Result<String, ConfigureError> {
+    match read_apply_state(configuration)?.previous_pinned_hash() {
+        Some(hash) => Ok(hash.to_string()),
+        None => Err(ConfigureError::NoPreviousPinnedHash),
+    }
+}
+
+/// Write the apply state, making sure git will ignore it – it only describes this machine
+fn save_apply_state(path: &Path, state: &ApplyState) -> Result<(), ConfigureError> {
+    create_parent_directory_for_path_if_not_exists(path)?;
+
+    let ignore_file_path = path.with_file_name(".gitignore");
+    if !ignore_file_path.exists() && write(&ignore_file_path, "state.json\n.lock\n").is_err() {
+        return Err(ConfigureError::StateFileNotWritable);
+    }
+
+    state.write_to(path)
 }
 
 pub fn find_keys_file() -> Result<PathBuf, ConfigureError> {
@@ -47,14 +241,41 @@ pub fn find_keys_file() -> Result<PathBuf, ConfigureError> {
             keys_file_path
         );
 
-        let empty_keys: HashMap<String, String> = Default::default();
-        save_keys(&keys_file_path, &empty_keys)?;
+        KeysFile::default().save(&keys_file_path)?;
     }
 
     Ok(keys_file_path)
 }
 
+static PROJECT_ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Use `path` as the project root, instead of the git repo the current directory is in
+///
+/// This is how projects that aren't in a git repo (or are in a bare CI checkout) use the tool.
+pub fn set_project_root(path: Option<&Path>) -> Result<(), ConfigureError> {
+    let path = match path {
+        Some(path) => match canonicalize(path) {
+            Ok(path) if path.is_dir() => Some(path),
+            _ => {
+                return Err(ConfigureError::ProjectRootNotFound {
+                    path: path.to_path_buf(),
+                })
+            }
+        },
+        None => None,
+    };
+
+    debug!("Using {:?} as the project root", path);
+    *PROJECT_ROOT.lock().unwrap_or_else(|err| err.into_inner()) = path;
+
+    Ok(())
+}
+
 pub fn find_project_root() -> Result<PathBuf, ConfigureError> {
+    if let Some(path) = &*PROJECT_ROOT.lock().unwrap_or_else(|err| err.into_inner()) {
+        return Ok(path.clone());
+    }
+
     let path = env::current_dir().expect("Unable to determine current directory");
 
     let repo = match git2::Repository::discover(&path) {
@@ -72,7 +293,33 @@ pub fn find_project_root() -> Result<PathBuf, ConfigureError> {
     Ok(project_root.to_path_buf())
 }
 
+static SECRETS_ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Use the secrets repo at `path`, instead of looking in `SECRETS_REPO`, the user's settings and the usual places
+pub fn set_secrets_root(path: Option<&Path>) -> Result<(), ConfigureError> {
+    let path = match path {
+        Some(path) => match canonicalize(path) {
+            Ok(path) if path.is_dir() => Some(path),
+            _ => {
+                return Err(ConfigureError::SecretsRootNotFound {
+                    path: path.to_path_buf(),
+                })
+            }
+        },
+        None => None,
+    };
+
+    debug!("Using {:?} as the secrets repo", path);
+    *SECRETS_ROOT.lock().unwrap_or_else(|err| err.into_inner()) = path;
+
+    Ok(())
+}
+
 pub fn find_secrets_repo() -> Result<PathBuf, ConfigureError> {
+    if let Some(path) = &*SECRETS_ROOT.lock().unwrap_or_else(|err| err.into_inner()) {
+        return Ok(path.clone());
+    }
+
     // Allow developers to specify where they want the secrets repo to be located using an environment variable
     if let Ok(var) = env::var(crate::SECRETS_KEY_NAME) {
         let user_secrets_path = Path::new(&var);
@@ -82,6 +329,22 @@ pub fn find_secrets_repo() -> Result<PathBuf, ConfigureError> {
         }
     }
 
+    // A project can keep the secrets repo as a submodule, so it doesn't depend on where anyone cloned theirs
+    if let Some(path) = find_secrets_submodule()? {
+        return Ok(path);
+    }
+
+    if let Some(settings_path) = &settings().secrets_repo {
+        if settings_path.is_dir() {
+            return Ok(settings_path.clone());
+        }
+
+        warn!(
+            "The secrets repo in your settings ({:?}) isn't a directory – looking in the default locations instead",
+            settings_path
+        );
+    }
+
     let home_dir = dirs::home_dir().expect("Unable to determine user home directory");
     let root_secrets_path = home_dir.join(".mobile-secrets");
 
@@ -101,6 +364,30 @@ pub fn find_secrets_repo() -> Result<PathBuf, ConfigureError> {
     Err(crate::configure::ConfigureError::SecretsNotPresent)
 }
 
+/// The secrets repo submodule named in the project's `.configure` file, checked out if it wasn't already
+///
+/// Projects that don't name one (or that can't be found or read yet) don't have one.
+fn find_secrets_submodule() -> Result<Option<PathBuf>, ConfigureError> {
+    let project_root = match find_project_root() {
+        Ok(project_root) => project_root,
+        Err(_) => return Ok(None),
+    };
+
+    let configure_file_path = project_root.join(".configure");
+    let submodule = read_configure_file_contents(&configure_file_path)
+        .and_then(|contents| parse_configuration(&configure_file_path, contents))
+        .ok()
+        .and_then(|configuration| configuration.secrets_submodule);
+
+    match submodule {
+        Some(submodule) => {
+            debug!("Using the secrets repo submodule at {:?}", submodule);
+            Ok(Some(check_out_submodule(&project_root, &submodule)?))
+        }
+        None => Ok(None),
+    }
+}
+
 pub fn read_configuration() -> Result<Configuration, ConfigureError> {
     read_configuration_from_file(&None)
 }
@@ -117,24 +404,164 @@ pub fn resolve_configure_file_path(
 pub fn read_configuration_from_file(
     configure_file_path: &Option<String>,
 ) -> Result<Configuration, ConfigureError> {
-    let configure_file_path = resolve_configure_file_path(configure_file_path)?;
+    read_configuration_at(&resolve_configure_file_path(configure_file_path)?)
+}
+
+/// Read the `.configure` file at `configure_file_path`, checking its fields and the version of the tool that wrote it
+pub fn read_configuration_at(configure_file_path: &Path) -> Result<Configuration, ConfigureError> {
+    let contents = read_configure_file_contents(configure_file_path)?;
+    let mut configuration = parse_configuration(configure_file_path, contents.clone())?;
+
+    if let Ok(project_root) = find_project_root() {
+        configuration.set_files_namespace(files_namespace_for(&project_root, configure_file_path));
+    }
+
+    report_unknown_fields(configure_file_path, unknown_configure_fields(&contents))?;
+
+    let tool_version = serde_json::from_str::<serde_json::Value>(&contents)
+        .ok()
+        .and_then(|value| value["tool_version"].as_str().map(str::to_string));
+    check_tool_version(configure_file_path, tool_version.as_deref())?;
+
+    Ok(configuration)
+}
+
+/// Parse the contents of the `.configure` file at `configure_file_path`, filling in anything it inherits or includes
+pub fn parse_configuration(
+    configure_file_path: &Path,
+    contents: String,
+) -> Result<Configuration, ConfigureError> {
+    let mut configuration = parse_extended_configuration(configure_file_path, contents)?;
+
+    let fragments = configuration
+        .include
+        .iter()
+        .map(|include| {
+            let path = include_path(configure_file_path, include)?;
+            debug!("{:?} includes {:?}", configure_file_path, path);
+
+            let fragment = std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str(&contents).ok())
+                .ok_or(ConfigureError::IncludeNotValid { path })?;
+
+            Ok((include.clone(), fragment))
+        })
+        .collect::<Result<Vec<_>, ConfigureError>>()?;
+
+    configuration.merge_included(fragments)?;
+    Ok(configuration)
+}
+
+/// Where `include` is, for a `.configure` file at `configure_file_path`
+///
+/// Fragments are looked up next to the `.configure` file, and can't reach outside its directory – an include is
+/// meant for files shared between a project's `.configure` files, not arbitrary files elsewhere on disk.
+fn include_path(configure_file_path: &Path, include: &str) -> Result<PathBuf, ConfigureError> {
+    let relative_path = Path::new(include);
+
+    let escapes_directory = relative_path.components().any(|component| {
+        !matches!(
+            component,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    });
+
+    if escapes_directory {
+        return Err(ConfigureError::IncludePathNotValid {
+            include: include.to_string(),
+        });
+    }
+
+    Ok(parent_directory(configure_file_path).join(relative_path))
+}
+
+/// The file the `.configure` file with `contents` extends, if it extends one
+fn extended_configuration_path(
+    configure_file_path: &Path,
+    contents: &str,
+) -> Result<Option<PathBuf>, ConfigureError> {
+    match serde_json::from_str::<serde_json::Value>(contents) {
+        Ok(value) => Ok(value["extends"]
+            .as_str()
+            .map(|extends| parent_directory(configure_file_path).join(extends))),
+        Err(_) => Err(ConfigureError::ConfigureFileNotValid),
+    }
+}
+
+/// Every other file the configuration at `configure_file_path` was read from – the file it extends, and its includes
+pub fn configuration_source_files(
+    configure_file_path: &Path,
+    configuration: &Configuration,
+) -> Result<Vec<PathBuf>, ConfigureError> {
+    let contents = read_configure_file_contents(configure_file_path)?;
+    let mut paths: Vec<PathBuf> = extended_configuration_path(configure_file_path, &contents)?
+        .into_iter()
+        .collect();
+
+    for include in &configuration.include {
+        paths.push(include_path(configure_file_path, include)?);
+    }
+
+    Ok(paths)
+}
+
+fn parse_extended_configuration(
+    configure_file_path: &Path,
+    contents: String,
+) -> Result<Configuration, ConfigureError> {
+    let parent_path = match extended_configuration_path(configure_file_path, &contents)? {
+        Some(parent_path) => parent_path,
+        None => return contents.parse(),
+    };
+
+    debug!("{:?} extends {:?}", configure_file_path, parent_path);
+
+    let parent_contents = match read_configure_file_contents(&parent_path) {
+        Ok(contents) => contents,
+        Err(_) => return Err(ConfigureError::ConfigureFileCannotBeExtended { path: parent_path }),
+    };
+
+    // Only one level is allowed, so files can't extend each other in a loop
+    let parent_extends = serde_json::from_str::<serde_json::Value>(&parent_contents)
+        .map(|value| !value["extends"].is_null())
+        .unwrap_or(false);
+
+    if parent_extends {
+        return Err(ConfigureError::ConfigureFileCannotBeExtended { path: parent_path });
+    }
+
+    Configuration::from_str_extending(contents, parent_contents)
+}
 
+/// The `.configure` file's JSON, as it's written – before anything serde doesn't recognise is dropped
+pub fn read_configure_file_contents(configure_file_path: &Path) -> Result<String, ConfigureError> {
     if !configure_file_path.is_file() {
-        return Err(ConfigureError::ConfigureFileNotReadable);
+        return Err(ConfigureError::ConfigureFileNotReadable {
+            path: configure_file_path.to_path_buf(),
+        });
     }
 
-    let mut file = match File::open(&configure_file_path) {
+    let mut file = match File::open(configure_file_path) {
         Ok(file) => file,
-        Err(_) => return Err(ConfigureError::ConfigureFileNotReadable),
+        Err(_) => {
+            return Err(ConfigureError::ConfigureFileNotReadable {
+                path: configure_file_path.to_path_buf(),
+            })
+        }
     };
 
     let mut file_contents = String::new();
     match file.read_to_string(&mut file_contents) {
         Ok(_) => (), // no-op
-        Err(_) => return Err(ConfigureError::ConfigureFileNotReadable),
+        Err(_) => {
+            return Err(ConfigureError::ConfigureFileNotReadable {
+                path: configure_file_path.to_path_buf(),
+            })
+        }
     };
 
-    Configuration::from_str(file_contents)
+    Ok(file_contents)
 }
 
 pub fn write_configuration(configuration: &Configuration) -> Result<(), ConfigureError> {
@@ -146,18 +573,24 @@ pub fn write_configuration_to(
     configuration: &Configuration,
     configure_file: &Path,
 ) -> Result<(), ConfigureError> {
-    let serialized = configuration.to_string()?;
+    let serialized = configuration.to_json()?;
 
     debug!("Writing to: {:?}", configure_file);
 
     let mut file = match File::create(configure_file) {
         Ok(file) => file,
-        Err(_) => return Err(ConfigureError::ConfigureFileNotWritable),
+        Err(_) => {
+            return Err(ConfigureError::ConfigureFileNotWritable {
+                path: configure_file.to_path_buf(),
+            })
+        }
     };
 
     match file.write_all(serialized.as_bytes()) {
         Ok(_) => Ok(()),
-        Err(_) => Err(ConfigureError::ConfigureFileNotWritable),
+        Err(_) => Err(ConfigureError::ConfigureFileNotWritable {
+            path: configure_file.to_path_buf(),
+        }),
     }
 }
 
@@ -170,68 +603,102 @@ pub fn generate_encryption_key_if_needed(
 
     let keys_file_path = find_keys_file()?;
 
-    let mut keys = read_keys(&keys_file_path)?;
-    keys.insert(
-        configuration.project_name.to_string(),
-        generate_key().to_string(),
-    );
+    let mut keys_file = KeysFile::read(&keys_file_path)?;
+    keys_file.insert(configuration.key_name(), generate_key()?.to_string());
+    keys_file.save(&keys_file_path)
+}
 
-    save_keys(&keys_file_path, &keys)
+/// Whether `keys.json` has an entry named `key_name` – `false` if there's no secrets repo to look in
+pub fn has_keys_named(key_name: &str) -> Result<bool, ConfigureError> {
+    let keys_file_path = match find_secrets_repo() {
+        Ok(secrets_root) => secrets_root.join("keys.json"),
+        Err(_) => return Ok(false),
+    };
+
+    if !keys_file_path.exists() {
+        return Ok(false);
+    }
+
+    Ok(KeysFile::read(&keys_file_path)?.contains(key_name))
 }
 
-pub fn encryption_key_for_configuration(
-    configuration: &Configuration,
-) -> Result<EncryptionKey, ConfigureError> {
+/// Move the keys stored under `key_name` to `new_key_name`, in both `keys.json` and `signing_keys.json`
+///
+/// With `keep_original`, the keys are copied instead, so other projects using the old name keep working.
+pub fn rename_keys(
+    key_name: &str,
+    new_key_name: &str,
+    keep_original: bool,
+) -> Result<(), ConfigureError> {
     let keys_file_path = find_keys_file()?;
+    let mut keys_file = KeysFile::read(&keys_file_path)?;
+    keys_file.rename(key_name, new_key_name, keep_original)?;
+    keys_file.save(&keys_file_path)?;
 
-    debug!("Reading keys from {:?}", keys_file_path);
-
-    let keys = read_keys(&keys_file_path)?;
+    let signing_keys_file_path = find_signing_keys_file()?;
+    let mut signing_keys = read_signing_keys(&signing_keys_file_path)?;
 
-    // This is the first key that matches in the `keys.json` file
-    let key = match keys.get(&configuration.project_name) {
-        Some(key) => key,
-        None => return Err(ConfigureError::MissingProjectKey),
+    let signing_key = match keep_original {
+        true => signing_keys.get(key_name).cloned(),
+        false => signing_keys.remove(key_name),
     };
 
-    EncryptionKey::from_str(key)
+    if let Some(signing_key) = signing_key {
+        signing_keys.insert(new_key_name.to_string(), signing_key);
+        save_signing_keys(&signing_keys_file_path, &signing_keys)?;
+    }
+
+    Ok(())
 }
 
-fn read_keys(source: &Path) -> Result<HashMap<String, String>, ConfigureError> {
-    let file = match File::open(&source) {
-        Ok(file) => file,
-        Err(_) => return Err(ConfigureError::KeysFileNotReadable),
-    };
+pub fn encryption_key_for_configuration(
+    configuration: &Configuration,
+) -> Result<EncryptionKey, ConfigureError> {
+    if configuration.passphrase_encryption {
+        return passphrase_key_for_configuration(configuration);
+    }
 
-    let map: HashMap<String, String> = match serde_json::from_reader(file) {
-        Ok(map) => map,
-        Err(_) => return Err(ConfigureError::KeysFileIsNotValid),
-    };
+    if let Some(name) = &configuration.key_provider {
+        let provider = crate::plugins::key_provider(configuration, name)?;
 
-    Ok(map)
-}
+        match provider.key(configuration.key_name())? {
+            Some(key) => return Ok(key),
+            None => debug!(
+                "The {:?} plugin doesn't have a key for {} – looking in keys.json",
+                name,
+                configuration.key_name()
+            ),
+        }
+    }
 
-fn save_keys(destination: &Path, keys: &HashMap<String, String>) -> Result<(), ConfigureError> {
-    let json = match serde_json::to_string_pretty(&keys) {
-        Ok(json) => json,
-        Err(_) => return Err(ConfigureError::KeysDataIsNotValid),
-    };
+    let keys_file_path = find_keys_file()?;
 
-    let mut file = match File::create(destination) {
-        Ok(file) => file,
-        Err(_) => return Err(ConfigureError::KeysFileNotWritable),
+    debug!("Reading keys from {:?}", keys_file_path);
+
+    let keys_file = KeysFile::read(&keys_file_path)?;
+
+    let key = match keys_file.current_key(configuration.key_name()) {
+        Some(key) => &key.key,
+        None => {
+            return Err(ConfigureError::MissingProjectKey {
+                project: configuration.key_name().to_string(),
+            })
+        }
     };
 
-    match file.write_all(json.as_bytes()) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(ConfigureError::KeysFileNotWritable),
-    }
+    EncryptionKey::from_str(key)
 }
 
-pub fn decrypt_files_for_configuration(
+/// Find the key that should be used to decrypt this project's files
+///
+/// Environment variables take priority over the project's entry in `keys.json`.
+pub fn decryption_key_for_configuration(
     configuration: &Configuration,
-) -> Result<(), ConfigureError> {
-    let project_root = find_project_root()?;
+) -> Result<EncryptionKey, ConfigureError> {
+    // A raw key can't decrypt passphrase-encrypted files, so the key overrides below don't apply to these projects
+    if configuration.passphrase_encryption {
+        return passphrase_key_for_configuration(configuration);
+    }
 
     let encryption_key: EncryptionKey;
 
@@ -241,161 +708,1322 @@ pub fn decrypt_files_for_configuration(
     //
     // We also have two sets of environment variables we accept – this makes it easier to transition between versions of the `configure` tool in production.
     // We check the temporary variable first, because it should override the permanent one when both are present
-    if let Ok(var) = env::var(crate::TEMP_ENCRYPTION_KEY_NAME) {
-        println!(
+    if let Ok(var) = env::var(crate::TEMP_ENCRYPTION_KEY_NAME).map(Zeroizing::new) {
+        info!(
             "Found an environment variable named {:}. Using its value as the encryption key",
             crate::TEMP_ENCRYPTION_KEY_NAME
         );
         encryption_key = EncryptionKey::from_str(&var)?;
-    } else if let Ok(var) = env::var(crate::ENCRYPTION_KEY_NAME) {
-        println!(
+    } else if let Ok(var) = env::var(crate::ENCRYPTION_KEY_NAME).map(Zeroizing::new) {
+        info!(
             "Found an environment variable named {:}. Using its value as the encryption key",
             crate::ENCRYPTION_KEY_NAME
         );
         encryption_key = EncryptionKey::from_str(&var)?;
+    } else if let Some(var) = crate::ci::key_from_ci() {
+        encryption_key = EncryptionKey::from_str(&var)?;
     } else if let Ok(var) = encryption_key_for_configuration(configuration) {
         encryption_key = var;
     } else {
         return Err(ConfigureError::MissingDecryptionKey);
     }
 
-    for file in &configuration.files_to_copy {
-        let source = project_root.join(&file.get_encrypted_destination());
-        let destination = project_root.join(&file.get_decrypted_destination());
+    Ok(encryption_key)
+}
 
-        create_parent_directory_for_path_if_not_exists(&destination)?;
+/// Every key that might decrypt this project's files, in the order they should be tried
+///
+/// That's the key from `decryption_key_for_configuration`, followed by the keys the project used before its key was
+/// rotated – an older pinned hash can still point at files encrypted with one of them. Keys set in environment
+/// variables or CI secrets replace `keys.json` entirely, so they're never combined with its older keys.
+pub fn decryption_keys_for_configuration(
+    configuration: &Configuration,
+) -> Result<Vec<EncryptionKey>, ConfigureError> {
+    let mut keys = vec![decryption_key_for_configuration(configuration)?];
+
+    let has_key_override = env::var(crate::TEMP_ENCRYPTION_KEY_NAME).is_ok()
+        || env::var(crate::ENCRYPTION_KEY_NAME).is_ok()
+        || crate::ci::key_from_ci().is_some();
+
+    // Older keys only come from `keys.json`, which a project with a key provider may not have
+    if configuration.passphrase_encryption
+        || has_key_override
+        || configuration.key_provider.is_some()
+    {
+        return Ok(keys);
+    }
 
-        // If the developer tries to run `configure_apply` while missing the encrypted originals, this script will crash saying "missing file"
-        // We can try to detect this scenario and fix things for the developer if the mobile secrets are available locally, but it's tricky because
-        // we'd need to basically run `configure update` inside this method for just the one file. For now, we'll just error out.
-        if !source.exists() {
-            info!("Encrypted original file at {:?} not found", source);
-            return Err(ConfigureError::EncryptedFileMissing {});
-        }
+    let keys_file = KeysFile::read(&find_keys_file()?)?;
 
-        // If the file already exists, make a backup of the old one in case we need it later
-        if destination.exists() {
-            let backup_destination = project_root.join(&file.get_backup_destination());
+    for stored_key in keys_file.keys_for_project(configuration.key_name()) {
+        if stored_key.role != KeyRole::Deprecated {
+            continue;
+        }
 
-            debug!(
-                "{:?} already exists – making a backup at {:?}",
-                destination, backup_destination
-            );
-            rename(&destination, &backup_destination)?;
+        match EncryptionKey::from_str(&stored_key.key) {
+            Ok(key) => keys.push(key),
+            Err(err) => warn!(
+                "Skipping a deprecated key for {} that isn't valid: {}",
+                configuration.key_name(),
+                err
+            ),
+        }
+    }
 
-            // Encrypt the file and write the encrypted contents to the destination
-            debug!(
-                "Encrypting file at {:?} and storing contents at {:?}",
-                source, destination
-            );
-            decrypt_file(&source, &destination, &encryption_key)?;
+    Ok(keys)
+}
 
-            // If the backup file is identical to the old file, remove the backup
-            let new_file_hash = hash_file(&destination);
-            let original_file_hash = hash_file(&backup_destination);
+// Remembered for the rest of the run, so an `update` that encrypts and then decrypts only asks once
+static PASSPHRASE: Mutex<Option<Zeroizing<String>>> = Mutex::new(None);
 
-            debug!("Original File Hash: {:?}", original_file_hash);
-            debug!("New File hash: {:?}", new_file_hash);
+/// The key for a project that uses `passphrase_encryption`
+///
+/// The passphrase comes from an environment variable if it's set. Otherwise the user is asked for it, as long as
+/// there's someone at the terminal to answer.
+fn passphrase_key_for_configuration(
+    configuration: &Configuration,
+) -> Result<EncryptionKey, ConfigureError> {
+    let mut passphrase = PASSPHRASE.lock().unwrap_or_else(|err| err.into_inner());
 
-            if hash_file(&destination)? == hash_file(&backup_destination)? {
-                debug!("Removing backup file because it's the same as the original");
-                remove_file(&backup_destination)?;
-            } else {
-                debug!("Keeping backup file because it differs from the original");
-            }
-        } else {
-            // Encrypt the file and write the encrypted contents to the destination
-            debug!(
-                "Encrypting file at {:?} and storing contents at {:?}",
-                source, destination
+    if passphrase.is_none() {
+        if let Ok(var) = env::var(crate::PASSPHRASE_KEY_NAME) {
+            info!(
+                "Found an environment variable named {:}. Using its value as the passphrase",
+                crate::PASSPHRASE_KEY_NAME
             );
-            decrypt_file(&source, &destination, &encryption_key)?;
+            *passphrase = Some(Zeroizing::new(var));
+        } else if std::io::stdin().is_terminal() && crate::ci::ci_environment().is_none() {
+            *passphrase = Some(Zeroizing::new(prompt_secret(
+                &format!("Passphrase for {}", configuration.project_name),
+                false,
+            )));
+        } else {
+            return Err(ConfigureError::MissingPassphrase);
         }
     }
 
-    Ok(())
+    EncryptionKey::from_passphrase(
+        passphrase
+            .as_ref()
+            .map_or("", |passphrase| passphrase.as_str()),
+    )
 }
 
-pub fn write_encrypted_files_for_configuration(
-    configuration: &Configuration,
-    encryption_key: EncryptionKey,
-) -> Result<(), ConfigureError> {
-    let project_root = find_project_root()?;
-    let secrets_root = find_secrets_repo()?;
-
-    for file in &configuration.files_to_copy {
-        let source = &secrets_root.join(&file.source);
-        let destination = project_root.join(&file.get_encrypted_destination());
+fn find_signing_keys_file() -> Result<PathBuf, ConfigureError> {
+    Ok(find_secrets_repo()?.join("signing_keys.json"))
+}
 
-        create_parent_directory_for_path_if_not_exists(&destination)?;
+fn read_signing_keys(source: &Path) -> Result<HashMap<String, String>, ConfigureError> {
+    // Signing is opt-in, so most secrets repos won't have this file
+    if !source.exists() {
+        return Ok(HashMap::new());
+    }
 
-        // Encrypt the file and write the encrypted contents to the destination
-        debug!(
-            "Encrypting file at {:?} and storing contents at {:?}",
-            source, destination
-        );
+    let file = match File::open(source) {
+        Ok(file) => file,
+        Err(_) => return Err(ConfigureError::SigningKeysFileNotReadable),
+    };
 
-        encrypt_file(source, &destination, &encryption_key)?;
+    match serde_json::from_reader(file) {
+        Ok(map) => Ok(map),
+        Err(_) => Err(ConfigureError::SigningKeysFileNotReadable),
     }
-
-    Ok(())
 }
 
-/// Returns the SHA-256 hash of a file at the given path
-fn hash_file(path: &Path) -> Result<String, Error> {
-    let input = File::open(path)?;
-    let mut reader = BufReader::new(input);
-    let mut context = Context::new(&SHA256);
-    let mut buffer = [0; 1024];
+fn save_signing_keys(
+    destination: &Path,
+    keys: &HashMap<String, String>,
+) -> Result<(), ConfigureError> {
+    let json = match serde_json::to_string_pretty(&keys) {
+        Ok(json) => json,
+        Err(_) => return Err(ConfigureError::SigningKeysFileNotWritable),
+    };
 
-    loop {
-        let count = reader.read(&mut buffer)?;
-        if count == 0 {
-            break;
-        }
-        context.update(&buffer[..count]);
+    match write(destination, json) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(ConfigureError::SigningKeysFileNotWritable),
     }
-
-    let digest = context.finish();
-
-    Ok(base64::encode(digest.as_ref()))
 }
 
-fn create_parent_directory_for_path_if_not_exists(path: &Path) -> Result<(), Error> {
-    let parent = match path.parent() {
-        Some(parent) => parent,
-        None => return Ok(()), // if we're in the root of the filesystem, we have no work to do
+/// The name the project's entry in `signing_keys.json` is stored under – its `origin` remote URL
+///
+/// This comes from the project repo's git config rather than the `.configure` file, so editing the file can't change
+/// which key it's checked against, or whether it's checked at all. Returns `None` for projects without an `origin`.
+fn project_signing_name() -> Result<Option<String>, ConfigureError> {
+    let repo = match git2::Repository::open(find_project_root()?) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(None),
     };
 
-    create_dir_all(parent)
+    let remote = match repo.find_remote("origin") {
+        Ok(remote) => remote,
+        Err(_) => return Ok(None),
+    };
+
+    Ok(remote.url().map(signing_name_for_remote_url))
 }
 
-pub fn infer_encryption_output_filename(path: &Path) -> PathBuf {
-    let mut string = path.to_path_buf().into_os_string().into_string().unwrap();
-    string.push_str(".enc");
-    Path::new(&string).to_path_buf()
+// `git@github.com:org/repo.git` and `git@github.com:org/repo` are the same project
+fn signing_name_for_remote_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url).to_string()
 }
 
-pub fn infer_decryption_output_filename(path: &Path) -> PathBuf {
-    let mut string = path.to_path_buf().into_os_string().into_string().unwrap();
+/// The project's signing key from `signing_keys.json`, if signing has been set up for it
+pub fn signing_key_for_configuration(
+    configuration: &Configuration,
+) -> Result<Option<SigningKey>, ConfigureError> {
+    let keys = read_signing_keys(&find_signing_keys_file()?)?;
 
-    if !string.ends_with(".enc") {
-        string.push_str(".decrypted");
-        return Path::new(&string).to_path_buf();
-    } else {
-        let filename_without_suffix: String = string
-            .chars()
-            .take(string.chars().count() - ".enc".chars().count())
-            .collect();
-        return Path::new(&filename_without_suffix).to_path_buf();
+    if let Some(key) = project_signing_name()?.and_then(|name| keys.get(&name)) {
+        return Ok(Some(SigningKey::from_str(key)?));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    // Import the parent scope
-    use super::*;
-    use std::ffi::OsStr;
+    // Keys used to be stored under the project's key name – they're still used for signing until they're moved
+    match keys.get(configuration.key_name()) {
+        Some(key) => Ok(Some(SigningKey::from_str(key)?)),
+        None => Ok(None),
+    }
+}
+
+/// The project's signing key, creating one in `signing_keys.json` if it doesn't have one yet
+///
+/// A key stored under the project's key name by an older version is moved to the project's remote URL, since that's
+/// the only place `apply` looks for it now.
+pub fn generate_signing_key_if_needed(
+    configuration: &Configuration,
+) -> Result<SigningKey, ConfigureError> {
+    let signing_name = match project_signing_name()? {
+        Some(signing_name) => signing_name,
+        None => return Err(ConfigureError::ProjectRemoteNotFound),
+    };
+
+    let signing_keys_file_path = find_signing_keys_file()?;
+    let mut keys = read_signing_keys(&signing_keys_file_path)?;
+
+    if let Some(key) = keys.get(&signing_name) {
+        return SigningKey::from_str(key);
+    }
+
+    let signing_key = match keys.remove(configuration.key_name()) {
+        Some(key) => {
+            info!(
+                "Moving the signing key for {:?} to {:?} – commit signing_keys.json in your secrets repo to share it",
+                configuration.key_name(),
+                signing_name
+            );
+            SigningKey::from_str(&key)?
+        }
+        None => {
+            info!(
+                "Creating a signing key for {:?} – commit signing_keys.json in your secrets repo to share it",
+                signing_name
+            );
+            SigningKey::generate()
+        }
+    };
+
+    keys.insert(signing_name, signing_key.to_string());
+    save_signing_keys(&signing_keys_file_path, &keys)?;
+
+    Ok(signing_key)
+}
+
+/// Find the key that `.configure` signatures for this project should be checked against
+///
+/// As with decryption, an environment variable takes priority so that CI machines without a secrets repo can still
+/// verify the file. Otherwise the key is the one stored under the project's remote URL – never anything named in the
+/// unverified `.configure` file. If there's no key at all, the project hasn't opted into signing.
+pub fn verifying_key_for_project() -> Result<Option<VerifyingKey>, ConfigureError> {
+    if let Ok(var) = env::var(crate::SIGNING_PUBLIC_KEY_NAME) {
+        debug!(
+            "Found an environment variable named {:}. Using its value as the signing key",
+            crate::SIGNING_PUBLIC_KEY_NAME
+        );
+        return Ok(Some(VerifyingKey::from_str(&var)?));
+    }
+
+    if find_secrets_repo().is_err() {
+        return Ok(None);
+    }
+
+    let signing_name = match project_signing_name()? {
+        Some(signing_name) => signing_name,
+        None => return Ok(None),
+    };
+
+    match read_signing_keys(&find_signing_keys_file()?)?.get(&signing_name) {
+        Some(key) => Ok(Some(SigningKey::from_str(key)?.verifying_key())),
+        None => Ok(None),
+    }
+}
+
+/// Write a detached signature for the `.configure` file at `configure_file_path`
+pub fn sign_configuration_file(
+    configure_file_path: &Path,
+    signing_key: &SigningKey,
+) -> Result<PathBuf, ConfigureError> {
+    let contents = match std::fs::read(configure_file_path) {
+        Ok(contents) => contents,
+        Err(_) => {
+            return Err(ConfigureError::ConfigureFileNotReadable {
+                path: configure_file_path.to_path_buf(),
+            })
+        }
+    };
+
+    let signature_path = signature_path_for(configure_file_path);
+
+    match write(&signature_path, signing_key.sign(&contents) + "\n") {
+        Ok(()) => Ok(signature_path),
+        Err(_) => Err(ConfigureError::ConfigureFileNotWritable {
+            path: signature_path,
+        }),
+    }
+}
+
+/// Check the `.configure` file at `configure_file_path`, and every file it extends or includes, against their
+/// detached signatures
+///
+/// Projects without a signing key aren't checked. Once a project has one, an unsigned or incorrectly signed file is
+/// an error – otherwise deleting the signature would be enough to get a change past this check. A signed file with no
+/// key to check it against is an error too, since the signature can't be trusted until it's been checked.
+pub fn verify_configuration_file(
+    configure_file_path: &Path,
+    configuration: &Configuration,
+) -> Result<(), ConfigureError> {
+    let signature_path = signature_path_for(configure_file_path);
+
+    let verifying_key = match verifying_key_for_project()? {
+        Some(verifying_key) => verifying_key,
+        None if signature_path.exists() => {
+            warn!(
+                "The .configure file is signed, but no signing key is available to verify it – set {:} or add the \
+                 key to signing_keys.json in your secrets repo",
+                crate::SIGNING_PUBLIC_KEY_NAME
+            );
+            return Err(ConfigureError::ConfigureFileSignatureInvalid);
+        }
+        None => return Ok(()),
+    };
+
+    verify_signature(&verifying_key, configure_file_path)?;
+
+    // An extended or included file changes the configuration as much as the `.configure` file itself does
+    for path in configuration_source_files(configure_file_path, configuration)? {
+        verify_signature(&verifying_key, &path)?;
+    }
+
+    debug!("The .configure file signature is valid");
+
+    Ok(())
+}
+
+fn verify_signature(verifying_key: &VerifyingKey, path: &Path) -> Result<(), ConfigureError> {
+    let signature_path = signature_path_for(path);
+
+    if !signature_path.exists() {
+        return Err(ConfigureError::ConfigureFileSignatureMissing {
+            path: signature_path,
+        });
+    }
+
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(_) => {
+            return Err(ConfigureError::ConfigureFileNotReadable {
+                path: path.to_path_buf(),
+            })
+        }
+    };
+
+    let signature = match std::fs::read_to_string(&signature_path) {
+        Ok(signature) => signature,
+        Err(_) => {
+            return Err(ConfigureError::ConfigureFileSignatureMissing {
+                path: signature_path,
+            })
+        }
+    };
+
+    verifying_key.verify(&contents, &signature)
+}
+
+/// Write the configuration, re-signing it if the project signs its `.configure` file
+pub fn write_signed_configuration_to(
+    configuration: &Configuration,
+    configure_file: &Path,
+) -> Result<(), ConfigureError> {
+    write_configuration_to(configuration, configure_file)?;
+
+    // Without a secrets repo there's no signing key to use – `apply` will point out the stale signature
+    if find_secrets_repo().is_err() {
+        return Ok(());
+    }
+
+    if let Some(signing_key) = signing_key_for_configuration(configuration)? {
+        sign_configuration_file(configure_file, &signing_key)?;
+    }
+
+    Ok(())
+}
+
+/// Stage the configuration (and its signature, if the project signs its `.configure` file) without writing it yet
+pub fn stage_signed_configuration(
+    configuration: &Configuration,
+    configure_file: &Path,
+    staged: &mut StagedChanges,
+) -> Result<(), ConfigureError> {
+    let serialized = configuration.to_json()?;
+
+    if find_secrets_repo().is_ok() {
+        if let Some(signing_key) = signing_key_for_configuration(configuration)? {
+            let signature = signing_key.sign(serialized.as_bytes()) + "\n";
+            staged.stage(signature_path_for(configure_file), signature.into_bytes());
+        }
+    }
+
+    staged.stage(configure_file.to_path_buf(), serialized.into_bytes());
+
+    Ok(())
+}
+
+/// Read the configuration file, refusing to use it if its signature doesn't check out
+pub fn read_verified_configuration_from_file(
+    configure_file_path: &Option<String>,
+) -> Result<Configuration, ConfigureError> {
+    let configuration = read_configuration_from_file(configure_file_path)?;
+    verify_configuration_file(
+        &resolve_configure_file_path(configure_file_path)?,
+        &configuration,
+    )?;
+
+    Ok(configuration)
+}
+
+/// Describe every file the configuration manages, including whether each copy of it exists
+pub fn list_files_for_configuration(
+    configuration: &Configuration,
+) -> Result<FileList, ConfigureError> {
+    let project_root = find_project_root()?;
+    let state = read_apply_state(configuration)?;
+
+    let files = configuration
+        .files_to_copy
+        .iter()
+        .map(|file| {
+            let encrypted_path = configuration.encrypted_destination_for(file);
+            let destination = project_root.join(file.get_decrypted_destination());
+
+            ManagedFile {
+                source: file.source.clone(),
+                encrypted_path: encrypted_path
+                    .strip_prefix(&project_root)
+                    .unwrap_or(&encrypted_path)
+                    .to_string_lossy()
+                    .to_string(),
+                destination: file.destination.clone(),
+                encrypted_file_exists: project_root.join(&encrypted_path).exists(),
+                destination_exists: destination.exists(),
+                last_applied_hash: state
+                    .applied_file(&file.destination)
+                    .map(|applied_file| applied_file.decrypted_hash.clone()),
+                expires: file.expires,
+            }
+        })
+        .collect();
+
+    Ok(FileList { files })
+}
+
+/// Compare each configured file against the record of the last successful apply
+pub fn status_for_configuration(
+    configuration: &Configuration,
+) -> Result<ConfigurationStatus, ConfigureError> {
+    let project_root = find_project_root()?;
+    let state = read_apply_state(configuration)?;
+    let overrides = LocalOverrides::read(&project_root)?;
+
+    let mut files = Vec::new();
+
+    for file in overrides.files_to_apply(configuration) {
+        let source = project_root.join(configuration.encrypted_destination_for(file));
+        let destination = project_root.join(overrides.destination_for(file));
+
+        let status = if !source.exists() {
+            FileStatus::EncryptedFileMissing
+        } else {
+            match state.applied_file(&file.destination) {
+                None => FileStatus::NotApplied,
+                Some(_) if !destination.exists() => FileStatus::DestinationMissing,
+                Some(applied_file) => {
+                    applied_file.status(&hash_file(&source)?, &hash_destination(&destination)?)
+                }
+            }
+        };
+
+        files.push((file.destination.clone(), status));
+    }
+
+    let last_applied_hash = match state.applied_at {
+        Some(_) => Some(state.pinned_hash),
+        None => None,
+    };
+
+    Ok(ConfigurationStatus {
+        pinned_hash: configuration.pinned_hash.clone(),
+        last_applied_hash,
+        applied_at: state.applied_at,
+        files,
+        provenance: provenance_for_configuration(configuration)?,
+    })
+}
+
+/// Decryption keys that have already been looked up, so applying several configurations only reads each key once
+#[derive(Debug, Default)]
+pub struct DecryptionKeys {
+    keys: HashMap<(bool, String), Vec<EncryptionKey>>,
+}
+
+impl DecryptionKeys {
+    /// Every key that might decrypt this configuration's files, looking them up the first time they're needed
+    pub fn for_configuration(
+        &mut self,
+        configuration: &Configuration,
+    ) -> Result<&[EncryptionKey], ConfigureError> {
+        let key = (
+            configuration.passphrase_encryption,
+            configuration.key_name().to_string(),
+        );
+
+        if !self.keys.contains_key(&key) {
+            let keys = decryption_keys_for_configuration(configuration)?;
+            self.keys.insert(key.clone(), keys);
+        }
+
+        Ok(&self.keys[&key])
+    }
+}
+
+pub fn decrypt_files_for_configuration(
+    configuration: &Configuration,
+    options: &ApplyOptions,
+) -> Result<(), ConfigureError> {
+    decrypt_files_for_configuration_with_keys(
+        configuration,
+        options,
+        &mut DecryptionKeys::default(),
+    )
+}
+
+/// Decrypt the configuration's files, using keys that were already looked up where possible
+pub fn decrypt_files_for_configuration_with_keys(
+    configuration: &Configuration,
+    options: &ApplyOptions,
+    decryption_keys: &mut DecryptionKeys,
+) -> Result<(), ConfigureError> {
+    let _lock = lock_project()?;
+    decrypt_files_while_locked(configuration, options, decryption_keys)
+}
+
+/// `decrypt_files_for_configuration_with_keys`, for an update that already holds the project's lock
+pub(crate) fn decrypt_files_while_locked(
+    configuration: &Configuration,
+    options: &ApplyOptions,
+    decryption_keys: &mut DecryptionKeys,
+) -> Result<(), ConfigureError> {
+    emit(Event::ApplyStarted);
+
+    let result = apply_files(configuration, options, decryption_keys);
+
+    match &result {
+        Ok(()) => emit(Event::ApplyFinished),
+        Err(err) => emit(Event::ApplyFailed { code: err.code() }),
+    }
+
+    result
+}
+
+fn apply_files(
+    configuration: &Configuration,
+    options: &ApplyOptions,
+    decryption_keys: &mut DecryptionKeys,
+) -> Result<(), ConfigureError> {
+    let project_root = find_project_root()?;
+
+    // A profile only applies some of the files, but the rest are still configured – they aren't retired below
+    let profile_configuration = match &options.profile {
+        Some(profile) => Some(configuration.for_profile(profile)?),
+        None => None,
+    };
+    let configured_files = configuration;
+    let configuration = profile_configuration.as_ref().unwrap_or(configured_files);
+
+    regenerate_missing_encrypted_files(configuration, options)?;
+
+    // A broken state file shouldn't stop the files from being applied, so treat every file as changed
+    let status = match status_for_configuration(configuration) {
+        Ok(status) if !options.force_decrypt => Some(status),
+        _ => None,
+    };
+
+    let state_file_path = get_state_file_path(configuration)?;
+    let mut state = read_apply_state(configuration).unwrap_or_else(|err| {
+        warn!("{} – local changes can't be detected for this run", err);
+        ApplyState::default()
+    });
+
+    let overrides = LocalOverrides::read(&project_root)?;
+    for destination in overrides.unknown_destinations(configured_files) {
+        warn!(
+            "{} mentions {:?}, but no file is decrypted there",
+            LOCAL_OVERRIDES_FILE_NAME, destination
+        );
+    }
+
+    let files = overrides.files_to_apply(configuration);
+    let destinations: Vec<&str> = overrides
+        .files_to_apply(configured_files)
+        .into_iter()
+        .map(|file| file.destination.as_str())
+        .collect();
+
+    state.retire_unconfigured_files(&destinations);
+    clean_up_previous_files(configured_files, &project_root, &mut state, options)?;
+
+    // Stale files are still applied – they're what's committed, and they might be exactly what's wanted
+    match encrypted_files_problems(configuration) {
+        Ok(problems) if !problems.is_empty() => {
+            for problem in &problems {
+                warn!("{}", problem);
+            }
+            warn!("Run `configure update` to regenerate the encrypted files from the pinned hash");
+        }
+        Ok(_) => (),
+        Err(err) => warn!(
+            "Unable to check whether the encrypted files are current: {}",
+            err
+        ),
+    }
+
+    if let Some(status) = &status {
+        if status.is_up_to_date() {
+            info!("Already up to date");
+            save_apply_state(&state_file_path, &state)?;
+            return Ok(());
+        }
+    }
+
+    let mut skipped_file_count = 0;
+
+    for (index, file) in files.into_iter().enumerate() {
+        // Record the files written so far, so the next apply knows they're up to date
+        if let Err(err) = options.cancellation.check() {
+            save_apply_state(&state_file_path, &state)?;
+            return Err(err);
+        }
+
+        let source = project_root.join(configuration.encrypted_destination_for(file));
+        let destination = project_root.join(overrides.destination_for(file));
+
+        let is_unchanged = match &status {
+            Some(status) => status.files[index].1 == FileStatus::UpToDate,
+            None => false,
+        };
+
+        if is_unchanged {
+            debug!(
+                "Skipping {:?} – it hasn't changed since the last apply",
+                destination
+            );
+            skipped_file_count += 1;
+            emit(Event::FileUnchanged { path: destination });
+            continue;
+        }
+
+        create_parent_directory_for_path_if_not_exists(&destination)?;
+
+        // Anything still missing couldn't be regenerated from the secrets repo, or the user chose not to
+        if !source.exists() {
+            info!("Encrypted original file at {:?} not found", source);
+            return Err(ConfigureError::EncryptedFileMissing { path: source });
+        }
+
+        // The keys are only looked up once we know there's something to decrypt
+        let encryption_keys = decryption_keys.for_configuration(configuration)?;
+
+        debug!("Decrypting file at {:?}", source);
+        let encrypted_file_hash = hash_file(&source)?;
+        let (decrypted_contents, key_index) =
+            decrypt_file_contents_with_keys(&source, encryption_keys)?;
+        let decrypted_contents =
+            crate::plugins::process_file(configuration, file, decrypted_contents)?;
+
+        if key_index > 0 {
+            info!(
+                "Decrypted {:?} with an older key for {} – its key has been rotated since",
+                source,
+                configuration.key_name()
+            );
+        }
+        let new_file_hash = hash_decrypted_contents(file, &decrypted_contents)?;
+
+        // If the file already exists, make a backup of the old one in case we need it later
+        if destination.exists() {
+            let original_file_hash = hash_destination(&destination)?;
+
+            debug!("Original File Hash: {:?}", original_file_hash);
+            debug!("New File hash: {:?}", new_file_hash);
+
+            if original_file_hash == new_file_hash {
+                debug!("{:?} is already up to date", destination);
+                state.record_applied_file(file, encrypted_file_hash, new_file_hash);
+                emit(Event::FileUnchanged { path: destination });
+                continue;
+            }
+
+            // The file differs from what we wrote last time, so someone edited it by hand
+            if state.is_locally_modified(&file.destination, &original_file_hash) {
+                // Directories are compared by their manifests, which list each file's hash
+                let (local_contents, new_contents) = if file.is_directory {
+                    (
+                        manifest_for_directory(&destination)?.into_bytes(),
+                        manifest_for_archive(&decrypted_contents)?.into_bytes(),
+                    )
+                } else {
+                    (std::fs::read(&destination)?, decrypted_contents.clone())
+                };
+
+                match options.resolve_conflict(&destination, &local_contents, &new_contents)? {
+                    ConflictResolution::Overwrite => {
+                        info!("Overwriting local changes to {:?}", destination)
+                    }
+                    ConflictResolution::Keep => {
+                        info!("Keeping local changes to {:?}", destination);
+                        state.record_kept_file(file, encrypted_file_hash, new_file_hash);
+                        emit(Event::FileKept { path: destination });
+                        continue;
+                    }
+                }
+            }
+
+            if settings().backups.enabled {
+                // Next to the file, even if `.configure.local` moved it
+                let backup_destination = match file.get_backup_destination().file_name() {
+                    Some(backup_name) => destination.with_file_name(backup_name),
+                    None => project_root.join(file.get_backup_destination()),
+                };
+
+                debug!(
+                    "{:?} already exists – making a backup at {:?}",
+                    destination, backup_destination
+                );
+                rename(&destination, &backup_destination)?;
+                emit(Event::BackupCreated {
+                    path: backup_destination,
+                });
+            } else if destination.is_dir() {
+                std::fs::remove_dir_all(&destination)?;
+            } else {
+                std::fs::remove_file(&destination)?;
+            }
+        }
+
+        debug!("Storing decrypted contents at {:?}", destination);
+        if file.is_directory {
+            extract_archive(&decrypted_contents, &destination)?;
+        } else if write(&destination, &decrypted_contents).is_err() {
+            return Err(ConfigureError::OutputFileNotWritable { path: destination });
+        }
+
+        state.record_applied_file(file, encrypted_file_hash, new_file_hash);
+        emit(Event::FileDecrypted { path: destination });
+    }
+
+    if skipped_file_count > 0 {
+        info!(
+            "Skipped {} file(s) that haven't changed since the last apply",
+            skipped_file_count
+        );
+    }
+
+    state.finish_apply(&configuration.pinned_hash, &destinations);
+
+    save_apply_state(&state_file_path, &state)?;
+    Ok(())
+}
+
+/// Deal with decrypted files left at destinations that were removed from the configuration
+///
+/// Files that were edited after they were applied are never touched. Unedited ones are moved to their new destination
+/// or deleted if the user agrees – otherwise they stay tracked so the next interactive apply can ask again.
+fn clean_up_previous_files(
+    configuration: &Configuration,
+    project_root: &Path,
+    state: &mut ApplyState,
+    options: &ApplyOptions,
+) -> Result<(), ConfigureError> {
+    let previous_files: Vec<(String, crate::state::AppliedFile)> = state
+        .previous_files
+        .iter()
+        .map(|(destination, applied_file)| (destination.clone(), applied_file.clone()))
+        .collect();
+
+    for (previous_destination, applied_file) in previous_files {
+        let path = project_root.join(&previous_destination);
+
+        if !path.exists() {
+            state.forget_previous_file(&previous_destination);
+            continue;
+        }
+
+        if hash_destination(&path)? != applied_file.decrypted_hash {
+            info!(
+                "{:?} is no longer configured, but it was edited after it was applied – leaving it alone",
+                path
+            );
+            state.forget_previous_file(&previous_destination);
+            continue;
+        }
+
+        // If a configured file still comes from the same source and hasn't been written yet, this destination was moved
+        let moved_to = configuration
+            .files_to_copy
+            .iter()
+            .find(|file| applied_file.source.as_ref() == Some(&file.source))
+            .map(|file| project_root.join(&file.destination))
+            .filter(|new_path| !new_path.exists());
+
+        match options.resolve_previous_file(&path, moved_to.as_deref()) {
+            Some(PreviousFileResolution::Move) => {
+                let new_path = moved_to.expect("Only files with a new destination can be moved");
+                info!("Moving {:?} to {:?}", path, new_path);
+                create_parent_directory_for_path_if_not_exists(&new_path)?;
+                rename(&path, &new_path)?;
+                state.forget_previous_file(&previous_destination);
+            }
+            Some(PreviousFileResolution::Delete) => {
+                info!("Deleting {:?}", path);
+                if path.is_dir() {
+                    std::fs::remove_dir_all(&path)?;
+                } else {
+                    std::fs::remove_file(&path)?;
+                }
+                state.forget_previous_file(&previous_destination);
+            }
+            Some(PreviousFileResolution::Keep) => {
+                state.forget_previous_file(&previous_destination);
+            }
+            None => warn!(
+                "{:?} is no longer configured – run `configure apply` interactively to clean it up",
+                path
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Encrypted files in the configuration's directory in `.configure-files` that don't belong to any configured file
+///
+/// Other `.configure` files' directories are inside the root one's, but only the files directly in it are looked at.
+pub fn orphaned_encrypted_files(
+    configuration: &Configuration,
+) -> Result<Vec<PathBuf>, ConfigureError> {
+    let encrypted_files_root = configuration.files_directory_in(&find_project_root()?);
+
+    if !encrypted_files_root.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let configured: Vec<PathBuf> = configuration
+        .files_to_copy
+        .iter()
+        .map(|file| configuration.encrypted_destination_for(file))
+        .collect();
+
+    let mut orphans = Vec::new();
+
+    for entry in std::fs::read_dir(&encrypted_files_root)? {
+        let path = entry?.path();
+
+        let is_encrypted_file = path.is_file()
+            && path
+                .extension()
+                .map(|extension| extension == "enc")
+                .unwrap_or(false);
+
+        if is_encrypted_file && !configured.contains(&path) {
+            orphans.push(path);
+        }
+    }
+
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// Encrypt every configured file from the secrets repo at the pinned hash, staging the results without writing them
+///
+/// The files are read from the commit itself, so the secrets repo doesn't need to be checked out at it.
+pub fn stage_encrypted_files_for_configuration(
+    configuration: &Configuration,
+    encryption_key: &EncryptionKey,
+    staged: &mut StagedChanges,
+    cancellation: &CancellationToken,
+) -> Result<(), ConfigureError> {
+    let project_root = find_project_root()?;
+    let secrets_root = find_secrets_repo()?;
+
+    let revision = Some(configuration.pinned_hash.as_str());
+
+    let mut unchanged_file_count = 0;
+
+    for file in &configuration.files_to_copy {
+        cancellation.check()?;
+
+        let encrypted_contents = encrypted_contents_for_file(
+            file,
+            source_contents(configuration, file, &secrets_root, revision)?,
+            &configuration.encrypted_destination_in(file, &project_root),
+            encryption_key,
+            nonce_mode_for_configuration(configuration),
+            provenance_for_file(configuration, file, &secrets_root, revision)?,
+        )?;
+
+        match encrypted_contents {
+            Some(contents) => {
+                staged.stage(
+                    configuration.encrypted_destination_in(file, &project_root),
+                    contents,
+                );
+                emit(Event::FileEncrypted {
+                    path: project_root.join(&file.destination),
+                });
+            }
+            None => unchanged_file_count += 1,
+        }
+    }
+
+    if unchanged_file_count > 0 {
+        info!(
+            "Left {} encrypted file(s) alone because their contents haven't changed",
+            unchanged_file_count
+        );
+    }
+
+    Ok(())
+}
+
+/// Stage a manifest recording the pinned hash and every encrypted file, once they've been staged themselves
+pub fn stage_encrypted_files_manifest(
+    configuration: &Configuration,
+    staged: &mut StagedChanges,
+) -> Result<(), ConfigureError> {
+    let mut manifest = EncryptedFilesManifest {
+        pinned_hash: configuration.pinned_hash.clone(),
+        tool_version: Some(TOOL_VERSION.to_string()),
+        ..Default::default()
+    };
+
+    for file in &configuration.files_to_copy {
+        let path = configuration.encrypted_destination_for(file);
+
+        let hash = match staged.staged_contents(&path) {
+            Some(contents) => hash_bytes(contents),
+            None => match hash_file(&path) {
+                Ok(hash) => hash,
+                Err(_) => return Err(ConfigureError::InputFileNotReadable { path }),
+            },
+        };
+
+        manifest.files.insert(encrypted_file_name(&path), hash);
+    }
+
+    staged.stage(
+        get_manifest_file_path(configuration)?,
+        manifest.to_json()?.into_bytes(),
+    );
+
+    Ok(())
+}
+
+/// Problems that suggest the encrypted files are out of date compared to the configuration
+///
+/// Projects last updated before the manifest was introduced have nothing to compare against, so they never have any.
+pub fn encrypted_files_problems(
+    configuration: &Configuration,
+) -> Result<Vec<String>, ConfigureError> {
+    let manifest_path = get_manifest_file_path(configuration)?;
+    let manifest = match EncryptedFilesManifest::read_from(&manifest_path)? {
+        Some(manifest) => manifest,
+        None => return Ok(Vec::new()),
+    };
+
+    check_tool_version(&manifest_path, manifest.tool_version.as_deref())?;
+
+    let mut encrypted_files = Vec::new();
+
+    for file in &configuration.files_to_copy {
+        let path = configuration.encrypted_destination_for(file);
+
+        // A missing file is reported when it's decrypted
+        if let Ok(hash) = hash_file(&path) {
+            encrypted_files.push((encrypted_file_name(&path), hash));
+        }
+    }
+
+    Ok(manifest.problems(&configuration.pinned_hash, &encrypted_files))
+}
+
+/// The manifest written by the last `update` of `configuration` – `None` if it was last updated before there was one
+pub fn read_encrypted_files_manifest(
+    configuration: &Configuration,
+) -> Result<Option<EncryptedFilesManifest>, ConfigureError> {
+    EncryptedFilesManifest::read_from(&get_manifest_file_path(configuration)?)
+}
+
+pub fn encrypted_file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Encrypt a single configured file from the secrets repo into the project
+///
+/// The file is read from the commit `revision` if there is one, or from the secrets repo's working copy – which is
+/// what `add-file` wants, since the file might not have been committed yet.
+pub fn write_encrypted_file_for_configuration(
+    configuration: &Configuration,
+    file: &crate::configure::File,
+    revision: Option<&str>,
+) -> Result<(), ConfigureError> {
+    let encryption_key = encryption_key_for_configuration(configuration)?;
+    let secrets_root = find_secrets_repo()?;
+    write_encrypted_file(
+        file,
+        &configuration.encrypted_destination_for(file),
+        source_contents(configuration, file, &secrets_root, revision)?,
+        &encryption_key,
+        nonce_mode_for_configuration(configuration),
+        provenance_for_file(configuration, file, &secrets_root, revision)?,
+    )?;
+
+    Ok(())
+}
+
+fn nonce_mode_for_configuration(configuration: &Configuration) -> NonceMode {
+    if configuration.deterministic_encryption {
+        NonceMode::Deterministic
+    } else {
+        NonceMode::Random
+    }
+}
+
+/// What to record about where `file` came from, if the project records provenance
+///
+/// Passphrase-encrypted files can't have provenance, so there's nothing to record for them. Files read from the
+/// working copy are recorded as coming from the commit it's checked out at.
+fn provenance_for_file(
+    configuration: &Configuration,
+    file: &crate::configure::File,
+    secrets_root: &Path,
+    revision: Option<&str>,
+) -> Result<Option<Provenance>, ConfigureError> {
+    if !configuration.record_provenance || configuration.passphrase_encryption {
+        return Ok(None);
+    }
+
+    let commit = match revision {
+        Some(revision) => revision.to_string(),
+        None => SecretsRepo {
+            path: secrets_root.to_path_buf(),
+        }
+        .current_hash()?,
+    };
+
+    Ok(Some(Provenance {
+        source: file.source.clone(),
+        commit,
+        encrypted_at: chrono::Utc::now().to_rfc3339(),
+    }))
+}
+
+/// Where each configured file's encrypted copy says it came from, keyed by destination
+///
+/// Files without provenance are left out. It isn't checked against the key here – that happens when the file is
+/// decrypted.
+pub fn provenance_for_configuration(
+    configuration: &Configuration,
+) -> Result<BTreeMap<String, Provenance>, ConfigureError> {
+    let mut provenance = BTreeMap::new();
+
+    for file in &configuration.files_to_copy {
+        let encrypted_contents = match std::fs::read(configuration.encrypted_destination_for(file))
+        {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+
+        if let Some(file_provenance) = read_provenance(&encrypted_contents) {
+            provenance.insert(file.destination.clone(), file_provenance);
+        }
+    }
+
+    Ok(provenance)
+}
+
+/// Encrypt `contents` into the project at `destination`, as `file`'s encrypted copy, returning `false` if the existing
+/// encrypted copy already had the same contents
+fn write_encrypted_file(
+    file: &crate::configure::File,
+    destination: &Path,
+    contents: Vec<u8>,
+    encryption_key: &EncryptionKey,
+    nonce_mode: NonceMode,
+    provenance: Option<Provenance>,
+) -> Result<bool, ConfigureError> {
+    let encrypted_contents = encrypted_contents_for_file(
+        file,
+        contents,
+        destination,
+        encryption_key,
+        nonce_mode,
+        provenance,
+    )?;
+
+    let encrypted_contents = match encrypted_contents {
+        Some(contents) => contents,
+        None => return Ok(false),
+    };
+
+    create_parent_directory_for_path_if_not_exists(destination)?;
+
+    match write(destination, encrypted_contents) {
+        Ok(()) => Ok(true),
+        Err(_) => Err(ConfigureError::OutputFileNotWritable {
+            path: destination.to_path_buf(),
+        }),
+    }
+}
+
+/// What gets encrypted for `file` – its contents, or an archive if it's a directory
+///
+/// With a `revision`, it's read from that commit without checking it out. Otherwise it's read from the secrets repo's
+/// working copy.
+fn source_contents(
+    configuration: &Configuration,
+    file: &crate::configure::File,
+    secrets_root: &Path,
+    revision: Option<&str>,
+) -> Result<Vec<u8>, ConfigureError> {
+    let source = secrets_root.join(&file.source);
+
+    let revision = match revision {
+        Some(revision) => revision,
+        None => return source_contents_from_working_copy(file, &source),
+    };
+
+    debug!(
+        "Reading {:?} from the secrets repo at {}",
+        file.source, revision
+    );
+
+    let repo = SecretsRepo {
+        path: secrets_root.to_path_buf(),
+    };
+
+    if repo.is_partial_clone()? {
+        repo.fetch_missing_objects(configuration, revision, &file.source)?;
+    }
+
+    if file.is_directory {
+        create_archive_from_entries(
+            &source,
+            &repo.read_directory_at_revision(revision, &file.source)?,
+        )
+    } else {
+        repo.read_file_at_revision(revision, &file.source)
+    }
+}
+
+fn source_contents_from_working_copy(
+    file: &crate::configure::File,
+    source: &Path,
+) -> Result<Vec<u8>, ConfigureError> {
+    if file.is_directory {
+        if !source.is_dir() {
+            return Err(ConfigureError::InputFileNotReadable {
+                path: source.to_path_buf(),
+            });
+        }
+
+        create_archive(source)
+    } else if source.is_dir() {
+        Err(ConfigureError::SourceIsDirectory {
+            path: source.to_path_buf(),
+        })
+    } else {
+        match std::fs::read(source) {
+            Ok(contents) => Ok(contents),
+            Err(_) => Err(ConfigureError::InputFileNotReadable {
+                path: source.to_path_buf(),
+            }),
+        }
+    }
+}
+
+/// The encrypted `contents` of `file`, or `None` if the existing file at `destination` already has the same contents
+fn encrypted_contents_for_file(
+    file: &crate::configure::File,
+    contents: Vec<u8>,
+    destination: &Path,
+    encryption_key: &EncryptionKey,
+    nonce_mode: NonceMode,
+    provenance: Option<Provenance>,
+) -> Result<Option<Vec<u8>>, ConfigureError> {
+    debug!("Encrypting {:?} for {:?}", file.source, destination);
+
+    // Re-encrypting unchanged contents would only produce a new nonce, and a meaningless diff in the project.
+    // If the existing file can't be decrypted (because the key changed, for instance) it's just replaced. Files
+    // are rewritten when provenance is turned on or off, though, so every file matches the configuration.
+    if let Ok(existing_contents) = std::fs::read(destination) {
+        let has_provenance = read_provenance(&existing_contents).is_some();
+
+        if let Ok(existing_contents) = decrypt_bytes(&existing_contents, encryption_key) {
+            if existing_contents == contents && has_provenance == provenance.is_some() {
+                debug!("{:?} is unchanged – not rewriting it", destination);
+                return Ok(None);
+            }
+        }
+    }
+
+    let encrypted_contents = encrypt_bytes_with_mode(&contents, encryption_key, nonce_mode)?;
+
+    match provenance {
+        Some(provenance) => Ok(Some(add_provenance(
+            &encrypted_contents,
+            &provenance,
+            encryption_key,
+        )?)),
+        None => Ok(Some(encrypted_contents)),
+    }
+}
+
+/// The hash of whatever is at `path` now – a file's contents, or a directory's manifest
+fn hash_destination(path: &Path) -> Result<String, ConfigureError> {
+    if path.is_dir() {
+        Ok(hash_bytes(manifest_for_directory(path)?.as_bytes()))
+    } else {
+        Ok(hash_file(path)?)
+    }
+}
+
+/// The hash that `hash_destination` will return once `decrypted_contents` are written for `file`
+fn hash_decrypted_contents(
+    file: &crate::configure::File,
+    decrypted_contents: &[u8],
+) -> Result<String, ConfigureError> {
+    if file.is_directory {
+        Ok(hash_bytes(
+            manifest_for_archive(decrypted_contents)?.as_bytes(),
+        ))
+    } else {
+        Ok(hash_bytes(decrypted_contents))
+    }
+}
+
+/// Returns the SHA-256 hash of the given bytes, in the same format as `hash_file`
+fn hash_bytes(bytes: &[u8]) -> String {
+    let digest = measure(Phase::Hashing, || ring::digest::digest(&SHA256, bytes));
+    base64::encode(digest.as_ref())
+}
+
+/// Returns the SHA-256 hash of a file at the given path
+fn hash_file(path: &Path) -> Result<String, Error> {
+    let _timer = timings::start(Phase::Hashing);
+    let input = File::open(path)?;
+    let mut reader = BufReader::new(input);
+    let mut context = Context::new(&SHA256);
+    let mut buffer = [0; 1024];
+
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        context.update(&buffer[..count]);
+    }
+
+    let digest = context.finish();
+
+    Ok(base64::encode(digest.as_ref()))
+}
+
+/// Put the newest backup of `file` back at its destination, returning the path of the backup that was restored
+///
+/// The file it replaces becomes a new backup, so restoring again undoes this.
+pub fn restore_latest_backup(file: &crate::configure::File) -> Result<PathBuf, ConfigureError> {
+    let project_root = find_project_root()?;
+    let destination = project_root.join(&file.destination);
+
+    let backup = match latest_backup(file, &project_root) {
+        Some(backup) => backup,
+        None => return Err(ConfigureError::BackupNotFound { path: destination }),
+    };
+
+    if destination.exists() {
+        rename(
+            &destination,
+            project_root.join(file.get_backup_destination()),
+        )?;
+    }
+
+    rename(&backup, &destination)?;
+
+    Ok(backup)
+}
+
+/// The newest backup `apply` made of `file`, if there is one
+fn latest_backup(file: &crate::configure::File, project_root: &Path) -> Option<PathBuf> {
+    let destination = project_root.join(&file.destination);
+
+    std::fs::read_dir(parent_directory(&destination))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let date = file.backup_date_from_file_name(entry.file_name().to_str()?)?;
+            Some((date, entry.path()))
+        })
+        .max_by_key(|(date, _)| *date)
+        .map(|(_, path)| path)
+}
+
+fn create_parent_directory_for_path_if_not_exists(path: &Path) -> Result<(), Error> {
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return Ok(()), // if we're in the root of the filesystem, we have no work to do
+    };
+
+    create_dir_all(parent)
+}
+
+pub fn infer_encryption_output_filename(path: &Path) -> PathBuf {
+    add_extension(path, "enc")
+}
+
+pub fn infer_decryption_output_filename(path: &Path) -> PathBuf {
+    match path.extension() {
+        Some(extension) if extension == "enc" => path.with_extension(""),
+        _ => add_extension(path, "decrypted"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Import the parent scope
+    use super::*;
+    use std::ffi::OsStr;
 
     #[test]
     fn test_find_project_root() {
@@ -416,10 +2044,43 @@ mod tests {
     }
 
     #[test]
-    fn test_find_configure_file_creates_it_if_missing() {
+    fn test_that_files_in_the_secrets_repo_are_listed() {
+        let secrets_root = std::env::temp_dir().join(format!(
+            "configure-secrets-files-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&secrets_root);
+        create_dir_all(secrets_root.join("android/WordPress")).unwrap();
+        create_dir_all(secrets_root.join(".git")).unwrap();
+        write(secrets_root.join("keys.json"), "{}").unwrap();
+        write(secrets_root.join(".git/config"), "").unwrap();
+        write(
+            secrets_root.join("android/WordPress/secrets.properties"),
+            "",
+        )
+        .unwrap();
+        write(secrets_root.join("README.md"), "").unwrap();
+
+        assert_eq!(
+            find_files_in_secrets_repo(&secrets_root).unwrap(),
+            vec!["README.md", "android/WordPress/secrets.properties"]
+        );
+
+        std::fs::remove_dir_all(&secrets_root).unwrap();
+    }
+
+    #[test]
+    fn test_find_configure_file_does_not_create_it_if_missing() {
+        delete_configure_file();
+        assert!(matches!(
+            find_configure_file(),
+            Err(ConfigureError::ConfigureFileNotFound { .. })
+        ));
+        assert!(!get_configure_file_path().unwrap().exists());
+
+        find_or_create_configure_file().unwrap();
+        assert!(find_configure_file().is_ok());
         delete_configure_file();
-        find_configure_file().unwrap();
-        assert!(get_configure_file_path().unwrap().exists());
     }
 
     #[test]
@@ -463,6 +2124,264 @@ mod tests {
         assert_eq!(infer_decryption_output_filename(&source), dest)
     }
 
+    #[test]
+    fn test_that_configure_files_are_found_in_subdirectories() {
+        let project_root = std::env::temp_dir().join(format!(
+            "configure-find-configure-files-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&project_root);
+
+        for directory in ["", "app", "libs/networking", ".git", "libs/.hidden"] {
+            create_dir_all(project_root.join(directory)).unwrap();
+            write(project_root.join(directory).join(".configure"), "").unwrap();
+        }
+
+        assert_eq!(
+            find_configure_files_in(&project_root).unwrap(),
+            vec![
+                project_root.join(".configure"),
+                project_root.join("app").join(".configure"),
+                project_root.join("libs/networking").join(".configure"),
+            ]
+        );
+
+        std::fs::remove_dir_all(&project_root).unwrap();
+    }
+
+    #[test]
+    fn test_that_the_nearest_configure_file_is_found_without_leaving_the_root() {
+        let project_root = std::env::temp_dir().join(format!(
+            "configure-nearest-configure-file-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&project_root);
+
+        let module = project_root.join("libs").join("networking");
+        create_dir_all(module.join("src")).unwrap();
+        write(project_root.join(".configure"), "").unwrap();
+        write(module.join(".configure"), "").unwrap();
+
+        let project_root = canonicalize(&project_root).unwrap();
+        let module = project_root.join("libs").join("networking");
+
+        assert_eq!(
+            nearest_configure_file(&module.join("src"), &project_root),
+            Some(module.join(".configure"))
+        );
+        assert_eq!(
+            nearest_configure_file(&project_root.join("libs"), &project_root),
+            Some(project_root.join(".configure"))
+        );
+        assert_eq!(nearest_configure_file(&module, &module.join("src")), None);
+
+        std::fs::remove_file(project_root.join(".configure")).unwrap();
+        assert_eq!(
+            nearest_configure_file(&project_root.join("libs"), &project_root),
+            None
+        );
+
+        std::fs::remove_dir_all(&project_root).unwrap();
+    }
+
+    #[test]
+    fn test_that_each_configure_file_keeps_its_files_separately() {
+        let project_root = std::env::temp_dir().join(format!(
+            "configure-files-namespace-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&project_root);
+
+        let module = project_root.join("libs").join("networking");
+        create_dir_all(&module).unwrap();
+        write(project_root.join(".configure"), "").unwrap();
+        write(module.join(".configure"), "").unwrap();
+        write(project_root.join("staging.configure"), "").unwrap();
+
+        assert_eq!(
+            files_namespace_for(&project_root, &project_root.join(".configure")),
+            PathBuf::new()
+        );
+        assert_eq!(
+            files_namespace_for(&project_root, &module.join(".configure")),
+            Path::new("libs").join("networking")
+        );
+        assert_eq!(
+            files_namespace_for(&project_root, &project_root.join("staging.configure")),
+            PathBuf::from("staging.configure")
+        );
+
+        // Modules with a file of the same name don't share its encrypted copy
+        let file = crate::configure::File::new("secrets.json", "app/secrets.json").unwrap();
+        let mut root_configuration = Configuration::default();
+        root_configuration.set_files_namespace(files_namespace_for(
+            &project_root,
+            &project_root.join(".configure"),
+        ));
+        let mut module_configuration = Configuration::default();
+        module_configuration.set_files_namespace(files_namespace_for(
+            &project_root,
+            &module.join(".configure"),
+        ));
+
+        assert_eq!(
+            root_configuration.encrypted_destination_in(&file, &project_root),
+            project_root.join(".configure-files/secrets.json.enc")
+        );
+        assert_eq!(
+            module_configuration.encrypted_destination_in(&file, &project_root),
+            project_root.join(".configure-files/libs/networking/secrets.json.enc")
+        );
+
+        // It's still empty – where its files go isn't part of what's in it
+        assert!(module_configuration.is_empty());
+
+        std::fs::remove_dir_all(&project_root).unwrap();
+    }
+
+    #[test]
+    fn test_that_sources_are_read_from_a_commit_without_disturbing_the_working_copy() {
+        let secrets_root = std::env::temp_dir().join(format!(
+            "configure-source-contents-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&secrets_root);
+
+        let repo = git2::Repository::init(&secrets_root).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let commit = |contents: &str| {
+            write(secrets_root.join("secrets.json"), contents).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("secrets.json")).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parents = match repo.head() {
+                Ok(head) => vec![head.peel_to_commit().unwrap()],
+                Err(_) => vec![],
+            };
+            let parents: Vec<&git2::Commit> = parents.iter().collect();
+            repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                contents,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+            .to_string()
+        };
+
+        let pinned_hash = commit("pinned");
+        commit("newer");
+
+        // Someone is in the middle of editing the secrets on another branch
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("editing", &head, false).unwrap();
+        repo.set_head("refs/heads/editing").unwrap();
+        write(secrets_root.join("secrets.json"), "local change").unwrap();
+
+        let file = crate::configure::File {
+            source: "secrets.json".to_string(),
+            destination: "config/secrets.json".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        };
+
+        assert_eq!(
+            source_contents(
+                &Configuration::default(),
+                &file,
+                &secrets_root,
+                Some(&pinned_hash)
+            )
+            .unwrap(),
+            b"pinned"
+        );
+        assert_eq!(
+            source_contents(&Configuration::default(), &file, &secrets_root, None).unwrap(),
+            b"local change"
+        );
+        assert_eq!(repo.head().unwrap().shorthand(), Some("editing"));
+
+        std::fs::remove_dir_all(&secrets_root).unwrap();
+    }
+
+    #[test]
+    fn test_that_latest_backup_finds_the_newest_backup_of_a_file() {
+        let project_root =
+            std::env::temp_dir().join(format!("configure-backup-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&project_root);
+        create_dir_all(project_root.join("config")).unwrap();
+
+        for name in [
+            "secrets-2024-01-01-00-00-00.json.bak",
+            "secrets-2024-02-01-00-00-00.json.bak",
+            "secrets-old.json.bak",
+            "other-2025-01-01-00-00-00.json.bak",
+        ] {
+            write(project_root.join("config").join(name), "").unwrap();
+        }
+
+        let file = crate::configure::File {
+            source: "secrets.json".to_string(),
+            destination: "config/secrets.json".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        };
+
+        assert_eq!(
+            latest_backup(&file, &project_root),
+            Some(
+                project_root
+                    .join("config")
+                    .join("secrets-2024-02-01-00-00-00.json.bak")
+            )
+        );
+    }
+
+    #[test]
+    fn test_that_includes_cannot_leave_the_configure_file_directory() {
+        let configure_file_path = Path::new("/project/.configure");
+
+        assert_eq!(
+            include_path(configure_file_path, "shared/android.json").unwrap(),
+            Path::new("/project/shared/android.json")
+        );
+        assert!(include_path(configure_file_path, "../other/android.json").is_err());
+        assert!(include_path(configure_file_path, "shared/../../android.json").is_err());
+        assert!(include_path(configure_file_path, "/etc/android.json").is_err());
+    }
+
+    #[test]
+    fn test_that_signing_names_ignore_a_trailing_git_extension() {
+        assert_eq!(
+            signing_name_for_remote_url("git@github.com:org/repo.git"),
+            signing_name_for_remote_url("git@github.com:org/repo")
+        );
+        assert_eq!(
+            signing_name_for_remote_url("https://github.com/org/repo/"),
+            "https://github.com/org/repo"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_that_output_filenames_can_be_derived_for_non_utf8_filenames() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let source = Path::new(OsStr::from_bytes(b"/secrets-\xff.json"));
+        let encrypted = Path::new(OsStr::from_bytes(b"/secrets-\xff.json.enc"));
+
+        assert_eq!(infer_encryption_output_filename(source), encrypted);
+        assert_eq!(infer_decryption_output_filename(encrypted), source);
+        assert_eq!(
+            infer_decryption_output_filename(source),
+            Path::new(OsStr::from_bytes(b"/secrets-\xff.json.decrypted"))
+        );
+    }
+
     fn delete_configure_file() {
         if get_configure_file_path().unwrap().exists() {
             std::fs::remove_file(get_configure_file_path().unwrap()).unwrap();
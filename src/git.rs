@@ -1,9 +1,622 @@
-use crate::string::distance_between_strings_in;
+use crate::events::{emit, Event};
+use crate::lock::{FileLock, LOCK_TIMEOUT};
+use crate::remote_cache::RemoteHeadsCache;
+use crate::timings::{self, Phase};
 use crate::Configuration;
 use crate::ConfigureError;
 use git2::Oid;
-use git2::{BranchType, ErrorCode, Repository, ResetType};
-use log::debug;
+use git2::{BranchType, ErrorCode, ObjectType, Repository, StatusOptions, Tree};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// The remote used when neither the `.configure` file nor the secrets repo says which one to use
+const DEFAULT_REMOTE: &str = "origin";
+
+/// How long git commands that talk to the server may take, and how often they're retried
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct NetworkPolicy {
+    /// How long a single attempt may run before it's killed
+    pub timeout: Duration,
+
+    /// How many times to try again after a failed or timed out attempt
+    pub retries: u32,
+
+    /// How long to wait before the first retry – this doubles after each attempt
+    pub initial_backoff: Duration,
+
+    /// Skip anything that only checks the server, and use what was last fetched instead
+    pub offline: bool,
+
+    /// How long to remember what the server said its branches point to – zero turns this off
+    pub cache_ttl: Duration,
+}
+
+impl Default for NetworkPolicy {
+    fn default() -> Self {
+        NetworkPolicy {
+            timeout: Duration::from_secs(60),
+            retries: 2,
+            initial_backoff: Duration::from_secs(2),
+            offline: false,
+            cache_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+impl NetworkPolicy {
+    /// The default policy, with the timeout and retry count overridden by environment variables if they're set
+    pub fn from_env() -> NetworkPolicy {
+        let mut policy = NetworkPolicy::default();
+
+        if let Some(seconds) = read_env_number(crate::GIT_TIMEOUT_KEY_NAME) {
+            policy.timeout = Duration::from_secs(seconds);
+        }
+
+        if let Some(retries) = read_env_number(crate::GIT_RETRIES_KEY_NAME) {
+            policy.retries = retries as u32;
+        }
+
+        if let Some(seconds) = read_env_number(crate::REMOTE_CACHE_TTL_KEY_NAME) {
+            policy.cache_ttl = Duration::from_secs(seconds);
+        }
+
+        if let Ok(value) = std::env::var(crate::OFFLINE_KEY_NAME) {
+            match crate::settings::parse_bool(&value) {
+                Some(offline) => policy.offline = offline,
+                None => warn!(
+                    "Ignoring {} – {:?} is not `true` or `false`",
+                    crate::OFFLINE_KEY_NAME,
+                    value
+                ),
+            }
+        }
+
+        policy
+    }
+
+    /// How long to wait before retry number `attempt` (starting at `1`)
+    fn backoff_for_attempt(&self, attempt: u32) -> Duration {
+        self.initial_backoff * 2u32.saturating_pow(attempt.saturating_sub(1))
+    }
+}
+
+fn read_env_number(name: &str) -> Option<u64> {
+    let value = std::env::var(name).ok()?;
+
+    match value.trim().parse::<u64>() {
+        Ok(number) => Some(number),
+        Err(_) => {
+            warn!("Ignoring {:} – {:?} is not a whole number", name, value);
+            None
+        }
+    }
+}
+
+/// Run `command` to completion, killing it if it takes longer than `timeout`
+pub fn run_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+) -> Result<Output, ConfigureError> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain the output on other threads so a chatty command can't block on a full pipe
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let started_at = Instant::now();
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if started_at.elapsed() >= timeout {
+            debug!("Killing {:?} after {:?}", command, timeout);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(ConfigureError::GitNetworkTimeout);
+        }
+
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_in_background<R: Read + Send + 'static>(
+    source: Option<R>,
+) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut source) = source {
+            let _ = source.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Run a command that talks to the server, retrying with backoff according to `policy`
+///
+/// If every attempt fails, the last output is returned so the caller can decide what to do with it. If the last
+/// attempt timed out, this returns `ConfigureError::GitNetworkTimeout`.
+pub(crate) fn run_network_command<F>(
+    make_command: F,
+    policy: &NetworkPolicy,
+) -> Result<Output, ConfigureError>
+where
+    F: Fn() -> Command,
+{
+    let _timer = timings::start(Phase::Network);
+    let mut attempt = 0;
+
+    loop {
+        let result = run_with_timeout(&mut make_command(), policy.timeout);
+
+        if !should_retry(&result) || attempt >= policy.retries {
+            return result;
+        }
+
+        attempt += 1;
+        std::thread::sleep(backoff_before_retry(policy, attempt));
+    }
+}
+
+/// Failed commands and timeouts are worth another try – errors starting the command aren't
+fn should_retry(result: &Result<Output, ConfigureError>) -> bool {
+    match result {
+        Ok(output) => !output.status.success(),
+        Err(ConfigureError::GitNetworkTimeout) => true,
+        Err(_) => false,
+    }
+}
+
+fn backoff_before_retry(policy: &NetworkPolicy, attempt: u32) -> Duration {
+    let backoff = policy.backoff_for_attempt(attempt);
+    warn!(
+        "A git network operation failed – retrying in {:?} (attempt {} of {})",
+        backoff, attempt, policy.retries
+    );
+    backoff
+}
+
+/// Run `command` to completion without blocking the thread, killing it if it takes longer than `timeout`
+///
+/// The command is also killed if the returned future is dropped before it finishes.
+#[cfg(feature = "tokio")]
+pub async fn run_with_timeout_async(
+    command: Command,
+    timeout: Duration,
+) -> Result<Output, ConfigureError> {
+    let mut command = tokio::process::Command::from(command);
+    command.stdin(Stdio::null()).kill_on_drop(true);
+
+    match tokio::time::timeout(timeout, command.output()).await {
+        Ok(output) => Ok(output?),
+        Err(_) => {
+            debug!("Killing {:?} after {:?}", command, timeout);
+            Err(ConfigureError::GitNetworkTimeout)
+        }
+    }
+}
+
+/// `run_network_command`, without blocking the thread
+#[cfg(feature = "tokio")]
+async fn run_network_command_async<F>(
+    make_command: F,
+    policy: &NetworkPolicy,
+) -> Result<Output, ConfigureError>
+where
+    F: Fn() -> Command,
+{
+    let _timer = timings::start(Phase::Network);
+    let mut attempt = 0;
+
+    loop {
+        let result = run_with_timeout_async(make_command(), policy.timeout).await;
+
+        if !should_retry(&result) || attempt >= policy.retries {
+            return result;
+        }
+
+        attempt += 1;
+        tokio::time::sleep(backoff_before_retry(policy, attempt)).await;
+    }
+}
+
+fn fetch_command(directory: &Path, remote: &str) -> Command {
+    let mut command = Command::new("git");
+    command
+        .arg("fetch")
+        .arg(remote)
+        .env("GIT_TERMINAL_PROMPT", "0") // Never wait for credentials that nobody is going to type
+        .current_dir(directory);
+    command
+}
+
+// A failed fetch has to stop an update – otherwise the project would be pinned to whatever was fetched last time,
+// with nothing to say it isn't the latest
+fn check_fetch_output(output: &Output, remote: &str) -> Result<(), ConfigureError> {
+    if !output.status.success() {
+        return Err(ConfigureError::SecretsFetchFailed {
+            remote: remote.to_string(),
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    debug!("Fetch Complete");
+    Ok(())
+}
+
+fn remote_hash_command(directory: &Path, remote: &str, branch_name: &str) -> Command {
+    let remote_ref = format!("refs/remotes/{}/{}", remote, branch_name);
+
+    debug!("Looking for remote ref: {:?}", remote_ref);
+
+    let mut command = Command::new("git");
+    command
+        .arg("rev-parse")
+        .arg(remote_ref)
+        .current_dir(directory);
+    command
+}
+
+// Only asks for the one branch, so the server doesn't send every ref it has
+fn ls_remote_command(directory: &Path, remote: &str, branch_name: &str) -> Command {
+    let mut command = Command::new("git");
+    command
+        .arg("ls-remote")
+        .arg(remote)
+        .arg(format!("refs/heads/{}", branch_name))
+        .env("GIT_TERMINAL_PROMPT", "0") // Never wait for credentials that nobody is going to type
+        .current_dir(directory);
+    command
+}
+
+/// The hash `git ls-remote` lists for `branch_name`, or an empty string if the server doesn't have that branch
+fn hash_from_ls_remote_output(
+    output: &Output,
+    branch_name: &str,
+) -> Result<String, ConfigureError> {
+    if !output.status.success() {
+        warn!(
+            "Unable to ask the server for the latest secrets: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(ConfigureError::GitStatusUnknownError);
+    }
+
+    let remote_ref = format!("refs/heads/{}", branch_name);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let hash = stdout
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .find(|(_, name)| *name == remote_ref)
+        .map(|(hash, _)| hash.to_string())
+        .unwrap_or_default();
+
+    debug!("Result: {}", hash);
+
+    Ok(hash)
+}
+
+fn hash_from_output(output: &Output) -> String {
+    let string = std::str::from_utf8(&output.stdout).expect("Unable to parse output");
+
+    debug!("Result: {}", string);
+
+    String::from(string.trim_end())
+}
+
+fn submodule_update_command(project_root: &Path, path: &str) -> Command {
+    let mut command = Command::new("git");
+    command
+        .arg("submodule")
+        .arg("update")
+        .arg("--init")
+        .arg("--")
+        .arg(path)
+        .env("GIT_TERMINAL_PROMPT", "0") // Never wait for credentials that nobody is going to type
+        .current_dir(project_root);
+    command
+}
+
+/// Where the project's submodule at `path` (relative to `project_root`) is checked out
+///
+/// A submodule that hasn't been checked out yet is initialised and cloned first, at the commit the project records
+/// for it – after that, it's fetched and read like any other secrets repo.
+pub fn check_out_submodule(project_root: &Path, path: &str) -> Result<PathBuf, ConfigureError> {
+    let repo = Repository::open(project_root)?;
+
+    let submodule = match repo.find_submodule(path) {
+        Ok(submodule) => submodule,
+        Err(_) => {
+            return Err(ConfigureError::SecretsSubmoduleNotFound {
+                path: path.to_string(),
+            })
+        }
+    };
+
+    let submodule_path = project_root.join(submodule.path());
+
+    if submodule.open().is_ok() {
+        return Ok(submodule_path);
+    }
+
+    info!("Checking out the secrets repo submodule at {:?}", path);
+
+    let directory = crate::paths::canonicalize(project_root)?;
+    let output = run_network_command(
+        || submodule_update_command(&directory, path),
+        &NetworkPolicy::from_env(),
+    )?;
+
+    if !output.status.success() {
+        warn!(
+            "Unable to check out the secrets repo submodule: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(ConfigureError::SecretsSubmoduleNotCheckedOut {
+            path: path.to_string(),
+        });
+    }
+
+    Ok(submodule_path)
+}
+
+/// A version of a file in the secrets repo's history
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileVersion {
+    /// When the commit that introduced this version was made, in seconds since the Unix epoch
+    pub committed_at: i64,
+
+    /// The file's contents, or `None` if this commit deleted it
+    pub contents: Option<Vec<u8>>,
+}
+
+/// A commit in the secrets repo, and which files it changed
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CommitSummary {
+    pub hash: String,
+
+    /// The first line of the commit message
+    pub subject: String,
+
+    /// Paths relative to the repo root – compared with the commit's first parent
+    pub changed_paths: Vec<String>,
+}
+
+impl CommitSummary {
+    pub fn short_hash(&self) -> &str {
+        &self.hash[..self.hash.len().min(7)]
+    }
+}
+
+/// A file or directory read from a commit in the secrets repo, without checking it out
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CommittedEntry {
+    /// Relative to the directory that was read
+    pub path: PathBuf,
+
+    /// Unix permissions – git only records whether a file is executable, so this is always `0o644` or `0o755`
+    pub mode: u32,
+
+    /// The file's contents, or `None` for a directory
+    pub contents: Option<Vec<u8>>,
+}
+
+/// How far apart the project's pinned commit and the head of its secrets branch are
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct CommitDistance {
+    /// Commits on the branch that the pinned commit doesn't have yet
+    pub ahead: usize,
+
+    /// Commits in the pinned commit's history that aren't on the branch – only when the pinned commit is from another
+    /// branch, or the branch was rewritten
+    pub behind: usize,
+}
+
+impl CommitDistance {
+    pub fn is_zero(&self) -> bool {
+        self.ahead == 0 && self.behind == 0
+    }
+}
+
+/// The remote `remote_for_configuration` settles on, before it's checked
+fn choose_remote(
+    repo: &Repository,
+    configuration: &Configuration,
+) -> Result<String, ConfigureError> {
+    let remotes: Vec<String> = repo.remotes()?.iter().flatten().map(String::from).collect();
+
+    if let Some(remote) = &configuration.remote {
+        if !remotes.contains(remote) {
+            return Err(ConfigureError::RemoteNotFound {
+                remote: remote.clone(),
+            });
+        }
+
+        return Ok(remote.clone());
+    }
+
+    // Set by `git checkout --track` or `git push -u` – `.` means the branch tracks another local branch
+    let tracked_remote = repo
+        .config()?
+        .get_string(&format!("branch.{}.remote", configuration.branch))
+        .ok()
+        .filter(|remote| remote != ".");
+
+    if let Some(remote) = tracked_remote {
+        debug!("{:?} tracks the {:?} remote", configuration.branch, remote);
+        return Ok(remote);
+    }
+
+    match remotes.as_slice() {
+        [remote] => Ok(remote.clone()),
+        _ => Ok(DEFAULT_REMOTE.to_string()),
+    }
+}
+
+/// Check that `remote` has the URL and host key the `.configure` file pins, if it pins them
+fn verify_remote(
+    repo: &Repository,
+    configuration: &Configuration,
+    remote: &str,
+) -> Result<(), ConfigureError> {
+    if configuration.remote_url.is_none() && configuration.remote_host_key.is_none() {
+        return Ok(());
+    }
+
+    // `origin` is the fallback even when there's no such remote, so it may not exist
+    let found = repo
+        .find_remote(remote)
+        .map_err(|_| ConfigureError::RemoteNotFound {
+            remote: remote.to_string(),
+        })?;
+
+    // git pushes to `pushurl` instead of `url` when it's set, so both have to match
+    let urls: Vec<String> = found
+        .url()
+        .into_iter()
+        .chain(found.pushurl())
+        .map(String::from)
+        .collect();
+
+    if let Some(expected) = &configuration.remote_url {
+        if let Some(actual) = urls
+            .iter()
+            .find(|url| normalize_remote_url(url) != normalize_remote_url(expected))
+        {
+            return Err(ConfigureError::RemoteUrlMismatch {
+                remote: remote.to_string(),
+                expected: expected.clone(),
+                actual: actual.clone(),
+            });
+        }
+    }
+
+    if let Some(fingerprint) = &configuration.remote_host_key {
+        for url in &urls {
+            match split_remote_url(url) {
+                Some((host, _)) if is_ssh_url(url) => verify_host_key(&host, fingerprint)?,
+                _ => warn!(
+                    "{} isn't an ssh URL, so the `remote_host_key` in the .configure file can't be checked",
+                    url
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `url` reduced to its host and path, so different ways of writing the same repository's URL compare equal
+///
+/// `git@github.com:org/secrets.git`, `ssh://git@github.com/org/secrets` and `https://github.com/org/secrets/` all
+/// become `github.com/org/secrets`. Local paths only lose a trailing `/` or `.git`.
+fn normalize_remote_url(url: &str) -> String {
+    let url = url.trim();
+
+    match split_remote_url(url) {
+        Some((host, path)) => {
+            let path = path.trim_matches('/');
+            format!("{}/{}", host, path.strip_suffix(".git").unwrap_or(path))
+        }
+        None => {
+            let path = url.trim_end_matches('/');
+            path.strip_suffix(".git").unwrap_or(path).to_string()
+        }
+    }
+}
+
+/// The lower-cased host (without a user or port) and the path of a remote URL, or `None` for a local path
+fn split_remote_url(url: &str) -> Option<(String, &str)> {
+    let (authority, path) = match url.split_once("://") {
+        Some((_, rest)) => rest.split_at(rest.find('/').unwrap_or(rest.len())),
+        None => {
+            // The scp-like `user@host:path` – a colon before any slash, that isn't after a Windows drive letter
+            let (authority, path) = url.split_once(':')?;
+            if authority.contains('/') || authority.len() == 1 {
+                return None;
+            }
+            (authority, path)
+        }
+    };
+
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    let host = host.split(':').next().unwrap_or(host);
+
+    Some((host.to_lowercase(), path))
+}
+
+fn is_ssh_url(url: &str) -> bool {
+    match url.split_once("://") {
+        Some((scheme, _)) => ["ssh", "git+ssh", "ssh+git"].contains(&scheme),
+        None => split_remote_url(url).is_some(),
+    }
+}
+
+/// Check that `known_hosts` has a key for `host` with `fingerprint` – ssh won't connect to a server with another key
+/// without asking, and fetches never ask
+fn verify_host_key(host: &str, fingerprint: &str) -> Result<(), ConfigureError> {
+    let mismatch = || ConfigureError::RemoteHostKeyMismatch {
+        host: host.to_string(),
+        fingerprint: fingerprint.to_string(),
+    };
+
+    debug!("Running `ssh-keygen -l -F {}`", host);
+    let output = Command::new("ssh-keygen")
+        .arg("-l")
+        .arg("-F")
+        .arg(host)
+        .output()
+        .map_err(|err| {
+            debug!("Unable to run ssh-keygen: {}", err);
+            mismatch()
+        })?;
+
+    let known = String::from_utf8_lossy(&output.stdout);
+    match host_key_fingerprints(&known).any(|known| fingerprints_match(known, fingerprint)) {
+        true => Ok(()),
+        false => Err(mismatch()),
+    }
+}
+
+/// The fingerprints in `ssh-keygen -l -F` output, which has a `# Host … found` comment before each key
+fn host_key_fingerprints(output: &str) -> impl Iterator<Item = &str> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .filter(|word| word.starts_with("SHA256:"))
+}
+
+// The `SHA256:` prefix and base64 padding are optional, since they're easy to drop when copying a fingerprint
+fn fingerprints_match(known: &str, pinned: &str) -> bool {
+    let trim = |fingerprint: &str| {
+        let fingerprint = fingerprint.trim();
+        fingerprint
+            .strip_prefix("SHA256:")
+            .unwrap_or(fingerprint)
+            .trim_end_matches('=')
+            .to_string()
+    };
+
+    trim(known) == trim(pinned)
+}
 
 pub struct SecretsRepo {
     pub path: std::path::PathBuf,
@@ -22,24 +635,132 @@ impl SecretsRepo {
         Ok(Repository::open(&self.path)?)
     }
 
-    // Assumes you're using `origin` as the remote name
-    pub fn update_local_copy(&self) -> Result<(), ConfigureError> {
-        debug!("Running `git fetch`");
+    // In the git directory, so it's never mistaken for a change to the secrets
+    fn fetch_lock_path(&self) -> Result<PathBuf, ConfigureError> {
+        Ok(self.get_repo()?.path().join("configure-fetch.lock"))
+    }
+
+    /// Fetch the latest commits from `remote` – use `remote_for_configuration` to find out which one a project uses
+    ///
+    /// When offline, nothing is fetched and the commits from the last fetch are used.
+    pub fn update_local_copy(&self, remote: &str) -> Result<(), ConfigureError> {
+        let policy = NetworkPolicy::from_env();
+        if policy.offline {
+            info!("Not fetching {} while offline", remote);
+            return Ok(());
+        }
+
+        // Two fetches updating the same refs at once make one of them fail
+        let _lock = FileLock::acquire(&self.fetch_lock_path()?, LOCK_TIMEOUT)?;
+
+        debug!("Running `git fetch {}`", remote);
+        let directory = crate::paths::canonicalize(&self.path)?;
+
+        emit(Event::FetchStarted {
+            remote: remote.to_string(),
+        });
+        let output = run_network_command(|| fetch_command(&directory, remote), &policy)?;
+        check_fetch_output(&output, remote)?;
+        emit(Event::FetchFinished {
+            remote: remote.to_string(),
+        });
+
+        Ok(())
+    }
+
+    /// `update_local_copy`, without blocking the thread – dropping the future stops the fetch
+    #[cfg(feature = "tokio")]
+    pub async fn update_local_copy_async(&self, remote: &str) -> Result<(), ConfigureError> {
+        let policy = NetworkPolicy::from_env();
+        if policy.offline {
+            info!("Not fetching {} while offline", remote);
+            return Ok(());
+        }
+
+        let _lock = FileLock::acquire_async(&self.fetch_lock_path()?, LOCK_TIMEOUT).await?;
 
-        std::process::Command::new("git")
-            .arg("fetch")
-            .current_dir(std::fs::canonicalize(&self.path).unwrap())
-            .output()?; // Wait for it to finish and collect its output
+        debug!("Running `git fetch {}`", remote);
+        let directory = crate::paths::canonicalize(&self.path)?;
 
-        debug!("Fetch Complete");
+        emit(Event::FetchStarted {
+            remote: remote.to_string(),
+        });
+        let output =
+            run_network_command_async(|| fetch_command(&directory, remote), &policy).await?;
+        check_fetch_output(&output, remote)?;
+        emit(Event::FetchFinished {
+            remote: remote.to_string(),
+        });
 
         Ok(())
     }
 
+    /// The remote that `configuration`'s secrets come from
+    ///
+    /// That's the `remote` in the `.configure` file if it has one. Otherwise it's the remote the branch tracks, then
+    /// the secrets repo's only remote if it has just one, and `origin` if none of those settle it.
+    ///
+    /// When the `.configure` file pins a `remote_url` or `remote_host_key`, the remote is checked against them before
+    /// it's returned, so nothing is fetched from the wrong fork or server.
+    pub fn remote_for_configuration(
+        &self,
+        configuration: &Configuration,
+    ) -> Result<String, ConfigureError> {
+        let repo = self.get_repo()?;
+        let remote = choose_remote(&repo, configuration)?;
+        verify_remote(&repo, configuration, &remote)?;
+        Ok(remote)
+    }
+
     pub fn status(&self) -> Result<RepoStatus, ConfigureError> {
         RepoStatus::from_repo(self)
     }
 
+    /// Where the secrets repo is, what's checked out, and how it compares with its upstream and `remote`
+    ///
+    /// Nothing is fetched, so the ahead and behind counts are as of the last fetch.
+    pub fn info(&self, remote: &str) -> Result<SecretsRepoInfo, ConfigureError> {
+        let repo = self.get_repo()?;
+
+        let head = repo.head()?;
+        let hash = head.peel_to_commit()?.id().to_string();
+
+        let (branch, distance) = if head.is_branch() {
+            let branch = git2::Branch::wrap(head);
+            let distance = match branch.upstream() {
+                Ok(upstream) => Some(repo.graph_ahead_behind(
+                    branch.get().peel_to_commit()?.id(),
+                    upstream.get().peel_to_commit()?.id(),
+                )?),
+                Err(_) => None,
+            };
+
+            (branch.name()?.map(String::from), distance)
+        } else {
+            (None, None)
+        };
+
+        let mut options = StatusOptions::new();
+        options.include_untracked(true).include_ignored(false);
+        let dirty = !repo.statuses(Some(&mut options))?.is_empty();
+
+        let remote_url = repo
+            .find_remote(remote)
+            .ok()
+            .and_then(|found| found.url().map(String::from));
+
+        Ok(SecretsRepoInfo {
+            path: self.path.clone(),
+            branch,
+            hash,
+            ahead: distance.map(|(ahead, _)| ahead),
+            behind: distance.map(|(_, behind)| behind),
+            dirty,
+            remote: remote.to_string(),
+            remote_url,
+        })
+    }
+
     pub fn current_branch(&self) -> Result<String, ConfigureError> {
         let repo = self.get_repo()?;
         let head = match repo.head() {
@@ -52,9 +773,11 @@ impl SecretsRepo {
             Err(_) => return Err(ConfigureError::GitGetCurrentBranchError),
         };
 
-        let head = head.as_ref().and_then(|h| h.shorthand());
-
-        Ok(head.unwrap().to_string())
+        // A repo without any commits isn't on a branch yet
+        match head.as_ref().and_then(|h| h.shorthand()) {
+            Some(head) => Ok(head.to_string()),
+            None => Err(ConfigureError::GitGetCurrentBranchError),
+        }
     }
 
     pub fn current_hash(&self) -> Result<String, ConfigureError> {
@@ -63,57 +786,372 @@ impl SecretsRepo {
         Ok(latest_commit.id().to_string())
     }
 
-    // TODO: I don't think this works right?
+    /// The commit `branch_name` points to, without checking it out
+    ///
+    /// That's the local branch if there is one. Otherwise it's the branch on one of the secrets repo's remotes, as of
+    /// the last fetch – a branch picked during `init` might not have been checked out yet.
     pub fn latest_local_hash_for_branch(
         &self,
         branch_name: &str,
     ) -> Result<String, ConfigureError> {
-        let current_branch = self.current_branch()?;
+        let repo = self.get_repo()?;
 
-        if current_branch != branch_name {
-            self.switch_to_branch(branch_name)?
+        let branch = match repo.find_branch(branch_name, BranchType::Local) {
+            Ok(branch) => branch,
+            Err(err) => {
+                let remote_branch = repo.remotes()?.iter().flatten().find_map(|remote| {
+                    repo.find_branch(&format!("{}/{}", remote, branch_name), BranchType::Remote)
+                        .ok()
+                });
+
+                match remote_branch {
+                    Some(branch) => branch,
+                    None => return Err(err.into()),
+                }
+            }
+        };
+
+        Ok(branch.get().peel_to_commit()?.id().to_string())
+    }
+
+    /// The newest commit on `branch_name` on `remote` as of the last fetch
+    pub fn latest_remote_hash_for_branch(
+        &self,
+        remote: &str,
+        branch_name: &str,
+    ) -> Result<String, ConfigureError> {
+        let directory = crate::paths::canonicalize(&self.path)?;
+
+        let output = run_with_timeout(
+            &mut remote_hash_command(&directory, remote, branch_name),
+            NetworkPolicy::from_env().timeout,
+        )?;
+
+        Ok(hash_from_output(&output))
+    }
+
+    /// The newest commit on `branch_name` on `remote`, without fetching anything
+    ///
+    /// This asks the server with `git ls-remote`, which is much quicker than a fetch when all that's needed is the
+    /// hash. The answer is cached for `NetworkPolicy::cache_ttl`. When offline, the server isn't contacted and the
+    /// hash from the last fetch is returned instead. An empty string means the server doesn't have the branch.
+    pub fn query_remote_hash_for_branch(
+        &self,
+        remote: &str,
+        branch_name: &str,
+    ) -> Result<String, ConfigureError> {
+        let policy = NetworkPolicy::from_env();
+
+        if policy.offline {
+            debug!(
+                "Offline – using the last fetched hash for {:?}",
+                branch_name
+            );
+            return self.latest_remote_hash_for_branch(remote, branch_name);
         }
 
-        let repo = self.get_repo()?;
-        let latest_commit = repo.head()?.peel_to_commit()?;
+        let cache_key = format!("{}/{}", remote, branch_name);
+        let mut cache = RemoteHeadsCache::read_from(self.get_repo()?.path());
+        if let Some(hash) = cache.get(&cache_key, policy.cache_ttl) {
+            return Ok(hash.to_string());
+        }
 
-        Ok(latest_commit.id().to_string())
+        let directory = crate::paths::canonicalize(&self.path)?;
+        let output = run_network_command(
+            || ls_remote_command(&directory, remote, branch_name),
+            &policy,
+        )?;
+
+        let hash = hash_from_ls_remote_output(&output, branch_name)?;
+        cache.insert(&cache_key, &hash);
+
+        Ok(hash)
     }
 
-    pub fn latest_remote_hash_for_branch(
+    /// `query_remote_hash_for_branch`, without blocking the thread
+    #[cfg(feature = "tokio")]
+    pub async fn query_remote_hash_for_branch_async(
         &self,
+        remote: &str,
         branch_name: &str,
     ) -> Result<String, ConfigureError> {
-        let remote_ref = "origin/".to_owned() + branch_name;
+        let policy = NetworkPolicy::from_env();
+
+        if policy.offline {
+            debug!(
+                "Offline – using the last fetched hash for {:?}",
+                branch_name
+            );
+            return self
+                .latest_remote_hash_for_branch_async(remote, branch_name)
+                .await;
+        }
 
-        debug!("Looking for remote ref: {:?}", remote_ref);
+        let cache_key = format!("{}/{}", remote, branch_name);
+        let mut cache = RemoteHeadsCache::read_from(self.get_repo()?.path());
+        if let Some(hash) = cache.get(&cache_key, policy.cache_ttl) {
+            return Ok(hash.to_string());
+        }
+
+        let directory = crate::paths::canonicalize(&self.path)?;
+        let output = run_network_command_async(
+            || ls_remote_command(&directory, remote, branch_name),
+            &policy,
+        )
+        .await?;
+
+        let hash = hash_from_ls_remote_output(&output, branch_name)?;
+        cache.insert(&cache_key, &hash);
 
-        let output = std::process::Command::new("git")
-            .arg("rev-parse")
-            .arg(remote_ref)
-            .current_dir(std::fs::canonicalize(&self.path).unwrap())
-            .output()?; // Wait for it to finish and collect its output
+        Ok(hash)
+    }
 
-        let string = std::str::from_utf8(&output.stdout).expect("Unable to parse output");
+    /// `latest_remote_hash_for_branch`, without blocking the thread
+    #[cfg(feature = "tokio")]
+    pub async fn latest_remote_hash_for_branch_async(
+        &self,
+        remote: &str,
+        branch_name: &str,
+    ) -> Result<String, ConfigureError> {
+        let directory = crate::paths::canonicalize(&self.path)?;
 
-        debug!("Result: {}", string);
+        let output = run_with_timeout_async(
+            remote_hash_command(&directory, remote, branch_name),
+            NetworkPolicy::from_env().timeout,
+        )
+        .await?;
 
-        Ok(String::from(string.trim_end()))
+        Ok(hash_from_output(&output))
     }
 
-    pub fn checkout_local_hash(&self, hash: &str) -> Result<(), ConfigureError> {
-        let repo = SecretsRepo::default().get_repo()?;
+    /// Each committed version of the file at `path` (relative to the repo root) on the current branch, oldest first
+    pub fn file_history(&self, path: &str) -> Result<Vec<FileVersion>, ConfigureError> {
+        let repo = self.get_repo()?;
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push_head()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+
+        let mut history = Vec::new();
+        let mut previous_blob_id = None;
 
-        let oid = Oid::from_str(hash)?;
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)?;
+            let blob_id = commit
+                .tree()?
+                .get_path(Path::new(path))
+                .ok()
+                .map(|entry| entry.id());
 
-        let obj = repo.find_commit(oid)?.into_object();
+            if blob_id == previous_blob_id {
+                continue;
+            }
+            previous_blob_id = blob_id;
+
+            let contents = match blob_id {
+                Some(blob_id) => Some(repo.find_blob(blob_id)?.content().to_vec()),
+                None => None,
+            };
 
-        repo.set_head_detached(oid)?;
-        repo.reset(&obj, ResetType::Hard, None)?;
+            history.push(FileVersion {
+                committed_at: commit.time().seconds(),
+                contents,
+            });
+        }
+
+        Ok(history)
+    }
+
+    /// Whether the secrets repo is a partial clone, which leaves objects on the server until they're needed
+    pub fn is_partial_clone(&self) -> Result<bool, ConfigureError> {
+        Ok(self
+            .get_repo()?
+            .config()?
+            .get_string("extensions.partialclone")
+            .is_ok())
+    }
+
+    /// Download anything a partial clone left out of `path` (relative to the repo root) in the commit `revision`
+    ///
+    /// libgit2 can't fetch missing objects itself, but git does whenever it reads one – `git archive` reads every
+    /// file under `path`, and its output is thrown away. The objects come from the remote the clone was made from, so
+    /// it's checked against `configuration`'s pinned `remote_url` and `remote_host_key` first, like any other fetch.
+    pub fn fetch_missing_objects(
+        &self,
+        configuration: &Configuration,
+        revision: &str,
+        path: &str,
+    ) -> Result<(), ConfigureError> {
+        let repo = self.get_repo()?;
+        let promisor = repo.config()?.get_string("extensions.partialclone")?;
+        verify_remote(&repo, configuration, &promisor)?;
+
+        debug!(
+            "Fetching {:?} at {} from {} for a partial clone",
+            path, revision, promisor
+        );
+
+        let output = Command::new("git")
+            .arg("archive")
+            .arg(revision)
+            .arg("--")
+            .arg(path)
+            .env("GIT_TERMINAL_PROMPT", "0") // Never wait for credentials that nobody is going to type
+            .current_dir(&self.path)
+            .stdout(Stdio::null())
+            .output()?;
+
+        if !output.status.success() {
+            return Err(ConfigureError::SecretsFetchFailed {
+                remote: promisor,
+                reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
 
         Ok(())
     }
 
+    /// The contents of the file at `path` (relative to the repo root) in the commit `revision`
+    ///
+    /// This reads straight from git's object database, so the working copy can be on any branch and have local
+    /// changes – it's never touched.
+    pub fn read_file_at_revision(
+        &self,
+        revision: &str,
+        path: &str,
+    ) -> Result<Vec<u8>, ConfigureError> {
+        let repo = self.get_repo()?;
+        let tree = tree_at_revision(&repo, revision)?;
+
+        let entry = match tree.get_path(Path::new(path)) {
+            Ok(entry) => entry,
+            Err(_) => return Err(not_in_revision(path, revision)),
+        };
+
+        match entry.kind() {
+            Some(ObjectType::Blob) => Ok(repo.find_blob(entry.id())?.content().to_vec()),
+            Some(ObjectType::Tree) => Err(ConfigureError::SourceIsDirectory {
+                path: self.path.join(path),
+            }),
+            _ => Err(not_in_revision(path, revision)),
+        }
+    }
+
+    /// Everything under the directory at `path` (relative to the repo root) in the commit `revision`, with parents
+    /// listed before their contents – like `read_file_at_revision`, the working copy isn't touched
+    pub fn read_directory_at_revision(
+        &self,
+        revision: &str,
+        path: &str,
+    ) -> Result<Vec<CommittedEntry>, ConfigureError> {
+        let repo = self.get_repo()?;
+        let tree = tree_at_revision(&repo, revision)?;
+
+        let directory = match tree.get_path(Path::new(path)) {
+            Ok(entry) if entry.kind() == Some(ObjectType::Tree) => repo.find_tree(entry.id())?,
+            Ok(_) => {
+                return Err(ConfigureError::InputFileNotReadable {
+                    path: self.path.join(path),
+                })
+            }
+            Err(_) => return Err(not_in_revision(path, revision)),
+        };
+
+        let mut entries = Vec::new();
+        add_committed_entries(&repo, &directory, Path::new(""), &mut entries)?;
+        Ok(entries)
+    }
+
+    /// The commits reachable from `to` but not from `from`, newest first
+    pub fn commits_between(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<CommitSummary>, ConfigureError> {
+        self.commit_log(to, Some(from), usize::MAX)
+    }
+
+    /// Up to `limit` commits leading up to (and including) `hash`, newest first
+    pub fn recent_commits(
+        &self,
+        hash: &str,
+        limit: usize,
+    ) -> Result<Vec<CommitSummary>, ConfigureError> {
+        self.commit_log(hash, None, limit)
+    }
+
+    fn commit_log(
+        &self,
+        to: &str,
+        from: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<CommitSummary>, ConfigureError> {
+        let repo = self.get_repo()?;
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(Oid::from_str(to)?)?;
+        if let Some(from) = from {
+            revwalk.hide(Oid::from_str(from)?)?;
+        }
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+        let mut commits = Vec::new();
+
+        for oid in revwalk.take(limit) {
+            let commit = repo.find_commit(oid?)?;
+            let tree = commit.tree()?;
+            let parent_tree = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+
+            let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+            let changed_paths = diff
+                .deltas()
+                .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+                .map(|path| path.to_string_lossy().to_string())
+                .collect();
+
+            commits.push(CommitSummary {
+                hash: commit.id().to_string(),
+                subject: commit.summary().unwrap_or_default().to_string(),
+                changed_paths,
+            });
+        }
+
+        Ok(commits)
+    }
+
+    /// The full hash of `hash` (which can be abbreviated), as long as it's `branch_head` or one of its ancestors
+    pub fn resolve_commit_on_branch(
+        &self,
+        hash: &str,
+        branch: &str,
+        branch_head: &str,
+    ) -> Result<String, ConfigureError> {
+        let repo = self.get_repo()?;
+        let not_on_branch = || ConfigureError::CommitNotOnBranch {
+            hash: hash.to_string(),
+            branch: branch.to_string(),
+        };
+
+        let commit = match repo
+            .revparse_single(hash)
+            .and_then(|object| object.peel_to_commit())
+        {
+            Ok(commit) => commit.id(),
+            Err(_) => return Err(not_on_branch()),
+        };
+
+        let head = Oid::from_str(branch_head)?;
+
+        if commit == head || repo.graph_descendant_of(head, commit)? {
+            Ok(commit.to_string())
+        } else {
+            Err(not_on_branch())
+        }
+    }
+
     pub fn switch_to_branch(&self, branch_name: &str) -> Result<(), ConfigureError> {
         debug!("Trying to check out branch: {:?}", branch_name);
 
@@ -126,29 +1164,48 @@ impl SecretsRepo {
         Ok(())
     }
 
-    pub fn switch_to_branch_at_revision(
-        &self,
-        branch_name: &str,
-        revision: &str,
-    ) -> Result<(), ConfigureError> {
-        // If we're asked to check out a commit that's not currently on a branch,
-        // just switch to it directly
-        if branch_name == "HEAD" {
-            return self.checkout_local_hash(revision);
-        }
+    /// Create a branch at the current commit and switch to it, leaving the working copy as it is
+    pub fn create_and_switch_to_branch(&self, branch_name: &str) -> Result<(), ConfigureError> {
+        debug!("Creating branch: {:?}", branch_name);
 
         let repo = self.get_repo()?;
-        let ref_name = "refs/heads/".to_owned() + branch_name;
+        let head = repo.head()?.peel_to_commit()?;
+        repo.branch(branch_name, &head, false)?;
 
-        repo.set_head(&ref_name)?;
+        self.switch_to_branch(branch_name)
+    }
 
-        let oid = Oid::from_str(revision)?;
+    /// Commit the files at `paths` (relative to the repo root) on the current branch, returning the new commit hash
+    pub fn commit_paths(
+        &self,
+        paths: &[&std::path::Path],
+        message: &str,
+    ) -> Result<String, ConfigureError> {
+        let repo = self.get_repo()?;
 
-        let obj = repo.find_commit(oid)?.into_object();
+        let mut index = repo.index()?;
+        for path in paths {
+            index.add_path(path)?;
+        }
+        index.write()?;
 
-        repo.reset(&obj, ResetType::Hard, None)?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let parent = repo.head()?.peel_to_commit()?;
 
-        Ok(())
+        // Uses the author configured for the secrets repo, just like `git commit` would
+        let signature = repo.signature()?;
+
+        let oid = repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &[&parent],
+        )?;
+
+        debug!("Created commit {:?}", oid);
+        Ok(oid.to_string())
     }
 
     pub fn local_branch_names(&self) -> Result<Vec<String>, ConfigureError> {
@@ -171,78 +1228,147 @@ impl SecretsRepo {
         Ok(branch_names)
     }
 
-    /// How far out of date the configure file is relative to the secrets repo
-    pub fn commits_ahead_of_configuration(&self, configuration: &Configuration) -> i32 {
-        let current_branch = self
-            .current_branch()
-            .expect("Unable to get current mobile secrets branch");
-        let current_hash = self
-            .current_hash()
-            .expect("Unable to get current mobile secrets hash");
+    /// Every branch in the secrets repo, local or on a remote, without the remote's name and in alphabetical order
+    pub fn branch_names(&self) -> Result<Vec<String>, ConfigureError> {
+        let repo = self.get_repo()?;
+        let mut branch_names = std::collections::BTreeSet::new();
+
+        for branch in repo.branches(None)? {
+            let (branch, branch_type) = branch?;
+
+            let name = match branch.name()? {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let name = match branch_type {
+                BranchType::Local => name,
+                // Remote branches are named like `origin/trunk`, and `origin/HEAD` just points at one of the others
+                BranchType::Remote => match name.split_once('/') {
+                    Some((_, "HEAD")) | None => continue,
+                    Some((_, name)) => name,
+                },
+            };
+
+            branch_names.insert(name.to_string());
+        }
 
-        self.switch_to_branch(&configuration.branch)
-            .expect("Unable to switch branches – you might need to fetch the most recent changes from the remote first?");
+        Ok(branch_names.into_iter().collect())
+    }
 
-        let latest_hash = self
-            .current_hash()
-            .expect("Unable to retrieve current secrets hash");
+    /// Whether `branch_name` is a local branch, or a branch on one of the secrets repo's remotes
+    pub fn branch_exists(&self, branch_name: &str) -> Result<bool, ConfigureError> {
+        let repo = self.get_repo()?;
 
-        let distance = self
-            .distance_between_local_commit_hashes(&configuration.pinned_hash, &latest_hash)
-            .expect("Unable to determine the distance between two hashes");
+        if repo.find_branch(branch_name, BranchType::Local).is_ok() {
+            return Ok(true);
+        }
 
-        // Restore the secrets repo to its state before starting
-        self.switch_to_branch_at_revision(&current_branch, &current_hash)
-            .expect("Unable to roll back to branch");
+        let remotes = repo.remotes()?;
+        let exists_on_a_remote = remotes.iter().flatten().any(|remote| {
+            repo.find_branch(&format!("{}/{}", remote, branch_name), BranchType::Remote)
+                .is_ok()
+        });
 
-        distance
+        Ok(exists_on_a_remote)
     }
 
-    // Returns the number of commits between two hashes. If the hashes aren't part of the same history
-    // or if `hash2` comes before `hash1`, the result will be `0`
-    fn distance_between_local_commit_hashes(
+    /// How far out of date the configure file is relative to its branch in the secrets repo
+    ///
+    /// The pinned commit has to share some history with the branch – if it doesn't, there's no meaningful distance
+    /// and this returns an error.
+    pub fn commits_ahead_of_configuration(
         &self,
-        hash1: &str,
-        hash2: &str,
-    ) -> Result<i32, ConfigureError> {
-        // If we're asked to calculate the distance between two of the same hash, we can skip a lot of work
-        if hash1 == hash2 {
-            debug!("Hashes are identical – skipping checks");
-            return Ok(0);
-        }
+        configuration: &Configuration,
+    ) -> Result<CommitDistance, ConfigureError> {
+        let latest_hash = self.latest_local_hash_for_branch(&configuration.branch)?;
 
-        let hash_list = self.get_hash_list()?;
-
-        match distance_between_strings_in(hash1, hash2, &hash_list) {
+        match self.distance_between_commits(&configuration.pinned_hash, &latest_hash)? {
             Some(distance) => Ok(distance),
-            None => Err(ConfigureError::GitStatusUnknownError),
+            None => Err(ConfigureError::PinnedHashNotInBranchHistory {
+                hash: configuration.pinned_hash.clone(),
+                branch: configuration.branch.clone(),
+            }),
         }
     }
 
-    fn get_hash_list(&self) -> Result<Vec<String>, std::io::Error> {
-        debug!("Opening secrets repo at {:?}", self.path);
+    /// How many commits `to` has that `from` doesn't (`ahead`), and the other way around (`behind`)
+    ///
+    /// Either can be abbreviated. Returns `None` if the two commits have no history in common.
+    fn distance_between_commits(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<Option<CommitDistance>, ConfigureError> {
+        let repo = self.get_repo()?;
+        let from = repo.revparse_single(from)?.peel_to_commit()?.id();
+        let to = repo.revparse_single(to)?.peel_to_commit()?.id();
 
-        let output = std::process::Command::new("git")
-            .arg("--no-pager")
-            .arg("log")
-            .arg("-10000")
-            .arg("--pretty=format:%H")
-            .current_dir(std::fs::canonicalize(&self.path).unwrap())
-            .output()?;
+        // If we're asked to calculate the distance between two of the same hash, we can skip a lot of work
+        if from == to {
+            debug!("Hashes are identical – skipping checks");
+            return Ok(Some(CommitDistance::default()));
+        }
 
-        debug!("Fetched hash list");
+        match repo.merge_base(from, to) {
+            Ok(_) => {}
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
 
-        let lines: Vec<String> = std::str::from_utf8(&output.stdout)
-            .expect("Unable to read hash list")
-            .lines()
-            .rev()
-            .map(|s| s.to_string())
-            .collect();
+        let (ahead, behind) = repo.graph_ahead_behind(to, from)?;
+        Ok(Some(CommitDistance { ahead, behind }))
+    }
+}
+
+/// The tree of the commit `revision` – a hash, a branch name, or anything else git understands
+fn tree_at_revision<'repo>(
+    repo: &'repo Repository,
+    revision: &str,
+) -> Result<Tree<'repo>, ConfigureError> {
+    Ok(repo.revparse_single(revision)?.peel_to_commit()?.tree()?)
+}
 
-        debug!("Hash list has {:} entries:", lines.len());
+fn not_in_revision(path: &str, revision: &str) -> ConfigureError {
+    ConfigureError::SourceNotInRevision {
+        path: path.to_string(),
+        revision: revision.to_string(),
+    }
+}
 
-        Ok(lines)
+fn add_committed_entries(
+    repo: &Repository,
+    tree: &Tree,
+    parent: &Path,
+    entries: &mut Vec<CommittedEntry>,
+) -> Result<(), ConfigureError> {
+    for entry in tree.iter() {
+        let path = parent.join(String::from_utf8_lossy(entry.name_bytes()).as_ref());
+
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                entries.push(CommittedEntry {
+                    path: path.clone(),
+                    mode: 0o755,
+                    contents: None,
+                });
+                add_committed_entries(repo, &repo.find_tree(entry.id())?, &path, entries)?;
+            }
+            Some(ObjectType::Blob) => entries.push(CommittedEntry {
+                path,
+                mode: if entry.filemode() & 0o111 != 0 {
+                    0o755
+                } else {
+                    0o644
+                },
+                contents: Some(repo.find_blob(entry.id())?.content().to_vec()),
+            }),
+            // Submodules are commits from another repo, so there's nothing here to read
+            _ => debug!("Skipping {:?} – it isn't a file or directory", path),
+        }
     }
+
+    Ok(())
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -253,142 +1379,777 @@ pub enum RepoSyncState {
     /// The server has commits that the local secrets repository does not have
     Behind,
 
+    /// Both have commits that the other doesn't – pulling would need a merge or a rebase
+    Diverged { ahead: usize, behind: usize },
+
     /// The local secrets repository and server are in sync
     Synced,
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct RepoStatus {
-    /// The local repository sync state – ahead of, behind, or in sync with the server
+    /// The local repository sync state – ahead of, behind, diverged from, or in sync with the server
     pub sync_state: RepoSyncState,
 
-    /// How many commits the local repository is out of sync by. If the repository is in sync,
-    /// this value will be `0`
-    pub distance: i32,
+    /// How many commits the local repository is out of sync by – for a diverged repository, that's the commits on
+    /// both sides. If the repository is in sync, this value will be `0`
+    pub distance: usize,
 }
 
 impl RepoStatus {
-    fn synced() -> RepoStatus {
-        RepoStatus {
-            sync_state: RepoSyncState::Synced,
-            distance: 0,
+    /// Compares the current branch with its upstream branch, which has to be set
+    fn from_repo(repo: &SecretsRepo) -> Result<RepoStatus, ConfigureError> {
+        let repo = repo.get_repo()?;
+
+        let head = repo.head()?;
+        if !head.is_branch() {
+            debug!("The secrets repo isn't on a branch, so it has no upstream to compare with");
+            return Err(ConfigureError::GitStatusUnknownError);
         }
-    }
 
-    fn from_repo(repo: &SecretsRepo) -> Result<RepoStatus, ConfigureError> {
-        let output = std::process::Command::new("git")
-            .arg("status")
-            .arg("--porcelain")
-            .arg("-b")
-            .current_dir(std::fs::canonicalize(&repo.path).unwrap())
-            .output()?; // Wait for it to finish and collect its output
+        let branch = git2::Branch::wrap(head);
+        let upstream = match branch.upstream() {
+            Ok(upstream) => upstream,
+            Err(_) => {
+                debug!("{:?} has no upstream branch", branch.name());
+                return Err(ConfigureError::GitStatusUnknownError);
+            }
+        };
 
-        let status = std::str::from_utf8(&output.stdout).expect("Unable to read output data");
+        let local = branch.get().peel_to_commit()?.id();
+        let remote = upstream.get().peel_to_commit()?.id();
+        let (ahead, behind) = repo.graph_ahead_behind(local, remote)?;
 
-        RepoStatus::parse_repo_status(status)
+        Ok(RepoStatus::from_counts(ahead, behind))
     }
 
-    fn parse_repo_status(status: &str) -> Result<RepoStatus, ConfigureError> {
-        if status.contains("ahead") {
-            return Ok(RepoStatus {
-                sync_state: RepoSyncState::Ahead,
-                distance: RepoStatus::parse_digits_from_repo_status(status)?,
-            });
-        }
+    fn from_counts(ahead: usize, behind: usize) -> RepoStatus {
+        let sync_state = match (ahead, behind) {
+            (0, 0) => RepoSyncState::Synced,
+            (_, 0) => RepoSyncState::Ahead,
+            (0, _) => RepoSyncState::Behind,
+            (ahead, behind) => RepoSyncState::Diverged { ahead, behind },
+        };
 
-        if status.contains("behind") {
-            return Ok(RepoStatus {
-                sync_state: RepoSyncState::Behind,
-                distance: RepoStatus::parse_digits_from_repo_status(status)?,
-            });
+        RepoStatus {
+            sync_state,
+            distance: ahead + behind,
         }
+    }
+}
 
-        if status.contains("...") {
-            return Ok(RepoStatus::synced());
-        }
+/// The state of the secrets repo, for tools that want to show it without running `git` themselves
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct SecretsRepoInfo {
+    pub path: PathBuf,
 
-        Err(ConfigureError::GitStatusUnknownError {})
-    }
+    /// The branch that's checked out, or `None` if HEAD is detached
+    pub branch: Option<String>,
+
+    /// The commit that's checked out
+    pub hash: String,
+
+    /// How many commits the branch has that its upstream doesn't – `None` if it has no upstream
+    pub ahead: Option<usize>,
+
+    /// How many commits the branch's upstream has that it doesn't – `None` if it has no upstream
+    pub behind: Option<usize>,
+
+    /// Whether the working copy has uncommitted changes or untracked files
+    pub dirty: bool,
+
+    /// The remote the project's secrets are fetched from
+    pub remote: String,
+
+    /// `None` if the secrets repo doesn't have that remote
+    pub remote_url: Option<String>,
+}
+
+impl fmt::Display for SecretsRepoInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Path:     {}", self.path.display())?;
+        writeln!(
+            f,
+            "Branch:   {}",
+            self.branch.as_deref().unwrap_or("(detached)")
+        )?;
+        writeln!(f, "Commit:   {}", self.hash)?;
+
+        match (self.ahead, self.behind) {
+            (Some(ahead), Some(behind)) => {
+                writeln!(f, "Upstream: {} ahead, {} behind", ahead, behind)?
+            }
+            _ => writeln!(f, "Upstream: (none)")?,
+        }
 
-    fn parse_digits_from_repo_status(status: &str) -> Result<i32, ConfigureError> {
-        let digits = status
-            .chars()
-            .filter(|c| c.is_ascii_digit())
-            .collect::<String>()
-            .parse::<i32>()?;
+        writeln!(
+            f,
+            "Changes:  {}",
+            if self.dirty {
+                "uncommitted changes"
+            } else {
+                "none"
+            }
+        )?;
 
-        Ok(digits)
+        match &self.remote_url {
+            Some(url) => write!(f, "Remote:   {} ({})", self.remote, url),
+            None => write!(f, "Remote:   {} (not found)", self.remote),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use git2::Signature;
 
     #[test]
-    fn test_that_parse_repo_status_returns_behind_for_behind_strings() {
-        assert_eq!(
-            RepoSyncState::Behind,
-            RepoStatus::parse_repo_status("## trunk...origin/trunk [behind 1]")
-                .unwrap()
-                .sync_state
+    fn test_that_backoff_doubles_after_each_attempt() {
+        let policy = NetworkPolicy {
+            initial_backoff: Duration::from_secs(2),
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff_for_attempt(1), Duration::from_secs(2));
+        assert_eq!(policy.backoff_for_attempt(2), Duration::from_secs(4));
+        assert_eq!(policy.backoff_for_attempt(3), Duration::from_secs(8));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_that_run_with_timeout_collects_output() {
+        let output =
+            run_with_timeout(Command::new("echo").arg("hello"), Duration::from_secs(5)).unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_that_run_with_timeout_kills_slow_commands() {
+        let result = run_with_timeout(Command::new("sleep").arg("5"), Duration::from_millis(100));
+        assert!(matches!(result, Err(ConfigureError::GitNetworkTimeout)));
+    }
+
+    #[cfg(all(unix, feature = "async"))]
+    #[test]
+    fn test_that_run_with_timeout_async_collects_output() {
+        let mut command = Command::new("echo");
+        command.arg("hello");
+        let output = async_runtime()
+            .block_on(run_with_timeout_async(command, Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+    }
+
+    #[cfg(all(unix, feature = "async"))]
+    #[test]
+    fn test_that_run_with_timeout_async_kills_slow_commands() {
+        let mut command = Command::new("sleep");
+        command.arg("5");
+        let result =
+            async_runtime().block_on(run_with_timeout_async(command, Duration::from_millis(100)));
+        assert!(matches!(result, Err(ConfigureError::GitNetworkTimeout)));
+    }
+
+    #[cfg(feature = "async")]
+    fn async_runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_that_run_network_command_retries_failures() {
+        let policy = NetworkPolicy {
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            offline: false,
+            cache_ttl: Duration::ZERO,
+        };
+        let attempts = std::cell::Cell::new(0);
+        let output = run_network_command(
+            || {
+                attempts.set(attempts.get() + 1);
+                Command::new("false")
+            },
+            &policy,
         )
+        .unwrap();
+        assert!(!output.status.success());
+        assert_eq!(attempts.get(), 3);
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_that_parse_repo_status_returns_ahead_for_ahead_strings() {
-        assert_eq!(
-            RepoSyncState::Ahead,
-            RepoStatus::parse_repo_status("## trunk...origin/trunk [ahead 1]")
-                .unwrap()
-                .sync_state
+    fn test_that_a_failed_fetch_is_an_error() {
+        let output = run_with_timeout(
+            Command::new("sh")
+                .arg("-c")
+                .arg("echo 'no such remote' >&2; exit 1"),
+            Duration::from_secs(5),
         )
+        .unwrap();
+
+        match check_fetch_output(&output, "origin") {
+            Err(ConfigureError::SecretsFetchFailed { remote, reason }) => {
+                assert_eq!(remote, "origin");
+                assert_eq!(reason, "no such remote");
+            }
+            result => panic!("Expected the fetch to fail, got {:?}", result),
+        }
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_that_parse_repo_status_returns_synced_for_synced_strings() {
-        assert_eq!(
-            RepoSyncState::Synced,
-            RepoStatus::parse_repo_status("## trunk...origin/trunk")
-                .unwrap()
-                .sync_state
+    fn test_that_ls_remote_output_is_matched_to_the_branch() {
+        let output = run_with_timeout(
+            Command::new("printf")
+                .arg("abc123\\trefs/heads/trunk-old\\ndef456\\trefs/heads/trunk\\n"),
+            Duration::from_secs(5),
         )
+        .unwrap();
+
+        assert_eq!(
+            hash_from_ls_remote_output(&output, "trunk").unwrap(),
+            "def456"
+        );
+        assert_eq!(hash_from_ls_remote_output(&output, "release").unwrap(), "");
+    }
+
+    /// A new repo with a commit for each `(file, message)`, where the file's contents are the message
+    fn repo_with_commits(name: &str, commits: &[(&str, &str)]) -> (SecretsRepo, Vec<String>) {
+        let path = std::env::temp_dir().join(format!(
+            "configure-git-{}-test-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        let git_repo = Repository::init(&path).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+
+        let hashes = commits
+            .iter()
+            .map(|(file, message)| {
+                std::fs::create_dir_all(path.join(file).parent().unwrap()).unwrap();
+                std::fs::write(path.join(file), message).unwrap();
+
+                let mut index = git_repo.index().unwrap();
+                index.add_path(Path::new(file)).unwrap();
+                let tree = git_repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+                let parents = match git_repo.head() {
+                    Ok(head) => vec![head.peel_to_commit().unwrap()],
+                    Err(_) => vec![],
+                };
+                let parents: Vec<&git2::Commit> = parents.iter().collect();
+
+                git_repo
+                    .commit(
+                        Some("HEAD"),
+                        &signature,
+                        &signature,
+                        message,
+                        &tree,
+                        &parents,
+                    )
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+
+        (SecretsRepo { path }, hashes)
+    }
+
+    #[test]
+    fn test_that_files_are_read_from_a_commit_without_checking_it_out() {
+        let (repo, hashes) = repo_with_commits(
+            "revision",
+            &[
+                ("a.json", "Add a"),
+                ("profiles/b.txt", "Add b"),
+                ("a.json", "Change a"),
+            ],
+        );
+        std::fs::write(repo.path.join("a.json"), "Local change").unwrap();
+
+        assert_eq!(
+            repo.read_file_at_revision(&hashes[0], "a.json").unwrap(),
+            b"Add a"
+        );
+        assert_eq!(
+            repo.read_file_at_revision(&hashes[2], "a.json").unwrap(),
+            b"Change a"
+        );
+        assert!(matches!(
+            repo.read_file_at_revision(&hashes[0], "profiles/b.txt"),
+            Err(ConfigureError::SourceNotInRevision { .. })
+        ));
+        assert!(matches!(
+            repo.read_file_at_revision(&hashes[1], "profiles"),
+            Err(ConfigureError::SourceIsDirectory { .. })
+        ));
+
+        let entries = repo
+            .read_directory_at_revision(&hashes[1], "profiles")
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![CommittedEntry {
+                path: PathBuf::from("b.txt"),
+                mode: 0o644,
+                contents: Some(b"Add b".to_vec()),
+            }]
+        );
+
+        // Nothing was checked out
+        assert_eq!(repo.current_hash().unwrap(), hashes[2]);
+        assert_eq!(
+            std::fs::read_to_string(repo.path.join("a.json")).unwrap(),
+            "Local change"
+        );
+    }
+
+    #[test]
+    fn test_that_distance_between_commits_counts_both_directions() {
+        let (repo, hashes) = repo_with_commits(
+            "distance",
+            &[
+                ("a.json", "Add a"),
+                ("b.json", "Add b"),
+                ("c.json", "Add c"),
+            ],
+        );
+
+        assert_eq!(
+            repo.distance_between_commits(&hashes[0], &hashes[2])
+                .unwrap(),
+            Some(CommitDistance {
+                ahead: 2,
+                behind: 0
+            })
+        );
+        assert_eq!(
+            repo.distance_between_commits(&hashes[2], &hashes[0][..7])
+                .unwrap(),
+            Some(CommitDistance {
+                ahead: 0,
+                behind: 2
+            })
+        );
+
+        // A commit without parents has no history in common with the others
+        let git_repo = Repository::open(&repo.path).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree = git_repo
+            .find_commit(Oid::from_str(&hashes[0]).unwrap())
+            .unwrap()
+            .tree()
+            .unwrap();
+        let unrelated = git_repo
+            .commit(None, &signature, &signature, "Unrelated", &tree, &[])
+            .unwrap();
+
+        assert_eq!(
+            repo.distance_between_commits(&unrelated.to_string(), &hashes[2])
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_that_commits_between_lists_newer_commits_with_their_changes() {
+        let (repo, hashes) = repo_with_commits(
+            "log",
+            &[
+                ("a.json", "Add a"),
+                ("b.json", "Add b"),
+                ("a.json", "Change a\n\nWith details"),
+            ],
+        );
+        let commits = repo.commits_between(&hashes[0], &hashes[2]).unwrap();
+
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].hash, hashes[2]);
+        assert_eq!(commits[0].subject, "Change a");
+        assert_eq!(commits[0].changed_paths, vec!["a.json"]);
+        assert_eq!(commits[1].subject, "Add b");
+        assert_eq!(commits[1].changed_paths, vec!["b.json"]);
+    }
+
+    #[test]
+    fn test_that_recent_commits_stops_at_the_limit() {
+        let (repo, hashes) =
+            repo_with_commits("recent", &[("a.json", "Add a"), ("b.json", "Add b")]);
+        let commits = repo.recent_commits(&hashes[1], 1).unwrap();
+
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].hash, hashes[1]);
+    }
+
+    #[test]
+    fn test_that_abbreviated_commits_on_the_branch_are_resolved() {
+        let (repo, hashes) =
+            repo_with_commits("resolve", &[("a.json", "Add a"), ("b.json", "Add b")]);
+
+        assert_eq!(
+            repo.resolve_commit_on_branch(&hashes[0][..7], "trunk", &hashes[1])
+                .unwrap(),
+            hashes[0]
+        );
+    }
+
+    #[test]
+    fn test_that_commits_after_the_branch_head_are_not_resolved() {
+        let (repo, hashes) =
+            repo_with_commits("not-on-branch", &[("a.json", "Add a"), ("b.json", "Add b")]);
+
+        assert!(matches!(
+            repo.resolve_commit_on_branch(&hashes[1], "trunk", &hashes[0]),
+            Err(ConfigureError::CommitNotOnBranch { .. })
+        ));
+        assert!(matches!(
+            repo.resolve_commit_on_branch("not-a-commit", "trunk", &hashes[1]),
+            Err(ConfigureError::CommitNotOnBranch { .. })
+        ));
     }
 
     #[test]
-    fn test_that_parse_repo_status_returns_error_for_garbage_string() {
-        assert!(RepoStatus::parse_repo_status("foo").is_err())
+    fn test_that_repo_status_is_synced_without_differences() {
+        assert_eq!(
+            RepoStatus::from_counts(0, 0),
+            RepoStatus {
+                sync_state: RepoSyncState::Synced,
+                distance: 0
+            }
+        );
     }
 
     #[test]
-    fn test_that_parse_repo_status_returns_correct_distance_for_behind_string() {
+    fn test_that_repo_status_is_ahead_or_behind_by_the_commits_on_one_side() {
         assert_eq!(
-            1,
-            RepoStatus::parse_repo_status("## trunk...origin/trunk [behind 1]")
-                .unwrap()
-                .distance
+            RepoStatus::from_counts(9321, 0),
+            RepoStatus {
+                sync_state: RepoSyncState::Ahead,
+                distance: 9321
+            }
         );
         assert_eq!(
-            9321,
-            RepoStatus::parse_repo_status("## trunk...origin/trunk [behind 9321]")
-                .unwrap()
-                .distance
+            RepoStatus::from_counts(0, 1),
+            RepoStatus {
+                sync_state: RepoSyncState::Behind,
+                distance: 1
+            }
         );
     }
 
     #[test]
-    fn test_that_parse_repo_status_returns_correct_distance_for_ahead_string() {
+    fn test_that_repo_status_is_diverged_with_commits_on_both_sides() {
         assert_eq!(
-            1,
-            RepoStatus::parse_repo_status("## trunk...origin/trunk [ahead 01]")
-                .unwrap()
-                .distance
+            RepoStatus::from_counts(2, 3),
+            RepoStatus {
+                sync_state: RepoSyncState::Diverged {
+                    ahead: 2,
+                    behind: 3
+                },
+                distance: 5
+            }
         );
+    }
+
+    #[test]
+    fn test_that_repo_status_compares_the_branch_with_its_upstream() {
+        let (repo, hashes) =
+            repo_with_commits("status-2024", &[("a.json", "Add a"), ("b.json", "Add b")]);
+        assert!(matches!(
+            repo.status(),
+            Err(ConfigureError::GitStatusUnknownError)
+        ));
+
+        let git_repo = Repository::open(&repo.path).unwrap();
+        git_repo
+            .remote("origin", "https://example.com/secrets.git")
+            .unwrap();
+        git_repo
+            .reference(
+                "refs/remotes/origin/release-2",
+                Oid::from_str(&hashes[0]).unwrap(),
+                false,
+                "test",
+            )
+            .unwrap();
+        let branch_name = repo.current_branch().unwrap();
+        git_repo
+            .find_branch(&branch_name, BranchType::Local)
+            .unwrap()
+            .set_upstream(Some("origin/release-2"))
+            .unwrap();
+
+        // Digits in the branch names don't end up in the distance
+        assert_eq!(
+            repo.status().unwrap(),
+            RepoStatus {
+                sync_state: RepoSyncState::Ahead,
+                distance: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_that_local_and_remote_branches_exist() {
+        let (repo, hashes) = repo_with_commits("branches", &[("a.json", "Add a")]);
+        let git_repo = Repository::open(&repo.path).unwrap();
+        let oid = git2::Oid::from_str(&hashes[0]).unwrap();
+
+        git_repo
+            .branch("release", &git_repo.find_commit(oid).unwrap(), false)
+            .unwrap();
+        git_repo
+            .remote("origin", "https://example.com/secrets.git")
+            .unwrap();
+        git_repo
+            .reference("refs/remotes/origin/remote-only", oid, false, "test")
+            .unwrap();
+
+        assert!(repo.branch_exists("release").unwrap());
+        assert!(repo.branch_exists("remote-only").unwrap());
+        assert!(!repo.branch_exists("missing").unwrap());
+
+        let branch_names = repo.branch_names().unwrap();
+        assert!(branch_names.contains(&"release".to_string()));
+        assert!(branch_names.contains(&"remote-only".to_string()));
+    }
+
+    #[test]
+    fn test_that_the_remote_is_found_for_a_configuration() {
+        let (repo, _) = repo_with_commits("remotes", &[("a.json", "Add a")]);
+        let git_repo = Repository::open(&repo.path).unwrap();
+        let mut configuration = Configuration {
+            branch: "trunk".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            repo.remote_for_configuration(&configuration).unwrap(),
+            "origin"
+        );
+
+        // The only remote is used, whatever it's called
+        git_repo
+            .remote("upstream", "https://example.com/secrets.git")
+            .unwrap();
+        assert_eq!(
+            repo.remote_for_configuration(&configuration).unwrap(),
+            "upstream"
+        );
+
+        // With more than one, the one the branch tracks
+        git_repo
+            .remote("fork", "https://example.com/fork/secrets.git")
+            .unwrap();
+        assert_eq!(
+            repo.remote_for_configuration(&configuration).unwrap(),
+            "origin"
+        );
+        git_repo
+            .config()
+            .unwrap()
+            .set_str("branch.trunk.remote", "fork")
+            .unwrap();
+        assert_eq!(
+            repo.remote_for_configuration(&configuration).unwrap(),
+            "fork"
+        );
+
+        // The `.configure` file has the final say
+        configuration.remote = Some("upstream".to_string());
+        assert_eq!(
+            repo.remote_for_configuration(&configuration).unwrap(),
+            "upstream"
+        );
+
+        configuration.remote = Some("mirror".to_string());
+        assert!(matches!(
+            repo.remote_for_configuration(&configuration),
+            Err(ConfigureError::RemoteNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_that_remotes_are_checked_against_the_pinned_url() {
+        let (repo, _) = repo_with_commits("pinned-remote", &[("a.json", "Add a")]);
+        let git_repo = Repository::open(&repo.path).unwrap();
+        git_repo
+            .remote("origin", "git@github.com:example/secrets.git")
+            .unwrap();
+
+        let mut configuration = Configuration {
+            branch: "trunk".to_string(),
+            remote_url: Some("https://github.com/example/secrets".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            repo.remote_for_configuration(&configuration).unwrap(),
+            "origin"
+        );
+
+        configuration.remote_url = Some("https://github.com/fork/secrets".to_string());
+        assert!(matches!(
+            repo.remote_for_configuration(&configuration),
+            Err(ConfigureError::RemoteUrlMismatch { .. })
+        ));
+
+        // Pushes go to the push URL, so that has to match as well
+        configuration.remote_url = Some("git@github.com:example/secrets.git".to_string());
+        git_repo
+            .remote_set_pushurl("origin", Some("git@github.com:fork/secrets.git"))
+            .unwrap();
+        assert!(matches!(
+            repo.remote_for_configuration(&configuration),
+            Err(ConfigureError::RemoteUrlMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_that_remote_urls_are_normalized() {
+        for url in [
+            "git@github.com:Example/secrets.git",
+            "ssh://git@GitHub.com:22/Example/secrets",
+            "https://github.com/Example/secrets/",
+        ] {
+            assert_eq!(normalize_remote_url(url), "github.com/Example/secrets");
+        }
+
+        assert_eq!(normalize_remote_url("/srv/secrets.git/"), "/srv/secrets");
+        assert_eq!(normalize_remote_url(r"C:\secrets"), r"C:\secrets");
+        assert!(is_ssh_url("git@github.com:example/secrets.git"));
+        assert!(!is_ssh_url("https://github.com/example/secrets"));
+    }
+
+    #[test]
+    fn test_that_host_key_fingerprints_are_read_from_ssh_keygen_output() {
+        let output = "# Host github.com found: line 1 \n\
+                      github.com ED25519 SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU\n";
+        let fingerprints: Vec<&str> = host_key_fingerprints(output).collect();
+
+        assert_eq!(
+            fingerprints,
+            vec!["SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU"]
+        );
+        assert!(fingerprints_match(
+            fingerprints[0],
+            "+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU="
+        ));
+        assert!(!fingerprints_match(fingerprints[0], "SHA256:AAAA"));
+    }
+
+    #[test]
+    fn test_that_unknown_submodules_are_not_checked_out() {
+        let (repo, _) = repo_with_commits("submodule", &[("a.json", "Add a")]);
+
+        assert!(matches!(
+            check_out_submodule(&repo.path, ".mobile-secrets"),
+            Err(ConfigureError::SecretsSubmoduleNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_that_the_latest_hash_for_a_branch_is_read_without_switching_to_it() {
+        let (repo, hashes) =
+            repo_with_commits("latest-local", &[("a.json", "Add a"), ("b.json", "Add b")]);
+        let git_repo = Repository::open(&repo.path).unwrap();
+        let first_commit = git_repo
+            .find_commit(Oid::from_str(&hashes[0]).unwrap())
+            .unwrap();
+        let current_branch = repo.current_branch().unwrap();
+
+        git_repo.branch("release", &first_commit, false).unwrap();
+        git_repo
+            .remote("origin", "https://example.com/secrets.git")
+            .unwrap();
+        git_repo
+            .reference(
+                "refs/remotes/origin/remote-only",
+                first_commit.id(),
+                false,
+                "test",
+            )
+            .unwrap();
+
+        assert_eq!(
+            repo.latest_local_hash_for_branch("release").unwrap(),
+            hashes[0]
+        );
+        assert_eq!(
+            repo.latest_local_hash_for_branch("remote-only").unwrap(),
+            hashes[0]
+        );
+        assert!(repo.latest_local_hash_for_branch("missing").is_err());
+        assert_eq!(repo.current_branch().unwrap(), current_branch);
+        assert_eq!(repo.current_hash().unwrap(), hashes[1]);
+    }
+
+    #[test]
+    fn test_that_a_repo_without_commits_has_no_current_branch() {
+        let (repo, _) = repo_with_commits("unborn", &[]);
+
+        assert!(matches!(
+            repo.current_branch(),
+            Err(ConfigureError::GitGetCurrentBranchError)
+        ));
+    }
+
+    #[test]
+    fn test_that_a_detached_head_is_reported_as_head() {
+        let (repo, hashes) =
+            repo_with_commits("detached", &[("a.json", "Add a"), ("b.json", "Add b")]);
+        Repository::open(&repo.path)
+            .unwrap()
+            .set_head_detached(git2::Oid::from_str(&hashes[0]).unwrap())
+            .unwrap();
+
+        assert_eq!(repo.current_branch().unwrap(), "HEAD");
+    }
+
+    #[test]
+    fn test_that_repo_info_describes_the_checkout() {
+        let (repo, hashes) = repo_with_commits("info", &[("a.json", "Add a"), ("b.json", "Add b")]);
+        let git_repo = Repository::open(&repo.path).unwrap();
+        let current_branch = repo.current_branch().unwrap();
+
+        let info = repo.info("origin").unwrap();
+        assert_eq!(info.branch.as_deref(), Some(current_branch.as_str()));
+        assert_eq!(info.hash, hashes[1]);
+        assert_eq!((info.ahead, info.behind), (None, None));
+        assert!(!info.dirty);
+        assert_eq!(info.remote_url, None);
+
+        git_repo
+            .remote("origin", "https://example.com/secrets.git")
+            .unwrap();
+        git_repo
+            .reference(
+                &format!("refs/remotes/origin/{}", current_branch),
+                Oid::from_str(&hashes[0]).unwrap(),
+                false,
+                "test",
+            )
+            .unwrap();
+        git_repo
+            .find_branch(&current_branch, BranchType::Local)
+            .unwrap()
+            .set_upstream(Some(&format!("origin/{}", current_branch)))
+            .unwrap();
+        std::fs::write(repo.path.join("c.json"), "Not committed").unwrap();
+
+        let info = repo.info("origin").unwrap();
+        assert_eq!((info.ahead, info.behind), (Some(1), Some(0)));
+        assert!(info.dirty);
         assert_eq!(
-            9321,
-            RepoStatus::parse_repo_status("## trunk...origin/trunk [ahead 9321]")
-                .unwrap()
-                .distance
+            info.remote_url.as_deref(),
+            Some("https://example.com/secrets.git")
         );
     }
 }
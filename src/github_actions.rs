@@ -0,0 +1,143 @@
+//! Handing a project's secrets to the later steps of a GitHub Actions job
+//!
+//! `configure gha-export` applies the project, then passes on the entries of its key-value files (see the `run`
+//! module). Each value is masked in the job's log with an `::add-mask::` command first, then written to the file in
+//! `GITHUB_ENV` so later steps get it as an environment variable – and to the one in `GITHUB_OUTPUT` as well, with
+//! `--outputs`, so workflows can use it as `steps.<id>.outputs.<name>`.
+//!
+//! Actions logs aren't a terminal, so running in Actions also turns off colors and prompts – see the `ci` module.
+
+use crate::run::SecretVariable;
+use crate::ConfigureError;
+use log::debug;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// The file that sets environment variables for the job's later steps
+const ENVIRONMENT_FILE_VARIABLE: &str = "GITHUB_ENV";
+
+/// The file that sets the current step's outputs
+const OUTPUT_FILE_VARIABLE: &str = "GITHUB_OUTPUT";
+
+/// Mask each of `variables`' values in the job's log, and pass them on to later steps – returning their names
+pub fn export_variables(
+    variables: &[SecretVariable],
+    outputs: bool,
+) -> Result<Vec<String>, ConfigureError> {
+    let environment_file = file_from_variable(ENVIRONMENT_FILE_VARIABLE)?;
+    let output_file = match outputs {
+        true => Some(file_from_variable(OUTPUT_FILE_VARIABLE)?),
+        false => None,
+    };
+
+    // Everything is masked before anything is written, so a failure part-way through can't leave a value unmasked
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for (_, value) in variables {
+        for command in mask_commands(value) {
+            writeln!(stdout, "{}", command)?;
+        }
+    }
+    stdout.flush()?;
+
+    let entries: String = variables
+        .iter()
+        .map(|(name, value)| file_command_entry(name, value))
+        .collect();
+    let entries = Zeroizing::new(entries);
+
+    append(&environment_file, &entries)?;
+    if let Some(output_file) = output_file {
+        append(&output_file, &entries)?;
+    }
+
+    Ok(variables.iter().map(|(name, _)| name.clone()).collect())
+}
+
+fn file_from_variable(variable: &str) -> Result<PathBuf, ConfigureError> {
+    match std::env::var(variable) {
+        Ok(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+        _ => Err(ConfigureError::GitHubActionsFileMissing {
+            variable: variable.to_string(),
+        }),
+    }
+}
+
+fn append(path: &Path, contents: &str) -> Result<(), ConfigureError> {
+    debug!("Adding {} bytes to {:?}", contents.len(), path);
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+/// The `::add-mask::` commands for `value` – one per line, since a mask only covers a single line of the log
+fn mask_commands(value: &str) -> Vec<String> {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("::add-mask::{}", escape_command_data(line)))
+        .collect()
+}
+
+/// `data` with the characters that end a workflow command escaped, as the Actions toolkit does
+fn escape_command_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// `name` and `value` in the heredoc-like format `GITHUB_ENV` and `GITHUB_OUTPUT` use, which allows multi-line values
+fn file_command_entry(name: &str, value: &str) -> String {
+    // A random delimiter, so a value can't end the entry early and set variables of its own
+    let delimiter = loop {
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        let delimiter = format!("ghadelimiter_{}", suffix);
+
+        if !value.contains(&delimiter) {
+            break delimiter;
+        }
+    };
+
+    format!("{}<<{}\n{}\n{}\n", name, delimiter, value, delimiter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_every_line_of_a_value_is_masked() {
+        assert_eq!(
+            mask_commands("-----BEGIN KEY-----\nabc%123\n\n-----END KEY-----"),
+            vec![
+                "::add-mask::-----BEGIN KEY-----",
+                "::add-mask::abc%25123",
+                "::add-mask::-----END KEY-----",
+            ]
+        );
+        assert!(mask_commands("").is_empty());
+    }
+
+    #[test]
+    fn test_that_entries_are_written_with_a_delimiter() {
+        let entry = file_command_entry("API_KEY", "first\nsecond");
+        let lines: Vec<&str> = entry.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("API_KEY<<ghadelimiter_"));
+        assert_eq!(&lines[1..3], &["first", "second"]);
+        assert_eq!(lines[0].split_once("<<").unwrap().1, lines[3]);
+    }
+}
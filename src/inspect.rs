@@ -0,0 +1,631 @@
+//! Showing what's in certificates, keystores and provisioning profiles without decrypting them to disk
+//!
+//! `configure inspect <destination>` decrypts the file in memory and prints what's safe to share – who a certificate
+//! was issued to and when it expires, the aliases in a keystore, or a provisioning profile's app ID and entitlements.
+//! Private keys are never printed. The kind of file is recognised by its destination's extension:
+//!
+//! * `.pem`, `.crt`, `.cer` – certificates, in PEM or DER
+//! * `.p12`, `.pfx` – PKCS #12 bundles
+//! * `.jks`, `.keystore` – Java keystores, in JKS or PKCS #12 format
+//! * `.mobileprovision`, `.provisionprofile` – Apple provisioning profiles
+//!
+//! PKCS #12 files are usually protected by a password. It's read from `CONFIGURE_KEYSTORE_PASSWORD` if that's set,
+//! then an empty password is tried, and then the user is asked for it if there's someone at the terminal.
+
+use crate::configure::{Configuration, File};
+use crate::encryption::decrypt_file_contents_with_keys;
+use crate::fs::DecryptionKeys;
+use crate::ui::{is_interactive_terminal, prompt_secret};
+use crate::ConfigureError;
+use log::debug;
+use openssl::pkcs12::Pkcs12;
+use openssl::x509::{X509NameRef, X509Ref, X509};
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// The environment variable a PKCS #12 file's password is read from
+pub const KEYSTORE_PASSWORD_VARIABLE: &str = "CONFIGURE_KEYSTORE_PASSWORD";
+
+/// The first four bytes of a JKS keystore
+const JKS_MAGIC: u32 = 0xFEED_FEED;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InspectedFileType {
+    Certificate,
+    Pkcs12,
+    Keystore,
+    ProvisioningProfile,
+}
+
+impl InspectedFileType {
+    fn for_destination(destination: &str) -> Option<InspectedFileType> {
+        let extension = Path::new(destination)
+            .extension()?
+            .to_string_lossy()
+            .to_lowercase();
+
+        match extension.as_str() {
+            "pem" | "crt" | "cer" => Some(InspectedFileType::Certificate),
+            "p12" | "pfx" => Some(InspectedFileType::Pkcs12),
+            "jks" | "keystore" => Some(InspectedFileType::Keystore),
+            "mobileprovision" | "provisionprofile" => Some(InspectedFileType::ProvisioningProfile),
+            _ => None,
+        }
+    }
+}
+
+/// A certificate's details, without anything secret
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct CertificateInfo {
+    /// The keystore entry the certificate is in, for keystores
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+
+    pub subject: String,
+    pub issuer: String,
+    pub serial_number: String,
+    pub not_before: String,
+    pub not_after: String,
+}
+
+/// What's in a provisioning profile, from the property list inside its signature
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct ProvisioningProfileInfo {
+    pub name: Option<String>,
+    pub team_name: Option<String>,
+    pub team_identifier: Option<String>,
+    pub application_identifier: Option<String>,
+    pub creation_date: Option<String>,
+    pub expiration_date: Option<String>,
+
+    /// The names of the entitlements the profile grants
+    pub entitlements: Vec<String>,
+
+    /// How many devices the profile can be installed on – none for App Store profiles
+    pub provisioned_devices: usize,
+}
+
+/// The non-sensitive details of one configured file
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Inspection {
+    pub destination: String,
+    pub file_type: InspectedFileType,
+    pub certificates: Vec<CertificateInfo>,
+
+    /// Whether the file has a private key in it – the key itself is never shown
+    pub has_private_key: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provisioning_profile: Option<ProvisioningProfileInfo>,
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.destination)?;
+
+        if let Some(profile) = &self.provisioning_profile {
+            let fields = [
+                ("Name", &profile.name),
+                ("Team", &profile.team_name),
+                ("Team ID", &profile.team_identifier),
+                ("App ID", &profile.application_identifier),
+                ("Created", &profile.creation_date),
+                ("Expires", &profile.expiration_date),
+            ];
+
+            for (label, value) in fields {
+                if let Some(value) = value {
+                    writeln!(f, "  {:<13}{}", format!("{}:", label), value)?;
+                }
+            }
+
+            writeln!(f, "  {:<13}{}", "Devices:", profile.provisioned_devices)?;
+            if !profile.entitlements.is_empty() {
+                writeln!(f, "  Entitlements:")?;
+                for entitlement in &profile.entitlements {
+                    writeln!(f, "    {}", entitlement)?;
+                }
+            }
+        }
+
+        for certificate in &self.certificates {
+            match &certificate.alias {
+                Some(alias) => writeln!(f, "  Certificate ({}):", alias)?,
+                None => writeln!(f, "  Certificate:")?,
+            }
+            writeln!(f, "    Subject:    {}", certificate.subject)?;
+            writeln!(f, "    Issuer:     {}", certificate.issuer)?;
+            writeln!(f, "    Serial:     {}", certificate.serial_number)?;
+            writeln!(f, "    Not before: {}", certificate.not_before)?;
+            writeln!(f, "    Not after:  {}", certificate.not_after)?;
+        }
+
+        if self.has_private_key {
+            writeln!(f, "  Has a private key")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decrypt the file configured at `destination` in memory, and describe what's in it
+pub fn inspect_file(
+    configuration: &Configuration,
+    destination: &str,
+) -> Result<Inspection, ConfigureError> {
+    let file = match configuration
+        .files_to_copy
+        .iter()
+        .find(|file| file.destination == destination)
+    {
+        Some(file) => file,
+        None => return Err(ConfigureError::DestinationNotConfigured),
+    };
+
+    let file_type = match InspectedFileType::for_destination(destination) {
+        Some(file_type) if !file.is_directory => file_type,
+        _ => {
+            return Err(ConfigureError::FileTypeNotInspectable {
+                destination: destination.to_string(),
+            })
+        }
+    };
+
+    let contents = decrypt_in_memory(configuration, file)?;
+    let not_inspectable = || ConfigureError::FileNotInspectable {
+        destination: destination.to_string(),
+    };
+
+    let mut inspection = Inspection {
+        destination: destination.to_string(),
+        file_type,
+        certificates: Vec::new(),
+        has_private_key: false,
+        provisioning_profile: None,
+    };
+
+    match file_type {
+        InspectedFileType::Certificate => {
+            let certificates = match contents.starts_with(b"-----BEGIN") {
+                true => X509::stack_from_pem(&contents),
+                false => X509::from_der(&contents).map(|certificate| vec![certificate]),
+            }
+            .map_err(|_| not_inspectable())?;
+
+            inspection.certificates = certificates
+                .iter()
+                .map(|certificate| certificate_info(certificate, None))
+                .collect();
+            inspection.has_private_key = contents
+                .windows(b"PRIVATE KEY-----".len())
+                .any(|window| window == b"PRIVATE KEY-----");
+        }
+        InspectedFileType::Keystore if read_u32(&contents, 0) == Some(JKS_MAGIC) => {
+            let entries = parse_jks(&contents).ok_or_else(not_inspectable)?;
+            inspection.has_private_key = entries.iter().any(|entry| entry.has_private_key);
+            inspection.certificates = entries
+                .iter()
+                .flat_map(|entry| {
+                    entry.certificates.iter().filter_map(move |der| {
+                        let certificate = X509::from_der(der).ok()?;
+                        Some(certificate_info(&certificate, Some(entry.alias.clone())))
+                    })
+                })
+                .collect();
+        }
+        InspectedFileType::Pkcs12 | InspectedFileType::Keystore => {
+            let pkcs12 = Pkcs12::from_der(&contents).map_err(|_| not_inspectable())?;
+            let parsed = parse_pkcs12(&pkcs12, destination).ok_or_else(not_inspectable)?;
+
+            inspection.has_private_key = true;
+            inspection
+                .certificates
+                .push(certificate_info(&parsed.cert, None));
+            if let Some(chain) = &parsed.chain {
+                inspection.certificates.extend(
+                    chain
+                        .iter()
+                        .map(|certificate| certificate_info(certificate, None)),
+                );
+            }
+        }
+        InspectedFileType::ProvisioningProfile => {
+            inspection.provisioning_profile =
+                Some(parse_provisioning_profile(&contents).ok_or_else(not_inspectable)?);
+        }
+    }
+
+    Ok(inspection)
+}
+
+fn decrypt_in_memory(
+    configuration: &Configuration,
+    file: &File,
+) -> Result<Zeroizing<Vec<u8>>, ConfigureError> {
+    let source = configuration.encrypted_destination_for(file);
+    if !source.exists() {
+        return Err(ConfigureError::EncryptedFileMissing { path: source });
+    }
+
+    let mut decryption_keys = DecryptionKeys::default();
+    let keys = decryption_keys.for_configuration(configuration)?;
+    let (contents, _) = decrypt_file_contents_with_keys(&source, keys)?;
+
+    Ok(Zeroizing::new(contents))
+}
+
+/// Open a PKCS #12 bundle with the password from the environment, an empty one, or one the user types in
+fn parse_pkcs12(pkcs12: &Pkcs12, destination: &str) -> Option<openssl::pkcs12::ParsedPkcs12> {
+    if let Ok(password) = std::env::var(KEYSTORE_PASSWORD_VARIABLE).map(Zeroizing::new) {
+        return pkcs12.parse(&password).ok();
+    }
+
+    if let Ok(parsed) = pkcs12.parse("") {
+        return Some(parsed);
+    }
+
+    if !is_interactive_terminal() {
+        debug!(
+            "{} needs a password – set {} to give it",
+            destination, KEYSTORE_PASSWORD_VARIABLE
+        );
+        return None;
+    }
+
+    let password = Zeroizing::new(prompt_secret(
+        &format!("Password for {}", destination),
+        false,
+    ));
+    pkcs12.parse(&password).ok()
+}
+
+fn certificate_info(certificate: &X509Ref, alias: Option<String>) -> CertificateInfo {
+    CertificateInfo {
+        alias,
+        subject: name_to_string(certificate.subject_name()),
+        issuer: name_to_string(certificate.issuer_name()),
+        serial_number: certificate
+            .serial_number()
+            .to_bn()
+            .and_then(|serial_number| serial_number.to_hex_str().map(|hex| hex.to_string()))
+            .unwrap_or_default(),
+        not_before: certificate.not_before().to_string(),
+        not_after: certificate.not_after().to_string(),
+    }
+}
+
+/// A certificate name like `CN=Apple Development: Jane Doe, OU=ABCDE12345, O=Automattic`
+fn name_to_string(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let field = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|value| value.to_string())
+                .unwrap_or_default();
+            format!("{}={}", field, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// One entry in a JKS keystore
+#[derive(Debug, Eq, PartialEq)]
+struct JksEntry {
+    alias: String,
+    has_private_key: bool,
+
+    /// The entry's certificates, in DER – they aren't encrypted, so no password is needed
+    certificates: Vec<Vec<u8>>,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let bytes = bytes.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Read the aliases and certificates from a JKS keystore, without checking its integrity
+///
+/// The format is big-endian: a magic number, a version and an entry count, then each entry's tag (1 for a private
+/// key, 2 for a trusted certificate), alias, and creation time, followed by its (encrypted) key and certificates.
+fn parse_jks(bytes: &[u8]) -> Option<Vec<JksEntry>> {
+    let version = read_u32(bytes, 4)?;
+    let count = read_u32(bytes, 8)?;
+    let mut offset = 12;
+    let mut entries = Vec::new();
+
+    // Length-prefixed fields: a `u16` length for text, and a `u32` length for data
+    let read_text = |offset: &mut usize| -> Option<String> {
+        let length = read_u16(bytes, *offset)? as usize;
+        let text = bytes.get(*offset + 2..*offset + 2 + length)?;
+        *offset += 2 + length;
+        Some(String::from_utf8_lossy(text).to_string())
+    };
+    let read_data = |offset: &mut usize| -> Option<Vec<u8>> {
+        let length = read_u32(bytes, *offset)? as usize;
+        let data = bytes.get(*offset + 4..*offset + 4 + length)?;
+        *offset += 4 + length;
+        Some(data.to_vec())
+    };
+    let read_certificate = |offset: &mut usize| -> Option<Vec<u8>> {
+        // Version 2 keystores name each certificate's type, which is always X.509
+        if version == 2 {
+            read_text(offset)?;
+        }
+        read_data(offset)
+    };
+
+    for _ in 0..count {
+        let tag = read_u32(bytes, offset)?;
+        offset += 4;
+        let alias = read_text(&mut offset)?;
+        offset += 8; // The creation time
+
+        let entry = match tag {
+            1 => {
+                read_data(&mut offset)?;
+                let chain_length = read_u32(bytes, offset)?;
+                offset += 4;
+
+                let certificates = (0..chain_length)
+                    .map(|_| read_certificate(&mut offset))
+                    .collect::<Option<Vec<_>>>()?;
+
+                JksEntry {
+                    alias,
+                    has_private_key: true,
+                    certificates,
+                }
+            }
+            2 => JksEntry {
+                alias,
+                has_private_key: false,
+                certificates: vec![read_certificate(&mut offset)?],
+            },
+            _ => return None,
+        };
+
+        entries.push(entry);
+    }
+
+    Some(entries)
+}
+
+/// Read the property list that's signed inside a provisioning profile
+fn parse_provisioning_profile(bytes: &[u8]) -> Option<ProvisioningProfileInfo> {
+    let text = String::from_utf8_lossy(bytes);
+    let start = text.find("<?xml")?;
+    let end = text[start..].find("</plist>")? + start;
+    let plist = &text[start..end];
+
+    let mut profile = ProvisioningProfileInfo {
+        name: string_for_key(plist, "Name"),
+        team_name: string_for_key(plist, "TeamName"),
+        team_identifier: string_for_key(plist, "TeamIdentifier"),
+        application_identifier: string_for_key(plist, "application-identifier"),
+        creation_date: date_for_key(plist, "CreationDate"),
+        expiration_date: date_for_key(plist, "ExpirationDate"),
+        ..Default::default()
+    };
+
+    if let Some(entitlements) = value_after_key(plist, "Entitlements") {
+        profile.entitlements = top_level_keys(entitlements);
+    }
+
+    if let Some(devices) = value_after_key(plist, "ProvisionedDevices") {
+        profile.provisioned_devices = devices.matches("<string>").count();
+    }
+
+    Some(profile)
+}
+
+/// The value element after `<key>key</key>`, including any elements inside it
+fn value_after_key<'a>(plist: &'a str, key: &str) -> Option<&'a str> {
+    let marker = format!("<key>{}</key>", key);
+    let start = plist.find(&marker)? + marker.len();
+    let value = plist[start..].trim_start();
+
+    Some(&value[..element_length(value)?])
+}
+
+/// The length of the element at the start of `xml`, including any elements inside it
+fn element_length(xml: &str) -> Option<usize> {
+    let tag_end = xml.find('>')?;
+    if xml[..tag_end].ends_with('/') {
+        return Some(tag_end + 1);
+    }
+
+    match xml.get(1..tag_end)? {
+        tag @ ("dict" | "array") => matching_close(xml, tag),
+        tag => xml
+            .find(&format!("</{}>", tag))
+            .map(|index| index + tag.len() + 3),
+    }
+}
+
+/// Where the element opened at the start of `xml` is closed, counting nested elements with the same tag
+fn matching_close(xml: &str, tag: &str) -> Option<usize> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut depth = 0;
+    let mut offset = 0;
+
+    loop {
+        let next_open = xml[offset..].find(&open).map(|index| index + offset);
+        let next_close = xml[offset..].find(&close).map(|index| index + offset)?;
+
+        match next_open {
+            Some(index) if index < next_close => {
+                depth += 1;
+                offset = index + open.len();
+            }
+            _ => {
+                depth -= 1;
+                offset = next_close + close.len();
+                if depth == 0 {
+                    return Some(offset);
+                }
+            }
+        }
+    }
+}
+
+/// The text of the `<tag>` element at the start of `xml`
+fn element_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let rest = xml.strip_prefix(&format!("<{}>", tag))?;
+    let end = rest.find(&format!("</{}>", tag))?;
+    Some(&rest[..end])
+}
+
+/// The string for `key` – or the first string, if its value is an array of them
+fn string_for_key(plist: &str, key: &str) -> Option<String> {
+    let value = value_after_key(plist, key)?;
+    let value = match value.strip_prefix("<array>") {
+        Some(array) => array.trim_start(),
+        None => value,
+    };
+
+    element_text(value, "string").map(str::to_string)
+}
+
+fn date_for_key(plist: &str, key: &str) -> Option<String> {
+    element_text(value_after_key(plist, key)?, "date").map(str::to_string)
+}
+
+/// The keys of the dictionary `dict`, leaving out the keys of any dictionaries inside it
+fn top_level_keys(dict: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut rest = match dict.strip_prefix("<dict>") {
+        Some(inner) => inner.trim_start(),
+        None => return keys,
+    };
+
+    while let Some(key) = element_text(rest, "key") {
+        keys.push(key.to_string());
+        rest = rest[format!("<key>{}</key>", key).len()..].trim_start();
+
+        // Skip the key's value, which might be a dictionary or an array
+        match element_length(rest) {
+            Some(length) => rest = rest[length..].trim_start(),
+            None => break,
+        }
+    }
+
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = r#"garbage<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>CreationDate</key>
+    <date>2024-01-02T03:04:05Z</date>
+    <key>Entitlements</key>
+    <dict>
+        <key>application-identifier</key>
+        <string>ABCDE12345.com.example.app</string>
+        <key>aps-environment</key>
+        <string>production</string>
+        <key>com.apple.developer.associated-domains</key>
+        <array>
+            <string>applinks:example.com</string>
+        </array>
+        <key>get-task-allow</key>
+        <false/>
+    </dict>
+    <key>ExpirationDate</key>
+    <date>2025-01-02T03:04:05Z</date>
+    <key>Name</key>
+    <string>Example App Store</string>
+    <key>TeamIdentifier</key>
+    <array>
+        <string>ABCDE12345</string>
+    </array>
+    <key>TeamName</key>
+    <string>Example Inc.</string>
+</dict>
+</plist>signature"#;
+
+    #[test]
+    fn test_that_provisioning_profiles_are_read() {
+        let profile = parse_provisioning_profile(PROFILE.as_bytes()).unwrap();
+
+        assert_eq!(profile.name.as_deref(), Some("Example App Store"));
+        assert_eq!(profile.team_identifier.as_deref(), Some("ABCDE12345"));
+        assert_eq!(
+            profile.application_identifier.as_deref(),
+            Some("ABCDE12345.com.example.app")
+        );
+        assert_eq!(
+            profile.expiration_date.as_deref(),
+            Some("2025-01-02T03:04:05Z")
+        );
+        assert_eq!(
+            profile.entitlements,
+            vec![
+                "application-identifier",
+                "aps-environment",
+                "com.apple.developer.associated-domains",
+                "get-task-allow"
+            ]
+        );
+        assert_eq!(profile.provisioned_devices, 0);
+    }
+
+    #[test]
+    fn test_that_jks_keystores_are_read_without_a_password() {
+        let mut keystore = Vec::new();
+        keystore.extend_from_slice(&JKS_MAGIC.to_be_bytes());
+        keystore.extend_from_slice(&2u32.to_be_bytes());
+        keystore.extend_from_slice(&1u32.to_be_bytes());
+        keystore.extend_from_slice(&1u32.to_be_bytes()); // A private key entry
+        keystore.extend_from_slice(&6u16.to_be_bytes());
+        keystore.extend_from_slice(b"upload");
+        keystore.extend_from_slice(&0u64.to_be_bytes());
+        keystore.extend_from_slice(&3u32.to_be_bytes());
+        keystore.extend_from_slice(b"key");
+        keystore.extend_from_slice(&1u32.to_be_bytes());
+        keystore.extend_from_slice(&5u16.to_be_bytes());
+        keystore.extend_from_slice(b"X.509");
+        keystore.extend_from_slice(&4u32.to_be_bytes());
+        keystore.extend_from_slice(b"cert");
+
+        assert_eq!(
+            parse_jks(&keystore),
+            Some(vec![JksEntry {
+                alias: "upload".to_string(),
+                has_private_key: true,
+                certificates: vec![b"cert".to_vec()],
+            }])
+        );
+        assert_eq!(parse_jks(&keystore[..keystore.len() - 2]), None);
+    }
+
+    #[test]
+    fn test_that_file_types_are_recognised_by_extension() {
+        assert_eq!(
+            InspectedFileType::for_destination("fastlane/Distribution.p12"),
+            Some(InspectedFileType::Pkcs12)
+        );
+        assert_eq!(
+            InspectedFileType::for_destination("app/release.KEYSTORE"),
+            Some(InspectedFileType::Keystore)
+        );
+        assert_eq!(
+            InspectedFileType::for_destination("App_Store.mobileprovision"),
+            Some(InspectedFileType::ProvisioningProfile)
+        );
+        assert_eq!(InspectedFileType::for_destination("secrets.json"), None);
+    }
+}
@@ -0,0 +1,750 @@
+//! The secrets repo's `keys.json`, and summaries of it so keys can be managed without showing every key on screen
+//!
+//! The original format maps each project name to its key:
+//!
+//! ```json
+//! { "my-project": "B6EeQVtVMBvtZQxEFruq8bUrlPqjtfYdxv2NpL18w1o=" }
+//! ```
+//!
+//! The structured format keeps every key a project has had, with metadata:
+//!
+//! ```json
+//! {
+//!   "version": 2,
+//!   "projects": {
+//!     "my-project": [
+//!       { "key": "…", "role": "current", "created_at": "2024-05-01T12:00:00Z" },
+//!       { "key": "…", "role": "deprecated", "created_at": "…", "deprecated_at": "2024-05-01T12:00:00Z" }
+//!     ]
+//!   }
+//! }
+//! ```
+//!
+//! Both are read transparently. A file in the original format is written back the same way until it needs something
+//! only the structured format can hold, like a deprecated key – so older versions of this tool can keep reading it.
+
+use crate::encryption::EncryptionKey;
+use crate::fs::{find_keys_file, find_secrets_repo};
+use crate::git::{FileVersion, SecretsRepo};
+use crate::schema::report_unknown_fields;
+use crate::ConfigureError;
+use chrono::{DateTime, TimeZone, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use zeroize::Zeroize;
+
+const KEYS_FILE_VERSION: u32 = 2;
+
+/// What a key in `keys.json` is used for
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum KeyRole {
+    /// Used to encrypt the project's files
+    #[default]
+    Current,
+
+    /// Replaced by a newer key, but kept so files encrypted with it can still be decrypted
+    Deprecated,
+
+    /// Any other role – these aren't used by default
+    Named(String),
+}
+
+impl From<String> for KeyRole {
+    fn from(role: String) -> Self {
+        match role.as_str() {
+            "current" => KeyRole::Current,
+            "deprecated" => KeyRole::Deprecated,
+            _ => KeyRole::Named(role),
+        }
+    }
+}
+
+impl From<KeyRole> for String {
+    fn from(role: KeyRole) -> Self {
+        match role {
+            KeyRole::Current => "current".to_string(),
+            KeyRole::Deprecated => "deprecated".to_string(),
+            KeyRole::Named(role) => role,
+        }
+    }
+}
+
+/// A single key in `keys.json`
+///
+/// The key is wiped from memory when this is dropped, and left out of its `Debug` output.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StoredKey {
+    /// The base64-encoded key
+    pub key: String,
+
+    #[serde(default)]
+    pub role: KeyRole,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated_at: Option<DateTime<Utc>>,
+}
+
+impl fmt::Debug for StoredKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StoredKey")
+            .field("key", &"****")
+            .field("role", &self.role)
+            .field("created_at", &self.created_at)
+            .field("deprecated_at", &self.deprecated_at)
+            .finish()
+    }
+}
+
+impl Drop for StoredKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl StoredKey {
+    fn new(key: String, created_at: Option<DateTime<Utc>>) -> StoredKey {
+        StoredKey {
+            key,
+            role: KeyRole::Current,
+            created_at,
+            deprecated_at: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeysFileContents {
+    Structured {
+        version: u32,
+        projects: BTreeMap<String, Vec<StoredKey>>,
+    },
+    Legacy(BTreeMap<String, String>),
+}
+
+/// Fields in a structured `keys.json` that this version doesn't read, as JSON pointers
+///
+/// The original format is just project names and keys, so it can't have any.
+fn unknown_fields(contents: &[u8]) -> Vec<String> {
+    const FILE_FIELDS: [&str; 2] = ["version", "projects"];
+    const KEY_FIELDS: [&str; 4] = ["key", "role", "created_at", "deprecated_at"];
+
+    let file = match serde_json::from_slice::<serde_json::Value>(contents) {
+        Ok(serde_json::Value::Object(file)) if file.contains_key("version") => file,
+        _ => return Vec::new(),
+    };
+
+    let mut fields: Vec<String> = file
+        .keys()
+        .filter(|field| !FILE_FIELDS.contains(&field.as_str()))
+        .map(|field| format!("/{}", field))
+        .collect();
+
+    let projects = file["projects"].as_object().into_iter().flatten();
+    for (project, keys) in projects {
+        let keys = keys.as_array().into_iter().flatten().enumerate();
+
+        for (index, key) in keys {
+            let unknown = key
+                .as_object()
+                .into_iter()
+                .flat_map(|key| key.keys())
+                .filter(|field| !KEY_FIELDS.contains(&field.as_str()))
+                .map(|field| format!("/projects/{}/{}/{}", project, index, field));
+            fields.extend(unknown);
+        }
+    }
+
+    fields
+}
+
+/// Every project's keys, in either format
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct KeysFile {
+    projects: BTreeMap<String, Vec<StoredKey>>,
+    is_structured: bool,
+}
+
+impl KeysFile {
+    pub fn read(source: &Path) -> Result<KeysFile, ConfigureError> {
+        let contents = match std::fs::read(source) {
+            Ok(contents) => contents,
+            Err(_) => {
+                return Err(ConfigureError::KeysFileNotReadable {
+                    path: source.to_path_buf(),
+                })
+            }
+        };
+
+        let keys_file = match KeysFile::from_slice(&contents) {
+            Some(keys_file) => keys_file,
+            None => {
+                return Err(ConfigureError::KeysFileIsNotValid {
+                    path: source.to_path_buf(),
+                })
+            }
+        };
+
+        report_unknown_fields(source, unknown_fields(&contents))?;
+
+        Ok(keys_file)
+    }
+
+    /// Parse either format – `None` if the contents aren't valid, or were written by a newer version of this tool
+    pub fn from_slice(contents: &[u8]) -> Option<KeysFile> {
+        match serde_json::from_slice(contents).ok()? {
+            KeysFileContents::Structured { version, projects } => {
+                if version > KEYS_FILE_VERSION {
+                    return None;
+                }
+
+                Some(KeysFile {
+                    projects,
+                    is_structured: true,
+                })
+            }
+            KeysFileContents::Legacy(keys) => Some(KeysFile {
+                projects: keys
+                    .into_iter()
+                    .map(|(project, key)| (project, vec![StoredKey::new(key, None)]))
+                    .collect(),
+                is_structured: false,
+            }),
+        }
+    }
+
+    pub fn save(&self, destination: &Path) -> Result<(), ConfigureError> {
+        let json = match self.to_json() {
+            Some(json) => json,
+            None => return Err(ConfigureError::KeysDataIsNotValid),
+        };
+
+        match std::fs::write(destination, json) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(ConfigureError::KeysFileNotWritable {
+                path: destination.to_path_buf(),
+            }),
+        }
+    }
+
+    pub fn to_json(&self) -> Option<String> {
+        if self.is_structured || !self.fits_legacy_format() {
+            let contents = serde_json::json!({
+                "version": KEYS_FILE_VERSION,
+                "projects": self.projects,
+            });
+
+            return serde_json::to_string_pretty(&contents).ok();
+        }
+
+        let legacy: BTreeMap<&String, &String> = self
+            .projects
+            .iter()
+            .map(|(project, keys)| (project, &keys[0].key))
+            .collect();
+
+        serde_json::to_string_pretty(&legacy).ok()
+    }
+
+    /// Whether the file will be written in the original format – just project names and keys
+    pub fn is_legacy(&self) -> bool {
+        !self.is_structured && self.fits_legacy_format()
+    }
+
+    /// Write the structured format from now on, even if every key would fit in the original one
+    pub fn upgrade(&mut self) {
+        self.is_structured = true;
+    }
+
+    // Creation dates are dropped when writing the original format, but nothing else is lost
+    fn fits_legacy_format(&self) -> bool {
+        self.projects
+            .values()
+            .all(|keys| keys.len() == 1 && keys[0].role == KeyRole::Current)
+    }
+
+    /// The key a project's files should be encrypted with – the newest one with the `current` role
+    pub fn current_key(&self, project: &str) -> Option<&StoredKey> {
+        self.projects
+            .get(project)?
+            .iter()
+            .rev()
+            .find(|key| key.role == KeyRole::Current)
+    }
+
+    /// Every key that has been used for a project, newest first
+    pub fn keys_for_project(&self, project: &str) -> Vec<&StoredKey> {
+        match self.projects.get(project) {
+            Some(keys) => keys.iter().rev().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Each project's current key
+    pub fn current_keys(&self) -> HashMap<String, String> {
+        self.projects
+            .keys()
+            .filter_map(|project| {
+                self.current_key(project)
+                    .map(|key| (project.clone(), key.key.clone()))
+            })
+            .collect()
+    }
+
+    /// Whether the project has any keys at all
+    pub fn contains(&self, project: &str) -> bool {
+        self.projects.contains_key(project)
+    }
+
+    /// Give a project's keys to another name – with `keep_original`, both names have them afterwards
+    pub fn rename(
+        &mut self,
+        project: &str,
+        new_project: &str,
+        keep_original: bool,
+    ) -> Result<(), ConfigureError> {
+        let keys = match self.projects.get(project) {
+            Some(keys) => keys.clone(),
+            None => {
+                return Err(ConfigureError::MissingProjectKey {
+                    project: project.to_string(),
+                })
+            }
+        };
+
+        if !keep_original {
+            self.projects.remove(project);
+        }
+
+        self.projects.insert(new_project.to_string(), keys);
+
+        Ok(())
+    }
+
+    /// Add a new project with its first key
+    pub fn insert(&mut self, project: &str, key: String) {
+        self.projects.insert(
+            project.to_string(),
+            vec![StoredKey::new(key, Some(Utc::now()))],
+        );
+    }
+
+    /// Give a project a new current key, keeping the old one so files encrypted with it can still be decrypted
+    pub fn rotate(&mut self, project: &str, new_key: String) -> Result<(), ConfigureError> {
+        let keys = match self.projects.get_mut(project) {
+            Some(keys) => keys,
+            None => {
+                return Err(ConfigureError::MissingProjectKey {
+                    project: project.to_string(),
+                })
+            }
+        };
+
+        let now = Utc::now();
+        for key in keys.iter_mut().filter(|key| key.role == KeyRole::Current) {
+            key.role = KeyRole::Deprecated;
+            key.deprecated_at = Some(now);
+        }
+
+        keys.push(StoredKey::new(new_key, Some(now)));
+        self.is_structured = true;
+
+        Ok(())
+    }
+}
+
+/// What's known about a project's key, without the key itself
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct KeyInfo {
+    pub project: String,
+
+    /// Identifies the key without revealing it – `None` if the key in `keys.json` isn't valid
+    pub fingerprint: Option<String>,
+
+    /// When the project's current key was first committed to the secrets repo, if it has been
+    pub created_at: Option<DateTime<Utc>>,
+
+    /// Other projects that use the same key
+    pub shared_with: Vec<String>,
+
+    /// How many keys this project used before its current one
+    pub previous_keys: usize,
+}
+
+impl fmt::Display for KeyInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Project:     {}", self.project)?;
+        writeln!(f, "Fingerprint: {}", fingerprint_string(&self.fingerprint))?;
+        writeln!(f, "Created:     {}", created_at_string(&self.created_at))?;
+        writeln!(f, "Shared with: {}", shared_with_string(&self.shared_with))?;
+        writeln!(f, "Older keys:  {}", self.previous_keys)
+    }
+}
+
+/// Every project in `keys.json`, sorted by name
+#[derive(Debug, Eq, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct KeyList {
+    pub keys: Vec<KeyInfo>,
+}
+
+impl fmt::Display for KeyList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.keys.is_empty() {
+            return writeln!(f, "No keys have been created");
+        }
+
+        let project_width = self
+            .keys
+            .iter()
+            .map(|key| key.project.chars().count())
+            .fold("Project".len(), usize::max);
+
+        writeln!(
+            f,
+            "{:<pw$}  {:<16}  {:<16}  Shared with",
+            "Project",
+            "Fingerprint",
+            "Created",
+            pw = project_width
+        )?;
+
+        for key in &self.keys {
+            writeln!(
+                f,
+                "{:<pw$}  {:<16}  {:<16}  {}",
+                key.project,
+                fingerprint_string(&key.fingerprint),
+                created_at_string(&key.created_at),
+                shared_with_string(&key.shared_with),
+                pw = project_width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn fingerprint_string(fingerprint: &Option<String>) -> &str {
+    fingerprint.as_deref().unwrap_or("invalid key")
+}
+
+fn created_at_string(created_at: &Option<DateTime<Utc>>) -> String {
+    match created_at {
+        Some(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+        None => "not committed".to_string(),
+    }
+}
+
+fn shared_with_string(shared_with: &[String]) -> String {
+    if shared_with.is_empty() {
+        "-".to_string()
+    } else {
+        shared_with.join(", ")
+    }
+}
+
+/// Summarize every key in the secrets repo's `keys.json`
+pub fn list_keys() -> Result<KeyList, ConfigureError> {
+    let keys_file = KeysFile::read(&find_keys_file()?)?;
+
+    // Creation dates are nice to have – a secrets repo without any commits shouldn't stop the keys being listed
+    let secrets_repo = SecretsRepo {
+        path: find_secrets_repo()?,
+    };
+
+    let history = match secrets_repo.file_history("keys.json") {
+        Ok(history) => history,
+        Err(err) => {
+            debug!("Unable to read the history of keys.json: {}", err);
+            Vec::new()
+        }
+    };
+
+    let mut list = summarize_keys(&keys_file.current_keys(), &history);
+
+    // A creation date recorded in the structured format is better than one inferred from the history
+    for key_info in &mut list.keys {
+        let keys = keys_file.keys_for_project(&key_info.project);
+
+        if let Some(created_at) = keys_file
+            .current_key(&key_info.project)
+            .and_then(|key| key.created_at)
+        {
+            key_info.created_at = Some(created_at);
+        }
+
+        key_info.previous_keys = keys
+            .iter()
+            .filter(|key| key.role == KeyRole::Deprecated)
+            .count();
+    }
+
+    Ok(list)
+}
+
+/// The key for `project`, exactly as it's stored in `keys.json`
+pub fn key_for_project(project: &str) -> Result<String, ConfigureError> {
+    match KeysFile::read(&find_keys_file()?)?.current_key(project) {
+        Some(key) => Ok(key.key.clone()),
+        None => Err(ConfigureError::MissingProjectKey {
+            project: project.to_string(),
+        }),
+    }
+}
+
+/// Give `project` a new key in `keys.json`, keeping the old one for decrypting older files. Returns the new key.
+pub fn rotate_key(project: &str) -> Result<EncryptionKey, ConfigureError> {
+    let keys_file_path = find_keys_file()?;
+    let mut keys_file = KeysFile::read(&keys_file_path)?;
+
+    let new_key = crate::encryption::generate_key()?;
+    keys_file.rotate(project, new_key.to_string())?;
+    keys_file.save(&keys_file_path)?;
+
+    Ok(new_key)
+}
+
+fn summarize_keys(keys: &HashMap<String, String>, history: &[FileVersion]) -> KeyList {
+    let fingerprints: HashMap<&String, Option<String>> = keys
+        .iter()
+        .map(|(project, key)| {
+            let fingerprint = EncryptionKey::from_str(key)
+                .ok()
+                .and_then(|key| key.fingerprint());
+            (project, fingerprint)
+        })
+        .collect();
+
+    let first_committed = first_committed_times(keys, history);
+
+    let mut summaries: Vec<KeyInfo> = keys
+        .keys()
+        .map(|project| {
+            let fingerprint = fingerprints[project].clone();
+
+            let mut shared_with: Vec<String> = fingerprints
+                .iter()
+                .filter(|(other, other_fingerprint)| {
+                    *other != &project
+                        && fingerprint.is_some()
+                        && **other_fingerprint == fingerprint
+                })
+                .map(|(other, _)| other.to_string())
+                .collect();
+            shared_with.sort();
+
+            KeyInfo {
+                project: project.clone(),
+                fingerprint,
+                created_at: first_committed
+                    .get(project)
+                    .and_then(|seconds| Utc.timestamp_opt(*seconds, 0).single()),
+                shared_with,
+                previous_keys: 0,
+            }
+        })
+        .collect();
+
+    summaries.sort_by(|a, b| a.project.cmp(&b.project));
+
+    KeyList { keys: summaries }
+}
+
+/// When each project's current key was committed, and has been in `keys.json` ever since
+fn first_committed_times(
+    keys: &HashMap<String, String>,
+    history: &[FileVersion],
+) -> HashMap<String, i64> {
+    let mut first_committed: HashMap<String, i64> = HashMap::new();
+
+    for version in history {
+        let committed_keys = version
+            .contents
+            .as_ref()
+            .and_then(|contents| KeysFile::from_slice(contents))
+            .map(|keys_file| keys_file.current_keys())
+            .unwrap_or_default();
+
+        for (project, key) in keys {
+            if committed_keys.get(project) == Some(key) {
+                first_committed
+                    .entry(project.clone())
+                    .or_insert(version.committed_at);
+            } else {
+                // The key was different (or missing) here, so any earlier date belongs to an older key
+                first_committed.remove(project);
+            }
+        }
+    }
+
+    first_committed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "B6EeQVtVMBvtZQxEFruq8bUrlPqjtfYdxv2NpL18w1o=";
+    const OTHER_KEY: &str = "EYs+Th0xnGONkUX2K3HpGJLMWOi/HWB0Q7JhBkNOHCs=";
+
+    fn keys(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(project, key)| (project.to_string(), key.to_string()))
+            .collect()
+    }
+
+    fn version(committed_at: i64, entries: &[(&str, &str)]) -> FileVersion {
+        FileVersion {
+            committed_at,
+            contents: Some(serde_json::to_vec(&keys(entries)).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_that_projects_with_the_same_key_are_shared() {
+        let list = summarize_keys(
+            &keys(&[("android", KEY), ("ios", KEY), ("web", OTHER_KEY)]),
+            &[],
+        );
+
+        assert_eq!(list.keys[0].project, "android");
+        assert_eq!(list.keys[0].shared_with, vec!["ios"]);
+        assert_eq!(list.keys[1].shared_with, vec!["android"]);
+        assert!(list.keys[2].shared_with.is_empty());
+        assert_ne!(list.keys[0].fingerprint, list.keys[2].fingerprint);
+    }
+
+    #[test]
+    fn test_that_invalid_keys_have_no_fingerprint_and_are_not_shared() {
+        let list = summarize_keys(&keys(&[("a", "nope"), ("b", "nope")]), &[]);
+        assert_eq!(list.keys[0].fingerprint, None);
+        assert!(list.keys[0].shared_with.is_empty());
+    }
+
+    #[test]
+    fn test_that_the_creation_date_is_when_the_current_key_was_committed() {
+        let history = [
+            version(100, &[("android", OTHER_KEY)]),
+            version(200, &[("android", KEY)]),
+            version(300, &[("android", KEY), ("ios", KEY)]),
+        ];
+
+        let times = first_committed_times(&keys(&[("android", KEY), ("ios", KEY)]), &history);
+        assert_eq!(times["android"], 200);
+        assert_eq!(times["ios"], 300);
+    }
+
+    #[test]
+    fn test_that_uncommitted_keys_have_no_creation_date() {
+        let history = [version(100, &[("android", OTHER_KEY)])];
+        let list = summarize_keys(&keys(&[("android", KEY)]), &history);
+        assert_eq!(list.keys[0].created_at, None);
+    }
+
+    #[test]
+    fn test_that_the_legacy_format_is_read_and_written_back_unchanged() {
+        let legacy = serde_json::to_string_pretty(&keys(&[("android", KEY)])).unwrap();
+        let keys_file = KeysFile::from_slice(legacy.as_bytes()).unwrap();
+
+        assert_eq!(keys_file.current_key("android").unwrap().key, KEY);
+        assert_eq!(keys_file.to_json().unwrap(), legacy);
+    }
+
+    #[test]
+    fn test_that_the_structured_format_round_trips() {
+        let mut keys_file = KeysFile::default();
+        keys_file.insert("android", KEY.to_string());
+        keys_file.rotate("android", OTHER_KEY.to_string()).unwrap();
+
+        let json = keys_file.to_json().unwrap();
+        assert!(json.contains("\"version\": 2"));
+        assert_eq!(KeysFile::from_slice(json.as_bytes()).unwrap(), keys_file);
+    }
+
+    #[test]
+    fn test_that_rotating_keeps_the_old_key_as_deprecated() {
+        let mut keys_file = KeysFile::default();
+        keys_file.insert("android", KEY.to_string());
+        keys_file.rotate("android", OTHER_KEY.to_string()).unwrap();
+
+        assert_eq!(keys_file.current_key("android").unwrap().key, OTHER_KEY);
+
+        let keys = keys_file.keys_for_project("android");
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[1].key, KEY);
+        assert_eq!(keys[1].role, KeyRole::Deprecated);
+        assert!(keys[1].deprecated_at.is_some());
+    }
+
+    #[test]
+    fn test_that_renaming_moves_or_copies_every_key() {
+        let mut keys_file = KeysFile::default();
+        keys_file.insert("android", KEY.to_string());
+        keys_file.rotate("android", OTHER_KEY.to_string()).unwrap();
+
+        keys_file.rename("android", "wordpress", true).unwrap();
+        assert!(keys_file.contains("android"));
+        assert_eq!(keys_file.keys_for_project("wordpress").len(), 2);
+
+        keys_file.rename("android", "jetpack", false).unwrap();
+        assert!(!keys_file.contains("android"));
+        assert_eq!(keys_file.current_key("jetpack").unwrap().key, OTHER_KEY);
+
+        assert!(keys_file.rename("android", "simplenote", false).is_err());
+    }
+
+    #[test]
+    fn test_that_named_roles_are_never_the_current_key() {
+        let json = format!(
+            r#"{{"version": 2, "projects": {{"android": [{{"key": "{}", "role": "ci"}}]}}}}"#,
+            KEY
+        );
+        let keys_file = KeysFile::from_slice(json.as_bytes()).unwrap();
+
+        assert_eq!(keys_file.current_key("android"), None);
+        assert_eq!(
+            keys_file.keys_for_project("android")[0].role,
+            KeyRole::Named("ci".to_string())
+        );
+    }
+
+    #[test]
+    fn test_that_newer_formats_are_not_valid() {
+        assert_eq!(
+            KeysFile::from_slice(br#"{"version": 3, "projects": {}}"#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_that_the_list_never_includes_keys() {
+        let list = summarize_keys(&keys(&[("android", KEY)]), &[]);
+        assert!(!list.to_string().contains(KEY));
+        assert!(!serde_json::to_string(&list).unwrap().contains(KEY));
+    }
+
+    #[test]
+    fn test_that_unknown_fields_in_structured_files_are_found() {
+        let json = br#"{
+            "version": 2,
+            "comment": "rotated in May",
+            "projects": { "android": [{ "key": "abc", "rol": "current" }] }
+        }"#;
+
+        assert_eq!(
+            unknown_fields(json),
+            vec!["/comment", "/projects/android/0/rol"]
+        );
+        assert!(unknown_fields(br#"{ "android": "abc" }"#).is_empty());
+    }
+}
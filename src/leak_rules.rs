@@ -0,0 +1,193 @@
+//! Rules for other secret scanners, taken from the `.configure` file
+//!
+//! Teams that already run gitleaks or git-secrets can point them at `configure export-leak-rules`, so the files
+//! configure decrypts are flagged by the scanner they already have without keeping a second list by hand.
+//!
+//! gitleaks gets a rule for each decrypted file's name, wherever it turns up in the project, on top of its default
+//! rules – with `.configure-files` allowed, since the encrypted copies are meant to be committed. git-secrets only
+//! looks at what's in files, not their names, so it gets the patterns `configure scan` uses for well-known secrets.
+
+use crate::configure::Configuration;
+use crate::scan::{PRIVATE_KEY_REGEX, SECRET_PATTERNS};
+use crate::ConfigureError;
+use serde::Serialize;
+use std::path::Path;
+
+/// Which scanner to write rules for
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LeakRulesFormat {
+    /// A gitleaks config file, in TOML
+    Gitleaks,
+
+    /// One pattern per line, for `git secrets --add-provider`
+    GitSecrets,
+}
+
+impl std::str::FromStr for LeakRulesFormat {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "gitleaks" => Ok(LeakRulesFormat::Gitleaks),
+            "git-secrets" => Ok(LeakRulesFormat::GitSecrets),
+            _ => Err(format!(
+                "Unknown format {:?} – expected `gitleaks` or `git-secrets`",
+                string
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct GitleaksConfig {
+    title: String,
+    extend: GitleaksExtend,
+    allowlist: GitleaksAllowlist,
+    rules: Vec<GitleaksRule>,
+}
+
+#[derive(Debug, Serialize)]
+struct GitleaksExtend {
+    #[serde(rename = "useDefault")]
+    use_default: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct GitleaksAllowlist {
+    description: String,
+    paths: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct GitleaksRule {
+    id: String,
+    description: String,
+    path: String,
+}
+
+/// The rules for `configuration`'s files in `format`
+pub fn leak_rules(
+    configuration: &Configuration,
+    format: LeakRulesFormat,
+) -> Result<String, ConfigureError> {
+    match format {
+        LeakRulesFormat::Gitleaks => gitleaks_config(configuration),
+        LeakRulesFormat::GitSecrets => Ok(git_secrets_patterns()),
+    }
+}
+
+fn gitleaks_config(configuration: &Configuration) -> Result<String, ConfigureError> {
+    let rules = configuration
+        .files_to_copy
+        .iter()
+        .enumerate()
+        .filter_map(|(index, file)| {
+            let name = Path::new(&file.destination).file_name()?.to_string_lossy();
+            let path = match file.is_directory {
+                true => format!("(^|/){}/", escape_regex(&name)),
+                false => format!("(^|/){}$", escape_regex(&name)),
+            };
+
+            Some(GitleaksRule {
+                id: format!("configure-{}-{}", configuration.project_name, index),
+                description: format!(
+                    "{} is decrypted by configure – only its encrypted copy in .configure-files should be committed",
+                    file.destination
+                ),
+                path,
+            })
+        })
+        .collect();
+
+    let config = GitleaksConfig {
+        title: format!(
+            "Secrets managed by configure for {}",
+            configuration.project_name
+        ),
+        extend: GitleaksExtend { use_default: true },
+        allowlist: GitleaksAllowlist {
+            description: "Encrypted copies of the secrets".to_string(),
+            paths: vec![r"^\.configure-files/".to_string()],
+        },
+        rules,
+    };
+
+    toml::to_string(&config).map_err(|_| ConfigureError::ConfigureDataNotValid)
+}
+
+fn git_secrets_patterns() -> String {
+    let mut patterns = vec![PRIVATE_KEY_REGEX.to_string()];
+    patterns.extend(SECRET_PATTERNS.iter().map(|pattern| pattern.regex()));
+
+    patterns
+        .iter()
+        .map(|pattern| format!("{}\n", pattern))
+        .collect()
+}
+
+/// `text` with the characters that mean something in a regular expression escaped
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars() {
+        if r"\.+*?()|[]{}^$".contains(character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configure::File;
+
+    #[test]
+    fn test_that_gitleaks_rules_match_each_decrypted_file_by_name() {
+        let configuration = Configuration {
+            project_name: "example".to_string(),
+            files_to_copy: vec![
+                File {
+                    source: "android/example/google-services.json".to_string(),
+                    destination: "app/google-services.json".to_string(),
+                    is_directory: false,
+                    expires: None,
+                    processors: Vec::new(),
+                },
+                File {
+                    source: "android/example/certificates".to_string(),
+                    destination: "app/certificates".to_string(),
+                    is_directory: true,
+                    expires: None,
+                    processors: Vec::new(),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let config = leak_rules(&configuration, LeakRulesFormat::Gitleaks).unwrap();
+        let parsed: toml::Value = toml::from_str(&config).unwrap();
+
+        assert_eq!(parsed["extend"]["useDefault"].as_bool(), Some(true));
+        assert_eq!(
+            parsed["rules"][0]["path"].as_str(),
+            Some(r"(^|/)google-services\.json$")
+        );
+        assert_eq!(
+            parsed["rules"][1]["path"].as_str(),
+            Some("(^|/)certificates/")
+        );
+    }
+
+    #[test]
+    fn test_that_git_secrets_gets_one_pattern_per_line() {
+        let patterns = leak_rules(&Configuration::default(), LeakRulesFormat::GitSecrets).unwrap();
+
+        assert_eq!(patterns.lines().count(), SECRET_PATTERNS.len() + 1);
+        assert!(patterns
+            .lines()
+            .any(|pattern| pattern == "AKIA[A-Z0-9]{16,}"));
+    }
+}
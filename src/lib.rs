@@ -1,185 +1,1572 @@
+pub mod encryption;
+mod error;
+
+// Everything that works with projects and secrets repos on disk. Without it (as in WebAssembly builds), only the
+// encryption module is available.
+#[cfg(feature = "fs")]
+mod archive;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod build_info;
+#[cfg(feature = "fs")]
+mod cancel;
+#[cfg(feature = "fs")]
+mod ci;
+#[cfg(feature = "fs")]
 mod configure;
-mod encryption;
+#[cfg(feature = "fs")]
+mod daemon;
+#[cfg(feature = "fs")]
+mod ephemeral;
+#[cfg(feature = "fs")]
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fs")]
 mod fs;
+#[cfg(feature = "fs")]
 mod git;
-mod string;
+#[cfg(feature = "fs")]
+mod github_actions;
+#[cfg(feature = "fs")]
+mod inspect;
+#[cfg(feature = "fs")]
+mod keys;
+#[cfg(feature = "fs")]
+mod leak_rules;
+#[cfg(feature = "fs")]
+mod local;
+#[cfg(feature = "fs")]
+mod lock;
+#[cfg(feature = "fs")]
+mod logging;
+#[cfg(feature = "fs")]
+mod migrate;
+#[cfg(feature = "fs")]
+mod password_manager;
+#[cfg(feature = "fs")]
+mod paths;
+#[cfg(feature = "fs")]
+mod platform;
+#[cfg(feature = "fs")]
+mod plugins;
+#[cfg(feature = "fs")]
+mod push;
+mod redact;
+#[cfg(feature = "fs")]
+mod remote_cache;
+#[cfg(feature = "fs")]
+mod run;
+#[cfg(feature = "fs")]
+mod scan;
+#[cfg(feature = "fs")]
+mod schema;
+#[cfg(feature = "fs")]
+mod settings;
+#[cfg(feature = "fs")]
+mod signing;
+#[cfg(feature = "fs")]
+mod sparse;
+#[cfg(feature = "fs")]
+mod state;
+#[cfg(feature = "fs")]
+mod template;
+mod timings;
+#[cfg(feature = "fs")]
+mod transaction;
+#[cfg(feature = "fs")]
 mod ui;
+#[cfg(feature = "fs")]
+mod vault;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+#[cfg(feature = "fs")]
+mod watch;
+
+#[cfg(feature = "async")]
+pub use crate::asynchronous::{latest_secrets_hash_async, status_async, update_async};
+pub use crate::build_info::{build_info, BuildInfo};
+#[cfg(feature = "fs")]
+pub use crate::cancel::CancellationToken;
+#[cfg(feature = "fs")]
+pub use crate::ci::{ci_environment, detect_ci_environment, CiEnvironment};
+#[cfg(feature = "fs")]
+use crate::configure::*;
+#[cfg(feature = "fs")]
+pub use crate::configure::{
+    ApplyOptions, Configuration, ConfigurationBuilder, ConfigurationFragment, ConflictPolicy, File,
+    Plugin, Profile, UpdatePlan,
+};
+#[cfg(feature = "fs")]
+use crate::encryption::EncryptionKey;
+#[cfg(feature = "fs")]
+pub use crate::ephemeral::{EphemeralApply, EphemeralFile, EPHEMERAL_DIRECTORY_VARIABLE};
+pub use crate::error::ConfigureError;
+#[cfg(feature = "fs")]
+pub use crate::events::{clear_event_listener, set_event_listener, Event};
+#[cfg(feature = "fs")]
+use crate::fs::*;
+#[cfg(feature = "fs")]
+pub use crate::git::{NetworkPolicy, SecretsRepoInfo};
+#[cfg(feature = "fs")]
+pub use crate::inspect::{
+    CertificateInfo, InspectedFileType, Inspection, ProvisioningProfileInfo,
+    KEYSTORE_PASSWORD_VARIABLE,
+};
+#[cfg(feature = "fs")]
+pub use crate::keys::{KeyInfo, KeyList};
+#[cfg(feature = "fs")]
+pub use crate::leak_rules::LeakRulesFormat;
+#[cfg(feature = "fs")]
+pub use crate::logging::set_log_handler;
+#[cfg(feature = "fs")]
+pub use crate::password_manager::{PasswordManager, PasswordManagerKeyProvider};
+#[cfg(feature = "fs")]
+pub use crate::plugins::{
+    register_file_processor, register_key_provider, FileProcessor, KeyProvider,
+};
+#[cfg(feature = "fs")]
+pub use crate::push::PushSecretOptions;
+pub use crate::redact::RedactingLogger;
+#[cfg(feature = "fs")]
+pub use crate::scan::{ScanFinding, ScanFindingKind};
+#[cfg(feature = "fs")]
+pub use crate::settings::{settings, BackupSettings, CiSettings, Settings, UiSettings};
+#[cfg(feature = "fs")]
+pub use crate::sparse::SparseCheckout;
+#[cfg(feature = "fs")]
+pub use crate::state::{ConfigurationStatus, FileList, FileStatus, ManagedFile};
+#[cfg(feature = "fs")]
+use crate::ui::{confirm, heading, message};
+#[cfg(feature = "fs")]
+pub use crate::vault::VaultKeyProvider;
+#[cfg(feature = "fs")]
+pub use crate::watch::WatchOptions;
+
+#[cfg(feature = "fs")]
+use log::{debug, error, info, warn};
+#[cfg(feature = "fs")]
+use std::path::Path;
+#[cfg(feature = "fs")]
+use std::path::PathBuf;
+#[cfg(feature = "fs")]
+use std::str::FromStr;
+#[cfg(feature = "fs")]
+use std::time::Duration;
+
+#[cfg(feature = "fs")]
+/// Set up a project to use the configure tool
+///
+/// Android and iOS projects are recognised, and the secrets they usually need are offered as suggestions.
+pub fn init() {
+    offer_current_directory_as_project_root(true);
+    find_or_create_configure_file().expect("Unable to create a `.configure` file");
+
+    let configuration =
+        read_configuration().expect("Unable to read configuration from `.configure` file");
+
+    if let Err(err) = setup_configuration(configuration) {
+        error!("Unable to set up the project: {}", err);
+        std::process::exit(err.code());
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Set up a project to use the configure tool, starting from a shared template
+///
+/// Anything the project's `.configure` file already has is kept – the template only fills in what's missing.
+///
+/// # Arguments
+///
+/// * `template` - The name of a template in the secrets repo's `templates` directory, or the path or URL of one
+///
+pub fn init_from_template(template: String) {
+    offer_current_directory_as_project_root(true);
+    find_or_create_configure_file().expect("Unable to create a `.configure` file");
+
+    let mut configuration =
+        read_configuration().expect("Unable to read configuration from `.configure` file");
+
+    match template::read_template(&template) {
+        Ok(template) => template::apply_template(&mut configuration, template),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(err.code());
+        }
+    }
+
+    if let Err(err) = setup_configuration(configuration) {
+        error!("Unable to set up the project: {}", err);
+        std::process::exit(err.code());
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Decrypts secrets already present in the repository
+///
+/// To get secrets into the repository, use `configure_update`
+///
+/// # Arguments
+///
+/// * `configuration` - The project's parsed `ConfigurationFile` object.
+/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
+///
+pub fn apply(interactive: bool, configuration_file_path: Option<String>) {
+    apply_with_options(
+        configuration_file_path,
+        ApplyOptions {
+            interactive,
+            ..Default::default()
+        },
+    )
+}
+
+#[cfg(feature = "fs")]
+/// Decrypts secrets already present in the repository, with full control over how files are written
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+/// * `options` - Whether to prompt the user, and how to handle destination files that were modified since the last apply
+///
+/// # Panics
+///
+/// If the secrets can't be applied – use `try_apply_with_options` to handle the error instead.
+pub fn apply_with_options(configuration_file_path: Option<String>, options: ApplyOptions) {
+    try_apply_with_options(configuration_file_path, options).expect("Unable to apply the secrets");
+}
+
+#[cfg(feature = "fs")]
+/// `apply_with_options`, returning an error instead of panicking
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+/// * `options` - Whether to prompt the user, and how to handle destination files that were modified since the last apply
+///
+pub fn try_apply_with_options(
+    configuration_file_path: Option<String>,
+    options: ApplyOptions,
+) -> Result<(), ConfigureError> {
+    offer_current_directory_as_project_root(options.interactive);
+
+    // A missing `.configure` file is more likely a wrong directory than a new project, so this doesn't offer `init`
+    let configuration = read_verified_configuration_from_file(&configuration_file_path)?;
+
+    if configuration.is_empty() {
+        if options.interactive {
+            setup_configuration(configuration)?;
+        } else {
+            warn!("Unable to apply configuration – it is empty");
+        }
+        return Ok(());
+    }
+
+    apply_configuration(&configuration, &options)
+}
+
+#[cfg(feature = "fs")]
+/// Decrypts the project's secrets into a new temporary directory instead of the project, and leaves them there
+///
+/// Nothing in the project is changed, and the caller is responsible for deleting the directory.
+///
+/// # Arguments
+///
+/// * `profile` - Only decrypt the files in this profile, with its key.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn apply_ephemeral(
+    profile: Option<String>,
+    configuration_file_path: Option<String>,
+) -> Result<EphemeralApply, ConfigureError> {
+    let configuration = read_ephemeral_configuration(profile, &configuration_file_path)?;
+    let directory = ephemeral::EphemeralDirectory::create(&configuration.project_name)?;
+    let files = ephemeral::decrypt_files_into(&configuration, &directory)?;
+
+    Ok(EphemeralApply {
+        directory: directory.keep(),
+        files,
+    })
+}
+
+#[cfg(feature = "fs")]
+/// Decrypts the project's secrets into a new temporary directory, runs a command, and deletes the directory once it
+/// exits
+///
+/// The command finds the directory in the `CONFIGURE_EPHEMERAL_DIR` environment variable. Returns its exit code.
+///
+/// # Arguments
+///
+/// * `command` - The program to run, followed by its arguments.
+/// * `profile` - Only decrypt the files in this profile, with its key.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn run_with_ephemeral_files(
+    command: Vec<String>,
+    profile: Option<String>,
+    configuration_file_path: Option<String>,
+) -> Result<i32, ConfigureError> {
+    let configuration = read_ephemeral_configuration(profile, &configuration_file_path)?;
+    let directory = ephemeral::EphemeralDirectory::create(&configuration.project_name)?;
+    ephemeral::decrypt_files_into(&configuration, &directory)?;
+
+    ephemeral::run_with_directory(&command, directory)
+}
+
+#[cfg(feature = "fs")]
+fn read_ephemeral_configuration(
+    profile: Option<String>,
+    configuration_file_path: &Option<String>,
+) -> Result<Configuration, ConfigureError> {
+    let configuration = read_verified_configuration_from_file(configuration_file_path)?;
+
+    match profile {
+        Some(profile) => configuration.for_profile(&profile),
+        None => Ok(configuration),
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Decrypts the secrets for several configuration files in one go, like the modules of a monorepo
+///
+/// Each key is only looked up once, however many configurations use it. A configuration that fails doesn't stop the
+/// others from being applied, but the process exits with the first failure's code once they've all been tried.
+///
+/// # Arguments
+///
+/// * `configuration_file_paths` - The configuration files to apply
+/// * `recursive` - Also apply every `.configure` file found in the project's subdirectories
+/// * `options` - Whether to prompt the user, and how to handle destination files that were modified since the last apply
+///
+pub fn apply_all(configuration_file_paths: Vec<String>, recursive: bool, options: ApplyOptions) {
+    offer_current_directory_as_project_root(options.interactive);
+
+    let mut paths: Vec<PathBuf> = configuration_file_paths
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+
+    if recursive {
+        let project_root = find_project_root().expect("Unable to find project root");
+        let found = find_configure_files_in(&project_root)
+            .expect("Unable to search the project for `.configure` files");
+
+        for path in found {
+            if !paths
+                .iter()
+                .any(|existing| paths::same_file(existing, &path))
+            {
+                paths.push(path);
+            }
+        }
+    }
+
+    let mut decryption_keys = DecryptionKeys::default();
+    let mut first_error: Option<ConfigureError> = None;
+
+    for path in paths {
+        heading(&format!("Applying {}", path.display()));
+
+        let configuration_file_path = Some(path.to_string_lossy().to_string());
+        let result = read_verified_configuration_from_file(&configuration_file_path).and_then(
+            |configuration| {
+                if configuration.is_empty() {
+                    warn!("Skipping {:?} – it is empty", path);
+                    return Ok(());
+                }
+
+                configuration.warn_about_expiring_files();
+                decrypt_files_for_configuration_with_keys(
+                    &configuration,
+                    &options,
+                    &mut decryption_keys,
+                )
+            },
+        );
+
+        if let Err(err) = result {
+            error!("Unable to apply {:?}: {}", path, err);
+            first_error.get_or_insert(err);
+        }
+    }
+
+    if let Some(err) = first_error {
+        std::process::exit(err.code());
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Decrypts secrets already present in the repository, returning an error instead of exiting
+///
+/// For hosts like IDE plugins that need to stop partway through – cancel `options.cancellation` from another thread,
+/// and this returns `ConfigureError::Cancelled` before the next file is written.
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+/// * `options` - Whether to prompt the user, how to handle modified destination files, and the cancellation token
+///
+pub fn apply_cancellable(
+    configuration_file_path: Option<String>,
+    options: ApplyOptions,
+) -> Result<(), ConfigureError> {
+    encryption::init()?;
+    let configuration = read_verified_configuration_from_file(&configuration_file_path)?;
+
+    if configuration.is_empty() {
+        warn!("Unable to apply configuration – it is empty");
+        return Ok(());
+    }
+
+    decrypt_files_for_configuration(&configuration, &options)
+}
+
+#[cfg(feature = "fs")]
+/// Adds encrypted secrets files to the configuration, or updates existing ones.
+///
+/// Prompts the user to decrypt them when it finishes.
+///
+/// # Arguments
+///
+/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
+/// * `configuration_file_path` - An optional path to the configuration file that should be updated. Useful for when the working directory differs from the root project directory (as when using the gradle plugin, for instance). If this value is `None`, the default configuration file path will be used.
+///
+/// # Panics
+///
+/// If the project can't be updated – use `try_update` to handle the error instead.
+pub fn update(interactive: bool, configuration_file_path: Option<String>) {
+    try_update(interactive, false, configuration_file_path).expect("Unable to update the project");
+}
+
+#[cfg(feature = "fs")]
+/// `update`, returning an error instead of panicking
+///
+/// # Arguments
+///
+/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
+/// * `accept_plan` - Go ahead without asking anything – staying on the same branch and taking the latest secrets – after showing what will change. Files changed since they were last applied are still asked about.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn try_update(
+    interactive: bool,
+    accept_plan: bool,
+    configuration_file_path: Option<String>,
+) -> Result<(), ConfigureError> {
+    let configuration = read_configuration_from_file(&configuration_file_path)?;
+
+    if configuration.is_empty() {
+        if interactive {
+            setup_configuration(configuration)?;
+        } else {
+            warn!("Current configuration is empty – unable to update when running in non-interactive mode");
+        }
+        return Ok(());
+    }
+
+    try_update_configuration(
+        configuration_file_path,
+        interactive,
+        accept_plan,
+        true,
+        None,
+        &CancellationToken::new(),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(feature = "fs")]
+/// Updates the project to a specific commit on its secrets branch, instead of the latest one
+///
+/// This is useful for holding back a bad secrets change while taking the ones before it.
+///
+/// # Arguments
+///
+/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
+/// * `accept_plan` - Go ahead without asking, after showing what will change
+/// * `commit_hash` - The commit to pin the project to – it can be abbreviated, but must be on the project's branch
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn update_to_commit(
+    interactive: bool,
+    accept_plan: bool,
+    commit_hash: String,
+    configuration_file_path: Option<String>,
+) {
+    let cancellation = CancellationToken::new();
+
+    if let Err(err) = try_update_configuration(
+        configuration_file_path,
+        interactive,
+        accept_plan,
+        true,
+        Some(&commit_hash),
+        &cancellation,
+    ) {
+        error!("Unable to update the project: {}", err);
+        std::process::exit(err.code());
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Pins every `.configure` file under a directory to the same secrets, and regenerates their encrypted files
+///
+/// Each of the secrets repo's remotes is only fetched once. Without a commit, each configuration moves to the latest commit on its
+/// branch – configurations that share a branch end up with the same pinned hash. Files that extend another one are
+/// updated after it, so they keep inheriting its pinned hash rather than writing their own. A configuration that
+/// fails doesn't stop the others from being updated, but the process exits with the first failure's code once
+/// they've all been tried.
+///
+/// # Arguments
+///
+/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
+/// * `commit_hash` - The commit to pin every configuration to – it can be abbreviated, but must be on each configuration's branch
+/// * `root` - The directory to search for `.configure` files. If this value is `None`, the project root will be used.
+///
+pub fn sync_pins(interactive: bool, commit_hash: Option<String>, root: Option<String>) {
+    let root = match root {
+        Some(root) => PathBuf::from(root),
+        None => find_project_root().expect("Unable to find project root"),
+    };
+
+    let mut configurations = Vec::new();
+
+    for path in find_configure_files_in(&root).expect("Unable to search for `.configure` files") {
+        match read_configuration_from_file(&Some(path.to_string_lossy().to_string())) {
+            Ok(configuration) if configuration.is_empty() => {
+                warn!("Skipping {:?} – it is empty", path)
+            }
+            Ok(configuration) => configurations.push((path, configuration)),
+            Err(err) => error!("Unable to read {:?}: {}", path, err),
+        }
+    }
+
+    if configurations.is_empty() {
+        warn!("No `.configure` files found in {:?}", root);
+        return;
+    }
+
+    // Update the files others extend first, so the ones extending them see the new pinned hash
+    configurations.sort_by_key(|(_, configuration)| configuration.extends.is_some());
+
+    let secrets_repo = git::SecretsRepo {
+        path: find_secrets_repo().expect("Unable to find secrets repo"),
+    };
+
+    let mut remotes = std::collections::BTreeSet::new();
+    for (_, configuration) in &configurations {
+        match secrets_repo.remote_for_configuration(configuration) {
+            Ok(remote) => remotes.insert(remote),
+            Err(err) => {
+                error!("Unable to find the secrets remote: {}", err);
+                std::process::exit(err.code());
+            }
+        };
+    }
+
+    for remote in &remotes {
+        if let Err(err) = secrets_repo.update_local_copy(remote) {
+            error!("Unable to fetch the latest secrets: {}", err);
+            std::process::exit(err.code());
+        }
+    }
+
+    let cancellation = CancellationToken::new();
+    let mut results = Vec::new();
+
+    for (path, configuration) in configurations {
+        heading(&format!("Syncing {}", path.display()));
+
+        let target = match &commit_hash {
+            Some(commit_hash) => Ok(commit_hash.clone()),
+            None => secrets_repo
+                .remote_for_configuration(&configuration)
+                .and_then(|remote| {
+                    secrets_repo.latest_remote_hash_for_branch(&remote, &configuration.branch)
+                }),
+        };
+
+        let result = target.and_then(|target| {
+            try_update_configuration(
+                Some(path.to_string_lossy().to_string()),
+                interactive,
+                false,
+                false,
+                Some(&target),
+                &cancellation,
+            )
+        });
+
+        results.push((path, configuration.pinned_hash, result));
+    }
+
+    heading("Summary");
+
+    let mut pinned_hashes = std::collections::BTreeSet::new();
+    let mut first_error: Option<ConfigureError> = None;
+
+    for (path, previous_hash, result) in results {
+        match result {
+            Ok(configuration) if configuration.pinned_hash == previous_hash => {
+                message(&format!(
+                    "{} – already at {}",
+                    path.display(),
+                    configuration.pinned_hash
+                ));
+                pinned_hashes.insert(configuration.pinned_hash);
+            }
+            Ok(configuration) => {
+                message(&format!(
+                    "{} – {} → {}",
+                    path.display(),
+                    previous_hash,
+                    configuration.pinned_hash
+                ));
+                pinned_hashes.insert(configuration.pinned_hash);
+            }
+            Err(err) => {
+                error!("{} – unable to update: {}", path.display(), err);
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    if pinned_hashes.len() > 1 {
+        warn!(
+            "The configurations are pinned to {} different commits – they use different secrets branches",
+            pinned_hashes.len()
+        );
+    }
+
+    if let Some(err) = first_error {
+        std::process::exit(err.code());
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Moves the project back to the secrets it used before its last update, and applies them
+///
+/// Each apply remembers the commit it replaced in the configuration's `state.json` in `.configure-files`, so running
+/// this again goes further back.
+///
+/// # Arguments
+///
+/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn rollback(interactive: bool, configuration_file_path: Option<String>) {
+    let previous_hash = match read_configuration_from_file(&configuration_file_path)
+        .and_then(|configuration| previous_pinned_hash(&configuration))
+    {
+        Ok(previous_hash) => previous_hash,
+        Err(err) => {
+            error!("Unable to roll back: {}", err);
+            std::process::exit(err.code());
+        }
+    };
+
+    info!("Rolling back to {}", previous_hash);
+    update_to_commit(interactive, false, previous_hash, configuration_file_path)
+}
+
+#[cfg(feature = "fs")]
+/// Puts the newest backup of each of the given files back in place
+///
+/// The files being replaced are backed up first, so restoring again undoes this.
+///
+/// # Arguments
+///
+/// * `destinations` - The files to restore, as they're written in the `.configure` file
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn restore_backups(destinations: Vec<String>, configuration_file_path: Option<String>) {
+    let configuration = read_configuration_from_file(&configuration_file_path)
+        .expect("Unable to read project configuration");
+
+    for destination in destinations {
+        let result = match configuration
+            .files_to_copy
+            .iter()
+            .find(|file| file.destination == destination)
+        {
+            Some(file) => restore_latest_backup(file),
+            None => Err(ConfigureError::DestinationNotConfigured),
+        };
+
+        match result {
+            Ok(backup) => info!("Restored {} from {:?}", destination, backup),
+            Err(err) => {
+                error!("Unable to restore {}: {}", destination, err);
+                std::process::exit(err.code());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Updates the project to the latest secrets, returning an error instead of exiting
+///
+/// If `cancellation` is cancelled from another thread, this stops at the next step or file with
+/// `ConfigureError::Cancelled`. The secrets repo is put back on the branch and commit it started on, and the project's
+/// files are left as they were.
+///
+/// # Arguments
+///
+/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+/// * `cancellation` - Stops the update once it's cancelled
+///
+pub fn update_cancellable(
+    interactive: bool,
+    configuration_file_path: Option<String>,
+    cancellation: &CancellationToken,
+) -> Result<(), ConfigureError> {
+    encryption::init()?;
+
+    let configuration = read_configuration_from_file(&configuration_file_path)?;
+
+    if configuration.is_empty() {
+        warn!("Current configuration is empty – unable to update without setting it up first");
+        return Ok(());
+    }
+
+    try_update_configuration(
+        configuration_file_path,
+        interactive,
+        false,
+        true,
+        None,
+        cancellation,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(feature = "fs")]
+/// Compares the project's decrypted files with the last successful `apply`
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn status(configuration_file_path: Option<String>) -> ConfigurationStatus {
+    let configuration = read_configuration_from_file(&configuration_file_path)
+        .expect("Unable to read configuration from `.configure` file");
+    configuration.warn_about_expiring_files();
+
+    status_for_configuration(&configuration).expect("Unable to determine configuration status")
+}
+
+#[cfg(feature = "fs")]
+/// The secrets repo's branch, commit, sync state, working copy and remote – for showing alongside a build
+///
+/// Nothing is fetched, so how far the branch is ahead of or behind its upstream is as of the last fetch.
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn secrets_repo_status(
+    configuration_file_path: Option<String>,
+) -> Result<SecretsRepoInfo, ConfigureError> {
+    let configuration = read_configuration_from_file(&configuration_file_path)?;
+    let secrets_repo = git::SecretsRepo {
+        path: find_secrets_repo()?,
+    };
+
+    let remote = secrets_repo.remote_for_configuration(&configuration)?;
+    secrets_repo.info(&remote)
+}
+
+#[cfg(feature = "fs")]
+/// Look for copies of the project's secrets, and anything that looks like a well-known kind of secret, outside the
+/// configured destinations – so they're caught before they're committed
+///
+/// Copies are found by comparing against the decrypted files, so apply the project first to look for them.
+///
+/// # Arguments
+///
+/// * `commit` - The commit to scan, like `HEAD`. If this value is `None`, the working tree is scanned, leaving out ignored files.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn scan(
+    commit: Option<String>,
+    configuration_file_path: Option<String>,
+) -> Result<Vec<ScanFinding>, ConfigureError> {
+    let configuration = read_configuration_from_file(&configuration_file_path)?;
+    scan::scan_project(&configuration, &find_project_root()?, commit.as_deref())
+}
+
+#[cfg(feature = "fs")]
+/// Rules for another secret scanner that flag the project's decrypted files, so they're kept in step with `.configure`
+///
+/// # Arguments
+///
+/// * `format` - The scanner to write rules for.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn export_leak_rules(
+    format: LeakRulesFormat,
+    configuration_file_path: Option<String>,
+) -> Result<String, ConfigureError> {
+    let configuration = read_configuration_from_file(&configuration_file_path)?;
+    leak_rules::leak_rules(&configuration, format)
+}
+
+#[cfg(feature = "fs")]
+/// Check out only the secrets repo directories the project's files come from, for secrets repos too big to check out
+/// in full
+///
+/// Without a configuration file path, the directories for every `.configure` file in the project are checked out, so
+/// each module of a monorepo keeps working.
+///
+/// # Arguments
+///
+/// * `clone_url` - Make a new blobless partial clone of the secrets repo from this URL first, so the contents of other files aren't downloaded either. It's an error if there's already a secrets repo.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, every `.configure` file under the project root is used.
+///
+pub fn secrets_sparse_init(
+    clone_url: Option<String>,
+    configuration_file_path: Option<String>,
+) -> Result<SparseCheckout, ConfigureError> {
+    let configurations = match configuration_file_path {
+        Some(path) => vec![read_configuration_from_file(&Some(path))?],
+        None => find_configure_files_in(&find_project_root()?)?
+            .iter()
+            .map(|path| read_configuration_from_file(&Some(path.to_string_lossy().to_string())))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    sparse::sparse_init(&configurations, clone_url.as_deref())
+}
+
+#[cfg(feature = "fs")]
+/// The subject, expiry and other details that are safe to share of a certificate, keystore or provisioning profile
+///
+/// The file is decrypted in memory, so it doesn't need to be applied first. Private keys are never included.
+///
+/// # Arguments
+///
+/// * `destination` - Where the file is decrypted to, as it's written in the `.configure` file.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn inspect(
+    destination: String,
+    configuration_file_path: Option<String>,
+) -> Result<Inspection, ConfigureError> {
+    let configuration = read_configuration_from_file(&configuration_file_path)?;
+    inspect::inspect_file(&configuration, &destination)
+}
+
+#[cfg(feature = "fs")]
+/// Run a command with the entries of the project's `.env`, `.properties` and `.xcconfig` files in its environment
+///
+/// The files are decrypted in memory – nothing is written to disk. Returns the command's exit code.
+///
+/// # Arguments
+///
+/// * `command` - The program to run, followed by its arguments.
+/// * `files` - The destinations of the files to pass on. If this is empty, every key-value file is used.
+/// * `profile` - Only use the files in this profile, decrypted with its key.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn run_with_secrets(
+    command: Vec<String>,
+    files: Vec<String>,
+    profile: Option<String>,
+    configuration_file_path: Option<String>,
+) -> Result<i32, ConfigureError> {
+    let mut configuration = read_configuration_from_file(&configuration_file_path)?;
+    if let Some(profile) = profile {
+        configuration = configuration.for_profile(&profile)?;
+    }
+
+    let variables = run::environment_for_configuration(&configuration, &files)?;
+    run::run_with_environment(&command, variables)
+}
+
+#[cfg(feature = "fs")]
+/// Apply the project's secrets in a GitHub Actions job, and pass the entries of its `.env`, `.properties` and
+/// `.xcconfig` files on to the job's later steps as environment variables
+///
+/// Every value is masked in the job's log first. Returns the names of the variables that were set.
+///
+/// # Arguments
+///
+/// * `files` - The destinations of the files to pass on. If this is empty, every key-value file is used.
+/// * `profile` - Only apply the files in this profile, decrypted with its key.
+/// * `outputs` - Set the values as the step's outputs as well.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn gha_export(
+    files: Vec<String>,
+    profile: Option<String>,
+    outputs: bool,
+    configuration_file_path: Option<String>,
+) -> Result<Vec<String>, ConfigureError> {
+    let configuration = read_verified_configuration_from_file(&configuration_file_path)?;
+    configuration.warn_about_expiring_files();
+
+    decrypt_files_for_configuration(
+        &configuration,
+        &ApplyOptions {
+            profile: profile.clone(),
+            ..Default::default()
+        },
+    )?;
+
+    let configuration = match profile {
+        Some(profile) => configuration.for_profile(&profile)?,
+        None => configuration,
+    };
+
+    // A project without key-value files has nothing to pass on, but its files were still applied
+    let variables = match run::environment_for_configuration(&configuration, &files) {
+        Err(ConfigureError::NoSecretsForEnvironment) if files.is_empty() => Vec::new(),
+        variables => variables?,
+    };
+
+    github_actions::export_variables(&variables, outputs)
+}
+
+#[cfg(feature = "fs")]
+/// Adds a file to the project's `.configure` file without prompting
+///
+/// # Arguments
+///
+/// * `source` - The file's path, relative to the root of the secrets repo
+/// * `destination` - Where the decrypted file should be written, relative to the project root
+/// * `encrypt` - Whether to encrypt the file into the project right away, from the secrets repo's current checkout. Otherwise it's encrypted on the next `update`.
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn add_file(
+    source: String,
+    destination: String,
+    encrypt: bool,
+    configuration_file_path: Option<String>,
+) {
+    let mut configuration = read_configuration_from_file(&configuration_file_path)
+        .expect("Unable to read project configuration");
 
-use crate::configure::*;
-use crate::encryption::EncryptionKey;
-use crate::fs::*;
+    let secrets_root = find_secrets_repo().expect("Unable to find secrets repo");
+    let source_path = secrets_root.join(&source);
+    if !source_path.exists() {
+        let err = ConfigureError::SourceFileMissing { path: source_path };
+        error!("{}", err);
+        std::process::exit(err.code());
+    }
 
-use libc::c_char;
-use log::debug;
-use std::ffi::CStr;
-use std::path::Path;
-use std::path::PathBuf;
+    let file = match source_path.is_dir() {
+        true => File::directory(&source, &destination),
+        false => File::new(&source, &destination),
+    };
+    let file = match file {
+        Ok(file) => file,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(err.code());
+        }
+    };
 
-/// Set up a project to use the configure tool
+    info!("Adding {:?} → {:?}", file.source, file.destination);
+    if let Err(err) = configuration.add_file(file) {
+        error!("{}", err);
+        std::process::exit(err.code());
+    }
+
+    // Only once it's known not to conflict, so another file's encrypted copy can't be overwritten
+    if encrypt {
+        let file = configuration
+            .files_to_copy
+            .last()
+            .expect("The file was just added");
+        write_encrypted_file_for_configuration(&configuration, file, None)
+            .expect("Unable to encrypt file");
+    }
+
+    save_configuration(&configuration, &configuration_file_path);
+}
+
+#[cfg(feature = "fs")]
+/// Removes the file written to `destination` from the project's `.configure` file
 ///
-#[no_mangle]
-pub extern "C" fn init() {
-    init_encryption();
-    let configuration =
-        read_configuration().expect("Unable to read configuration from `.configure` file");
-    setup_configuration(configuration);
+/// The encrypted and decrypted copies of the file are left in place.
+pub fn remove_file(destination: String, configuration_file_path: Option<String>) {
+    let mut configuration = read_configuration_from_file(&configuration_file_path)
+        .expect("Unable to read project configuration");
+
+    let file = configuration
+        .remove_file(&destination)
+        .expect("Unable to remove file from configuration");
+    info!("Removing {:?} → {:?}", file.source, file.destination);
+
+    save_configuration(&configuration, &configuration_file_path);
 }
 
-/// Decrypts secrets already present in the repository
+#[cfg(feature = "fs")]
+/// Removes the file at `index` (starting from zero) in `files_to_copy` from the project's `.configure` file
 ///
-/// To get secrets into the repository, use `configure_update`
+/// The encrypted and decrypted copies of the file are left in place.
+pub fn remove_file_at_index(index: usize, configuration_file_path: Option<String>) {
+    let mut configuration = read_configuration_from_file(&configuration_file_path)
+        .expect("Unable to read project configuration");
+
+    let file = configuration
+        .remove_file_at(index)
+        .expect("Unable to remove file from configuration");
+    info!("Removing {:?} → {:?}", file.source, file.destination);
+
+    save_configuration(&configuration, &configuration_file_path);
+}
+
+#[cfg(feature = "fs")]
+fn save_configuration(configuration: &Configuration, configuration_file_path: &Option<String>) {
+    let configure_file_path = resolve_configure_file_path(configuration_file_path)
+        .expect("Unable to find `.configure` file");
+    write_signed_configuration_to(configuration, &configure_file_path)
+        .expect("Unable to save project configuration");
+}
+
+#[cfg(feature = "fs")]
+/// Deletes encrypted files in `.configure-files` that no longer belong to any file in the `.configure` file
+///
+/// Only the `.configure` file's own directory in `.configure-files` is looked at, so other modules' files are kept.
 ///
 /// # Arguments
 ///
-/// * `configuration` - The project's parsed `ConfigurationFile` object.
-/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
+/// * `interactive` - Whether to ask for confirmation before deleting anything
+/// * `dry_run` - Only list the files that would be deleted
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
 ///
-pub fn apply(interactive: bool, configuration_file_path: Option<String>) {
-    init_encryption();
+pub fn gc(interactive: bool, dry_run: bool, configuration_file_path: Option<String>) {
     let configuration = read_configuration_from_file(&configuration_file_path)
         .expect("Unable to read configuration from `.configure` file");
 
-    if configuration.is_empty() {
-        if interactive {
-            setup_configuration(configuration);
-        } else {
-            ui::warn("Unable to apply configuration – it is empty");
-        }
-    } else {
-        apply_configuration(&configuration);
+    let orphans =
+        orphaned_encrypted_files(&configuration).expect("Unable to look for orphaned files");
+
+    if orphans.is_empty() {
+        info!("No orphaned encrypted files found");
+        return;
+    }
+
+    for orphan in &orphans {
+        info!("{:?} doesn't belong to any configured file", orphan);
+    }
+
+    if dry_run || (interactive && !confirm(&format!("Delete {} orphaned file(s)?", orphans.len())))
+    {
+        return;
     }
+
+    for orphan in &orphans {
+        std::fs::remove_file(orphan).expect("Unable to delete orphaned file");
+    }
+
+    info!("Deleted {} orphaned file(s)", orphans.len());
 }
 
-/// An FFI-compatible version of the `apply` function
+#[cfg(feature = "fs")]
+/// Upgrades a project set up by an older version of this tool
 ///
-/// # Safety
+/// Encrypted files kept next to their destinations are moved into `.configure-files`, the `.configure` file is
+/// rewritten in the current format, and a flat `keys.json` is converted to the structured format. Every change is
+/// shown before it's made.
 ///
-/// This function takes a C string as input and needs to handle deallocation of it. The function will panic
-/// if the string is null.
-#[export_name = "apply"]
-pub unsafe extern "C" fn c_compatible_apply(
-    interactive: bool,
-    configuration_file_path: *const c_char,
-) {
-    let c_str = {
-        assert!(!configuration_file_path.is_null());
-        CStr::from_ptr(configuration_file_path)
-    };
+/// # Arguments
+///
+/// * `interactive` - Whether to ask for confirmation before changing anything
+/// * `dry_run` - Only show the changes that would be made
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn migrate(interactive: bool, dry_run: bool, configuration_file_path: Option<String>) {
+    let project_root = find_project_root().expect("Unable to find project root");
+    let configure_file_path = resolve_configure_file_path(&configuration_file_path)
+        .expect("Unable to find `.configure` file");
+    let keys_file_path = find_secrets_repo()
+        .ok()
+        .map(|secrets_root| secrets_root.join("keys.json"));
+
+    let migration = migrate::Migration::plan(
+        &project_root,
+        &configure_file_path,
+        keys_file_path.as_deref(),
+    )
+    .expect("Unable to work out what needs to be migrated");
+
+    if migration.is_empty() {
+        info!("This project is already up to date");
+        return;
+    }
+
+    heading("Changes");
+    message(&migration.to_string());
+
+    if !migration.steps.iter().any(|step| step.is_automatic()) {
+        return;
+    }
 
-    let configuration_file_path = c_str.to_str().unwrap();
-    apply(interactive, Some(configuration_file_path.to_string()))
+    if dry_run || (interactive && !confirm("Make these changes?")) {
+        return;
+    }
+
+    migration.apply().expect("Unable to migrate project");
+
+    info!("Project migrated");
 }
 
-/// Adds encrypted secrets files to the configuration, or updates existing ones.
+#[cfg(feature = "fs")]
+/// Copies a local file into the secrets repo, optionally on a new branch and in a new commit
 ///
-/// Prompts the user to decrypt them when it finishes.
+/// Nothing is pushed to the server – review the change in the secrets repo and push it yourself.
 ///
 /// # Arguments
 ///
-/// * `interactive` - Whether to prompt the user for confirmation before performing destructive operations
-/// * `configuration_file_path` - An optional path to the configuration file that should be updated. Useful for when the working directory differs from the root project directory (as when using the gradle plugin, for instance). If this value is `None`, the default configuration file path will be used.
+/// * `local_file` - The plaintext secret to copy
+/// * `repo_path` - Where the secret should live, relative to the root of the secrets repo
+/// * `options` - Whether to create a branch or commit, and the commit message to use
 ///
-pub fn update(interactive: bool, configuration_file_path: Option<String>) {
-    init_encryption();
+pub fn push_secret(local_file: String, repo_path: String, options: PushSecretOptions) {
+    push::push_secret(Path::new(&local_file), &repo_path, &options)
+        .expect("Unable to add the file to the secrets repo");
+}
 
+#[cfg(feature = "fs")]
+/// Lists every file managed by the project's `.configure` file
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn list_files(configuration_file_path: Option<String>) -> FileList {
     let configuration = read_configuration_from_file(&configuration_file_path)
         .expect("Unable to read configuration from `.configure` file");
 
-    if configuration.is_empty() {
-        if interactive {
-            setup_configuration(configuration)
-        } else {
-            ui::warn("Current configuration is empty – unable to update when running in non-interactive mode");
-        }
-    } else {
-        update_configuration(configuration_file_path, interactive);
-    }
+    list_files_for_configuration(&configuration).expect("Unable to list configured files")
 }
 
-/// An FFI-compatible version of the `update` function
+#[cfg(feature = "fs")]
+/// Runs a long-lived server that performs `apply`, `update`, and `status` on behalf of build tools
 ///
-/// # Safety
+/// Requests and responses are single lines of JSON sent over a Unix domain socket (or a named pipe on Windows). Keeping
+/// one process around avoids paying for process startup and libsodium initialization on every build step.
 ///
-/// This function takes a C string as input and needs to handle deallocation of it. The function will panic
-/// if the string is null.
-#[export_name = "update"]
-pub unsafe extern "C" fn c_compatible_update(
-    interactive: bool,
-    configuration_file_path: *const c_char,
-) {
-    let c_str = {
-        assert!(!configuration_file_path.is_null());
-        CStr::from_ptr(configuration_file_path)
-    };
+/// # Arguments
+///
+/// * `socket_path` - Where to listen. If this value is `None`, a per-user socket in `$XDG_RUNTIME_DIR` (or the user's home directory) is used. The socket's directory must only be writable by the user. On Windows, this is the pipe's name.
+/// * `idle_timeout` - How long to wait for a request before shutting down. If this value is `None`, the daemon stops after ten idle minutes.
+///
+pub fn run_daemon(socket_path: Option<String>, idle_timeout: Option<Duration>) {
+    let socket_path = socket_path
+        .map(PathBuf::from)
+        .unwrap_or_else(daemon::default_socket_path);
+
+    daemon::run(
+        &socket_path,
+        idle_timeout.unwrap_or(daemon::DEFAULT_IDLE_TIMEOUT),
+    )
+    .expect("Unable to run daemon");
+}
+
+#[cfg(feature = "fs")]
+/// Asks a running daemon to shut down
+///
+/// # Arguments
+///
+/// * `socket_path` - The socket the daemon is listening on. If this value is `None`, the default per-user socket is used.
+///
+pub fn stop_daemon(socket_path: Option<String>) {
+    let socket_path = socket_path
+        .map(PathBuf::from)
+        .unwrap_or_else(daemon::default_socket_path);
 
-    let configuration_file_path = c_str.to_str().unwrap();
-    update(interactive, Some(configuration_file_path.to_string()))
+    daemon::stop(&socket_path).expect("Unable to reach the daemon – is it running?");
+    info!("Daemon stopped");
 }
 
+#[cfg(feature = "fs")]
+/// Keeps the project's secrets up to date until the process is stopped
+///
+/// Changes to the `.configure` file or the encrypted files are applied (or reported, if `options.apply_automatically` is `false`).
+/// The secrets repo is fetched periodically, and new commits on the configured branch are reported so the user can run `update`.
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+/// * `options` - How often to check for changes, and whether to apply them automatically
+///
+pub fn watch(configuration_file_path: Option<String>, options: WatchOptions) {
+    watch::watch(&configuration_file_path, &options);
+}
+
+#[cfg(feature = "fs")]
+/// Signs the project's `.configure` file, creating a signing key for the project if it doesn't have one yet
+///
+/// The signature is written next to the `.configure` file, and should be committed with it – so are signatures for the
+/// file it extends and any files it includes, which are checked the same way. Once a project has a signing key in the
+/// secrets repo, `apply` and `update` refuse to use a `.configure` file whose signature is missing or doesn't match,
+/// so changes to `files_to_copy` can't be slipped in without someone who has the key signing them.
+///
+/// # Arguments
+///
+/// * `configuration_file_path` - An optional path to the configuration file. If this value is `None`, the default configuration file path will be used.
+///
+pub fn sign(configuration_file_path: Option<String>) {
+    let configuration = read_configuration_from_file(&configuration_file_path)
+        .expect("Unable to read configuration from `.configure` file");
+    let configure_file_path = resolve_configure_file_path(&configuration_file_path)
+        .expect("Unable to find `.configure` file");
+
+    let signing_key =
+        generate_signing_key_if_needed(&configuration).expect("Unable to find signing key");
+    let signature_path = sign_configuration_file(&configure_file_path, &signing_key)
+        .expect("Unable to sign configuration");
+
+    for path in configuration_source_files(&configure_file_path, &configuration)
+        .expect("Unable to find the files the configuration includes")
+    {
+        let signature_path =
+            sign_configuration_file(&path, &signing_key).expect("Unable to sign configuration");
+        info!("Wrote signature to {:?}", signature_path);
+    }
+
+    info!(
+        "Wrote signature to {:?} – commit it alongside the .configure file",
+        signature_path
+    );
+    info!(
+        "CI machines without a secrets repo can verify it by setting {:} to {:}",
+        SIGNING_PUBLIC_KEY_NAME,
+        signing_key.verifying_key()
+    );
+}
+
+#[cfg(feature = "fs")]
 /// Update the project name in the project `.configure` file
 ///
+/// If the project's keys are stored under its old name, they'd no longer be found. When running interactively, the
+/// user can choose to rename or copy them in `keys.json`. Otherwise, `key_name` is set to the old name so the project
+/// keeps using the same keys.
+///
 /// # Arguments
 ///
 /// * `project_name` – the new project name that should be written to the `.configure` file.
-#[no_mangle]
-pub fn update_project_name(project_name: String, configuration_file_path: Option<String>) {
+/// * `interactive` – whether to ask what should happen to the project's keys
+pub fn update_project_name(
+    project_name: String,
+    interactive: bool,
+    configuration_file_path: Option<String>,
+) {
     let mut configuration = read_configuration_from_file(&configuration_file_path)
-        .expect("Unable to read project configuration");
-    configuration.project_name = project_name;
-    write_configuration(&configuration).expect("Unable to save project configuration");
+        .expect("Unable to read configuration from `.configure` file");
+
+    rename_project(&mut configuration, project_name, interactive)
+        .expect("Unable to move the project's keys to its new name");
+
+    let configure_file_path = resolve_configure_file_path(&configuration_file_path)
+        .expect("Unable to find `.configure` file");
+    write_signed_configuration_to(&configuration, &configure_file_path)
+        .expect("Unable to save project configuration");
+}
+
+#[cfg(feature = "fs")]
+/// Update the name of the project's entry in `keys.json`
+///
+/// Projects with the same key name share a key. Removing the key name goes back to using the project name.
+///
+/// # Arguments
+///
+/// * `key_name` – the entry in `keys.json` to use, or `None` to use the project name
+pub fn update_key_name(key_name: Option<String>, configuration_file_path: Option<String>) {
+    modify_configuration(&configuration_file_path, |configuration| {
+        configuration.key_name = key_name
+    })
+    .expect("Unable to save project configuration");
 }
 
+#[cfg(feature = "fs")]
 /// Update the branch name in the project `.configure` file.
 ///
 /// # Arguments
 ///
 /// * `branch_name` – the new branch name read_configurationthat should be written to the `configure` file
-#[no_mangle]
-pub fn update_branch_name(branch_name: String, configuration_file_path: Option<String>) {
-    let mut configuration = read_configuration_from_file(&configuration_file_path)
-        .expect("Unable to read project configuration");
-    configuration.branch = branch_name;
-    write_configuration(&configuration).expect("Unable to save project configuration");
+/// * `allow_missing_branch` – write the branch name even if the secrets repo has no branch with that name
+pub fn update_branch_name(
+    branch_name: String,
+    allow_missing_branch: bool,
+    configuration_file_path: Option<String>,
+) {
+    if !allow_missing_branch {
+        if let Err(err) = check_branch_exists(&git::SecretsRepo::default(), &branch_name) {
+            error!("Unable to update the branch name: {}", err);
+            std::process::exit(err.code());
+        }
+    }
+
+    modify_configuration(&configuration_file_path, |configuration| {
+        configuration.branch = branch_name
+    })
+    .expect("Unable to save project configuration");
 }
 
+#[cfg(feature = "fs")]
 /// Update the pinned hash in the project `.configure` file
 ///
 /// # Arguments
 ///
 /// * `pinned_hash` – the commit hash to copy configuration files from
-#[no_mangle]
 pub fn update_pinned_hash(pinned_hash: String, configuration_file_path: Option<String>) {
-    let mut configuration = read_configuration_from_file(&configuration_file_path)
-        .expect("Unable to read project configuration");
-    configuration.pinned_hash = pinned_hash;
-    write_configuration(&configuration).expect("Unable to save project configuration");
+    modify_configuration(&configuration_file_path, |configuration| {
+        configuration.pinned_hash = pinned_hash
+    })
+    .expect("Unable to save project configuration");
+}
+
+#[cfg(feature = "fs")]
+/// Read the configuration file, change it, and save it (re-signing it if the project signs its `.configure` file)
+pub(crate) fn modify_configuration<F>(
+    configuration_file_path: &Option<String>,
+    modify: F,
+) -> Result<(), ConfigureError>
+where
+    F: FnOnce(&mut Configuration),
+{
+    let mut configuration = read_configuration_from_file(configuration_file_path)?;
+    modify(&mut configuration);
+
+    let configure_file_path = resolve_configure_file_path(configuration_file_path)?;
+    write_signed_configuration_to(&configuration, &configure_file_path)
 }
 
+#[cfg(feature = "fs")]
 /// Validate a project's .configure file
 ///
-#[no_mangle]
+/// Fields the schema doesn't know about are warnings, unless strict parsing is turned on with `set_strict_parsing`.
 pub fn validate() {
-    init_encryption();
-    let configuration =
-        read_configuration().expect("Unable to read configuration from `.configure` file");
+    let configuration = match check_configure_file_schema().and_then(|_| read_configuration()) {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(err.code());
+        }
+    };
 
     if configuration.is_empty() {
-        ui::warn("Unable to validate configuration – it is empty");
-    } else {
-        validate_configuration(configuration);
+        warn!("Unable to validate configuration – it is empty");
+        return;
+    }
+
+    if let Err(err) = configuration
+        .check_destinations()
+        .and_then(|_| configuration.check_profiles())
+        .and_then(|_| configuration.check_plugins())
+    {
+        error!("{}", err);
+        std::process::exit(err.code());
+    }
+
+    configuration.warn_about_expiring_files();
+
+    let provenance =
+        provenance_for_configuration(&configuration).expect("Unable to read encrypted files");
+    for (destination, provenance) in provenance {
+        info!(
+            "{} was encrypted from {} at {} on {}",
+            destination, provenance.source, provenance.commit, provenance.encrypted_at
+        );
+    }
+
+    validate_configuration(configuration);
+}
+
+#[cfg(feature = "fs")]
+/// Check the `.configure` file's JSON against the schema, logging anything that doesn't match
+///
+/// Unknown fields are left out – they're reported whenever the file is read.
+fn check_configure_file_schema() -> Result<(), ConfigureError> {
+    let contents = read_configure_file_contents(&find_configure_file()?)?;
+    let violations: Vec<_> = schema::validate_against_schema(&contents)?
+        .into_iter()
+        .filter(|violation| !violation.is_unknown_field())
+        .collect();
+
+    for violation in &violations {
+        error!("{}", violation);
+    }
+
+    match violations.len() {
+        0 => Ok(()),
+        count => Err(ConfigureError::ConfigureFileDoesNotMatchSchema { count }),
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Treat fields that `.configure` and `keys.json` files aren't expected to have as errors, instead of warnings
+pub fn set_strict_parsing(strict: bool) {
+    schema::set_strict(strict);
+}
+
+#[cfg(feature = "fs")]
+/// Use `path` as the project root, instead of the git repo the current directory is in
+///
+/// # Arguments
+///
+/// * `path` - The project's directory, or `None` to go back to looking for the git repo
+///
+pub fn set_project_root(path: Option<PathBuf>) {
+    if let Err(err) = fs::set_project_root(path.as_deref()) {
+        error!("{}", err);
+        std::process::exit(err.code());
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Use the secrets repo at `path`, instead of the one in `SECRETS_REPO`, the user's settings, or the usual places
+///
+/// # Arguments
+///
+/// * `path` - The secrets repo's directory, or `None` to go back to looking for it
+///
+pub fn set_secrets_root(path: Option<PathBuf>) {
+    if let Err(err) = fs::set_secrets_root(path.as_deref()) {
+        error!("{}", err);
+        std::process::exit(err.code());
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Outside of a git repo, offer to use the current directory as the project root instead of giving up
+///
+/// Without anyone to ask, nothing changes – the command fails and explains how to give the project root.
+fn offer_current_directory_as_project_root(interactive: bool) {
+    if !interactive || !matches!(find_project_root(), Err(ConfigureError::ProjectNotPresent)) {
+        return;
+    }
+
+    let current_directory = std::env::current_dir().expect("Unable to determine current directory");
+
+    ui::warn(
+        "This directory isn't in a git repo, so the project root can't be found automatically.",
+    );
+    if confirm(&format!(
+        "Use {:?} as the project root? (Pass --project-root to skip this question)",
+        current_directory
+    )) {
+        set_project_root(Some(current_directory));
     }
 }
 
+#[cfg(feature = "fs")]
+/// Ask the secrets server for the latest hashes every time, instead of reusing recent answers
+pub fn set_remote_cache_enabled(enabled: bool) {
+    remote_cache::set_enabled(enabled);
+}
+
+#[cfg(feature = "fs")]
+/// Record how long network access, hashing, encryption and writing files take, for `print_timings`
+pub fn set_timings_enabled(enabled: bool) {
+    timings::set_enabled(enabled);
+}
+
+#[cfg(feature = "fs")]
+/// Print how long each phase took since timings were turned on with `set_timings_enabled`
+pub fn print_timings() {
+    heading("Timings");
+    message(&timings::timings().to_string());
+}
+
+#[cfg(feature = "fs")]
+/// The JSON Schema for `.configure` files
+pub fn configure_file_schema() -> &'static str {
+    schema::CONFIGURE_FILE_SCHEMA
+}
+
+#[cfg(feature = "fs")]
+/// The branches in the secrets repo, for completing branch names in the shell
+///
+/// Completion runs on every press of the tab key, so it shouldn't print anything but the names – if the secrets repo
+/// can't be read, there's nothing to complete.
+pub fn secrets_branch_names() -> Vec<String> {
+    git::SecretsRepo::default()
+        .branch_names()
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "fs")]
 /// Create an encryption key suitable for use with this project
 ///
 /// The encryption key will be written to the `keys.json` file at the root of your local secrets repository. You will need to commit this change yourself.
-#[no_mangle]
-pub fn generate_encryption_key() -> String {
-    crate::encryption::generate_key().to_string()
+pub fn generate_encryption_key() -> Result<String, ConfigureError> {
+    Ok(crate::encryption::generate_key()?.to_string())
+}
+
+#[cfg(feature = "fs")]
+/// Lists every project in the secrets repo's `keys.json`, with a fingerprint of its key rather than the key itself
+pub fn list_keys() -> KeyList {
+    keys::list_keys().expect("Unable to list keys")
+}
+
+#[cfg(feature = "fs")]
+/// Prints what's known about a project's key, and the key itself if `reveal` is set and the user confirms
+///
+/// # Arguments
+///
+/// * `project_name` - The project's name in `keys.json`
+/// * `reveal` - Whether to print the key, after asking the user whether anyone else can see their screen
+///
+pub fn show_key(project_name: String, reveal: bool) {
+    let list = list_keys();
+
+    let key_info = match list.keys.iter().find(|key| key.project == project_name) {
+        Some(key_info) => key_info,
+        None => {
+            let err = ConfigureError::MissingProjectKey {
+                project: project_name,
+            };
+            error!("{}", err);
+            std::process::exit(err.code());
+        }
+    };
+
+    print!("{}", key_info);
+
+    if reveal && confirm("This prints the key on screen. Continue?") {
+        println!(
+            "Key:         {}",
+            keys::key_for_project(&project_name).expect("Unable to read the key")
+        );
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Replaces a project's key in `keys.json` with a new one
+///
+/// The old key is kept as a deprecated key, so files encrypted with it can still be decrypted. Run `update` afterwards
+/// to re-encrypt the project's files with the new key, then commit the changes to the secrets repo yourself.
+///
+/// # Arguments
+///
+/// * `project_name` - The project's name in `keys.json`
+///
+pub fn rotate_key(project_name: String) {
+    match keys::rotate_key(&project_name) {
+        Ok(key) => {
+            heading(&format!("Rotated the key for {}", project_name));
+            message(&format!(
+                "New fingerprint: {}",
+                key.fingerprint().unwrap_or_default()
+            ));
+            message("Run `configure update` to re-encrypt the project's files, then commit the changes to the secrets repo");
+        }
+        Err(err) => {
+            error!("Unable to rotate the key: {}", err);
+            std::process::exit(err.code());
+        }
+    }
 }
 
+#[cfg(feature = "fs")]
 /// Finds the `.configure` file in the current project and returns a string containing it.
-#[no_mangle]
 pub fn find_configuration_file() -> String {
     match fs::find_configure_file() {
         Ok(path) => match path.into_os_string().into_string() {
@@ -190,6 +1577,7 @@ pub fn find_configuration_file() -> String {
     }
 }
 
+#[cfg(feature = "fs")]
 /// A wrapper around `encrypt_single_file` that takes strings instead of paths.
 ///
 /// This makes it easier to call from command line arguments.
@@ -203,6 +1591,7 @@ pub fn encrypt_single_file_path(
     encrypt_single_file(input_file_path, output_file_path, encryption_key_string)
 }
 
+#[cfg(feature = "fs")]
 pub fn encrypt_single_file(
     input_file: PathBuf,
     output_file: Option<PathBuf>,
@@ -212,13 +1601,22 @@ pub fn encrypt_single_file(
         Some(encryption_key_string) => match EncryptionKey::from_str(&encryption_key_string) {
             Ok(encryption_key) => encryption_key,
             Err(err) => {
-                println!("{:?}", err);
-                std::process::exit(err as i32);
+                error!("{:?}", err);
+                std::process::exit(err.code());
             }
         },
         None => {
-            let key = crate::encryption::generate_key();
-            println!("Using autogenerated key {:}.\n\nBe sure to save it somewhere right away – it won't be available again.", key);
+            let key = match crate::encryption::generate_key() {
+                Ok(key) => key,
+                Err(err) => {
+                    error!("Unable to generate an encryption key: {}", err);
+                    std::process::exit(err.code());
+                }
+            };
+
+            // The key goes straight to stdout rather than through the logs, which would mask it – and mustn't keep it
+            warn!("No encryption key was given, so this one was generated. Be sure to save it somewhere right away – it won't be available again.");
+            println!("{}", key);
             key
         }
     };
@@ -237,6 +1635,7 @@ pub fn encrypt_single_file(
     .expect("Unable to encrypt file");
 }
 
+#[cfg(feature = "fs")]
 /// A wrapper around `encrypt_single_file` that takes strings instead of paths.
 ///
 /// This makes it easier to call from command line arguments.
@@ -251,7 +1650,7 @@ pub fn decrypt_single_file_path(
     decrypt_single_file(input_file_path, output_file_path, encryption_key_string)
 }
 
-#[no_mangle]
+#[cfg(feature = "fs")]
 pub fn decrypt_single_file(
     input_file: PathBuf,
     output_file: Option<PathBuf>,
@@ -260,8 +1659,8 @@ pub fn decrypt_single_file(
     let encryption_key = match EncryptionKey::from_str(&encryption_key_string) {
         Ok(encryption_key) => encryption_key,
         Err(err) => {
-            println!("{:?}", err);
-            std::process::exit(err as i32);
+            error!("{:?}", err);
+            std::process::exit(err.code());
         }
     };
 
@@ -279,17 +1678,117 @@ pub fn decrypt_single_file(
     .expect("Unable to decrypt file");
 }
 
-fn init_encryption() {
-    debug!("libConfigure initializing encryption");
-    encryption::init();
-    debug!("libConfigure encryption initialization successful");
+#[cfg(feature = "fs")]
+/// A wrapper around `encrypt_single_file_with_passphrase` that takes strings instead of paths.
+pub fn encrypt_single_file_path_with_passphrase(input_file: &str, output_file: Option<String>) {
+    encrypt_single_file_with_passphrase(PathBuf::from(input_file), output_file.map(PathBuf::from))
+}
+
+#[cfg(feature = "fs")]
+/// Encrypt a single file with a key derived from a passphrase, so it can be shared without a `keys.json` file
+///
+/// The passphrase is read from the `CONFIGURE_PASSPHRASE` environment variable, or asked for (twice) if it isn't set.
+pub fn encrypt_single_file_with_passphrase(input_file: PathBuf, output_file: Option<PathBuf>) {
+    let output_file = match output_file {
+        Some(path) => path,
+        None => infer_encryption_output_filename(&input_file),
+    };
+
+    encryption::encrypt_file(&input_file, &output_file, &read_passphrase(true))
+        .expect("Unable to encrypt file");
+}
+
+#[cfg(feature = "fs")]
+/// A wrapper around `decrypt_single_file_with_passphrase` that takes strings instead of paths.
+pub fn decrypt_single_file_path_with_passphrase(input_file: &str, output_file: Option<String>) {
+    decrypt_single_file_with_passphrase(PathBuf::from(input_file), output_file.map(PathBuf::from))
+}
+
+#[cfg(feature = "fs")]
+/// Decrypt a single file that was encrypted with `encrypt_single_file_with_passphrase`
+///
+/// The passphrase is read from the `CONFIGURE_PASSPHRASE` environment variable, or asked for if it isn't set.
+pub fn decrypt_single_file_with_passphrase(input_file: PathBuf, output_file: Option<PathBuf>) {
+    let output_file = match output_file {
+        Some(path) => path,
+        None => infer_decryption_output_filename(&input_file),
+    };
+
+    encryption::decrypt_file(&input_file, &output_file, &read_passphrase(false))
+        .expect("Unable to decrypt file");
+}
+
+#[cfg(feature = "fs")]
+fn read_passphrase(with_confirmation: bool) -> EncryptionKey {
+    let passphrase = zeroize::Zeroizing::new(match std::env::var(PASSPHRASE_KEY_NAME) {
+        Ok(var) => var,
+        Err(_) => ui::prompt_secret("Passphrase", with_confirmation),
+    });
+
+    match EncryptionKey::from_passphrase(&passphrase) {
+        Ok(encryption_key) => encryption_key,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(err.code());
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+/// Whether there's someone at a terminal to answer questions – when there isn't, commands should run non-interactively
+pub fn is_interactive_terminal() -> bool {
+    ui::is_interactive_terminal()
+}
+
+#[cfg(feature = "fs")]
+/// Hide the messages, warnings and progress indicators commands print, leaving only errors and prompts
+///
+/// Logging isn't affected – pick the log level when setting up the logger.
+pub fn set_quiet(quiet: bool) {
+    ui::set_quiet(quiet);
+}
+
+/// Mask anything that looks like a key, and any passphrase that's been used, in panic messages
+///
+/// This replaces the default panic hook, so call it early – before anything that might panic.
+pub fn redact_panic_messages() {
+    redact::install_panic_hook();
+}
+
+#[cfg(feature = "fs")]
+/// Turn styled output on or off, overriding the user's settings and what the terminal supports
+pub fn set_colors_enabled(enabled: bool) {
+    ui::set_colors_enabled(enabled);
 }
 
+#[cfg(feature = "fs")]
 const SECRETS_KEY_NAME: &str = "SECRETS_REPO";
+#[cfg(feature = "fs")]
 const ENCRYPTION_KEY_NAME: &str = "CONFIGURE_ENCRYPTION_KEY";
+#[cfg(feature = "fs")]
 const TEMP_ENCRYPTION_KEY_NAME: &str = "CONFIGURE_ENCRYPTION_KEY_TEMP"; // Useful when switching between versions of the plugin
+#[cfg(feature = "fs")]
+const PASSPHRASE_KEY_NAME: &str = "CONFIGURE_PASSPHRASE"; // For projects with `passphrase_encryption` turned on
+#[cfg(feature = "fs")]
+const GIT_TIMEOUT_KEY_NAME: &str = "CONFIGURE_GIT_TIMEOUT"; // In seconds
+#[cfg(feature = "fs")]
+const GIT_RETRIES_KEY_NAME: &str = "CONFIGURE_GIT_RETRIES";
+#[cfg(feature = "fs")]
+const OFFLINE_KEY_NAME: &str = "CONFIGURE_OFFLINE"; // Never contact the secrets server – use what was last fetched
+#[cfg(feature = "fs")]
+const REMOTE_CACHE_TTL_KEY_NAME: &str = "CONFIGURE_REMOTE_CACHE_TTL"; // In seconds
+#[cfg(feature = "fs")]
+const SETTINGS_FILE_KEY_NAME: &str = "CONFIGURE_SETTINGS"; // Overrides the location of `config.toml`
+#[cfg(feature = "fs")]
+const DEFAULT_BRANCH_KEY_NAME: &str = "CONFIGURE_DEFAULT_BRANCH";
+#[cfg(feature = "fs")]
+const KEEP_BACKUPS_KEY_NAME: &str = "CONFIGURE_KEEP_BACKUPS";
+#[cfg(feature = "fs")]
+const CI_KEY_NAME: &str = "CONFIGURE_CI"; // Overrides CI detection
+#[cfg(feature = "fs")]
+const SIGNING_PUBLIC_KEY_NAME: &str = "CONFIGURE_SIGNING_PUBLIC_KEY"; // Lets CI verify signed `.configure` files without a secrets repo
 
-#[cfg(test)]
+#[cfg(all(test, feature = "fs"))]
 mod tests {
     // Import the parent scope
     use super::*;
@@ -304,7 +1803,7 @@ mod tests {
     #[test]
     fn test_that_single_file_encryption_works_end_to_end() {
         let random_string = __randomstring();
-        let key = crate::encryption::generate_key().to_string();
+        let key = crate::encryption::generate_key().unwrap().to_string();
 
         let input_file_path = __tempfile();
         let input_file_path_string = input_file_path.to_str().unwrap();
@@ -0,0 +1,141 @@
+//! Changes to what `apply` writes on one machine, kept in a `.configure.local` file at the project root
+//!
+//! The file leaves some of the configured files out – not every developer has (or wants) the release signing secrets
+//! on their laptop – or decrypts them somewhere else. Files are picked by their destination in `.configure`:
+//!
+//! ```json
+//! {
+//!     "exclude": ["app/release.keystore"],
+//!     "destinations": { "app/google-services.json": "local/google-services.json" }
+//! }
+//! ```
+//!
+//! It's never committed – add it to the project's `.gitignore`. Only decrypted files are affected, so the shared
+//! `.configure` file and the encrypted files in `.configure-files` stay the same for everyone.
+
+use crate::configure::{Configuration, File};
+use crate::ConfigureError;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const LOCAL_OVERRIDES_FILE_NAME: &str = ".configure.local";
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocalOverrides {
+    /// The destinations of files that aren't decrypted on this machine
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Where to decrypt files on this machine instead, by their destination in `.configure`
+    #[serde(default)]
+    pub destinations: BTreeMap<String, String>,
+}
+
+impl LocalOverrides {
+    /// Read the overrides for the project at `project_root` – there aren't any if it has no `.configure.local` file
+    pub fn read(project_root: &Path) -> Result<LocalOverrides, ConfigureError> {
+        let path = project_root.join(LOCAL_OVERRIDES_FILE_NAME);
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(LocalOverrides::default())
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        serde_json::from_str(&contents).map_err(|_| ConfigureError::LocalOverridesNotValid { path })
+    }
+
+    /// The files in `configuration` that are decrypted on this machine
+    pub fn files_to_apply<'a>(&self, configuration: &'a Configuration) -> Vec<&'a File> {
+        configuration
+            .files_to_copy
+            .iter()
+            .filter(|file| !self.exclude.contains(&file.destination))
+            .collect()
+    }
+
+    /// Where `file` is decrypted to on this machine, relative to the project root
+    pub fn destination_for<'a>(&'a self, file: &'a File) -> &'a str {
+        match self.destinations.get(&file.destination) {
+            Some(destination) => destination,
+            None => &file.destination,
+        }
+    }
+
+    /// Destinations named in the overrides that `configuration` doesn't have – probably typos
+    pub fn unknown_destinations<'a>(&'a self, configuration: &Configuration) -> Vec<&'a str> {
+        self.exclude
+            .iter()
+            .chain(self.destinations.keys())
+            .filter(|destination| {
+                !configuration
+                    .files_to_copy
+                    .iter()
+                    .any(|file| &&file.destination == destination)
+            })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configuration() -> Configuration {
+        let file = |destination: &str| File {
+            source: format!("android/{}", destination),
+            destination: destination.to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        };
+
+        Configuration {
+            files_to_copy: vec![
+                file("app/google-services.json"),
+                file("app/release.keystore"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_that_local_overrides_exclude_and_move_files() {
+        let overrides: LocalOverrides = serde_json::from_str(
+            r#"{
+                "exclude": ["app/release.keystore", "app/missing.json"],
+                "destinations": { "app/google-services.json": "local/google-services.json" }
+            }"#,
+        )
+        .unwrap();
+        let configuration = configuration();
+
+        let files = overrides.files_to_apply(&configuration);
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            overrides.destination_for(files[0]),
+            "local/google-services.json"
+        );
+        assert_eq!(
+            overrides.unknown_destinations(&configuration),
+            vec!["app/missing.json"]
+        );
+    }
+
+    #[test]
+    fn test_that_a_missing_local_overrides_file_changes_nothing() {
+        let overrides = LocalOverrides::read(Path::new("/nonexistent/project")).unwrap();
+        let configuration = configuration();
+
+        assert_eq!(overrides.files_to_apply(&configuration).len(), 2);
+        assert_eq!(
+            overrides.destination_for(&configuration.files_to_copy[1]),
+            "app/release.keystore"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Keeping two `configure` processes from changing the same files at the same time
+//!
+//! An update or apply holds the project's lock (`.configure-files/.lock`) for as long as it's writing, and a fetch
+//! holds one in the secrets repo's git directory – git itself fails when two fetches update the same refs at once. A
+//! second process waits for the first to finish instead of interleaving its writes with it.
+//!
+//! The lock is a file created with `create_new`, so only one process can make it, and it's deleted when the lock is
+//! dropped. A process that's killed leaves it behind, so a lock older than `STALE_LOCK_AGE` is taken over.
+
+use crate::ConfigureError;
+use log::{debug, info, warn};
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long to wait for another process to finish before giving up
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How old a lock has to be before it's assumed its process was killed – no update takes anywhere near this long
+pub const STALE_LOCK_AGE: Duration = Duration::from_secs(60 * 60);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A held lock, which is released when this is dropped
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Wait until the lock at `path` can be taken, for up to `timeout`
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<FileLock, ConfigureError> {
+        let started_at = Instant::now();
+        let mut reported_waiting = false;
+
+        loop {
+            if let Some(lock) = FileLock::try_acquire(path)? {
+                return Ok(lock);
+            }
+
+            if started_at.elapsed() >= timeout {
+                return Err(ConfigureError::LockNotAvailable {
+                    path: path.to_path_buf(),
+                });
+            }
+
+            if !reported_waiting {
+                info!(
+                    "Waiting for another configure process to finish with {:?}",
+                    path
+                );
+                reported_waiting = true;
+            }
+
+            std::thread::sleep(RETRY_INTERVAL);
+        }
+    }
+
+    /// `acquire`, without blocking the thread while it waits
+    #[cfg(feature = "tokio")]
+    pub async fn acquire_async(path: &Path, timeout: Duration) -> Result<FileLock, ConfigureError> {
+        let started_at = Instant::now();
+
+        loop {
+            if let Some(lock) = FileLock::try_acquire(path)? {
+                return Ok(lock);
+            }
+
+            if started_at.elapsed() >= timeout {
+                return Err(ConfigureError::LockNotAvailable {
+                    path: path.to_path_buf(),
+                });
+            }
+
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+
+    /// Take the lock at `path` if nobody else has it – `None` means another process does
+    pub fn try_acquire(path: &Path) -> Result<Option<FileLock>, ConfigureError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        if let Some(lock) = create_lock_file(path)? {
+            return Ok(Some(lock));
+        }
+
+        if !is_stale(path) {
+            return Ok(None);
+        }
+
+        warn!(
+            "Removing {:?} – the process that made it seems to have stopped",
+            path
+        );
+        let _ = std::fs::remove_file(path);
+
+        // If another process took the stale lock over first, it's theirs now
+        create_lock_file(path)
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        debug!("Unlocking {:?}", self.path);
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(
+                "Unable to remove {:?} – delete it by hand: {}",
+                self.path, err
+            );
+        }
+    }
+}
+
+fn create_lock_file(path: &Path) -> Result<Option<FileLock>, ConfigureError> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(mut file) => {
+            // Only for someone wondering who left a lock behind – nothing reads it
+            let _ = writeln!(file, "{}", std::process::id());
+            debug!("Locked {:?}", path);
+            Ok(Some(FileLock {
+                path: path.to_path_buf(),
+            }))
+        }
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn is_stale(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map(|age| age >= STALE_LOCK_AGE)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_a_lock_can_only_be_held_once() {
+        let path = std::env::temp_dir()
+            .join(format!("configure-lock-test-{}", std::process::id()))
+            .join(".lock");
+
+        let lock = FileLock::try_acquire(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(FileLock::try_acquire(&path).unwrap().is_none());
+        assert!(matches!(
+            FileLock::acquire(&path, Duration::from_millis(300)),
+            Err(ConfigureError::LockNotAvailable { .. })
+        ));
+
+        drop(lock);
+        assert!(!path.exists());
+        assert!(FileLock::try_acquire(&path).unwrap().is_some());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
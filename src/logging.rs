@@ -0,0 +1,56 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+
+type LogHandler = Box<dyn Fn(Level, &str) + Send + Sync>;
+
+static LOG_HANDLER: Mutex<Option<LogHandler>> = Mutex::new(None);
+static LOGGER: CallbackLogger = CallbackLogger;
+
+/// A `log` implementation that forwards every record to the registered handler
+struct CallbackLogger;
+
+impl Log for CallbackLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if let Ok(handler) = LOG_HANDLER.lock() {
+            if let Some(handler) = handler.as_ref() {
+                handler(
+                    record.level(),
+                    &crate::redact::redact(&record.args().to_string()),
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Send the library's log messages to `handler` instead of printing them
+///
+/// Messages are redacted first, so the handler never sees anything that looks like a key.
+/// Embedders can use this to capture, filter, or silence the tool's output. If the host process has already
+/// installed its own `log` implementation, that logger keeps receiving messages and the handler is never called.
+///
+/// # Arguments
+///
+/// * `handler` - Called with the level and formatted message for each log record
+/// * `max_level` - The most verbose level that should be delivered to the handler
+pub fn set_log_handler<F>(handler: F, max_level: LevelFilter)
+where
+    F: Fn(Level, &str) + Send + Sync + 'static,
+{
+    if let Ok(mut current_handler) = LOG_HANDLER.lock() {
+        *current_handler = Some(Box::new(handler));
+    }
+
+    // This fails if a logger is already installed – there's nothing useful we can do about that
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(max_level);
+}
@@ -0,0 +1,383 @@
+//! Upgrading projects set up by older versions of this tool
+//!
+//! Older projects kept their encrypted files next to where they're decrypted, might have a `.configure` file that's
+//! missing fields or written differently, and often share a `keys.json` in the original flat format. A `Migration`
+//! describes what needs to change to bring a project up to date, so it can be previewed before anything is touched.
+
+use crate::fs::{
+    files_namespace_for, parse_configuration, read_configure_file_contents,
+    stage_signed_configuration,
+};
+use crate::keys::KeysFile;
+use crate::transaction::StagedChanges;
+use crate::{Configuration, ConfigureError};
+use log::debug;
+use similar::TextDiff;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Environment variables older versions read, and the ones that replace them
+const LEGACY_ENVIRONMENT_VARIABLES: [(&str, &str); 1] =
+    [(crate::TEMP_ENCRYPTION_KEY_NAME, crate::ENCRYPTION_KEY_NAME)];
+
+/// A single change needed to bring a project up to date
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MigrationStep {
+    /// Move an encrypted file from where an older version kept it into the configuration's directory in
+    /// `.configure-files`
+    MoveEncryptedFile { from: PathBuf, to: PathBuf },
+
+    /// Rewrite the `.configure` file in the current format
+    RewriteConfiguration {
+        path: PathBuf,
+        before: String,
+        after: String,
+    },
+
+    /// Switch `keys.json` to the structured format
+    UpgradeKeysFile { path: PathBuf },
+
+    /// An environment variable that's set under a name that's being phased out – this has to be changed by hand
+    RenameEnvironmentVariable {
+        from: &'static str,
+        to: &'static str,
+    },
+}
+
+impl MigrationStep {
+    /// Whether `apply` can make this change, or it's left for the user to do
+    pub fn is_automatic(&self) -> bool {
+        !matches!(self, MigrationStep::RenameEnvironmentVariable { .. })
+    }
+}
+
+impl fmt::Display for MigrationStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationStep::MoveEncryptedFile { from, to } => {
+                write!(f, "Move {} to {}", from.display(), to.display())
+            }
+            MigrationStep::RewriteConfiguration {
+                path,
+                before,
+                after,
+            } => {
+                writeln!(f, "Rewrite {}", path.display())?;
+                write!(
+                    f,
+                    "{}",
+                    TextDiff::from_lines(before, after)
+                        .unified_diff()
+                        .header("before", "after")
+                )
+            }
+            MigrationStep::UpgradeKeysFile { path } => {
+                write!(f, "Convert {} to the structured format", path.display())
+            }
+            MigrationStep::RenameEnvironmentVariable { from, to } => write!(
+                f,
+                "Rename the {} environment variable to {} wherever it's set (for example, in CI)",
+                from, to
+            ),
+        }
+    }
+}
+
+/// Everything needed to bring a project up to date
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Migration {
+    pub steps: Vec<MigrationStep>,
+}
+
+impl Migration {
+    /// Work out what needs to change, without changing anything
+    ///
+    /// # Arguments
+    ///
+    /// * `project_root` - The root of the project's git repo
+    /// * `configure_file` - The project's `.configure` file
+    /// * `keys_file` - The secrets repo's `keys.json`, if there's a secrets repo on this machine
+    pub fn plan(
+        project_root: &Path,
+        configure_file: &Path,
+        keys_file: Option<&Path>,
+    ) -> Result<Migration, ConfigureError> {
+        let before = read_configure_file_contents(configure_file)?;
+        let mut configuration = configuration_from_legacy_contents(configure_file, &before)?;
+        configuration.set_files_namespace(files_namespace_for(project_root, configure_file));
+        let after = configuration.to_json()?;
+
+        let mut steps = Vec::new();
+
+        for file in &configuration.files_to_copy {
+            let to = configuration.encrypted_destination_in(file, project_root);
+
+            // The oldest versions kept the file next to its destination, and later ones kept every `.configure` file's
+            // files in `.configure-files` itself
+            let from = vec![
+                file.get_legacy_encrypted_destination_in(project_root),
+                file.get_encrypted_destination_in(project_root),
+            ]
+            .into_iter()
+            .find(|from| from != &to && from.is_file());
+
+            // A file that's already been moved is left alone, even if an old copy was left behind
+            if let Some(from) = from.filter(|_| !to.exists()) {
+                steps.push(MigrationStep::MoveEncryptedFile { from, to });
+            }
+        }
+
+        if before.trim_end() != after {
+            steps.push(MigrationStep::RewriteConfiguration {
+                path: configure_file.to_path_buf(),
+                before,
+                after,
+            });
+        }
+
+        if let Some(keys_file) = keys_file.filter(|path| path.is_file()) {
+            if KeysFile::read(keys_file)?.is_legacy() {
+                steps.push(MigrationStep::UpgradeKeysFile {
+                    path: keys_file.to_path_buf(),
+                });
+            }
+        }
+
+        for (from, to) in LEGACY_ENVIRONMENT_VARIABLES {
+            if std::env::var_os(from).is_some() {
+                steps.push(MigrationStep::RenameEnvironmentVariable { from, to });
+            }
+        }
+
+        Ok(Migration { steps })
+    }
+
+    /// Whether the project is already up to date
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Make every automatic change
+    ///
+    /// New files are written together, and only once they're all in place are the old encrypted files removed – if
+    /// anything goes wrong before that, the project is left as it was.
+    pub fn apply(&self) -> Result<(), ConfigureError> {
+        let mut staged = StagedChanges::default();
+        let mut moved_files = Vec::new();
+
+        for step in &self.steps {
+            match step {
+                MigrationStep::MoveEncryptedFile { from, to } => {
+                    let contents = match std::fs::read(from) {
+                        Ok(contents) => contents,
+                        Err(_) => {
+                            return Err(ConfigureError::InputFileNotReadable { path: from.clone() })
+                        }
+                    };
+
+                    staged.stage(to.clone(), contents);
+                    moved_files.push(from);
+                }
+                MigrationStep::RewriteConfiguration { path, after, .. } => {
+                    let configuration = parse_configuration(path, after.clone())?;
+                    stage_signed_configuration(&configuration, path, &mut staged)?;
+                }
+                MigrationStep::UpgradeKeysFile { path } => {
+                    let mut keys_file = KeysFile::read(path)?;
+                    keys_file.upgrade();
+
+                    match keys_file.to_json() {
+                        Some(json) => staged.stage(path.clone(), json.into_bytes()),
+                        None => return Err(ConfigureError::KeysDataIsNotValid),
+                    }
+                }
+                MigrationStep::RenameEnvironmentVariable { .. } => (),
+            }
+        }
+
+        staged.commit()?;
+
+        for path in moved_files {
+            debug!("Removing {:?}", path);
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}", step)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read a `.configure` file written by any version of this tool
+///
+/// Early versions only wrote the fields they had values for, so any that are missing are filled in with their
+/// defaults rather than treated as invalid. Files that extend another one are newer than that, and are left to take
+/// the fields they leave out from it.
+fn configuration_from_legacy_contents(
+    configure_file: &Path,
+    contents: &str,
+) -> Result<Configuration, ConfigureError> {
+    let mut value: serde_json::Value = match serde_json::from_str(contents) {
+        Ok(value) => value,
+        Err(_) => return Err(ConfigureError::ConfigureFileNotValid),
+    };
+
+    if value.get("extends").is_some() {
+        return parse_configuration(configure_file, contents.to_string());
+    }
+
+    let fields = match value.as_object_mut() {
+        Some(fields) => fields,
+        None => return Err(ConfigureError::ConfigureFileNotValid),
+    };
+
+    for field in ["project_name", "branch", "pinned_hash"] {
+        fields
+            .entry(field)
+            .or_insert_with(|| serde_json::Value::String(String::new()));
+    }
+
+    fields
+        .entry("files_to_copy")
+        .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+
+    match serde_json::from_value(value) {
+        Ok(configuration) => Ok(configuration),
+        Err(_) => Err(ConfigureError::ConfigureFileNotValid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project_directory(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "configure-migrate-{}-test-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_that_missing_configuration_fields_are_filled_in() {
+        let configuration = configuration_from_legacy_contents(
+            Path::new(".configure"),
+            r#"{ "project_name": "Demo" }"#,
+        )
+        .unwrap();
+
+        assert_eq!(configuration.project_name, "Demo");
+        assert!(configuration.branch.is_empty());
+        assert!(configuration.files_to_copy.is_empty());
+    }
+
+    #[test]
+    fn test_that_an_up_to_date_project_needs_no_migration() {
+        let root = project_directory("up-to-date");
+        let configure_file = root.join(".configure");
+        std::fs::write(
+            &configure_file,
+            Configuration::default().to_string().unwrap(),
+        )
+        .unwrap();
+
+        let migration = Migration::plan(&root, &configure_file, None).unwrap();
+        assert!(migration.steps.iter().all(|step| !step.is_automatic()));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_that_encrypted_files_next_to_their_destination_are_moved() {
+        let root = project_directory("legacy-layout");
+        let configure_file = root.join(".configure");
+        std::fs::write(
+            &configure_file,
+            r#"{"project_name":"Demo","branch":"trunk","pinned_hash":"abc","files_to_copy":[{"file":"android/secrets.json","destination":"app/secrets.json"}]}"#,
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        std::fs::write(root.join("app/secrets.json.enc"), b"encrypted").unwrap();
+
+        let migration = Migration::plan(&root, &configure_file, None).unwrap();
+        assert!(migration.steps.contains(&MigrationStep::MoveEncryptedFile {
+            from: root.join("app/secrets.json.enc"),
+            to: root.join(".configure-files/secrets.json.enc"),
+        }));
+        assert!(migration
+            .steps
+            .iter()
+            .any(|step| matches!(step, MigrationStep::RewriteConfiguration { .. })));
+
+        migration.apply().unwrap();
+
+        assert!(!root.join("app/secrets.json.enc").exists());
+        assert_eq!(
+            std::fs::read(root.join(".configure-files/secrets.json.enc")).unwrap(),
+            b"encrypted"
+        );
+
+        let migrated = Migration::plan(&root, &configure_file, None).unwrap();
+        assert!(migrated.steps.iter().all(|step| !step.is_automatic()));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_that_a_modules_encrypted_files_are_moved_into_its_own_directory() {
+        let root = project_directory("module-layout");
+        let module = root.join("android");
+        std::fs::create_dir_all(&module).unwrap();
+        let configure_file = module.join(".configure");
+        std::fs::write(
+            &configure_file,
+            r#"{"project_name":"Demo","branch":"trunk","pinned_hash":"abc","files_to_copy":[{"file":"android/secrets.json","destination":"app/secrets.json"}]}"#,
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join(".configure-files")).unwrap();
+        std::fs::write(root.join(".configure-files/secrets.json.enc"), b"encrypted").unwrap();
+
+        let migration = Migration::plan(&root, &configure_file, None).unwrap();
+        assert!(migration.steps.contains(&MigrationStep::MoveEncryptedFile {
+            from: root.join(".configure-files/secrets.json.enc"),
+            to: root.join(".configure-files/android/secrets.json.enc"),
+        }));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_that_flat_keys_files_are_upgraded() {
+        let root = project_directory("flat-keys");
+        let configure_file = root.join(".configure");
+        std::fs::write(
+            &configure_file,
+            Configuration::default().to_string().unwrap(),
+        )
+        .unwrap();
+        let keys_file = root.join("keys.json");
+        std::fs::write(&keys_file, r#"{ "Demo": "a2V5" }"#).unwrap();
+
+        let migration = Migration::plan(&root, &configure_file, Some(&keys_file)).unwrap();
+        assert!(migration.steps.contains(&MigrationStep::UpgradeKeysFile {
+            path: keys_file.clone()
+        }));
+
+        migration.apply().unwrap();
+        assert!(!KeysFile::read(&keys_file).unwrap().is_legacy());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
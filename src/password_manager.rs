@@ -0,0 +1,137 @@
+//! Built-in key providers that read project keys from 1Password or Bitwarden
+//!
+//! A project uses one with `"key_provider": "1password"` or `"key_provider": "bitwarden"` in its `.configure` file,
+//! along with a `key_reference` saying where the key is. The key is read with the password manager's command line
+//! tool, which has to be signed in already:
+//!
+//! * 1Password – `op read`, so the reference is a secret reference like `op://Mobile/WordPress-Android/password`.
+//!   `OP_SERVICE_ACCOUNT_TOKEN` works too, for CI.
+//! * Bitwarden – `bw get password`, so the reference is an item's ID or name. The session is taken from `BW_SESSION`.
+//!
+//! When the tool isn't installed, the key is looked for in `keys.json` as usual.
+
+use crate::encryption::EncryptionKey;
+use crate::plugins::KeyProvider;
+use crate::ConfigureError;
+use log::{debug, info};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PasswordManager {
+    OnePassword,
+    Bitwarden,
+}
+
+impl PasswordManager {
+    /// The password manager a `.configure` file's `key_provider` names, if it's one of these
+    pub fn for_provider_name(name: &str) -> Option<PasswordManager> {
+        match name {
+            "1password" => Some(PasswordManager::OnePassword),
+            "bitwarden" => Some(PasswordManager::Bitwarden),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            PasswordManager::OnePassword => "1Password",
+            PasswordManager::Bitwarden => "Bitwarden",
+        }
+    }
+
+    fn command(&self, reference: &str) -> Command {
+        match self {
+            PasswordManager::OnePassword => {
+                let mut command = Command::new("op");
+                command.arg("read").arg("--no-newline").arg(reference);
+                command
+            }
+            PasswordManager::Bitwarden => {
+                let mut command = Command::new("bw");
+                command
+                    .arg("get")
+                    .arg("password")
+                    .arg(reference)
+                    .arg("--nointeraction");
+                command
+            }
+        }
+    }
+}
+
+/// Reads a project's key from a password manager – see the module documentation for how it's set up
+#[derive(Debug, Clone)]
+pub struct PasswordManagerKeyProvider {
+    pub manager: PasswordManager,
+
+    /// Where the key is, in the password manager's own terms
+    pub reference: String,
+}
+
+impl KeyProvider for PasswordManagerKeyProvider {
+    fn key(&self, key_name: &str) -> Result<Option<EncryptionKey>, ConfigureError> {
+        debug!(
+            "Reading {}'s key from {} at {}",
+            key_name,
+            self.manager.name(),
+            self.reference
+        );
+
+        let output = match self
+            .manager
+            .command(&self.reference)
+            .stdin(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!(
+                    "The {} command line tool isn't installed, so it isn't asked for {}'s key",
+                    self.manager.name(),
+                    key_name
+                );
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let stdout = Zeroizing::new(output.stdout);
+        if !output.status.success() {
+            return Err(ConfigureError::KeyProviderFailed {
+                provider: self.manager.name().to_string(),
+                reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+
+        info!(
+            "Using the key for {} from {}",
+            key_name,
+            self.manager.name()
+        );
+        let key = String::from_utf8_lossy(&stdout);
+        Ok(Some(EncryptionKey::from_str(key.trim())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_password_managers_are_found_by_provider_name() {
+        assert_eq!(
+            PasswordManager::for_provider_name("1password"),
+            Some(PasswordManager::OnePassword)
+        );
+        assert_eq!(
+            PasswordManager::for_provider_name("bitwarden"),
+            Some(PasswordManager::Bitwarden)
+        );
+        assert_eq!(PasswordManager::for_provider_name("vault"), None);
+
+        let command = PasswordManager::OnePassword.command("op://Mobile/example/password");
+        assert_eq!(command.get_program(), "op");
+    }
+}
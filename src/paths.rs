@@ -0,0 +1,113 @@
+//! Path handling that works the same way on Windows as on macOS and Linux
+//!
+//! `.configure` files always use `/` between path components, so they can be shared between platforms – Rust's
+//! `Path` accepts that on Windows too. What it doesn't smooth over is covered here.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The absolute form of `path`, in a form other programs can use
+///
+/// On Windows, `std::fs::canonicalize` returns extended-length paths like `\\?\C:\Users\…`, which `git` and many
+/// other tools don't understand. These are turned back into regular paths whenever that's possible.
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    dunce::canonicalize(path)
+}
+
+/// `path` with `extension` added after any extension it already has – `secrets.json` becomes `secrets.json.enc`
+///
+/// This works with the `OsStr` directly, so file names that aren't valid UTF-8 are kept as they are.
+pub fn add_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path: OsString = path.as_os_str().to_os_string();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Whether two paths point at the same file, once they're made absolute – `false` if either doesn't exist
+pub fn same_file(path: &Path, other: &Path) -> bool {
+    match (canonicalize(path), canonicalize(other)) {
+        (Ok(path), Ok(other)) => path == other,
+        _ => false,
+    }
+}
+
+/// The directory containing `path` – a relative path with no directory is in the current one, not the root
+pub fn parent_directory(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+/// `path` as it should be written to a `.configure` file, with `/` between its components
+///
+/// On Windows, paths typed with `\` are converted. Elsewhere `\` is a valid character in a file name, so it's left
+/// alone.
+pub fn portable(path: &str) -> String {
+    if cfg!(windows) {
+        path.replace('\\', "/")
+    } else {
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_add_extension_keeps_existing_extensions() {
+        assert_eq!(
+            add_extension(Path::new("config/secrets.json"), "enc"),
+            Path::new("config/secrets.json.enc")
+        );
+    }
+
+    #[test]
+    fn test_that_add_extension_works_for_files_without_an_extension() {
+        assert_eq!(
+            add_extension(Path::new("Gemfile"), "enc"),
+            Path::new("Gemfile.enc")
+        );
+    }
+
+    #[test]
+    fn test_that_parent_directory_of_a_bare_file_name_is_relative() {
+        assert_eq!(parent_directory(Path::new("file.txt")), Path::new(""));
+        assert_eq!(parent_directory(Path::new("a/file.txt")), Path::new("a"));
+    }
+
+    #[test]
+    fn test_that_canonicalize_returns_an_absolute_path() {
+        let path = canonicalize(Path::new(".")).unwrap();
+        assert!(path.is_absolute());
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_that_portable_paths_use_forward_slashes() {
+        assert_eq!(portable(r"config\secrets.json"), "config/secrets.json");
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_that_portable_paths_keep_backslashes_in_file_names() {
+        assert_eq!(portable(r"config\secrets.json"), r"config\secrets.json");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_that_canonicalize_does_not_return_extended_length_paths() {
+        let path = canonicalize(Path::new(".")).unwrap();
+        assert!(!path.to_string_lossy().starts_with(r"\\?\"));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_that_parent_directory_works_with_drive_letters() {
+        assert_eq!(parent_directory(Path::new(r"C:\")), Path::new(""));
+        assert_eq!(
+            parent_directory(Path::new(r"C:\secrets.json")),
+            Path::new(r"C:\")
+        );
+    }
+}
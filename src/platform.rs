@@ -0,0 +1,247 @@
+//! Recognising Android and iOS projects, so `init` can suggest the secrets they usually need
+//!
+//! The suggestions are only a starting point – `init` asks before adding each one, and lets the user change both
+//! paths first.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Platform {
+    /// Found by a `settings.gradle` (or `settings.gradle.kts`) at the project root
+    Android,
+    /// Found by an `.xcodeproj` at the project root, or in one of its directories
+    Ios,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Platform::Android => write!(f, "Android"),
+            Platform::Ios => write!(f, "iOS"),
+        }
+    }
+}
+
+/// A file the project probably needs, with paths using `/` like the ones in `.configure`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SuggestedFile {
+    pub platform: Platform,
+
+    /// Where the file would be in the secrets repo
+    pub source: String,
+
+    /// Where it would be decrypted to in the project
+    pub destination: String,
+}
+
+/// The platforms the project at `project_root` is built for – a monorepo can have both
+pub fn detect_platforms(project_root: &Path) -> Vec<Platform> {
+    let mut platforms = Vec::new();
+
+    if is_android_project(project_root) {
+        platforms.push(Platform::Android);
+    }
+
+    if find_xcode_project(project_root).is_some() {
+        platforms.push(Platform::Ios);
+    }
+
+    platforms
+}
+
+/// The usual secrets for each platform the project is built for
+///
+/// Sources are suggested under `<platform>/<project_name>/` in the secrets repo.
+pub fn suggested_files(project_root: &Path, project_name: &str) -> Vec<SuggestedFile> {
+    let mut suggestions = Vec::new();
+
+    if is_android_project(project_root) {
+        let module = android_app_module(project_root);
+
+        for (file_name, destination) in [
+            (
+                "google-services.json",
+                join(&module, "google-services.json"),
+            ),
+            ("sentry.properties", "sentry.properties".to_string()),
+        ] {
+            suggestions.push(SuggestedFile {
+                platform: Platform::Android,
+                source: secrets_path("android", project_name, file_name),
+                destination,
+            });
+        }
+    }
+
+    if let Some((directory, app_name)) = find_xcode_project(project_root) {
+        for (file_name, destination) in [
+            (
+                "Secrets.xcconfig",
+                join(&directory, "Config/Secrets.xcconfig"),
+            ),
+            (
+                "GoogleService-Info.plist",
+                join(&join(&directory, &app_name), "GoogleService-Info.plist"),
+            ),
+            ("sentry.properties", join(&directory, "sentry.properties")),
+        ] {
+            suggestions.push(SuggestedFile {
+                platform: Platform::Ios,
+                source: secrets_path("ios", project_name, file_name),
+                destination,
+            });
+        }
+    }
+
+    suggestions
+}
+
+fn is_android_project(project_root: &Path) -> bool {
+    ["settings.gradle", "settings.gradle.kts"]
+        .iter()
+        .any(|file_name| project_root.join(file_name).is_file())
+}
+
+/// The module that builds the app – the first one applying the Android application plugin, or `app` by convention
+fn android_app_module(project_root: &Path) -> String {
+    let applies_application_plugin = |module: &Path| {
+        ["build.gradle", "build.gradle.kts"]
+            .iter()
+            .any(|file_name| {
+                std::fs::read_to_string(module.join(file_name))
+                    .map(|contents| contents.contains("com.android.application"))
+                    .unwrap_or(false)
+            })
+    };
+
+    sorted_directories(project_root)
+        .into_iter()
+        .find(|(_, path)| applies_application_plugin(path))
+        .map(|(name, _)| name)
+        .unwrap_or_else(|| "app".to_string())
+}
+
+/// The directory the Xcode project is in (relative to `project_root`, empty for the root itself) and the name of its
+/// app, taken from the project's name
+fn find_xcode_project(project_root: &Path) -> Option<(String, String)> {
+    let project_in = |directory: &Path| {
+        sorted_entries(directory)
+            .into_iter()
+            .find(|(name, path)| name.ends_with(".xcodeproj") && path.is_dir())
+            .map(|(name, _)| name.trim_end_matches(".xcodeproj").to_string())
+    };
+
+    if let Some(app_name) = project_in(project_root) {
+        return Some((String::new(), app_name));
+    }
+
+    sorted_directories(project_root)
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with('.'))
+        .find_map(|(name, path)| project_in(&path).map(|app_name| (name, app_name)))
+}
+
+/// The entries in `directory` by name, so the same project is found every time
+fn sorted_entries(directory: &Path) -> Vec<(String, PathBuf)> {
+    let mut entries: Vec<(String, PathBuf)> = match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path(),
+                )
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    entries.sort();
+    entries
+}
+
+fn sorted_directories(directory: &Path) -> Vec<(String, PathBuf)> {
+    sorted_entries(directory)
+        .into_iter()
+        .filter(|(_, path)| path.is_dir())
+        .collect()
+}
+
+fn secrets_path(platform_directory: &str, project_name: &str, file_name: &str) -> String {
+    join(&join(platform_directory, project_name), file_name)
+}
+
+/// Join two `/`-separated paths, either of which may be empty
+fn join(directory: &str, path: &str) -> String {
+    match (directory.is_empty(), path.is_empty()) {
+        (true, _) => path.to_string(),
+        (_, true) => directory.to_string(),
+        _ => format!("{}/{}", directory, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project_directory(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "configure-platform-{}-test-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_that_android_projects_are_detected() {
+        let root = project_directory("android");
+        std::fs::write(root.join("settings.gradle.kts"), "").unwrap();
+        std::fs::create_dir_all(root.join("WordPress")).unwrap();
+        std::fs::write(
+            root.join("WordPress/build.gradle"),
+            "plugins { id 'com.android.application' }",
+        )
+        .unwrap();
+
+        assert_eq!(detect_platforms(&root), vec![Platform::Android]);
+        assert!(
+            suggested_files(&root, "WordPress").contains(&SuggestedFile {
+                platform: Platform::Android,
+                source: "android/WordPress/google-services.json".to_string(),
+                destination: "WordPress/google-services.json".to_string(),
+            })
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_that_ios_projects_are_detected_one_level_down() {
+        let root = project_directory("ios");
+        std::fs::create_dir_all(root.join("ios/Demo.xcodeproj")).unwrap();
+
+        assert_eq!(detect_platforms(&root), vec![Platform::Ios]);
+        assert!(suggested_files(&root, "Demo").contains(&SuggestedFile {
+            platform: Platform::Ios,
+            source: "ios/Demo/GoogleService-Info.plist".to_string(),
+            destination: "ios/Demo/GoogleService-Info.plist".to_string(),
+        }));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_that_other_projects_have_no_suggestions() {
+        let root = project_directory("other");
+        std::fs::write(root.join("Cargo.toml"), "").unwrap();
+
+        assert!(detect_platforms(&root).is_empty());
+        assert!(suggested_files(&root, "Demo").is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! Key providers and file processors, so projects can plug in their own secret backends and file transforms
+//!
+//! A `.configure` file names a `key_provider` to get its key from instead of `keys.json`, and each file can list
+//! `processors` that its decrypted contents go through, in order, before they're written to its destination. A name
+//! refers to a plugin registered by the program embedding this crate, or else to an external command in the
+//! `.configure` file's `plugins`. `vault`, `1password` and `bitwarden` are built in, as key providers – see the
+//! `vault` and `password_manager` modules.
+//!
+//! External commands are run from the project root:
+//!
+//! - A key provider gets the key's name in `CONFIGURE_KEY_NAME`, and prints the key – or nothing, if it doesn't have
+//!   one, in which case `keys.json` is used.
+//! - A file processor gets the decrypted contents on stdin and the file's destination in `CONFIGURE_DESTINATION`, and
+//!   prints the contents to write.
+
+use crate::configure::{Configuration, File, Plugin};
+use crate::encryption::EncryptionKey;
+use crate::fs::find_project_root;
+use crate::password_manager::{PasswordManager, PasswordManagerKeyProvider};
+use crate::vault::{VaultKeyProvider, VAULT_PROVIDER_NAME};
+use crate::ConfigureError;
+use log::debug;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
+
+/// The environment variable an external key provider finds the key's name in
+pub const KEY_NAME_VARIABLE: &str = "CONFIGURE_KEY_NAME";
+
+/// The environment variable an external file processor finds the file's destination in
+pub const DESTINATION_VARIABLE: &str = "CONFIGURE_DESTINATION";
+
+/// Somewhere other than `keys.json` to get a project's key from, like Vault
+pub trait KeyProvider: Send + Sync {
+    /// The key stored under `key_name`, or `None` if this provider doesn't have one
+    fn key(&self, key_name: &str) -> Result<Option<EncryptionKey>, ConfigureError>;
+}
+
+/// A change to a file's decrypted contents before they're written, like merging a plist with one in the project
+pub trait FileProcessor: Send + Sync {
+    /// The contents to write to `destination` (relative to the project root) in place of `contents`
+    fn process(&self, destination: &str, contents: Vec<u8>) -> Result<Vec<u8>, ConfigureError>;
+}
+
+struct Registry {
+    key_providers: BTreeMap<String, Arc<dyn KeyProvider>>,
+    file_processors: BTreeMap<String, Arc<dyn FileProcessor>>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    key_providers: BTreeMap::new(),
+    file_processors: BTreeMap::new(),
+});
+
+/// Make `provider` available to `.configure` files as the `key_provider` called `name`
+///
+/// A registered provider is used instead of an external command with the same name.
+pub fn register_key_provider<P>(name: &str, provider: P)
+where
+    P: KeyProvider + 'static,
+{
+    let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    registry
+        .key_providers
+        .insert(name.to_string(), Arc::new(provider));
+}
+
+/// Make `processor` available to `.configure` files as the file processor called `name`
+///
+/// A registered processor is used instead of an external command with the same name.
+pub fn register_file_processor<P>(name: &str, processor: P)
+where
+    P: FileProcessor + 'static,
+{
+    let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    registry
+        .file_processors
+        .insert(name.to_string(), Arc::new(processor));
+}
+
+/// A plugin that runs a command – what the `plugins` in a `.configure` file become
+struct ExternalCommand {
+    name: String,
+    command: Vec<String>,
+}
+
+impl ExternalCommand {
+    fn run(&self, variable: (&str, &str), input: Option<&[u8]>) -> Result<Vec<u8>, ConfigureError> {
+        let failed = || ConfigureError::PluginFailed {
+            plugin: self.name.clone(),
+        };
+
+        let (program, arguments) = self.command.split_first().ok_or_else(failed)?;
+
+        debug!("Running the {:?} plugin: {:?}", self.name, self.command);
+        let mut child = Command::new(program)
+            .args(arguments)
+            .env(variable.0, variable.1)
+            .current_dir(find_project_root()?)
+            .stdin(match input {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                debug!("Unable to start {}: {}", program, err);
+                failed()
+            })?;
+
+        // Written from another thread, so a command that prints as it reads can't fill its stdout and stall
+        let writer = match (input, child.stdin.take()) {
+            (Some(input), Some(mut stdin)) => {
+                let input = Zeroizing::new(input.to_vec());
+                Some(std::thread::spawn(move || stdin.write_all(&input)))
+            }
+            _ => None,
+        };
+
+        let output = child.wait_with_output()?;
+        if let Some(writer) = writer {
+            // A command that exits without reading everything closes the pipe early – its exit status says whether
+            // that's a problem
+            if let Ok(Err(err)) = writer.join() {
+                debug!(
+                    "The {:?} plugin didn't read all its input: {}",
+                    self.name, err
+                );
+            }
+        }
+
+        match output.status.success() {
+            true => Ok(output.stdout),
+            false => Err(failed()),
+        }
+    }
+}
+
+impl KeyProvider for ExternalCommand {
+    fn key(&self, key_name: &str) -> Result<Option<EncryptionKey>, ConfigureError> {
+        let output = Zeroizing::new(self.run((KEY_NAME_VARIABLE, key_name), None)?);
+        let key = String::from_utf8_lossy(&output);
+
+        match key.trim() {
+            "" => Ok(None),
+            key => Ok(Some(EncryptionKey::from_str(key)?)),
+        }
+    }
+}
+
+impl FileProcessor for ExternalCommand {
+    fn process(&self, destination: &str, contents: Vec<u8>) -> Result<Vec<u8>, ConfigureError> {
+        let contents = Zeroizing::new(contents);
+        self.run((DESTINATION_VARIABLE, destination), Some(&contents))
+    }
+}
+
+fn external_command(configuration: &Configuration, name: &str) -> Option<ExternalCommand> {
+    configuration
+        .plugins
+        .get(name)
+        .map(|Plugin { command }| ExternalCommand {
+            name: name.to_string(),
+            command: command.clone(),
+        })
+}
+
+/// The key provider called `name` – a registered one, then one of `configuration`'s external commands, then a
+/// built-in one
+pub fn key_provider(
+    configuration: &Configuration,
+    name: &str,
+) -> Result<Arc<dyn KeyProvider>, ConfigureError> {
+    let registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+
+    if let Some(provider) = registry.key_providers.get(name) {
+        return Ok(provider.clone());
+    }
+
+    if let Some(command) = external_command(configuration, name) {
+        return Ok(Arc::new(command));
+    }
+
+    if let Some(manager) = PasswordManager::for_provider_name(name) {
+        let reference = configuration.key_reference.clone().ok_or_else(|| {
+            ConfigureError::KeyReferenceMissing {
+                provider: name.to_string(),
+            }
+        })?;
+
+        return Ok(Arc::new(PasswordManagerKeyProvider { manager, reference }));
+    }
+
+    match name {
+        VAULT_PROVIDER_NAME => Ok(Arc::new(VaultKeyProvider)),
+        _ => Err(ConfigureError::PluginNotFound {
+            plugin: name.to_string(),
+        }),
+    }
+}
+
+/// The file processor called `name` – a registered one, or else one of `configuration`'s external commands
+pub fn file_processor(
+    configuration: &Configuration,
+    name: &str,
+) -> Result<Arc<dyn FileProcessor>, ConfigureError> {
+    let registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+
+    if let Some(processor) = registry.file_processors.get(name) {
+        return Ok(processor.clone());
+    }
+
+    match external_command(configuration, name) {
+        Some(command) => Ok(Arc::new(command)),
+        None => Err(ConfigureError::PluginNotFound {
+            plugin: name.to_string(),
+        }),
+    }
+}
+
+/// `contents`, after going through each of `file`'s processors in turn
+pub fn process_file(
+    configuration: &Configuration,
+    file: &File,
+    contents: Vec<u8>,
+) -> Result<Vec<u8>, ConfigureError> {
+    file.processors.iter().try_fold(contents, |contents, name| {
+        debug!("Processing {} with {:?}", file.destination, name);
+        file_processor(configuration, name)?.process(&file.destination, contents)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Uppercase;
+
+    impl FileProcessor for Uppercase {
+        fn process(&self, _: &str, contents: Vec<u8>) -> Result<Vec<u8>, ConfigureError> {
+            Ok(contents.to_ascii_uppercase())
+        }
+    }
+
+    #[test]
+    fn test_that_files_go_through_each_registered_processor() {
+        register_file_processor("test-uppercase", Uppercase);
+
+        let file = File {
+            source: "a.txt".to_string(),
+            destination: "a.txt".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: vec!["test-uppercase".to_string()],
+        };
+
+        assert_eq!(
+            process_file(&Configuration::default(), &file, b"secret".to_vec()).unwrap(),
+            b"SECRET".to_vec()
+        );
+
+        let file = File {
+            processors: vec!["missing".to_string()],
+            ..file
+        };
+        assert!(matches!(
+            process_file(&Configuration::default(), &file, Vec::new()),
+            Err(ConfigureError::PluginNotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::fs::find_secrets_repo;
+use crate::git::SecretsRepo;
+use crate::ConfigureError;
+use log::{debug, info};
+use std::path::{Component, Path, PathBuf};
+
+/// The commit message used when the caller doesn't provide one
+pub const DEFAULT_COMMIT_MESSAGE: &str = "{action} {path}";
+
+/// Options that control what `push_secret` does with the secrets repo after copying a file into it
+#[derive(Debug, Clone, Default)]
+pub struct PushSecretOptions {
+    /// Create this branch (from the current commit) and switch to it before copying the file
+    pub branch: Option<String>,
+
+    /// Commit the file once it's copied
+    pub commit: bool,
+
+    /// The commit message. `{action}` is replaced with "Add" or "Update", `{path}` with the file's path in the
+    /// secrets repo, and `{file}` with its file name. If this is `None`, `DEFAULT_COMMIT_MESSAGE` is used.
+    pub message: Option<String>,
+}
+
+/// Copy `local_file` into the secrets repo at `repo_path`, returning the new commit hash if one was made
+pub fn push_secret(
+    local_file: &Path,
+    repo_path: &str,
+    options: &PushSecretOptions,
+) -> Result<Option<String>, ConfigureError> {
+    let relative_path = validate_secret_path(repo_path)?;
+
+    if !local_file.is_file() {
+        return Err(ConfigureError::InputFileNotReadable {
+            path: local_file.to_path_buf(),
+        });
+    }
+
+    let secrets_root = find_secrets_repo()?;
+    let repo = SecretsRepo {
+        path: secrets_root.clone(),
+    };
+
+    if let Some(branch) = &options.branch {
+        info!("Creating secrets branch {:?}", branch);
+        repo.create_and_switch_to_branch(branch)?;
+    }
+
+    let destination = secrets_root.join(&relative_path);
+    let action = if destination.exists() {
+        "Update"
+    } else {
+        "Add"
+    };
+
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    debug!("Copying {:?} to {:?}", local_file, destination);
+    if std::fs::copy(local_file, &destination).is_err() {
+        return Err(ConfigureError::OutputFileNotWritable { path: destination });
+    }
+
+    info!(
+        "Copied {:?} into the secrets repo at {:?}",
+        local_file, repo_path
+    );
+
+    if !options.commit {
+        return Ok(None);
+    }
+
+    let template = options.message.as_deref().unwrap_or(DEFAULT_COMMIT_MESSAGE);
+    let message = render_commit_message(template, action, &relative_path);
+
+    let hash = repo.commit_paths(&[&relative_path], &message)?;
+    info!("Committed {:?} to the secrets repo as {}", message, hash);
+
+    Ok(Some(hash))
+}
+
+/// Make sure `repo_path` stays inside the secrets repo, and normalize it for use with git
+fn validate_secret_path(repo_path: &str) -> Result<PathBuf, ConfigureError> {
+    let path = Path::new(repo_path);
+
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            _ => return Err(ConfigureError::SecretPathNotValid),
+        }
+    }
+
+    // Writing into git's own data would corrupt the repo
+    if normalized.as_os_str().is_empty() || normalized.starts_with(".git") {
+        return Err(ConfigureError::SecretPathNotValid);
+    }
+
+    Ok(normalized)
+}
+
+fn render_commit_message(template: &str, action: &str, relative_path: &Path) -> String {
+    let path = relative_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let file = relative_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    template
+        .replace("{action}", action)
+        .replace("{path}", &path)
+        .replace("{file}", &file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_secret_paths_are_normalized() {
+        assert_eq!(
+            validate_secret_path("./android/secrets.json").unwrap(),
+            PathBuf::from("android/secrets.json")
+        )
+    }
+
+    #[test]
+    fn test_that_secret_paths_cannot_leave_the_secrets_repo() {
+        assert!(validate_secret_path("../secrets.json").is_err());
+        assert!(validate_secret_path("android/../../secrets.json").is_err());
+        assert!(validate_secret_path("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_that_secret_paths_cannot_point_into_git_directory() {
+        assert!(validate_secret_path(".git/config").is_err());
+        assert!(validate_secret_path("").is_err());
+    }
+
+    #[test]
+    fn test_that_commit_message_template_is_filled_in() {
+        assert_eq!(
+            render_commit_message(
+                "{action} {file} ({path})",
+                "Add",
+                Path::new("android/secrets.json")
+            ),
+            "Add secrets.json (android/secrets.json)"
+        )
+    }
+}
@@ -0,0 +1,197 @@
+//! Keeping secrets out of log messages and panics
+//!
+//! Anything that looks like an encryption key (a base64-encoded 32-byte value) is masked, along with any passphrase
+//! that's been used during this run. Decrypted contents are never logged in the first place, so there's nothing here
+//! to catch them.
+
+use log::{Log, Metadata, Record};
+use std::borrow::Cow;
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+const MASK: &str = "****";
+
+/// The length of a base64-encoded 32-byte key, including its single `=` of padding
+const ENCODED_KEY_LENGTH: usize = 44;
+
+static SECRETS: Mutex<Vec<Zeroizing<String>>> = Mutex::new(Vec::new());
+
+/// Secrets shorter than this aren't masked – hiding every `a` in a message would make it unreadable
+const MIN_SECRET_LENGTH: usize = 6;
+
+/// Mask `secret` wherever it appears in later messages – for secrets that can't be recognised by their shape
+pub fn add_secret(secret: &str) {
+    if secret.len() < MIN_SECRET_LENGTH {
+        return;
+    }
+
+    if let Ok(mut secrets) = SECRETS.lock() {
+        if !secrets.iter().any(|existing| existing.as_str() == secret) {
+            secrets.push(Zeroizing::new(secret.to_string()));
+        }
+    }
+}
+
+/// `message`, with anything that looks like a key and every secret added with `add_secret` masked
+pub fn redact(message: &str) -> Cow<'_, str> {
+    let mut redacted = Cow::Borrowed(message);
+
+    if let Ok(secrets) = SECRETS.lock() {
+        for secret in secrets.iter() {
+            if redacted.contains(secret.as_str()) {
+                redacted = Cow::Owned(redacted.replace(secret.as_str(), MASK));
+            }
+        }
+    }
+
+    if contains_encoded_key(&redacted) {
+        redacted = Cow::Owned(mask_encoded_keys(&redacted));
+    }
+
+    redacted
+}
+
+fn is_base64(character: char) -> bool {
+    character.is_ascii_alphanumeric() || character == '+' || character == '/'
+}
+
+/// The runs of base64 characters in `message` that could be a whole key, as byte ranges
+fn encoded_keys(message: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut start = None;
+
+    message
+        .char_indices()
+        .chain(std::iter::once((message.len(), ' ')))
+        .filter_map(move |(index, character)| {
+            if is_base64(character) {
+                start.get_or_insert(index);
+                return None;
+            }
+
+            let run_start = start.take()?;
+
+            // A key is 43 base64 characters followed by `=`
+            if character == '=' && index - run_start == ENCODED_KEY_LENGTH - 1 {
+                Some((run_start, index + 1))
+            } else {
+                None
+            }
+        })
+}
+
+fn contains_encoded_key(message: &str) -> bool {
+    encoded_keys(message).next().is_some()
+}
+
+fn mask_encoded_keys(message: &str) -> String {
+    let mut masked = String::with_capacity(message.len());
+    let mut copied_up_to = 0;
+
+    for (start, end) in encoded_keys(message) {
+        masked.push_str(&message[copied_up_to..start]);
+        masked.push_str(MASK);
+        copied_up_to = end;
+    }
+
+    masked.push_str(&message[copied_up_to..]);
+    masked
+}
+
+/// A logger that redacts every message before passing it on
+pub struct RedactingLogger<L: Log> {
+    inner: L,
+}
+
+impl<L: Log> RedactingLogger<L> {
+    pub fn new(inner: L) -> RedactingLogger<L> {
+        RedactingLogger { inner }
+    }
+}
+
+impl<L: Log> Log for RedactingLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = record.args().to_string();
+
+        self.inner.log(
+            &Record::builder()
+                .args(format_args!("{}", redact(&message)))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Redact panic messages before they're printed, so a key in an `expect` message can't end up in a build log
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => "Box<dyn Any>".to_string(),
+            },
+        };
+
+        let location = match info.location() {
+            Some(location) => format!(" at {}:{}", location.file(), location.line()),
+            None => String::new(),
+        };
+
+        eprintln!("configure panicked{}: {}", location, redact(&message));
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "ls0e2Ff5B2ihUa5xIo1Yh2Pp4BnXUp1dpFU5OnqXeUw=";
+
+    #[test]
+    fn test_that_keys_are_masked() {
+        assert_eq!(
+            redact(&format!("Using autogenerated key {}.", KEY)),
+            "Using autogenerated key ****."
+        );
+        assert_eq!(
+            redact(&format!("{{\"demo\":\"{}\"}}", KEY)),
+            "{\"demo\":\"****\"}"
+        );
+    }
+
+    #[test]
+    fn test_that_other_messages_are_left_alone() {
+        assert!(matches!(
+            redact("Decrypting android/secrets.json.enc"),
+            Cow::Borrowed(_)
+        ));
+
+        // Commit hashes and shorter base64 values aren't keys
+        let message = "Pinned to 8c3b5f6bd86d4e19b57a2f2a3e0b6fa8e8d1c2a4 (abc=)";
+        assert_eq!(redact(message), message);
+    }
+
+    #[test]
+    fn test_that_added_secrets_are_masked() {
+        add_secret("correct horse battery staple");
+        assert_eq!(
+            redact("The passphrase is correct horse battery staple"),
+            "The passphrase is ****"
+        );
+    }
+}
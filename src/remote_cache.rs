@@ -0,0 +1,121 @@
+//! Remembering what the secrets server said its branches point to
+//!
+//! A build that runs `configure` once per module would otherwise ask the server the same question over and over. The
+//! answers are kept in the secrets repo's git directory for a short while (`NetworkPolicy::cache_ttl`), so only the
+//! first lookup goes over the network.
+
+use crate::transaction::write_atomically;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turn the cache off for this process, so every lookup asks the server
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+struct CachedHead {
+    hash: String,
+
+    /// When the server was asked, in seconds since the Unix epoch
+    looked_up_at: u64,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RemoteHeadsCache {
+    #[serde(skip)]
+    path: PathBuf,
+
+    heads: BTreeMap<String, CachedHead>,
+}
+
+impl RemoteHeadsCache {
+    /// The cache kept in `git_dir`, which is empty if it hasn't been written yet or can't be read
+    pub fn read_from(git_dir: &Path) -> RemoteHeadsCache {
+        let path = git_dir.join("configure").join("remote-heads.json");
+
+        let mut cache: RemoteHeadsCache = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        cache.path = path;
+        cache
+    }
+
+    /// The hash `branch_name` pointed to on the server, if it was looked up less than `ttl` ago
+    pub fn get(&self, branch_name: &str, ttl: Duration) -> Option<&str> {
+        if !ENABLED.load(Ordering::Relaxed) || ttl.is_zero() {
+            return None;
+        }
+
+        let head = self.heads.get(branch_name)?;
+        let age = now().saturating_sub(head.looked_up_at);
+
+        if age < ttl.as_secs() {
+            debug!(
+                "Using the cached hash for {:?} from {}s ago",
+                branch_name, age
+            );
+            Some(&head.hash)
+        } else {
+            None
+        }
+    }
+
+    /// Remember what the server said `branch_name` points to
+    ///
+    /// Failing to write the cache isn't an error – the next lookup just asks the server again. Another process
+    /// updating at the same time can't leave it half-written, though one of the two answers may be lost.
+    pub fn insert(&mut self, branch_name: &str, hash: &str) {
+        self.heads.insert(
+            branch_name.to_string(),
+            CachedHead {
+                hash: hash.to_string(),
+                looked_up_at: now(),
+            },
+        );
+
+        let json = serde_json::to_string_pretty(self).unwrap_or_default();
+
+        if let Err(err) = write_atomically(&self.path, json.as_bytes()) {
+            debug!("Unable to write the remote heads cache: {}", err);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_cached_heads_expire() {
+        let git_dir = std::env::temp_dir().join(format!(
+            "configure-remote-cache-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&git_dir);
+
+        RemoteHeadsCache::read_from(&git_dir).insert("trunk", "abc123");
+
+        let cache = RemoteHeadsCache::read_from(&git_dir);
+        assert_eq!(cache.get("trunk", Duration::from_secs(60)), Some("abc123"));
+        assert_eq!(cache.get("trunk", Duration::ZERO), None);
+        assert_eq!(cache.get("release", Duration::from_secs(60)), None);
+
+        std::fs::remove_dir_all(&git_dir).unwrap();
+    }
+}
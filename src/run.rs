@@ -0,0 +1,232 @@
+//! Running a command with the project's secrets in its environment, instead of in decrypted files
+//!
+//! `configure run -- fastlane beta` decrypts the configured key-value files – `.env`, `.properties` and `.xcconfig`
+//! files – in memory, and passes each of their entries to the command as an environment variable. Nothing is written
+//! to disk, and the decrypted values are zeroed once the command has finished.
+//!
+//! Keys become variable names by upper-casing them and replacing anything that isn't a letter, digit or `_` with `_`,
+//! so `sentry.auth_token` is passed as `SENTRY_AUTH_TOKEN`.
+
+use crate::configure::{Configuration, File};
+use crate::encryption::decrypt_file_contents_with_keys;
+use crate::fs::DecryptionKeys;
+use crate::ConfigureError;
+use log::{debug, info, warn};
+use std::path::Path;
+use std::process::Command;
+use zeroize::Zeroizing;
+
+/// The extensions of files whose entries can be passed on as environment variables
+const KEY_VALUE_EXTENSIONS: [&str; 3] = ["env", "properties", "xcconfig"];
+
+/// An environment variable for the command, whose value is zeroed when it's dropped
+pub type SecretVariable = (String, Zeroizing<String>);
+
+fn is_key_value_file(file: &File) -> bool {
+    let path = Path::new(&file.destination);
+
+    if file.is_directory {
+        return false;
+    }
+
+    // `.env` on its own has no extension, only a name
+    path.file_name().map_or(false, |name| name == ".env")
+        || path.extension().map_or(false, |extension| {
+            KEY_VALUE_EXTENSIONS.iter().any(|known| extension == *known)
+        })
+}
+
+/// The environment variable name for `key`
+fn variable_name(key: &str) -> String {
+    key.chars()
+        .map(|character| match character.is_ascii_alphanumeric() {
+            true => character.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+/// The `key = value` entries in `contents`, leaving out blank lines and comments
+///
+/// A leading `export` is allowed (as in `.env` files), and quotes around the whole value are removed. The `$()`
+/// that `.xcconfig` files need to stop `//` starting a comment is removed too.
+fn parse_key_values(contents: &str) -> Vec<(String, String)> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            !(line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with('!')
+                || line.starts_with("//"))
+        })
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let (key, value) = (key.trim(), value.trim());
+
+            if key.is_empty() {
+                return None;
+            }
+
+            let is_quoted = value.len() >= 2
+                && ((value.starts_with('"') && value.ends_with('"'))
+                    || (value.starts_with('\'') && value.ends_with('\'')));
+            let value = match is_quoted {
+                true => &value[1..value.len() - 1],
+                false => value,
+            };
+
+            Some((variable_name(key), value.replace("$()", "")))
+        })
+        .collect()
+}
+
+/// The entries of `configuration`'s key-value files as environment variables, decrypted in memory
+///
+/// `only` limits them to the files with those destinations – every key-value file is used if it's empty. When two
+/// files have the same key, the later file wins.
+pub fn environment_for_configuration(
+    configuration: &Configuration,
+    only: &[String],
+) -> Result<Vec<SecretVariable>, ConfigureError> {
+    if let Some(destination) = only.iter().find(|destination| {
+        !configuration
+            .files_to_copy
+            .iter()
+            .any(|file| &&file.destination == destination)
+    }) {
+        debug!("{:?} isn't a configured destination", destination);
+        return Err(ConfigureError::DestinationNotConfigured);
+    }
+
+    let mut decryption_keys = DecryptionKeys::default();
+    let mut variables: Vec<SecretVariable> = Vec::new();
+
+    for file in &configuration.files_to_copy {
+        if !only.is_empty() && !only.contains(&file.destination) {
+            continue;
+        }
+
+        if !is_key_value_file(file) {
+            if !only.is_empty() {
+                warn!(
+                    "{} isn't a .env, .properties or .xcconfig file, so its contents can't be passed on",
+                    file.destination
+                );
+            }
+            continue;
+        }
+
+        let source = configuration.encrypted_destination_for(file);
+        if !source.exists() {
+            return Err(ConfigureError::EncryptedFileMissing { path: source });
+        }
+
+        let keys = decryption_keys.for_configuration(configuration)?;
+        let (contents, _) = decrypt_file_contents_with_keys(&source, keys)?;
+        let contents = Zeroizing::new(contents);
+
+        let text = match std::str::from_utf8(&contents) {
+            Ok(text) => text,
+            Err(_) => {
+                warn!("{} isn't text, so it's left out", file.destination);
+                continue;
+            }
+        };
+
+        for (name, value) in parse_key_values(text) {
+            variables.retain(|(existing, _)| existing != &name);
+            variables.push((name, Zeroizing::new(value)));
+        }
+    }
+
+    if variables.is_empty() {
+        return Err(ConfigureError::NoSecretsForEnvironment);
+    }
+
+    Ok(variables)
+}
+
+/// Run `command` with `variables` added to its environment, and wait for it to finish
+///
+/// Returns the command's exit code – a command that was stopped by a signal counts as exit code 1.
+pub fn run_with_environment(
+    command: &[String],
+    variables: Vec<SecretVariable>,
+) -> Result<i32, ConfigureError> {
+    let (program, arguments) = match command.split_first() {
+        Some(split) => split,
+        None => {
+            return Err(ConfigureError::CommandNotRunnable {
+                command: String::new(),
+            })
+        }
+    };
+
+    info!(
+        "Running {} with {} secret(s) in its environment",
+        program,
+        variables.len()
+    );
+
+    let status = Command::new(program)
+        .args(arguments)
+        .envs(variables.iter().map(|(name, value)| (name, value.as_str())))
+        .status()
+        .map_err(|err| {
+            debug!("Unable to start {}: {}", program, err);
+            ConfigureError::CommandNotRunnable {
+                command: program.clone(),
+            }
+        })?;
+
+    // Zero the values now, rather than whenever they'd otherwise be dropped
+    drop(variables);
+
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_key_value_files_are_parsed_into_variables() {
+        let contents = r#"
+            # Sentry
+            sentry.auth_token = abc123
+            export API_KEY="quoted value"
+            // From an .xcconfig
+            SERVER_URL = https:/$()/example.com
+            not an entry
+            ! a properties comment
+        "#;
+
+        assert_eq!(
+            parse_key_values(contents),
+            vec![
+                ("SENTRY_AUTH_TOKEN".to_string(), "abc123".to_string()),
+                ("API_KEY".to_string(), "quoted value".to_string()),
+                ("SERVER_URL".to_string(), "https://example.com".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_that_only_key_value_files_are_used() {
+        let file = |destination: &str, is_directory: bool| File {
+            source: destination.to_string(),
+            destination: destination.to_string(),
+            is_directory,
+            expires: None,
+            processors: Vec::new(),
+        };
+
+        assert!(is_key_value_file(&file(".env", false)));
+        assert!(is_key_value_file(&file("app/secrets.properties", false)));
+        assert!(is_key_value_file(&file("Config/Secrets.xcconfig", false)));
+        assert!(!is_key_value_file(&file("app/google-services.json", false)));
+        assert!(!is_key_value_file(&file("config.env", true)));
+    }
+}
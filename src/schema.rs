@@ -0,0 +1,355 @@
+//! The JSON Schema for `.configure` files, and a check of a `.configure` file's JSON against it
+//!
+//! Serde ignores fields it doesn't know about, so a typo like `files_to_cpy` would otherwise go unnoticed. Only the
+//! parts of JSON Schema that the schema itself uses are checked: `type`, `properties`, `required`, `items`, and
+//! `additionalProperties` (either `false`, or a schema for the values of a map).
+//!
+//! Unknown fields are also reported whenever a `.configure` or `keys.json` file is read – as warnings, or as errors
+//! in strict mode. So are files written by a newer version of this tool, which may rely on things this one doesn't
+//! understand.
+
+use crate::configure::INHERITED_FIELDS;
+use crate::ConfigureError;
+use log::warn;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// The schema, as published in `schema/configure.schema.json`
+pub const CONFIGURE_FILE_SCHEMA: &str = include_str!("../schema/configure.schema.json");
+
+/// Something in a `.configure` file that doesn't match the schema
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SchemaViolation {
+    /// Where the problem is, as a JSON pointer like `/files_to_copy/0/file`
+    pub path: String,
+    pub kind: SchemaViolationKind,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SchemaViolationKind {
+    /// A field the format doesn't have – usually a typo
+    UnknownField,
+    MissingField,
+    WrongType {
+        expected: String,
+    },
+}
+
+impl SchemaViolation {
+    pub fn is_unknown_field(&self) -> bool {
+        self.kind == SchemaViolationKind::UnknownField
+    }
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = match self.path.as_str() {
+            "" => "The file",
+            path => path,
+        };
+
+        match &self.kind {
+            SchemaViolationKind::UnknownField => write!(f, "{} isn't a known field", path),
+            SchemaViolationKind::MissingField => write!(f, "{} is required but missing", path),
+            SchemaViolationKind::WrongType { expected } => {
+                write!(f, "{} should be of type {}", path, expected)
+            }
+        }
+    }
+}
+
+/// Check the contents of a `.configure` file against the schema, returning everything that doesn't match
+pub fn validate_against_schema(contents: &str) -> Result<Vec<SchemaViolation>, ConfigureError> {
+    let schema: Value =
+        serde_json::from_str(CONFIGURE_FILE_SCHEMA).expect("The embedded schema is valid JSON");
+    let value: Value =
+        serde_json::from_str(contents).map_err(|_| ConfigureError::ConfigureFileNotValid)?;
+
+    let mut violations = Vec::new();
+    check_value(&value, &schema, "", &mut violations);
+
+    // A file that extends another can leave out anything it inherits
+    if value.get("extends").is_some() {
+        violations.retain(|violation| {
+            let field = violation.path.trim_start_matches('/');
+            !(violation.kind == SchemaViolationKind::MissingField
+                && INHERITED_FIELDS.contains(&field))
+        });
+    }
+
+    Ok(violations)
+}
+
+/// The fields in a `.configure` file that the format doesn't have, as JSON pointers
+pub fn unknown_configure_fields(contents: &str) -> Vec<String> {
+    validate_against_schema(contents)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(SchemaViolation::is_unknown_field)
+        .map(|violation| violation.path)
+        .collect()
+}
+
+/// Treat unknown fields in `.configure` and `keys.json`, and files written by a newer version, as errors instead of
+/// warnings
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Warn about the unknown `fields` found in the file at `path` – or fail, in strict mode
+pub fn report_unknown_fields(path: &Path, fields: Vec<String>) -> Result<(), ConfigureError> {
+    if fields.is_empty() {
+        return Ok(());
+    }
+
+    if STRICT.load(Ordering::Relaxed) {
+        return Err(ConfigureError::UnknownFields {
+            path: path.to_path_buf(),
+            fields: fields.join(", "),
+        });
+    }
+
+    for field in fields {
+        warn!("Ignoring {} in {:?} – it isn't a known field", field, path);
+    }
+
+    Ok(())
+}
+
+/// The version of this tool, as recorded in the files it writes
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Warn if the file at `path` was written by a newer version of this tool than this one – or fail, in strict mode
+///
+/// Files that don't record a version were written before versions were recorded, so they're always old enough.
+pub fn check_tool_version(path: &Path, version: Option<&str>) -> Result<(), ConfigureError> {
+    let version = match version {
+        Some(version) if is_newer_version(version, TOOL_VERSION) => version,
+        _ => return Ok(()),
+    };
+
+    if STRICT.load(Ordering::Relaxed) {
+        return Err(ConfigureError::WrittenByNewerVersion {
+            path: path.to_path_buf(),
+            version: version.to_string(),
+        });
+    }
+
+    warn!(
+        "{:?} was written by configure {}, which is newer than this version ({}) – it may not be read correctly",
+        path, version, TOOL_VERSION
+    );
+
+    Ok(())
+}
+
+/// Whether `version` is later than `current`, comparing their `major.minor.patch` numbers
+///
+/// Anything after the numbers (like `-beta.1`) is ignored, as is a version that can't be parsed.
+fn is_newer_version(version: &str, current: &str) -> bool {
+    fn numbers(version: &str) -> Option<Vec<u64>> {
+        version
+            .split(|character| character == '-' || character == '+')
+            .next()?
+            .split('.')
+            .map(|number| number.parse().ok())
+            .collect()
+    }
+
+    match (numbers(version), numbers(current)) {
+        (Some(version), Some(current)) => version > current,
+        _ => false,
+    }
+}
+
+fn check_value(value: &Value, schema: &Value, path: &str, violations: &mut Vec<SchemaViolation>) {
+    if let Some(expected) = schema["type"].as_str() {
+        if !has_type(value, expected) {
+            violations.push(SchemaViolation {
+                path: path.to_string(),
+                kind: SchemaViolationKind::WrongType {
+                    expected: expected.to_string(),
+                },
+            });
+            return;
+        }
+    }
+
+    match value {
+        Value::Object(object) => check_object(object, schema, path, violations),
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                let item_path = format!("{}/{}", path, index);
+                check_value(item, &schema["items"], &item_path, violations);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn check_object(
+    object: &Map<String, Value>,
+    schema: &Value,
+    path: &str,
+    violations: &mut Vec<SchemaViolation>,
+) {
+    let properties = schema["properties"].as_object();
+
+    for required in schema["required"].as_array().into_iter().flatten() {
+        let required = required.as_str().unwrap_or_default();
+
+        if !object.contains_key(required) {
+            violations.push(SchemaViolation {
+                path: format!("{}/{}", path, required),
+                kind: SchemaViolationKind::MissingField,
+            });
+        }
+    }
+
+    for (key, value) in object {
+        let field_path = format!("{}/{}", path, key);
+
+        match properties.and_then(|properties| properties.get(key)) {
+            Some(field_schema) => check_value(value, field_schema, &field_path, violations),
+            None if schema["additionalProperties"] == Value::Bool(false) => {
+                violations.push(SchemaViolation {
+                    path: field_path,
+                    kind: SchemaViolationKind::UnknownField,
+                })
+            }
+            None if schema["additionalProperties"].is_object() => check_value(
+                value,
+                &schema["additionalProperties"],
+                &field_path,
+                violations,
+            ),
+            None => (),
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_only_later_versions_are_newer() {
+        assert!(is_newer_version("0.7.0", "0.6.5"));
+        assert!(is_newer_version("0.6.10", "0.6.5"));
+        assert!(is_newer_version("1.0.0-beta.1", "0.6.5"));
+        assert!(!is_newer_version("0.6.5", "0.6.5"));
+        assert!(!is_newer_version("0.5.9", "0.6.5"));
+        assert!(!is_newer_version("not a version", "0.6.5"));
+    }
+
+    #[test]
+    fn test_that_a_valid_configure_file_has_no_violations() {
+        let contents = r#"{
+            "project_name": "example",
+            "branch": "trunk",
+            "pinned_hash": "abc123",
+            "files_to_copy": [{ "file": "a.json", "destination": "a.json", "directory": false }],
+            "deterministic_encryption": true
+        }"#;
+
+        assert_eq!(validate_against_schema(contents).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_that_misspelled_fields_are_reported() {
+        let contents = r#"{
+            "project_name": "example",
+            "branch": "trunk",
+            "pinned_hash": "abc123",
+            "files_to_cpy": [],
+            "files_to_copy": [{ "file": "a.json", "destinaton": "a.json" }]
+        }"#;
+
+        let violations: Vec<String> = validate_against_schema(contents)
+            .unwrap()
+            .iter()
+            .map(|violation| violation.to_string())
+            .collect();
+
+        assert_eq!(
+            violations,
+            vec![
+                "/files_to_cpy isn't a known field",
+                "/files_to_copy/0/destination is required but missing",
+                "/files_to_copy/0/destinaton isn't a known field",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_that_files_that_extend_another_can_leave_out_inherited_fields() {
+        let contents = r#"{
+            "extends": "../.configure",
+            "files_to_copy": []
+        }"#;
+
+        assert_eq!(validate_against_schema(contents).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_that_fields_with_the_wrong_type_are_reported() {
+        let contents = r#"{
+            "project_name": "example",
+            "branch": "trunk",
+            "pinned_hash": "abc123",
+            "files_to_copy": {}
+        }"#;
+
+        assert_eq!(
+            validate_against_schema(contents).unwrap(),
+            vec![SchemaViolation {
+                path: "/files_to_copy".to_string(),
+                kind: SchemaViolationKind::WrongType {
+                    expected: "array".to_string()
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_that_profiles_are_checked_against_the_schema() {
+        let contents = r#"{
+            "project_name": "example",
+            "branch": "trunk",
+            "pinned_hash": "abc123",
+            "files_to_copy": [],
+            "profiles": {
+                "ci": { "files": ["a.json"], "key_name": "example-ci" },
+                "developer": { "file": ["a.json"] }
+            }
+        }"#;
+
+        let violations: Vec<String> = validate_against_schema(contents)
+            .unwrap()
+            .iter()
+            .map(|violation| violation.to_string())
+            .collect();
+
+        assert_eq!(violations.len(), 2);
+        assert!(violations
+            .iter()
+            .all(|violation| violation.contains("/profiles/developer")));
+    }
+}
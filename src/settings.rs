@@ -0,0 +1,297 @@
+//! User-wide defaults, read from `configure/config.toml` in the user's config directory
+//!
+//! That's `$XDG_CONFIG_HOME/configure/config.toml` if `XDG_CONFIG_HOME` is set, and `~/.config/configure/config.toml`
+//! otherwise. `CONFIGURE_SETTINGS` can point at a different file. Every setting is optional:
+//!
+//! ```toml
+//! secrets_repo = "~/src/mobile-secrets"
+//! default_branch = "trunk"
+//!
+//! [ui]
+//! color = false
+//!
+//! [backups]
+//! enabled = false
+//!
+//! [ci]
+//! enabled = true
+//! key_secret = "MOBILE_SECRETS_KEY"
+//! ```
+//!
+//! Environment variables take priority over the file: `CONFIGURE_DEFAULT_BRANCH`, `NO_COLOR`, `CONFIGURE_KEEP_BACKUPS`
+//! and `CONFIGURE_CI` – and colors are turned off in CI, whose logs aren't a terminal. `SECRETS_REPO` overrides
+//! `secrets_repo` too, but it's checked every time the secrets repo is looked up, so hosts can change it while they're
+//! running.
+
+use log::{debug, warn};
+use serde::Deserialize;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[derive(Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Where to find the secrets repo, instead of looking in `~/.mobile-secrets` and `~/Projects/.mobile-secrets`
+    pub secrets_repo: Option<PathBuf>,
+
+    /// The branch to suggest when choosing a secrets branch for a project
+    pub default_branch: Option<String>,
+
+    pub ui: UiSettings,
+    pub backups: BackupSettings,
+    pub ci: CiSettings,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct UiSettings {
+    /// Whether to style output with colors – when this isn't set, it depends on the terminal
+    pub color: Option<bool>,
+}
+
+#[derive(Debug, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    /// Whether `apply` keeps a `.bak` copy of each file it replaces
+    pub enabled: bool,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        BackupSettings { enabled: true }
+    }
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CiSettings {
+    /// Whether to run as in CI – when this isn't set, it depends on the CI's own environment variables
+    pub enabled: Option<bool>,
+
+    /// The name of the CI secret with the project's key, when it isn't `CONFIGURE_ENCRYPTION_KEY`
+    pub key_secret: Option<String>,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// The user's settings, read the first time they're needed
+///
+/// Reading them also applies the UI preferences, so call this before printing anything if those should apply.
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| {
+        let settings = load_settings();
+
+        if let Some(color) = settings.ui.color {
+            crate::ui::set_colors_enabled(color);
+        }
+
+        settings
+    })
+}
+
+fn load_settings() -> Settings {
+    let mut settings = match settings_file_path() {
+        Some(path) if path.exists() => read_settings(&path),
+        _ => Settings::default(),
+    };
+
+    apply_env_overrides(&mut settings, |name| env::var(name).ok());
+
+    settings
+}
+
+fn settings_file_path() -> Option<PathBuf> {
+    if let Ok(path) = env::var(crate::SETTINGS_FILE_KEY_NAME) {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => dirs::home_dir()?.join(".config"),
+    };
+
+    Some(config_dir.join("configure").join("config.toml"))
+}
+
+/// Read the settings file at `path` – if it isn't valid, it's ignored so a typo can't stop every command from working
+fn read_settings(path: &Path) -> Settings {
+    debug!("Reading settings from {:?}", path);
+
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!("Ignoring the settings file at {:?}: {}", path, err);
+            return Settings::default();
+        }
+    };
+
+    match parse_settings(&contents) {
+        Ok(settings) => settings,
+        Err(err) => {
+            warn!("Ignoring the settings file at {:?}: {}", path, err);
+            Settings::default()
+        }
+    }
+}
+
+fn parse_settings(contents: &str) -> Result<Settings, toml::de::Error> {
+    let mut settings: Settings = toml::from_str(contents)?;
+    settings.secrets_repo = settings.secrets_repo.map(|path| expand_home(&path));
+    Ok(settings)
+}
+
+fn apply_env_overrides(settings: &mut Settings, var: impl Fn(&str) -> Option<String>) {
+    if let Some(branch) = var(crate::DEFAULT_BRANCH_KEY_NAME) {
+        settings.default_branch = Some(branch);
+    }
+
+    if let Some(value) = var(crate::CI_KEY_NAME) {
+        match parse_bool(&value) {
+            Some(enabled) => settings.ci.enabled = Some(enabled),
+            None => warn!(
+                "Ignoring {} – {:?} is not `true` or `false`",
+                crate::CI_KEY_NAME,
+                value
+            ),
+        }
+    }
+
+    // https://no-color.org – any non-empty value turns colors off
+    if var("NO_COLOR").is_some_and(|value| !value.is_empty())
+        || crate::ci::detect_ci_environment(settings.ci.enabled, &var).is_some()
+    {
+        settings.ui.color = Some(false);
+    }
+
+    if let Some(value) = var(crate::KEEP_BACKUPS_KEY_NAME) {
+        match parse_bool(&value) {
+            Some(enabled) => settings.backups.enabled = enabled,
+            None => warn!(
+                "Ignoring {} – {:?} is not `true` or `false`",
+                crate::KEEP_BACKUPS_KEY_NAME,
+                value
+            ),
+        }
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home_dir)) => home_dir.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_that_an_empty_settings_file_uses_the_defaults() {
+        let settings = parse_settings("").unwrap();
+
+        assert_eq!(settings, Settings::default());
+        assert!(settings.backups.enabled);
+    }
+
+    #[test]
+    fn test_that_settings_are_read_from_toml() {
+        let settings = parse_settings(
+            r#"
+            secrets_repo = "/srv/secrets"
+            default_branch = "trunk"
+
+            [ui]
+            color = false
+
+            [backups]
+            enabled = false
+
+            [ci]
+            enabled = false
+            key_secret = "MOBILE_SECRETS_KEY"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.secrets_repo, Some(PathBuf::from("/srv/secrets")));
+        assert_eq!(settings.default_branch, Some("trunk".to_string()));
+        assert_eq!(settings.ui.color, Some(false));
+        assert!(!settings.backups.enabled);
+        assert_eq!(settings.ci.enabled, Some(false));
+        assert_eq!(
+            settings.ci.key_secret,
+            Some("MOBILE_SECRETS_KEY".to_string())
+        );
+    }
+
+    #[test]
+    fn test_that_the_secrets_repo_path_can_start_with_a_tilde() {
+        let settings = parse_settings(r#"secrets_repo = "~/secrets""#).unwrap();
+
+        assert_eq!(
+            settings.secrets_repo,
+            Some(dirs::home_dir().unwrap().join("secrets"))
+        );
+    }
+
+    #[test]
+    fn test_that_environment_variables_override_the_settings_file() {
+        let mut settings = parse_settings(r#"default_branch = "trunk""#).unwrap();
+
+        apply_env_overrides(
+            &mut settings,
+            env(&[
+                ("CONFIGURE_DEFAULT_BRANCH", "main"),
+                ("NO_COLOR", "1"),
+                ("CONFIGURE_KEEP_BACKUPS", "no"),
+            ]),
+        );
+
+        assert_eq!(settings.default_branch, Some("main".to_string()));
+        assert_eq!(settings.ui.color, Some(false));
+        assert!(!settings.backups.enabled);
+    }
+
+    #[test]
+    fn test_that_invalid_boolean_overrides_are_ignored() {
+        let mut settings = Settings::default();
+        apply_env_overrides(&mut settings, env(&[("CONFIGURE_KEEP_BACKUPS", "maybe")]));
+
+        assert!(settings.backups.enabled);
+    }
+
+    #[test]
+    fn test_that_colors_are_turned_off_in_ci_unless_it_is_overridden() {
+        let mut settings = Settings::default();
+        apply_env_overrides(&mut settings, env(&[("BUILDKITE", "true")]));
+        assert_eq!(settings.ui.color, Some(false));
+
+        let mut settings = Settings::default();
+        apply_env_overrides(
+            &mut settings,
+            env(&[("BUILDKITE", "true"), ("CONFIGURE_CI", "false")]),
+        );
+        assert_eq!(settings.ci.enabled, Some(false));
+        assert_eq!(settings.ui.color, None);
+    }
+}
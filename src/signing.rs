@@ -0,0 +1,162 @@
+use crate::ConfigureError;
+use log::debug;
+use sodiumoxide::base64::Variant;
+use sodiumoxide::base64::{decode, encode};
+use sodiumoxide::crypto::sign;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The private half of a project's signing key – this lives in the secrets repo, never in the project
+#[derive(Debug, Eq, PartialEq)]
+pub struct SigningKey {
+    pub key: sign::SecretKey,
+}
+
+/// The public half of a project's signing key, used to check `.configure` signatures
+#[derive(Debug, Eq, PartialEq)]
+pub struct VerifyingKey {
+    pub key: sign::PublicKey,
+}
+
+impl SigningKey {
+    pub fn generate() -> SigningKey {
+        debug!("Generating a signing key");
+
+        let (_, secret_key) = sign::gen_keypair();
+        SigningKey { key: secret_key }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey {
+            key: self.key.public_key(),
+        }
+    }
+
+    /// Sign `bytes`, returning the base64-encoded detached signature
+    pub fn sign(&self, bytes: &[u8]) -> String {
+        encode(sign::sign_detached(bytes, &self.key), Variant::Original)
+    }
+}
+
+impl FromStr for SigningKey {
+    type Err = ConfigureError;
+
+    fn from_str(signing_key: &str) -> Result<Self, Self::Err> {
+        match sign::SecretKey::from_slice(&decode_base64(signing_key)?) {
+            Some(key) => Ok(SigningKey { key }),
+            None => Err(ConfigureError::SigningKeyNotValid),
+        }
+    }
+}
+
+impl fmt::Display for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&encode(&self.key, Variant::Original))
+    }
+}
+
+impl VerifyingKey {
+    /// Check a base64-encoded detached signature against `bytes`
+    pub fn verify(&self, bytes: &[u8], signature: &str) -> Result<(), ConfigureError> {
+        let signature = match decode(signature.trim(), Variant::Original) {
+            Ok(signature) => signature,
+            Err(_) => return Err(ConfigureError::ConfigureFileSignatureInvalid),
+        };
+
+        let signature = match sign::Signature::from_slice(&signature) {
+            Some(signature) => signature,
+            None => return Err(ConfigureError::ConfigureFileSignatureInvalid),
+        };
+
+        if sign::verify_detached(&signature, bytes, &self.key) {
+            Ok(())
+        } else {
+            Err(ConfigureError::ConfigureFileSignatureInvalid)
+        }
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = ConfigureError;
+
+    fn from_str(verifying_key: &str) -> Result<Self, Self::Err> {
+        match sign::PublicKey::from_slice(&decode_base64(verifying_key)?) {
+            Some(key) => Ok(VerifyingKey { key }),
+            None => Err(ConfigureError::SigningKeyNotValid),
+        }
+    }
+}
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&encode(self.key, Variant::Original))
+    }
+}
+
+fn decode_base64(string: &str) -> Result<Vec<u8>, ConfigureError> {
+    match decode(string.trim(), Variant::Original) {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => Err(ConfigureError::SigningKeyNotValid),
+    }
+}
+
+/// The detached signature for a `.configure` file is committed next to it, as `.configure.sig`
+pub fn signature_path_for(configure_file_path: &Path) -> PathBuf {
+    let mut file_name = configure_file_path
+        .file_name()
+        .unwrap_or_default()
+        .to_os_string();
+    file_name.push(".sig");
+
+    configure_file_path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_signatures_can_be_verified() {
+        let signing_key = SigningKey::generate();
+        let signature = signing_key.sign(b"{}");
+        assert!(signing_key
+            .verifying_key()
+            .verify(b"{}", &signature)
+            .is_ok())
+    }
+
+    #[test]
+    fn test_that_signatures_for_modified_contents_are_rejected() {
+        let signing_key = SigningKey::generate();
+        let signature = signing_key.sign(b"{}");
+        assert!(matches!(
+            signing_key.verifying_key().verify(b"{ }", &signature),
+            Err(ConfigureError::ConfigureFileSignatureInvalid)
+        ))
+    }
+
+    #[test]
+    fn test_that_signatures_from_another_key_are_rejected() {
+        let signature = SigningKey::generate().sign(b"{}");
+        let verifying_key = SigningKey::generate().verifying_key();
+        assert!(verifying_key.verify(b"{}", &signature).is_err())
+    }
+
+    #[test]
+    fn test_that_signing_key_survives_a_round_trip() {
+        let signing_key = SigningKey::generate();
+        assert_eq!(
+            SigningKey::from_str(&signing_key.to_string()).unwrap(),
+            signing_key
+        )
+    }
+
+    #[test]
+    fn test_that_signature_path_is_next_to_configure_file() {
+        assert_eq!(
+            signature_path_for(Path::new("/project/.configure")),
+            PathBuf::from("/project/.configure.sig")
+        )
+    }
+}
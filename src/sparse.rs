@@ -0,0 +1,205 @@
+//! Checking out only the parts of a large secrets repo that a project uses
+//!
+//! `configure secrets sparse-init` turns on git's sparse checkout in cone mode, with the directories of each
+//! configured file's source – files at the root of the repo, like `keys.json`, are always checked out. Given a URL, it
+//! makes a new blobless partial clone first, so the contents of files outside those directories aren't downloaded
+//! either.
+//!
+//! Secrets are read straight from git's object database at the pinned commit, and libgit2 can't download objects a
+//! partial clone left out. So before reading from a partial clone, the files' objects are fetched with the git
+//! command line – see `SecretsRepo::fetch_missing_objects`.
+
+use crate::configure::Configuration;
+use crate::fs::find_secrets_repo;
+use crate::settings::settings;
+use crate::ConfigureError;
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What `sparse-init` did to the secrets repo
+#[derive(Debug, Clone, Serialize)]
+pub struct SparseCheckout {
+    /// Where the secrets repo is
+    pub path: PathBuf,
+
+    /// Whether it was cloned, rather than already there
+    pub cloned: bool,
+
+    /// The directories that are checked out, besides the files at the root
+    pub directories: Vec<String>,
+}
+
+/// The secrets repo directories `configurations`' files come from, without any that are inside another
+pub fn sparse_directories(configurations: &[Configuration]) -> Vec<String> {
+    let directories: BTreeSet<String> = configurations
+        .iter()
+        .flat_map(|configuration| &configuration.files_to_copy)
+        .filter_map(|file| {
+            let source = Path::new(&file.source);
+            let directory = match file.is_directory {
+                true => source,
+                false => source.parent()?,
+            };
+
+            match directory.to_string_lossy().trim_matches('/') {
+                "" => None,
+                directory => Some(directory.to_string()),
+            }
+        })
+        .collect();
+
+    // Cone mode checks out everything under a directory, so listing its subdirectories as well does nothing
+    directories
+        .iter()
+        .filter(|directory| {
+            !directories.iter().any(|other| {
+                directory.len() > other.len()
+                    && directory.starts_with(other.as_str())
+                    && directory[other.len()..].starts_with('/')
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Limit the secrets repo's working copy to `configurations`' directories, cloning it from `clone_url` first if
+/// that's given
+pub fn sparse_init(
+    configurations: &[Configuration],
+    clone_url: Option<&str>,
+) -> Result<SparseCheckout, ConfigureError> {
+    let directories = sparse_directories(configurations);
+
+    let (path, cloned) = match clone_url {
+        Some(url) => {
+            if let Ok(path) = find_secrets_repo() {
+                return Err(ConfigureError::SecretsRepoAlreadyExists { path });
+            }
+
+            let path = clone_destination();
+            let branch = configurations
+                .iter()
+                .map(|configuration| configuration.branch.as_str())
+                .find(|branch| !branch.is_empty());
+
+            clone_partially(url, branch, &path)?;
+            (path, true)
+        }
+        None => (find_secrets_repo()?, false),
+    };
+
+    run_git(&path, &["sparse-checkout", "init", "--cone"])?;
+
+    let mut arguments = vec!["sparse-checkout", "set"];
+    arguments.extend(directories.iter().map(String::as_str));
+    run_git(&path, &arguments)?;
+
+    info!(
+        "Checked out {} director{} of the secrets repo at {:?}",
+        directories.len(),
+        if directories.len() == 1 { "y" } else { "ies" },
+        path
+    );
+
+    Ok(SparseCheckout {
+        path,
+        cloned,
+        directories,
+    })
+}
+
+/// Where a new clone of the secrets repo goes – the first of the places `find_secrets_repo` looks that's been chosen,
+/// or `~/.mobile-secrets`
+fn clone_destination() -> PathBuf {
+    if let Ok(path) = std::env::var(crate::SECRETS_KEY_NAME) {
+        return PathBuf::from(path);
+    }
+
+    if let Some(path) = &settings().secrets_repo {
+        return path.clone();
+    }
+
+    dirs::home_dir()
+        .expect("Unable to determine user home directory")
+        .join(".mobile-secrets")
+}
+
+fn clone_partially(url: &str, branch: Option<&str>, path: &Path) -> Result<(), ConfigureError> {
+    let mut command = Command::new("git");
+    command
+        .arg("clone")
+        .arg("--filter=blob:none")
+        .arg("--sparse")
+        .env("GIT_TERMINAL_PROMPT", "0"); // Never wait for credentials that nobody is going to type
+
+    if let Some(branch) = branch {
+        command.arg("--branch").arg(branch);
+    }
+
+    info!("Cloning {} into {:?}", url, path);
+    check_output(command.arg(url).arg(path))
+}
+
+fn run_git(directory: &Path, arguments: &[&str]) -> Result<(), ConfigureError> {
+    debug!("Running `git {}` in {:?}", arguments.join(" "), directory);
+    check_output(Command::new("git").args(arguments).current_dir(directory))
+}
+
+fn check_output(command: &mut Command) -> Result<(), ConfigureError> {
+    let output = command
+        .output()
+        .map_err(|err| ConfigureError::SparseCheckoutFailed {
+            reason: err.to_string(),
+        })?;
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(ConfigureError::SparseCheckoutFailed {
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configure::File;
+
+    #[test]
+    fn test_that_sparse_directories_cover_each_source_once() {
+        let file = |source: &str, is_directory: bool| File {
+            source: source.to_string(),
+            destination: source.to_string(),
+            is_directory,
+            expires: None,
+            processors: Vec::new(),
+        };
+
+        let configurations = vec![
+            Configuration {
+                files_to_copy: vec![
+                    file("android/example/google-services.json", false),
+                    file("android/example/certificates", true),
+                    file("shared.json", false),
+                ],
+                ..Default::default()
+            },
+            Configuration {
+                files_to_copy: vec![
+                    file("android/example-wear/secrets.properties", false),
+                    file("android", true),
+                    file("ios/example/Secrets.swift", false),
+                ],
+                ..Default::default()
+            },
+        ];
+
+        assert_eq!(
+            sparse_directories(&configurations),
+            vec!["android".to_string(), "ios/example".to_string()]
+        );
+    }
+}
@@ -0,0 +1,708 @@
+use crate::configure::File;
+use crate::encryption::Provenance;
+use crate::ConfigureError;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+/// A machine-local record of what the last successful `apply` wrote into the project
+///
+/// This lives next to the encrypted files in `.configure-files`, but it describes a single checkout and must not be committed.
+#[derive(Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApplyState {
+    /// The secrets repo commit that the applied files were encrypted from
+    #[serde(default)]
+    pub pinned_hash: String,
+
+    /// When the last successful apply finished
+    #[serde(default)]
+    pub applied_at: Option<DateTime<Utc>>,
+
+    /// Each file written by the last apply, keyed by its destination
+    #[serde(default)]
+    pub files: BTreeMap<String, AppliedFile>,
+
+    /// Files that were applied to a destination that's no longer in the configuration, and haven't been cleaned up yet
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub previous_files: BTreeMap<String, AppliedFile>,
+
+    /// The commits that earlier applies used, oldest first – `rollback` goes back to the last one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_pinned_hashes: Vec<String>,
+}
+
+// How many earlier pinned hashes to remember
+const PINNED_HASH_HISTORY_LIMIT: usize = 10;
+
+/// The hashes of a single file at the time it was applied
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct AppliedFile {
+    /// Where the file came from in the secrets repo – used to notice when a file's destination moves
+    #[serde(default)]
+    pub source: Option<String>,
+
+    /// The hash of the `.enc` file that was decrypted
+    pub encrypted_hash: String,
+
+    /// The hash of the decrypted contents written to the destination
+    pub decrypted_hash: String,
+}
+
+impl AppliedFile {
+    /// How a file whose encrypted copy and destination have these hashes compares to when it was applied
+    pub fn status(&self, encrypted_hash: &str, decrypted_hash: &str) -> FileStatus {
+        if encrypted_hash != self.encrypted_hash {
+            FileStatus::Outdated
+        } else if decrypted_hash != self.decrypted_hash {
+            FileStatus::LocallyModified
+        } else {
+            FileStatus::UpToDate
+        }
+    }
+}
+
+impl ApplyState {
+    /// Read the state file at `path`, returning an empty state if it doesn't exist yet
+    pub fn read_from(path: &Path) -> Result<ApplyState, ConfigureError> {
+        if !path.exists() {
+            return Ok(ApplyState::default());
+        }
+
+        let contents = match read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Err(ConfigureError::StateFileNotReadable),
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(state) => Ok(state),
+            Err(_) => Err(ConfigureError::StateFileNotReadable),
+        }
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<(), ConfigureError> {
+        let json = match serde_json::to_string_pretty(&self) {
+            Ok(json) => json,
+            Err(_) => return Err(ConfigureError::StateFileNotWritable),
+        };
+
+        match write(path, json) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(ConfigureError::StateFileNotWritable),
+        }
+    }
+
+    /// The record for `destination` from the last apply, if there was one
+    pub fn applied_file(&self, destination: &str) -> Option<&AppliedFile> {
+        self.files.get(destination)
+    }
+
+    pub fn record_applied_file(
+        &mut self,
+        file: &File,
+        encrypted_hash: String,
+        decrypted_hash: String,
+    ) {
+        self.previous_files.remove(&file.destination);
+        self.files.insert(
+            file.destination.clone(),
+            AppliedFile {
+                source: Some(file.source.clone()),
+                encrypted_hash,
+                decrypted_hash,
+            },
+        );
+    }
+
+    /// Record a destination that was edited by hand and kept, instead of being overwritten with `decrypted_hash`
+    ///
+    /// It's reported as modified locally from then on, rather than outdated, so nothing applies it again
+    /// automatically – but the next apply still asks what to do with it.
+    pub fn record_kept_file(
+        &mut self,
+        file: &File,
+        encrypted_hash: String,
+        decrypted_hash: String,
+    ) {
+        self.record_applied_file(file, encrypted_hash, decrypted_hash);
+    }
+
+    /// Move records for files that are no longer part of the configuration into `previous_files`
+    pub fn retire_unconfigured_files(&mut self, destinations: &[&str]) {
+        let retired: Vec<String> = self
+            .files
+            .keys()
+            .filter(|destination| !destinations.contains(&destination.as_str()))
+            .cloned()
+            .collect();
+
+        for destination in retired {
+            if let Some(applied_file) = self.files.remove(&destination) {
+                self.previous_files.insert(destination, applied_file);
+            }
+        }
+    }
+
+    /// Stop tracking a previous destination once it's been cleaned up (or the user chose to keep it)
+    pub fn forget_previous_file(&mut self, destination: &str) {
+        self.previous_files.remove(destination);
+    }
+
+    /// Mark the apply as finished, retiring records for files that are no longer part of the configuration
+    pub fn finish_apply(&mut self, pinned_hash: &str, destinations: &[&str]) {
+        self.retire_unconfigured_files(destinations);
+        self.record_pinned_hash(pinned_hash);
+        self.pinned_hash = pinned_hash.to_string();
+        self.applied_at = Some(Utc::now());
+    }
+
+    /// Remember the hash being replaced, so it can be rolled back to
+    fn record_pinned_hash(&mut self, pinned_hash: &str) {
+        if self.pinned_hash.is_empty() || self.pinned_hash == pinned_hash {
+            return;
+        }
+
+        // Going back to the previous hash is a rollback, so the one being left shouldn't become the next rollback target
+        if self.previous_pinned_hashes.last().map(String::as_str) == Some(pinned_hash) {
+            self.previous_pinned_hashes.pop();
+            return;
+        }
+
+        self.previous_pinned_hashes.push(self.pinned_hash.clone());
+
+        let excess = self
+            .previous_pinned_hashes
+            .len()
+            .saturating_sub(PINNED_HASH_HISTORY_LIMIT);
+        self.previous_pinned_hashes.drain(..excess);
+    }
+
+    /// The pinned hash `rollback` should return to
+    pub fn previous_pinned_hash(&self) -> Option<&str> {
+        self.previous_pinned_hashes.last().map(String::as_str)
+    }
+
+    /// Whether the file at `destination` was changed by someone other than `apply`
+    ///
+    /// If there's no record of the destination, we can't tell – in that case it's not treated as modified.
+    pub fn is_locally_modified(&self, destination: &str, current_hash: &str) -> bool {
+        match self.applied_file(destination) {
+            Some(applied_file) => applied_file.decrypted_hash != current_hash,
+            None => false,
+        }
+    }
+}
+
+/// A record of where the encrypted files in `.configure-files` came from, written by every `update`
+///
+/// Unlike `ApplyState`, this is committed with the encrypted files. `apply` compares it with the `.configure` file, so
+/// encrypted files that weren't regenerated after someone changed the pinned hash by hand are noticed.
+#[derive(Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct EncryptedFilesManifest {
+    /// The secrets repo commit the encrypted files were generated from
+    pub pinned_hash: String,
+
+    /// The hash of each encrypted file, keyed by its name in `.configure-files`
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+
+    /// The version of configure that wrote the encrypted files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+}
+
+impl EncryptedFilesManifest {
+    /// Read the manifest at `path` – `None` if the project was last updated by a version that didn't write one
+    pub fn read_from(path: &Path) -> Result<Option<EncryptedFilesManifest>, ConfigureError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = match read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => {
+                return Err(ConfigureError::InputFileNotReadable {
+                    path: path.to_path_buf(),
+                })
+            }
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(manifest) => Ok(Some(manifest)),
+            Err(_) => Err(ConfigureError::InputFileNotReadable {
+                path: path.to_path_buf(),
+            }),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, ConfigureError> {
+        match serde_json::to_string_pretty(&self) {
+            Ok(json) => Ok(json + "\n"),
+            Err(_) => Err(ConfigureError::ConfigureDataNotValid),
+        }
+    }
+
+    /// Why the encrypted files might not match the configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `pinned_hash` - The pinned hash in the `.configure` file
+    /// * `encrypted_files` - Each encrypted file's name in `.configure-files`, and the hash of its contents
+    pub fn problems(&self, pinned_hash: &str, encrypted_files: &[(String, String)]) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.pinned_hash != pinned_hash {
+            problems.push(format!(
+                "The encrypted files were generated from {}, but the .configure file is pinned to {}",
+                self.pinned_hash, pinned_hash
+            ));
+        }
+
+        for (name, hash) in encrypted_files {
+            match self.files.get(name) {
+                Some(recorded_hash) if recorded_hash == hash => (),
+                Some(_) => problems.push(format!("{} was changed after it was generated", name)),
+                None => problems.push(format!(
+                    "{} wasn't generated by `configure update`, so it may not match the pinned hash",
+                    name
+                )),
+            }
+        }
+
+        problems
+    }
+}
+
+/// How a single configured file compares to the last apply
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    /// The destination matches what the last apply wrote, from the same encrypted file
+    UpToDate,
+
+    /// There's no record of this file being applied
+    NotApplied,
+
+    /// The encrypted file changed since it was last applied (usually after `configure update`)
+    Outdated,
+
+    /// The destination was edited since it was last applied
+    LocallyModified,
+
+    /// The destination was deleted since it was last applied
+    DestinationMissing,
+
+    /// There's no encrypted copy of this file in the project
+    EncryptedFileMissing,
+}
+
+impl fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            FileStatus::UpToDate => "up to date",
+            FileStatus::NotApplied => "not applied",
+            FileStatus::Outdated => "outdated",
+            FileStatus::LocallyModified => "modified locally",
+            FileStatus::DestinationMissing => "missing",
+            FileStatus::EncryptedFileMissing => "encrypted file missing",
+        };
+
+        f.write_str(description)
+    }
+}
+
+/// How the project's decrypted files compare to its configuration
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct ConfigurationStatus {
+    /// The pinned hash in the `.configure` file
+    pub pinned_hash: String,
+
+    /// The pinned hash at the time of the last successful apply, if there was one
+    pub last_applied_hash: Option<String>,
+
+    /// When the last successful apply finished
+    pub applied_at: Option<DateTime<Utc>>,
+
+    /// The status of each configured file, keyed by destination, in configuration order
+    pub files: Vec<(String, FileStatus)>,
+
+    /// Where each encrypted file came from, keyed by destination – only for files that recorded it
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub provenance: BTreeMap<String, Provenance>,
+}
+
+impl ConfigurationStatus {
+    /// Whether running `apply` would have nothing to do
+    pub fn is_up_to_date(&self) -> bool {
+        self.last_applied_hash.as_ref() == Some(&self.pinned_hash)
+            && self
+                .files
+                .iter()
+                .all(|(_, status)| *status == FileStatus::UpToDate)
+    }
+}
+
+impl fmt::Display for ConfigurationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Pinned hash:       {}", self.pinned_hash)?;
+
+        match (&self.last_applied_hash, &self.applied_at) {
+            (Some(hash), Some(date)) => writeln!(
+                f,
+                "Last applied hash: {} ({})",
+                hash,
+                date.format("%Y-%m-%d %H:%M:%S UTC")
+            )?,
+            _ => writeln!(f, "Last applied hash: never applied")?,
+        }
+
+        writeln!(f)?;
+
+        for (destination, status) in &self.files {
+            match self.provenance.get(destination) {
+                Some(provenance) => writeln!(
+                    f,
+                    "{:<24} {} (from {} at {})",
+                    status.to_string(),
+                    destination,
+                    provenance.source,
+                    provenance.commit
+                )?,
+                None => writeln!(f, "{:<24} {}", status.to_string(), destination)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A single file managed by the `.configure` file, and where each copy of it lives
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct ManagedFile {
+    /// Where the file comes from in the secrets repo
+    pub source: String,
+
+    /// Where the encrypted copy is kept in the project, relative to the project root
+    pub encrypted_path: String,
+
+    /// Where the decrypted file is written in the project
+    pub destination: String,
+
+    pub encrypted_file_exists: bool,
+
+    pub destination_exists: bool,
+
+    /// The hash of the decrypted contents written by the last apply, if it was ever applied
+    pub last_applied_hash: Option<String>,
+
+    /// When the secret stops working, if the `.configure` file says
+    pub expires: Option<NaiveDate>,
+}
+
+/// Every file managed by the `.configure` file, in configuration order
+#[derive(Debug, Eq, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct FileList {
+    pub files: Vec<ManagedFile>,
+}
+
+impl fmt::Display for FileList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.files.is_empty() {
+            return writeln!(f, "No files are configured");
+        }
+
+        let source_width = column_width("Source", self.files.iter().map(|file| &file.source));
+        let encrypted_width = column_width(
+            "Encrypted",
+            self.files.iter().map(|file| &file.encrypted_path),
+        );
+        let destination_width = column_width(
+            "Destination",
+            self.files.iter().map(|file| &file.destination),
+        );
+
+        writeln!(
+            f,
+            "{:<sw$}  {:<ew$}  {:<dw$}  {:<9}  {:<11}  Last applied",
+            "Source",
+            "Encrypted",
+            "Destination",
+            "Enc file",
+            "Destination",
+            sw = source_width,
+            ew = encrypted_width,
+            dw = destination_width,
+        )?;
+
+        for file in &self.files {
+            let last_applied_hash = match &file.last_applied_hash {
+                Some(hash) => hash.chars().take(12).collect(),
+                None => "never".to_string(),
+            };
+
+            writeln!(
+                f,
+                "{:<sw$}  {:<ew$}  {:<dw$}  {:<9}  {:<11}  {}",
+                file.source,
+                file.encrypted_path,
+                file.destination,
+                yes_or_no(file.encrypted_file_exists),
+                yes_or_no(file.destination_exists),
+                last_applied_hash,
+                sw = source_width,
+                ew = encrypted_width,
+                dw = destination_width,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn column_width<'a, I: Iterator<Item = &'a String>>(heading: &str, values: I) -> usize {
+    values
+        .map(|value| value.chars().count())
+        .fold(heading.len(), usize::max)
+}
+
+fn yes_or_no(value: bool) -> &'static str {
+    if value {
+        "present"
+    } else {
+        "missing"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_manifest() -> EncryptedFilesManifest {
+        EncryptedFilesManifest {
+            pinned_hash: "abc123".to_string(),
+            files: vec![("foo.json.enc".to_string(), "encrypted".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_that_a_matching_manifest_has_no_problems() {
+        let files = [("foo.json.enc".to_string(), "encrypted".to_string())];
+        assert!(test_manifest().problems("abc123", &files).is_empty());
+    }
+
+    #[test]
+    fn test_that_a_hand_edited_pinned_hash_is_a_problem() {
+        let files = [("foo.json.enc".to_string(), "encrypted".to_string())];
+        let problems = test_manifest().problems("def456", &files);
+
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("def456"));
+    }
+
+    #[test]
+    fn test_that_changed_or_unrecorded_encrypted_files_are_problems() {
+        let files = [
+            ("foo.json.enc".to_string(), "other".to_string()),
+            ("bar.json.enc".to_string(), "encrypted".to_string()),
+        ];
+        assert_eq!(test_manifest().problems("abc123", &files).len(), 2);
+    }
+
+    #[test]
+    fn test_that_destination_without_record_is_not_locally_modified() {
+        assert!(!ApplyState::default().is_locally_modified("foo.json", "hash"))
+    }
+
+    #[test]
+    fn test_that_destination_with_matching_hash_is_not_locally_modified() {
+        let state = test_state();
+        assert!(!state.is_locally_modified("foo.json", "decrypted"))
+    }
+
+    #[test]
+    fn test_that_destination_with_different_hash_is_locally_modified() {
+        let state = test_state();
+        assert!(state.is_locally_modified("foo.json", "other-hash"))
+    }
+
+    #[test]
+    fn test_that_finish_apply_drops_files_that_are_no_longer_configured() {
+        let mut state = test_state();
+        state.finish_apply("abc123", &["bar.json"]);
+        assert!(state.applied_file("foo.json").is_none());
+        assert!(state.previous_files.contains_key("foo.json"));
+        assert_eq!(state.pinned_hash, "abc123");
+        assert!(state.applied_at.is_some());
+    }
+
+    #[test]
+    fn test_that_finish_apply_remembers_the_previous_pinned_hash() {
+        let mut state = test_state();
+        state.pinned_hash = "first".to_string();
+
+        state.finish_apply("second", &[]);
+        state.finish_apply("second", &[]);
+
+        assert_eq!(state.previous_pinned_hashes, vec!["first"]);
+        assert_eq!(state.previous_pinned_hash(), Some("first"));
+    }
+
+    #[test]
+    fn test_that_rolling_back_forgets_the_newer_pinned_hash() {
+        let mut state = test_state();
+        state.pinned_hash = "first".to_string();
+
+        state.finish_apply("second", &[]);
+        state.finish_apply("third", &[]);
+        state.finish_apply("second", &[]);
+
+        assert_eq!(state.previous_pinned_hashes, vec!["first"]);
+    }
+
+    #[test]
+    fn test_that_pinned_hash_history_is_limited() {
+        let mut state = test_state();
+        state.pinned_hash = "0".to_string();
+
+        for hash in 1..=PINNED_HASH_HISTORY_LIMIT + 5 {
+            state.finish_apply(&hash.to_string(), &[]);
+        }
+
+        assert_eq!(
+            state.previous_pinned_hashes.len(),
+            PINNED_HASH_HISTORY_LIMIT
+        );
+        assert_eq!(
+            state.previous_pinned_hash(),
+            Some((PINNED_HASH_HISTORY_LIMIT + 4).to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn test_that_missing_state_file_reads_as_empty_state() {
+        let path = std::env::temp_dir().join(format!(
+            "configure-state-that-does-not-exist-{}.json",
+            std::process::id()
+        ));
+        assert_eq!(ApplyState::read_from(&path).unwrap(), ApplyState::default())
+    }
+
+    #[test]
+    fn test_that_state_survives_a_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "configure-state-round-trip-{}.json",
+            std::process::id()
+        ));
+        let state = test_state();
+        state.write_to(&path).unwrap();
+        assert_eq!(ApplyState::read_from(&path).unwrap(), state);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_that_status_is_up_to_date_when_hash_and_files_match() {
+        assert!(test_status(FileStatus::UpToDate, Some("abc123")).is_up_to_date())
+    }
+
+    #[test]
+    fn test_that_status_is_not_up_to_date_when_never_applied() {
+        assert!(!test_status(FileStatus::UpToDate, None).is_up_to_date())
+    }
+
+    #[test]
+    fn test_that_status_is_not_up_to_date_when_a_file_changed() {
+        assert!(!test_status(FileStatus::LocallyModified, Some("abc123")).is_up_to_date())
+    }
+
+    #[test]
+    fn test_that_file_list_columns_fit_the_longest_value() {
+        let list = FileList {
+            files: vec![ManagedFile {
+                source: "a-rather-long/source-path.json".to_string(),
+                encrypted_path: ".configure-files/source-path.json.enc".to_string(),
+                destination: "app/source-path.json".to_string(),
+                encrypted_file_exists: true,
+                destination_exists: false,
+                last_applied_hash: None,
+                expires: None,
+                processors: Vec::new(),
+            }],
+        };
+
+        let table = list.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0].find("Encrypted"),
+            lines[1].find(".configure-files")
+        );
+        assert!(lines[1].ends_with("present    missing      never"));
+    }
+
+    #[test]
+    fn test_that_file_list_serializes_as_an_array() {
+        let list = FileList { files: vec![] };
+        assert_eq!(serde_json::to_string(&list).unwrap(), "[]");
+    }
+
+    #[test]
+    fn test_that_reapplying_a_previous_destination_stops_tracking_it() {
+        let mut state = test_state();
+        state.retire_unconfigured_files(&[]);
+        state.record_applied_file(
+            &test_file(),
+            "encrypted".to_string(),
+            "decrypted".to_string(),
+        );
+        assert!(state.previous_files.is_empty());
+        assert!(state.applied_file("foo.json").is_some());
+    }
+
+    #[test]
+    fn test_that_state_without_sources_can_still_be_read() {
+        let state: ApplyState = serde_json::from_str(
+            r#"{"pinned_hash":"abc123","files":{"foo.json":{"encrypted_hash":"e","decrypted_hash":"d"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(state.applied_file("foo.json").unwrap().source, None);
+    }
+
+    fn test_file() -> File {
+        File {
+            source: "android/foo.json".to_string(),
+            destination: "foo.json".to_string(),
+            is_directory: false,
+            expires: None,
+            processors: Vec::new(),
+        }
+    }
+
+    fn test_state() -> ApplyState {
+        let mut state = ApplyState::default();
+        state.record_applied_file(
+            &test_file(),
+            "encrypted".to_string(),
+            "decrypted".to_string(),
+        );
+        state
+    }
+
+    fn test_status(
+        file_status: FileStatus,
+        last_applied_hash: Option<&str>,
+    ) -> ConfigurationStatus {
+        ConfigurationStatus {
+            pinned_hash: "abc123".to_string(),
+            last_applied_hash: last_applied_hash.map(|hash| hash.to_string()),
+            applied_at: None,
+            files: vec![("foo.json".to_string(), file_status)],
+            provenance: BTreeMap::new(),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Starting a `.configure` file from a shared template
+//!
+//! A template is a `.configure` file with only the parts that are the same across projects – usually the branch, the
+//! encryption settings, and the list of files. Anything else is left out, and `{project_name}` in a file's paths is
+//! replaced with the project's name once it's known. Templates can be named (and kept in the secrets repo's
+//! `templates` directory), or read from a path or a URL.
+
+use crate::configure::File;
+use crate::fs::find_secrets_repo;
+use crate::git::{run_network_command, NetworkPolicy};
+use crate::{Configuration, ConfigureError};
+use log::debug;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Replaced with the project's name in the paths of a template's files
+pub const PROJECT_NAME_PLACEHOLDER: &str = "{project_name}";
+
+/// Where the secrets repo keeps named templates
+const TEMPLATES_DIRECTORY: &str = "templates";
+
+/// Where to find a template, as given to `init --template`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TemplateSource {
+    /// `templates/<name>.configure` in the secrets repo
+    Named(String),
+    Path(PathBuf),
+    Url(String),
+}
+
+impl TemplateSource {
+    /// URLs are recognised by their scheme, and paths by having more than one component or a `.` in their name –
+    /// anything else is the name of a template in the secrets repo
+    pub fn parse(template: &str) -> TemplateSource {
+        if template.starts_with("https://") || template.starts_with("http://") {
+            return TemplateSource::Url(template.to_string());
+        }
+
+        let path = Path::new(template);
+        if path.components().count() > 1 || template.contains('.') {
+            return TemplateSource::Path(path.to_path_buf());
+        }
+
+        TemplateSource::Named(template.to_string())
+    }
+
+    fn read(&self) -> Result<String, ConfigureError> {
+        let not_found = || ConfigureError::TemplateNotFound {
+            template: self.to_string(),
+        };
+
+        match self {
+            TemplateSource::Named(name) => {
+                let path = find_secrets_repo()?
+                    .join(TEMPLATES_DIRECTORY)
+                    .join(format!("{}.configure", name));
+                std::fs::read_to_string(path).map_err(|_| not_found())
+            }
+            TemplateSource::Path(path) => std::fs::read_to_string(path).map_err(|_| not_found()),
+            TemplateSource::Url(url) => {
+                let policy = NetworkPolicy::from_env();
+                if policy.offline {
+                    debug!("Not downloading {} while offline", url);
+                    return Err(not_found());
+                }
+
+                let output = run_network_command(
+                    || {
+                        let mut command = Command::new("curl");
+                        command.args([
+                            "--fail",
+                            "--silent",
+                            "--show-error",
+                            "--location",
+                            url.as_str(),
+                        ]);
+                        command
+                    },
+                    &policy,
+                )?;
+
+                if !output.status.success() {
+                    debug!(
+                        "Unable to download {}: {}",
+                        url,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                    return Err(not_found());
+                }
+
+                String::from_utf8(output.stdout).map_err(|_| ConfigureError::TemplateNotValid {
+                    template: self.to_string(),
+                })
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for TemplateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateSource::Named(name) => write!(f, "{:?}", name),
+            TemplateSource::Path(path) => write!(f, "{}", path.display()),
+            TemplateSource::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Read the template `template` refers to
+pub fn read_template(template: &str) -> Result<Configuration, ConfigureError> {
+    let source = TemplateSource::parse(template);
+    debug!("Reading the template {}", source);
+
+    configuration_from_template_contents(&source.read()?).map_err(|_| {
+        ConfigureError::TemplateNotValid {
+            template: source.to_string(),
+        }
+    })
+}
+
+/// Parse a template, which can leave out any field – it can't extend another file, though
+fn configuration_from_template_contents(contents: &str) -> Result<Configuration, ConfigureError> {
+    let mut fields = match serde_json::from_str(contents) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => return Err(ConfigureError::ConfigureFileNotValid),
+    };
+
+    if fields.contains_key("extends") {
+        return Err(ConfigureError::ConfigureFileNotValid);
+    }
+
+    // The pinned hash is always taken from the secrets repo when the project is set up
+    fields.remove("pinned_hash");
+
+    for field in ["project_name", "branch", "pinned_hash"] {
+        fields
+            .entry(field)
+            .or_insert_with(|| serde_json::Value::String(String::new()));
+    }
+
+    fields
+        .entry("files_to_copy")
+        .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+
+    match serde_json::from_value(serde_json::Value::Object(fields)) {
+        Ok(configuration) => Ok(configuration),
+        Err(_) => Err(ConfigureError::ConfigureFileNotValid),
+    }
+}
+
+/// Fill in whatever `configuration` doesn't have yet from `template`
+///
+/// Nothing already in the configuration is changed – files are only added if nothing is written to their destination.
+pub fn apply_template(configuration: &mut Configuration, template: Configuration) {
+    if configuration.project_name.is_empty() {
+        configuration.project_name = template.project_name;
+    }
+
+    if configuration.key_name.is_none() {
+        configuration.key_name = template.key_name;
+    }
+
+    if configuration.secrets_submodule.is_none() {
+        configuration.secrets_submodule = template.secrets_submodule;
+    }
+
+    if configuration.remote.is_none() {
+        configuration.remote = template.remote;
+    }
+
+    if configuration.branch.is_empty() {
+        configuration.branch = template.branch;
+    }
+
+    configuration.deterministic_encryption |= template.deterministic_encryption;
+    configuration.passphrase_encryption |= template.passphrase_encryption;
+    configuration.record_provenance |= template.record_provenance;
+
+    for file in template.files_to_copy {
+        if let Err(err) = configuration.add_file(file) {
+            debug!("Skipping a file from the template: {}", err);
+        }
+    }
+}
+
+/// Replace `{project_name}` in the paths of the configuration's files
+pub fn fill_in_project_name(configuration: &mut Configuration) {
+    let project_name = configuration.project_name.clone();
+
+    for File {
+        source,
+        destination,
+        ..
+    } in &mut configuration.files_to_copy
+    {
+        *source = source.replace(PROJECT_NAME_PLACEHOLDER, &project_name);
+        *destination = destination.replace(PROJECT_NAME_PLACEHOLDER, &project_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_template_sources_are_recognised() {
+        assert_eq!(
+            TemplateSource::parse("android-app"),
+            TemplateSource::Named("android-app".to_string())
+        );
+        assert_eq!(
+            TemplateSource::parse("../templates/android.configure"),
+            TemplateSource::Path(PathBuf::from("../templates/android.configure"))
+        );
+        assert_eq!(
+            TemplateSource::parse("https://example.com/android.configure"),
+            TemplateSource::Url("https://example.com/android.configure".to_string())
+        );
+    }
+
+    #[test]
+    fn test_that_templates_fill_in_what_the_configuration_is_missing() {
+        let template = configuration_from_template_contents(
+            r#"{
+                "branch": "trunk",
+                "pinned_hash": "abc",
+                "deterministic_encryption": true,
+                "files_to_copy": [
+                    { "file": "android/{project_name}/google-services.json", "destination": "app/google-services.json" },
+                    { "file": "android/{project_name}/sentry.properties", "destination": "sentry.properties" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(template.pinned_hash.is_empty());
+
+        let mut configuration = Configuration {
+            project_name: "WordPress".to_string(),
+            ..Default::default()
+        };
+        configuration
+            .add_file(File {
+                source: "custom/sentry.properties".to_string(),
+                destination: "sentry.properties".to_string(),
+                is_directory: false,
+                expires: None,
+                processors: Vec::new(),
+            })
+            .unwrap();
+
+        apply_template(&mut configuration, template);
+        fill_in_project_name(&mut configuration);
+
+        assert_eq!(configuration.branch, "trunk");
+        assert!(configuration.deterministic_encryption);
+        assert_eq!(configuration.files_to_copy.len(), 2);
+        assert_eq!(
+            configuration.files_to_copy[0].source,
+            "custom/sentry.properties"
+        );
+        assert_eq!(
+            configuration.files_to_copy[1].source,
+            "android/WordPress/google-services.json"
+        );
+    }
+
+    #[test]
+    fn test_that_templates_cannot_extend_other_files() {
+        assert!(configuration_from_template_contents(r#"{ "extends": "../.configure" }"#).is_err());
+    }
+}
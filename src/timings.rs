@@ -0,0 +1,162 @@
+//! How long each kind of work took, for diagnosing slow builds
+//!
+//! The lowest-level functions for each phase – running network commands, hashing, encrypting, writing files – time
+//! themselves with `measure`, so anything built on top of them is covered without adding timers of its own. Nothing
+//! is recorded unless timings have been turned on.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TOTALS: Mutex<BTreeMap<Phase, PhaseTotal>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Phase {
+    /// Talking to the secrets server
+    Network,
+    /// Turning a passphrase into a key
+    KeyDerivation,
+    Hashing,
+    Encryption,
+    Decryption,
+    /// Writing files
+    Io,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::Network => "Network",
+            Phase::KeyDerivation => "Key derivation",
+            Phase::Hashing => "Hashing",
+            Phase::Encryption => "Encryption",
+            Phase::Decryption => "Decryption",
+            Phase::Io => "File IO",
+        };
+
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PhaseTotal {
+    pub duration: Duration,
+    pub count: u32,
+}
+
+/// Start (or stop) recording timings for this process
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Run `work`, adding how long it took to `phase`'s total
+pub fn measure<T>(phase: Phase, work: impl FnOnce() -> T) -> T {
+    let _timer = start(phase);
+    work()
+}
+
+/// Adds the time from now until it's dropped to a phase's total – for work that `measure` can't wrap, like futures
+pub struct Timer {
+    phase: Phase,
+    started_at: Instant,
+}
+
+pub fn start(phase: Phase) -> Option<Timer> {
+    // `Instant` isn't available everywhere (like in WebAssembly), so don't touch it unless asked to
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    Some(Timer {
+        phase,
+        started_at: Instant::now(),
+    })
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Ok(mut totals) = TOTALS.lock() {
+            let total = totals.entry(self.phase).or_default();
+            total.duration += self.started_at.elapsed();
+            total.count += 1;
+        }
+    }
+}
+
+/// Everything recorded so far, in a fixed order
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Timings {
+    pub phases: Vec<(Phase, PhaseTotal)>,
+}
+
+pub fn timings() -> Timings {
+    let phases = match TOTALS.lock() {
+        Ok(totals) => totals
+            .iter()
+            .map(|(phase, total)| (*phase, *total))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    Timings { phases }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.phases.is_empty() {
+            return write!(f, "Nothing was timed");
+        }
+
+        for (index, (phase, total)) in self.phases.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+
+            write!(
+                f,
+                "{:<16}{:>10.3}s  ({} call{})",
+                phase,
+                total.duration.as_secs_f64(),
+                total.count,
+                if total.count == 1 { "" } else { "s" }
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_timings_are_listed_by_phase() {
+        let timings = Timings {
+            phases: vec![
+                (
+                    Phase::Network,
+                    PhaseTotal {
+                        duration: Duration::from_millis(1500),
+                        count: 1,
+                    },
+                ),
+                (
+                    Phase::Decryption,
+                    PhaseTotal {
+                        duration: Duration::from_millis(20),
+                        count: 3,
+                    },
+                ),
+            ],
+        };
+
+        assert_eq!(
+            timings.to_string(),
+            "Network              1.500s  (1 call)\nDecryption           0.020s  (3 calls)"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use crate::timings::{self, Phase};
+use crate::ConfigureError;
+use log::debug;
+use std::path::{Path, PathBuf};
+
+/// Files that should be written together, or not at all
+///
+/// Nothing touches the disk until `commit` is called, so work that might still fail can stage its output here
+/// without leaving the project half-updated.
+#[derive(Debug, Default)]
+pub struct StagedChanges {
+    files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+/// The files that a commit replaced, so the commit can be undone if a later step fails
+#[derive(Debug, Default)]
+pub struct CommittedChanges {
+    previous_files: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+
+impl StagedChanges {
+    /// Write `contents` to `path` when the changes are committed. Staging the same path again replaces its contents.
+    pub fn stage(&mut self, path: PathBuf, contents: Vec<u8>) {
+        self.files.retain(|(staged_path, _)| staged_path != &path);
+        self.files.push((path, contents));
+    }
+
+    /// The contents staged for `path`, if it's been staged
+    pub fn staged_contents(&self, path: &Path) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|(staged_path, _)| staged_path == path)
+            .map(|(_, contents)| contents.as_slice())
+    }
+
+    /// Write every staged file. If any of them can't be written, the ones already written are put back.
+    pub fn commit(self) -> Result<CommittedChanges, ConfigureError> {
+        let mut committed = CommittedChanges::default();
+
+        for (path, contents) in self.files {
+            let previous_contents = if path.exists() {
+                match std::fs::read(&path) {
+                    Ok(contents) => Some(contents),
+                    Err(_) => {
+                        committed.roll_back();
+                        return Err(ConfigureError::OutputFileNotWritable { path });
+                    }
+                }
+            } else {
+                None
+            };
+
+            if let Err(err) = write_atomically(&path, &contents) {
+                committed.roll_back();
+                return Err(err);
+            }
+
+            committed.previous_files.push((path, previous_contents));
+        }
+
+        Ok(committed)
+    }
+}
+
+impl CommittedChanges {
+    /// Put every file back the way it was before the commit – files that didn't exist are removed
+    pub fn roll_back(self) {
+        for (path, previous_contents) in self.previous_files.into_iter().rev() {
+            debug!("Rolling back {:?}", path);
+
+            // This is best-effort – there's nothing more useful to do if the original can't be restored
+            let _ = match previous_contents {
+                Some(contents) => write_atomically(&path, &contents),
+                None => std::fs::remove_file(&path).map_err(ConfigureError::from),
+            };
+        }
+    }
+}
+
+/// Write to a temporary file next to `path`, then move it into place, so `path` is never left half-written
+///
+/// The temporary file is named after this process, so two runs writing the same file at once don't share one.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), ConfigureError> {
+    let _timer = timings::start(Phase::Io);
+
+    if let Some(parent) = path.parent() {
+        if std::fs::create_dir_all(parent).is_err() {
+            return Err(ConfigureError::OutputFileNotWritable {
+                path: path.to_path_buf(),
+            });
+        }
+    }
+
+    let mut temporary_file_name = path.file_name().unwrap_or_default().to_os_string();
+    temporary_file_name.push(format!(".{}.configure-tmp", std::process::id()));
+    let temporary_path = path.with_file_name(temporary_file_name);
+
+    if std::fs::write(&temporary_path, contents).is_err() {
+        let _ = std::fs::remove_file(&temporary_path);
+        return Err(ConfigureError::OutputFileNotWritable {
+            path: path.to_path_buf(),
+        });
+    }
+
+    match std::fs::rename(&temporary_path, path) {
+        Ok(()) => Ok(()),
+        Err(_) => {
+            let _ = std::fs::remove_file(&temporary_path);
+            Err(ConfigureError::OutputFileNotWritable {
+                path: path.to_path_buf(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_committing_writes_every_staged_file() {
+        let directory = test_directory("commit");
+        let mut staged = StagedChanges::default();
+        staged.stage(directory.join("a.enc"), b"a".to_vec());
+        staged.stage(directory.join("nested/b.enc"), b"b".to_vec());
+
+        staged.commit().unwrap();
+
+        assert_eq!(std::fs::read(directory.join("a.enc")).unwrap(), b"a");
+        assert_eq!(std::fs::read(directory.join("nested/b.enc")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn test_that_a_failed_commit_restores_files_it_already_wrote() {
+        let directory = test_directory("failed-commit");
+        std::fs::write(directory.join("existing.enc"), b"old").unwrap();
+        std::fs::write(directory.join("not-a-directory"), b"").unwrap();
+
+        let mut staged = StagedChanges::default();
+        staged.stage(directory.join("existing.enc"), b"new".to_vec());
+        staged.stage(directory.join("created.enc"), b"new".to_vec());
+        staged.stage(directory.join("not-a-directory/c.enc"), b"c".to_vec());
+
+        assert!(staged.commit().is_err());
+        assert_eq!(
+            std::fs::read(directory.join("existing.enc")).unwrap(),
+            b"old"
+        );
+        assert!(!directory.join("created.enc").exists());
+    }
+
+    #[test]
+    fn test_that_rolling_back_a_commit_restores_previous_files() {
+        let directory = test_directory("roll-back");
+        std::fs::write(directory.join(".configure"), b"old").unwrap();
+
+        let mut staged = StagedChanges::default();
+        staged.stage(directory.join(".configure"), b"new".to_vec());
+        staged.commit().unwrap().roll_back();
+
+        assert_eq!(std::fs::read(directory.join(".configure")).unwrap(), b"old");
+    }
+
+    fn test_directory(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("configure-transaction-test-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
@@ -1,51 +1,37 @@
-use console::{style, Term};
-use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
-
-///
-/// Print a heading-style message to the console
-pub fn heading(string: &str) {
-    println!("{}", style(string).green());
-}
-
-///
-/// Print a warning to the console
-pub fn warn(string: &str) {
-    println!("{}", style(string).yellow());
-}
-
+mod browse;
+// Without the `interactive-ui` feature, the same functions print plain text and read answers from stdin, so
+// embedders don't need the terminal UI crates
+#[cfg(not(feature = "interactive-ui"))]
+mod plain;
+mod search;
+#[cfg(feature = "interactive-ui")]
+mod terminal;
+
+pub use browse::{prompt_for_path, PathKind};
+#[cfg(not(feature = "interactive-ui"))]
+pub use plain::*;
+#[cfg(feature = "interactive-ui")]
+pub use terminal::*;
+
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Whether someone is at a terminal to answer questions and watch progress
 ///
-/// Print a blank line to the console
-pub fn newline() {
-    println!();
+/// Piped output, build tools like Gradle and CI all run without one – prompting there would wait forever.
+pub fn is_interactive_terminal() -> bool {
+    std::io::stdin().is_terminal()
+        && std::io::stdout().is_terminal()
+        && crate::ci::ci_environment().is_none()
 }
 
-///
-/// Prompt the user to input text on the command line
-pub fn prompt(message: &str) -> String {
-    heading(message);
-    Input::<String>::new().interact_text().unwrap()
-}
-
-///
-/// Ask the user for confirmation
-pub fn confirm(message: &str) -> bool {
-    Confirm::new().with_prompt(message).interact().unwrap()
+/// Only show errors and the questions prompts ask – messages, warnings and progress indicators are hidden
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
 }
 
-///
-/// Allow the user to provide a list of items to select from
-pub fn select(items: Vec<String>, selected: &str) -> Result<String, git2::Error> {
-    let index_of_current_branch = items
-        .iter()
-        .position(|name| *name == selected)
-        .expect("Unable to find current branch in repo branch list");
-
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .items(&items)
-        .default(index_of_current_branch)
-        .interact_on_opt(&Term::stderr())
-        .expect("You must select an option")
-        .unwrap();
-
-    Ok(items[selection].clone())
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
 }
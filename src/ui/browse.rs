@@ -0,0 +1,118 @@
+//! Completing paths as they're typed, and browsing the directories they're in, shared by both UIs
+//!
+//! Neither UI can complete a path when tab is pressed, so an unfinished path is completed when it's entered instead –
+//! a single match is filled in for the user to check, and several are offered as a list to pick from. A path ending
+//! in `/` lists what's in that directory, so the whole tree can be browsed one directory at a time.
+
+use super::{prompt, prompt_with_initial_text, select};
+use std::path::Path;
+
+/// What the path being asked for is expected to point to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PathKind {
+    /// Something that's already there – unfinished paths are completed
+    Existing,
+
+    /// Something that might not exist yet – only paths ending in `/` are browsed, so new names aren't "completed"
+    New,
+}
+
+/// Ask for a `/`-separated path relative to `root`, completing it and browsing directories until it's finished
+pub fn prompt_for_path(
+    message: &str,
+    root: &Path,
+    initial_text: Option<&str>,
+    kind: PathKind,
+) -> String {
+    let message = format!("{} End it with / to see what's in a directory.", message);
+    let mut path = initial_text.unwrap_or_default().to_string();
+
+    loop {
+        path = match path.is_empty() {
+            true => prompt(&message),
+            false => prompt_with_initial_text(&message, &path),
+        };
+        path = path.trim_start_matches("./").to_string();
+
+        let is_unfinished = match kind {
+            PathKind::Existing => path.is_empty() || !root.join(&path).exists(),
+            PathKind::New => path.is_empty(),
+        };
+
+        if !is_unfinished && !path.ends_with('/') {
+            return path;
+        }
+
+        let completions = path_completions(root, &path);
+        path = match completions.as_slice() {
+            [] => return path,
+            [completion] => completion.clone(),
+            _ => select(&completions, None).unwrap_or(path),
+        };
+    }
+}
+
+/// The entries in `root` that could finish `partial`, a `/`-separated path relative to it
+///
+/// Each completion is the whole path, with a `/` after directories so they can be browsed in turn. Hidden entries
+/// are only included once a `.` has been typed.
+pub fn path_completions(root: &Path, partial: &str) -> Vec<String> {
+    let (directory, prefix) = match partial.rfind('/') {
+        Some(index) => partial.split_at(index + 1),
+        None => ("", partial),
+    };
+
+    let entries = match std::fs::read_dir(root.join(directory)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut completions: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            match entry.path().is_dir() {
+                true => Some(format!("{}{}/", directory, name)),
+                false => Some(format!("{}{}", directory, name)),
+            }
+        })
+        .collect();
+
+    completions.sort();
+    completions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_paths_are_completed_from_what_is_on_disk() {
+        let root =
+            std::env::temp_dir().join(format!("configure-browse-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("android/WordPress")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join("android/WordPress/secrets.properties"), "").unwrap();
+        std::fs::write(root.join("android/WordPress/sentry.properties"), "").unwrap();
+
+        assert_eq!(path_completions(&root, ""), vec!["android/"]);
+        assert_eq!(path_completions(&root, "."), vec![".git/"]);
+        assert_eq!(path_completions(&root, "andr"), vec!["android/"]);
+        assert_eq!(
+            path_completions(&root, "android/WordPress/se"),
+            vec![
+                "android/WordPress/secrets.properties",
+                "android/WordPress/sentry.properties"
+            ]
+        );
+        assert!(path_completions(&root, "ios/").is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+use std::io::{self, BufRead, Write};
+
+use super::is_quiet;
+use super::search::{fuzzy_matches, SEARCHABLE_LIST_LENGTH};
+use crate::ConfigureError;
+
+///
+/// Print a heading-style message to the console
+pub fn heading(string: &str) {
+    if !is_quiet() {
+        question(string);
+    }
+}
+
+///
+/// Print a warning to the console
+pub fn warn(string: &str) {
+    if !is_quiet() {
+        println!("{}", string);
+    }
+}
+
+///
+/// Print an unstyled message to the console
+pub fn message(string: &str) {
+    if !is_quiet() {
+        println!("{}", string);
+    }
+}
+
+///
+/// Emphasize part of a message
+pub fn highlight(string: &str) -> String {
+    string.to_string()
+}
+
+///
+/// Print a blank line to the console
+pub fn newline() {
+    if !is_quiet() {
+        println!();
+    }
+}
+
+///
+/// Prompt the user to input text on the command line
+pub fn prompt(message: &str) -> String {
+    question(message);
+    read_answer()
+}
+
+///
+/// Prompt the user to input text – without a terminal UI the suggestion can't be edited, so an empty answer accepts it
+pub fn prompt_with_initial_text(message: &str, initial_text: &str) -> String {
+    question(message);
+    print!("[{}] ", initial_text);
+
+    match read_answer() {
+        answer if answer.is_empty() => initial_text.to_string(),
+        answer => answer,
+    }
+}
+
+///
+/// Prompt the user for a secret – without a terminal UI the answer can't be hidden, so it's shown as it's typed
+pub fn prompt_secret(message: &str, with_confirmation: bool) -> String {
+    loop {
+        print!("{}: ", message);
+        let answer = read_answer();
+
+        if !with_confirmation {
+            return answer;
+        }
+
+        print!("Enter it again: ");
+        if read_answer() == answer {
+            return answer;
+        }
+
+        println!("The entries didn't match");
+    }
+}
+
+///
+/// Ask the user for confirmation
+pub fn confirm(message: &str) -> bool {
+    print!("{} [y/n] ", message);
+    matches!(read_answer().to_lowercase().as_str(), "y" | "yes")
+}
+
+///
+/// Ask the user to pick one of several options, returning the index of their choice
+pub fn choose(message: &str, items: &[&str]) -> usize {
+    question(message);
+    read_choice(items, 0)
+}
+
+///
+/// Allow the user to provide a list of items to select from, starting at `default` if it's one of them
+///
+/// Long lists are filtered with a search term first, so they don't scroll out of view.
+pub fn select(items: &[String], default: Option<&str>) -> Result<String, ConfigureError> {
+    if items.is_empty() {
+        return Err(ConfigureError::NothingToSelect);
+    }
+
+    let default_index = default
+        .and_then(|default| items.iter().position(|item| item == default))
+        .unwrap_or(0);
+
+    if items.len() <= SEARCHABLE_LIST_LENGTH {
+        let names: Vec<&str> = items.iter().map(String::as_str).collect();
+        let selection = read_choice(&names, default_index);
+
+        return Ok(items[selection].clone());
+    }
+
+    loop {
+        print!("Search {} items (leave empty to show all): ", items.len());
+        let query = read_answer();
+
+        let matches = fuzzy_matches(&query, items);
+        if matches.is_empty() {
+            println!("Nothing matches {:?}", query);
+            continue;
+        }
+
+        let names: Vec<&str> = matches.iter().map(|index| items[*index].as_str()).collect();
+        let default_match = matches
+            .iter()
+            .position(|index| *index == default_index)
+            .unwrap_or(0);
+        let selection = read_choice(&names, default_match);
+
+        return Ok(names[selection].to_string());
+    }
+}
+
+///
+/// Let the user pick any number of items by number, returning the ones they picked in the order they're listed
+///
+/// Numbers are separated by commas or spaces, and ranges like `3-7` can be used. An empty answer picks nothing.
+pub fn select_many(items: &[String]) -> Result<Vec<String>, ConfigureError> {
+    if items.is_empty() {
+        return Err(ConfigureError::NothingToSelect);
+    }
+
+    for (index, item) in items.iter().enumerate() {
+        println!("  {}) {}", index + 1, item);
+    }
+
+    loop {
+        print!("Choose any of 1-{}, like 1,3-5 [none]: ", items.len());
+
+        match parse_choices(&read_answer(), items.len()) {
+            Some(selection) => {
+                return Ok(selection
+                    .into_iter()
+                    .map(|index| items[index].clone())
+                    .collect())
+            }
+            None => println!("Please enter numbers between 1 and {}", items.len()),
+        }
+    }
+}
+
+///
+/// Plain output is never styled, so there's nothing to turn on or off
+pub fn set_colors_enabled(_enabled: bool) {}
+
+///
+/// Show progress for long-running work – without a terminal UI, this just prints the message
+pub struct Spinner;
+
+impl Spinner {
+    pub fn new(message: &str) -> Spinner {
+        if !is_quiet() {
+            println!("{}…", message);
+        }
+
+        Spinner
+    }
+
+    pub fn finish(self) {}
+}
+
+/// List `items` with numbers, and ask for one until the answer is valid. An empty answer picks `default`.
+fn read_choice(items: &[&str], default: usize) -> usize {
+    for (index, item) in items.iter().enumerate() {
+        println!("  {}) {}", index + 1, item);
+    }
+
+    loop {
+        print!("Choose 1-{} [{}]: ", items.len(), default + 1);
+        let answer = read_answer();
+
+        if answer.is_empty() {
+            return default;
+        }
+
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=items.len()).contains(&number) => return number - 1,
+            _ => println!("Please enter a number between 1 and {}", items.len()),
+        }
+    }
+}
+
+/// The indices picked by an answer like `1, 3-5`, in order and without repeats – `None` if any part isn't valid
+fn parse_choices(answer: &str, count: usize) -> Option<Vec<usize>> {
+    let mut picked = vec![false; count];
+
+    for part in answer
+        .split(|character: char| character == ',' || character.is_whitespace())
+        .filter(|part| !part.is_empty())
+    {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim().parse().ok()?, last.trim().parse().ok()?),
+            None => (part.parse().ok()?, part.parse().ok()?),
+        };
+
+        if first < 1 || last > count || first > last {
+            return None;
+        }
+
+        for number in first..=last {
+            picked[number - 1] = true;
+        }
+    }
+
+    Some((0..count).filter(|index| picked[*index]).collect())
+}
+
+fn read_answer() -> String {
+    io::stdout()
+        .flush()
+        .expect("Unable to write to the console");
+
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .expect("Unable to read from the console");
+
+    answer.trim().to_string()
+}
+
+/// Print the question for a prompt – this is shown even in quiet mode, or there would be nothing to answer
+fn question(string: &str) {
+    println!("{}", string);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_choices_can_be_listed_and_ranged() {
+        assert_eq!(parse_choices("", 5), Some(vec![]));
+        assert_eq!(parse_choices("4, 1", 5), Some(vec![0, 3]));
+        assert_eq!(parse_choices("2-4 3", 5), Some(vec![1, 2, 3]));
+        assert_eq!(parse_choices("6", 5), None);
+        assert_eq!(parse_choices("0-2", 5), None);
+        assert_eq!(parse_choices("two", 5), None);
+    }
+}
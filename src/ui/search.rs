@@ -0,0 +1,83 @@
+//! Filtering long lists down to the items a user is looking for, shared by both UIs
+
+/// Lists longer than this ask for a search term before showing the items
+pub const SEARCHABLE_LIST_LENGTH: usize = 15;
+
+/// The indices of the items in `items` that match `query`, best matches first
+///
+/// Items containing `query` come first, ordered by how early it appears. After them come items that contain the
+/// characters of `query` in order with gaps between them, ordered by how spread out those characters are – so
+/// `trk` finds `trunk`. Case is ignored, and an empty query matches everything in its original order.
+pub fn fuzzy_matches(query: &str, items: &[String]) -> Vec<usize> {
+    let query = query.trim().to_lowercase();
+
+    let mut matches: Vec<(MatchScore, usize)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| Some((score(&query, &item.to_lowercase())?, index)))
+        .collect();
+
+    // The sort is stable, so equally good matches stay in their original order
+    matches.sort_by_key(|(score, _)| *score);
+    matches.into_iter().map(|(_, index)| index).collect()
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+enum MatchScore {
+    /// The item contains the query, starting at this character
+    Substring(usize),
+
+    /// The item contains the query's characters in order, spread across this many characters
+    Subsequence(usize),
+}
+
+fn score(query: &str, item: &str) -> Option<MatchScore> {
+    if let Some(position) = item.find(query) {
+        return Some(MatchScore::Substring(item[..position].chars().count()));
+    }
+
+    let mut query_chars = query.chars().peekable();
+    let mut first_match = None;
+    let mut last_match = 0;
+
+    for (position, character) in item.chars().enumerate() {
+        if query_chars.peek() == Some(&character) {
+            query_chars.next();
+            first_match.get_or_insert(position);
+            last_match = position;
+        }
+    }
+
+    match (query_chars.peek(), first_match) {
+        (None, Some(first_match)) => Some(MatchScore::Subsequence(last_match - first_match)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_that_an_empty_query_matches_everything_in_order() {
+        let items = items(&["trunk", "release/1.0", "main"]);
+        assert_eq!(fuzzy_matches("", &items), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_that_substring_matches_come_before_subsequence_matches() {
+        let items = items(&["task/rename-keys", "trunk", "release/trunk-fix", "other"]);
+        assert_eq!(fuzzy_matches("trunk", &items), vec![1, 2]);
+        assert_eq!(fuzzy_matches("trk", &items), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_that_matching_ignores_case() {
+        let items = items(&["Feature/Login", "trunk"]);
+        assert_eq!(fuzzy_matches("LOGIN", &items), vec![0]);
+    }
+}
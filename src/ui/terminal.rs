@@ -0,0 +1,221 @@
+use console::{style, Term};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, MultiSelect, Password, Select};
+use indicatif::ProgressBar;
+use std::io::IsTerminal;
+
+use super::is_quiet;
+use super::search::{fuzzy_matches, SEARCHABLE_LIST_LENGTH};
+use crate::ConfigureError;
+
+///
+/// Print a heading-style message to the console
+pub fn heading(string: &str) {
+    if !is_quiet() {
+        question(string);
+    }
+}
+
+///
+/// Print a warning to the console
+pub fn warn(string: &str) {
+    if !is_quiet() {
+        println!("{}", style(string).yellow());
+    }
+}
+
+///
+/// Print an unstyled message to the console
+pub fn message(string: &str) {
+    if !is_quiet() {
+        println!("{}", string);
+    }
+}
+
+///
+/// Emphasize part of a message
+pub fn highlight(string: &str) -> String {
+    style(string).green().to_string()
+}
+
+///
+/// Print a blank line to the console
+pub fn newline() {
+    if !is_quiet() {
+        println!();
+    }
+}
+
+///
+/// Prompt the user to input text on the command line
+pub fn prompt(message: &str) -> String {
+    question(message);
+    Input::<String>::new().interact_text().unwrap()
+}
+
+///
+/// Prompt the user to input text, starting with `initial_text` so a suggestion can be accepted or edited
+pub fn prompt_with_initial_text(message: &str, initial_text: &str) -> String {
+    question(message);
+    Input::<String>::new()
+        .with_initial_text(initial_text)
+        .interact_text()
+        .unwrap()
+}
+
+///
+/// Prompt the user for a secret without showing it, asking twice if `with_confirmation` is set
+pub fn prompt_secret(message: &str, with_confirmation: bool) -> String {
+    let mut password = Password::new();
+    password.with_prompt(message);
+
+    if with_confirmation {
+        password.with_confirmation("Enter it again", "The entries didn't match");
+    }
+
+    password.interact().unwrap()
+}
+
+///
+/// Ask the user for confirmation
+pub fn confirm(message: &str) -> bool {
+    Confirm::new().with_prompt(message).interact().unwrap()
+}
+
+///
+/// Ask the user to pick one of several options, returning the index of their choice
+pub fn choose(message: &str, items: &[&str]) -> usize {
+    Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(message)
+        .items(items)
+        .default(0)
+        .interact_on(&Term::stderr())
+        .unwrap()
+}
+
+///
+/// Allow the user to provide a list of items to select from, starting at `default` if it's one of them
+///
+/// Long lists are filtered with a search term first, and shown a page at a time.
+pub fn select(items: &[String], default: Option<&str>) -> Result<String, ConfigureError> {
+    if items.is_empty() {
+        return Err(ConfigureError::NothingToSelect);
+    }
+
+    let default_index = default
+        .and_then(|default| items.iter().position(|item| item == default))
+        .unwrap_or(0);
+
+    if items.len() <= SEARCHABLE_LIST_LENGTH {
+        return match Select::with_theme(&ColorfulTheme::default())
+            .items(items)
+            .default(default_index)
+            .interact_on_opt(&Term::stderr())?
+        {
+            Some(selection) => Ok(items[selection].clone()),
+            None => Err(ConfigureError::Cancelled),
+        };
+    }
+
+    loop {
+        let query = Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Search {} items (leave empty to show all)",
+                items.len()
+            ))
+            .allow_empty(true)
+            .interact_text()?;
+
+        let matches = fuzzy_matches(&query, items);
+        if matches.is_empty() {
+            warn(&format!("Nothing matches {:?}", query));
+            continue;
+        }
+
+        let matching_items: Vec<&String> = matches.iter().map(|index| &items[*index]).collect();
+        let default_match = matches
+            .iter()
+            .position(|index| *index == default_index)
+            .unwrap_or(0);
+
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .items(&matching_items)
+            .default(default_match)
+            .paged(true)
+            .interact_on_opt(&Term::stderr())?;
+
+        // Escape goes back to searching
+        if let Some(selection) = selection {
+            return Ok(matching_items[selection].clone());
+        }
+    }
+}
+
+///
+/// Let the user tick any number of items, returning the ones they picked in the order they're listed
+///
+/// Long lists are shown a page at a time.
+pub fn select_many(items: &[String]) -> Result<Vec<String>, ConfigureError> {
+    if items.is_empty() {
+        return Err(ConfigureError::NothingToSelect);
+    }
+
+    message("Space ticks an item, and enter finishes.");
+
+    match MultiSelect::with_theme(&ColorfulTheme::default())
+        .items(items)
+        .paged(true)
+        .interact_on_opt(&Term::stderr())?
+    {
+        Some(selection) => Ok(selection
+            .into_iter()
+            .map(|index| items[index].clone())
+            .collect()),
+        None => Err(ConfigureError::Cancelled),
+    }
+}
+
+///
+/// Turn styled output on or off, overriding what the terminal supports
+pub fn set_colors_enabled(enabled: bool) {
+    console::set_colors_enabled(enabled);
+    console::set_colors_enabled_stderr(enabled);
+}
+
+///
+/// An animated indicator for long-running work
+pub struct Spinner {
+    bar: ProgressBar,
+}
+
+impl Spinner {
+    pub fn new(message: &str) -> Spinner {
+        if is_quiet() {
+            return Spinner {
+                bar: ProgressBar::hidden(),
+            };
+        }
+
+        // Redrawing the spinner in a log file would leave a line for every frame, so just say what's happening
+        if !std::io::stderr().is_terminal() || crate::ci::ci_environment().is_some() {
+            println!("{}…", message);
+            return Spinner {
+                bar: ProgressBar::hidden(),
+            };
+        }
+
+        let bar = ProgressBar::new_spinner();
+        bar.enable_steady_tick(125);
+        bar.set_message(message);
+        Spinner { bar }
+    }
+
+    /// Remove the spinner from the console
+    pub fn finish(self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+/// Print the question for a prompt – this is shown even in quiet mode, or there would be nothing to answer
+fn question(string: &str) {
+    println!("{}", style(string).green());
+}
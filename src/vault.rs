@@ -0,0 +1,173 @@
+//! A built-in key provider that reads project keys from HashiCorp Vault
+//!
+//! A project uses it with `"key_provider": "vault"` in its `.configure` file. The key is read with the `vault` command
+//! line tool, so it's set up the way Vault usually is – `VAULT_ADDR`, `VAULT_NAMESPACE` and the CA settings are passed
+//! straight through. Vault is only asked when `VAULT_ADDR` is set, so developers without it still get their key from
+//! `keys.json`.
+//!
+//! * `VAULT_TOKEN` (or the token `vault login` saved) is used as it is. For AppRole auth, set
+//!   `CONFIGURE_VAULT_ROLE_ID` and `CONFIGURE_VAULT_SECRET_ID` instead, and a token is requested with them first.
+//! * Keys are read from `secret/configure/<key name>` in a KV secrets engine, from a field called `key`. Set
+//!   `CONFIGURE_VAULT_PATH` to change the path – `{key_name}` in it is replaced by the key's name – and
+//!   `CONFIGURE_VAULT_FIELD` to change the field.
+
+use crate::encryption::EncryptionKey;
+use crate::plugins::KeyProvider;
+use crate::ConfigureError;
+use log::{debug, info};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// The name a `.configure` file uses for this provider
+pub const VAULT_PROVIDER_NAME: &str = "vault";
+
+/// The environment variable with the path of each key in Vault, where `{key_name}` is replaced by the key's name
+pub const VAULT_PATH_VARIABLE: &str = "CONFIGURE_VAULT_PATH";
+
+/// The environment variable with the field each key is in
+pub const VAULT_FIELD_VARIABLE: &str = "CONFIGURE_VAULT_FIELD";
+
+/// The environment variables with the role ID and secret ID to log in with, for AppRole auth
+pub const VAULT_ROLE_ID_VARIABLE: &str = "CONFIGURE_VAULT_ROLE_ID";
+pub const VAULT_SECRET_ID_VARIABLE: &str = "CONFIGURE_VAULT_SECRET_ID";
+
+const DEFAULT_PATH: &str = "secret/configure/{key_name}";
+const DEFAULT_FIELD: &str = "key";
+
+/// Reads project keys from Vault – see the module documentation for how it's set up
+#[derive(Debug, Default)]
+pub struct VaultKeyProvider;
+
+impl KeyProvider for VaultKeyProvider {
+    fn key(&self, key_name: &str) -> Result<Option<EncryptionKey>, ConfigureError> {
+        if std::env::var("VAULT_ADDR").is_err() {
+            debug!(
+                "VAULT_ADDR isn't set, so Vault isn't asked for {}'s key",
+                key_name
+            );
+            return Ok(None);
+        }
+
+        let path = secret_path(
+            &std::env::var(VAULT_PATH_VARIABLE).unwrap_or_else(|_| DEFAULT_PATH.to_string()),
+            key_name,
+        );
+        let field =
+            std::env::var(VAULT_FIELD_VARIABLE).unwrap_or_else(|_| DEFAULT_FIELD.to_string());
+
+        let mut command = vault_command();
+        command
+            .arg("kv")
+            .arg("get")
+            .arg(format!("-field={}", field))
+            .arg(&path);
+
+        if let Some(token) = approle_token()? {
+            command.env("VAULT_TOKEN", token.as_str());
+        }
+
+        debug!("Reading {}'s key from {} in Vault", key_name, path);
+        let output = run(&mut command, None)?;
+        let stdout = Zeroizing::new(output.stdout);
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            // Let the project fall back to `keys.json`, as it would without Vault
+            if stderr.contains("No value found") {
+                debug!("Vault doesn't have a key for {} at {}", key_name, path);
+                return Ok(None);
+            }
+
+            return Err(ConfigureError::VaultRequestFailed {
+                reason: stderr.trim().to_string(),
+            });
+        }
+
+        info!("Using the key for {} from Vault", key_name);
+        let key = String::from_utf8_lossy(&stdout);
+        Ok(Some(EncryptionKey::from_str(key.trim())?))
+    }
+}
+
+/// `template` with `{key_name}` replaced
+fn secret_path(template: &str, key_name: &str) -> String {
+    template.replace("{key_name}", key_name)
+}
+
+/// A token from logging in with AppRole, if a role ID and secret ID are set
+fn approle_token() -> Result<Option<Zeroizing<String>>, ConfigureError> {
+    let (role_id, secret_id) = match (
+        std::env::var(VAULT_ROLE_ID_VARIABLE),
+        std::env::var(VAULT_SECRET_ID_VARIABLE).map(Zeroizing::new),
+    ) {
+        (Ok(role_id), Ok(secret_id)) => (role_id, secret_id),
+        _ => return Ok(None),
+    };
+
+    debug!("Logging in to Vault with AppRole");
+
+    // The secret ID is passed on stdin (that's what `-` means), so it never shows up in the list of processes
+    let mut command = vault_command();
+    command
+        .arg("write")
+        .arg("-field=token")
+        .arg("auth/approle/login")
+        .arg(format!("role_id={}", role_id))
+        .arg("secret_id=-");
+
+    let output = run(&mut command, Some(secret_id.as_bytes()))?;
+    let token = Zeroizing::new(String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+    match output.status.success() && !token.is_empty() {
+        true => Ok(Some(token)),
+        false => Err(ConfigureError::VaultRequestFailed {
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }),
+    }
+}
+
+fn vault_command() -> Command {
+    let mut command = Command::new("vault");
+    command.env("VAULT_FORMAT", "table"); // `-field` prints the bare value, as long as JSON output wasn't asked for
+    command
+}
+
+fn run(command: &mut Command, input: Option<&[u8]>) -> Result<Output, ConfigureError> {
+    let mut child = command
+        .stdin(match input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| ConfigureError::VaultRequestFailed {
+            reason: format!("Unable to run `vault` – is it installed? ({})", err),
+        })?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input)?;
+    }
+
+    Ok(child.wait_with_output()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_the_key_name_is_put_into_the_secret_path() {
+        assert_eq!(
+            secret_path(DEFAULT_PATH, "WordPress-Android"),
+            "secret/configure/WordPress-Android"
+        );
+        assert_eq!(
+            secret_path("kv/mobile/{key_name}/encryption", "example"),
+            "kv/mobile/example/encryption"
+        );
+    }
+}
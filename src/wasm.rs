@@ -0,0 +1,43 @@
+//! JavaScript bindings for inspecting encrypted files in the browser
+//!
+//! Build with `cargo build --lib --target wasm32-unknown-unknown --no-default-features`, then run `wasm-bindgen` on
+//! the output. Nothing leaves the page – the file and key are only ever held in memory.
+
+use crate::encryption::{self, EncryptionKey};
+use wasm_bindgen::prelude::*;
+
+/// Create a new encryption key
+#[wasm_bindgen(js_name = generateKey)]
+pub fn generate_key() -> Result<String, JsValue> {
+    match encryption::generate_key() {
+        Ok(key) => Ok(key.to_string()),
+        Err(err) => Err(JsValue::from_str(&err.to_string())),
+    }
+}
+
+/// Encrypt `contents` with a base64-encoded key, giving the bytes of an `.enc` file
+#[wasm_bindgen]
+pub fn encrypt(contents: &[u8], key: &str) -> Result<Vec<u8>, JsValue> {
+    let key = parse_key(key)?;
+
+    match encryption::encrypt_bytes(contents, &key) {
+        Ok(encrypted_bytes) => Ok(encrypted_bytes),
+        Err(err) => Err(JsValue::from_str(&err.to_string())),
+    }
+}
+
+/// Decrypt the bytes of an `.enc` file with a base64-encoded key
+#[wasm_bindgen]
+pub fn decrypt(contents: &[u8], key: &str) -> Result<Vec<u8>, JsValue> {
+    let key = parse_key(key)?;
+
+    match encryption::decrypt_bytes(contents, &key) {
+        Ok(decrypted_bytes) => Ok(decrypted_bytes),
+        Err(err) => Err(JsValue::from_str(&err.to_string())),
+    }
+}
+
+fn parse_key(key: &str) -> Result<EncryptionKey, JsValue> {
+    key.parse::<EncryptionKey>()
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
@@ -0,0 +1,242 @@
+use crate::fs::{
+    decrypt_files_for_configuration, find_secrets_repo, read_verified_configuration_from_file,
+    status_for_configuration,
+};
+use crate::git::SecretsRepo;
+use crate::state::{ConfigurationStatus, FileStatus};
+use crate::{ApplyOptions, Configuration, ConflictPolicy};
+use log::{debug, info, warn};
+use std::time::{Duration, Instant};
+
+/// Options that control how often `watch` checks for changes, and what it does about them
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    /// How often to check the `.configure` file and encrypted files for changes
+    pub poll_interval: Duration,
+
+    /// How often to fetch the secrets repo to look for new commits on the configured branch
+    pub fetch_interval: Duration,
+
+    /// Whether local changes should be applied automatically. If not, they're only reported.
+    pub apply_automatically: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            poll_interval: Duration::from_secs(10),
+            fetch_interval: Duration::from_secs(15 * 60),
+            apply_automatically: true,
+        }
+    }
+}
+
+/// Whether `status` describes changes that `apply` can bring into the project
+///
+/// Locally modified destinations are left out – overwriting someone's edits is never done automatically.
+pub fn has_changes_to_apply(status: &ConfigurationStatus) -> bool {
+    if status.last_applied_hash.as_ref() != Some(&status.pinned_hash) {
+        return true;
+    }
+
+    status.files.iter().any(|(_, file_status)| {
+        matches!(
+            file_status,
+            FileStatus::NotApplied | FileStatus::Outdated | FileStatus::DestinationMissing
+        )
+    })
+}
+
+/// How `watch` applies changes – nobody is there to answer prompts, and a destination edited by hand is kept
+fn automatic_apply_options() -> ApplyOptions {
+    ApplyOptions {
+        interactive: false,
+        conflict_policy: Some(ConflictPolicy::Keep),
+        ..Default::default()
+    }
+}
+
+/// Watch the project and secrets repo until the process is stopped
+pub fn watch(configuration_file_path: &Option<String>, options: &WatchOptions) {
+    let mut last_fetch: Option<Instant> = None;
+    let mut last_reported_remote_hash: Option<String> = None;
+    let mut last_reported_status: Option<String> = None;
+    let mut last_reported_apply_error: Option<String> = None;
+
+    info!(
+        "Watching for changes every {:?} (fetching every {:?})",
+        options.poll_interval, options.fetch_interval
+    );
+
+    loop {
+        let configuration = match read_verified_configuration_from_file(configuration_file_path) {
+            Ok(configuration) => configuration,
+            Err(err) => {
+                warn!("Unable to read configuration: {}", err);
+                std::thread::sleep(options.poll_interval);
+                continue;
+            }
+        };
+
+        match status_for_configuration(&configuration) {
+            Ok(status) if has_changes_to_apply(&status) => {
+                if options.apply_automatically {
+                    // An apply that keeps failing is only reported once, rather than on every poll
+                    match last_reported_apply_error {
+                        Some(_) => debug!("Changes detected – trying to apply configuration again"),
+                        None => info!("Changes detected – applying configuration"),
+                    }
+
+                    match decrypt_files_for_configuration(
+                        &configuration,
+                        &automatic_apply_options(),
+                    ) {
+                        Ok(()) => {
+                            info!("Configuration applied");
+                            last_reported_apply_error = None;
+                        }
+                        Err(err) => {
+                            let description = err.to_string();
+                            if last_reported_apply_error.as_ref() != Some(&description) {
+                                warn!("Unable to apply configuration: {}", description);
+                                last_reported_apply_error = Some(description);
+                            }
+                        }
+                    }
+                } else {
+                    let description = status.to_string();
+                    if last_reported_status.as_ref() != Some(&description) {
+                        warn!("Changes are ready to apply – run `configure apply`");
+                        last_reported_status = Some(description);
+                    }
+                }
+            }
+            Ok(_) => debug!("Configuration is up to date"),
+            Err(err) => warn!("Unable to determine configuration status: {}", err),
+        }
+
+        let should_fetch = match last_fetch {
+            Some(last_fetch) => last_fetch.elapsed() >= options.fetch_interval,
+            None => true,
+        };
+
+        if should_fetch && !configuration.branch.is_empty() {
+            last_fetch = Some(Instant::now());
+
+            if let Some(latest_hash) = fetch_latest_hash(&configuration) {
+                if latest_hash != configuration.pinned_hash
+                    && last_reported_remote_hash.as_ref() != Some(&latest_hash)
+                {
+                    warn!(
+                        "New secrets are available on the {:?} branch – run `configure update` to use them",
+                        configuration.branch
+                    );
+                    last_reported_remote_hash = Some(latest_hash);
+                }
+            }
+        }
+
+        std::thread::sleep(options.poll_interval);
+    }
+}
+
+fn fetch_latest_hash(configuration: &Configuration) -> Option<String> {
+    let branch = &configuration.branch;
+
+    let repo = match find_secrets_repo() {
+        Ok(path) => SecretsRepo { path },
+        Err(err) => {
+            warn!("{}", err);
+            return None;
+        }
+    };
+
+    // Only the hash is needed here – `update` does the full fetch when it's time to use the new secrets
+    let remote = match repo.remote_for_configuration(configuration) {
+        Ok(remote) => remote,
+        Err(err) => {
+            warn!("{}", err);
+            return None;
+        }
+    };
+
+    match repo.query_remote_hash_for_branch(&remote, branch) {
+        Ok(hash) if !hash.is_empty() => Some(hash),
+        Ok(_) => {
+            warn!("Unable to find the {:?} branch on the server", branch);
+            None
+        }
+        Err(err) => {
+            warn!("Unable to find the latest secrets hash: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_that_a_new_pinned_hash_has_changes_to_apply() {
+        let mut status = test_status(FileStatus::UpToDate);
+        status.pinned_hash = "def456".to_string();
+        assert!(has_changes_to_apply(&status))
+    }
+
+    #[test]
+    fn test_that_outdated_files_have_changes_to_apply() {
+        assert!(has_changes_to_apply(&test_status(FileStatus::Outdated)))
+    }
+
+    #[test]
+    fn test_that_locally_modified_files_are_never_applied_automatically() {
+        assert!(!has_changes_to_apply(&test_status(
+            FileStatus::LocallyModified
+        )))
+    }
+
+    #[test]
+    fn test_that_automatic_applies_keep_locally_modified_destinations() {
+        let resolution = automatic_apply_options()
+            .resolve_conflict(
+                std::path::Path::new("foo.json"),
+                b"{\"edited\": true}",
+                b"{\"edited\": false}",
+            )
+            .unwrap();
+        assert_eq!(resolution, crate::configure::ConflictResolution::Keep)
+    }
+
+    #[test]
+    fn test_that_kept_files_are_not_applied_again_when_their_encrypted_file_changes() {
+        let file = crate::configure::File::new("a.json", "foo.json").unwrap();
+        let mut state = crate::state::ApplyState::default();
+        state.record_applied_file(&file, "old-encrypted".to_string(), "old".to_string());
+
+        // The encrypted file changed, but the destination was edited by hand, so it was kept
+        state.record_kept_file(&file, "new-encrypted".to_string(), "new".to_string());
+
+        let file_status = state
+            .applied_file("foo.json")
+            .unwrap()
+            .status("new-encrypted", "edited");
+        assert_eq!(file_status, FileStatus::LocallyModified);
+        assert!(!has_changes_to_apply(&test_status(file_status)))
+    }
+
+    #[test]
+    fn test_that_up_to_date_configuration_has_no_changes_to_apply() {
+        assert!(!has_changes_to_apply(&test_status(FileStatus::UpToDate)))
+    }
+
+    fn test_status(file_status: FileStatus) -> ConfigurationStatus {
+        ConfigurationStatus {
+            pinned_hash: "abc123".to_string(),
+            last_applied_hash: Some("abc123".to_string()),
+            applied_at: None,
+            files: vec![("foo.json".to_string(), file_status)],
+            provenance: Default::default(),
+        }
+    }
+}